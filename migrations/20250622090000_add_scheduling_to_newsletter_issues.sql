@@ -0,0 +1,6 @@
+-- Add migration script here
+ALTER TABLE newsletter_issues
+    ADD COLUMN status TEXT NOT NULL DEFAULT 'published',
+    ADD COLUMN send_at timestamptz;
+ALTER TABLE newsletter_issues ALTER COLUMN published_at DROP NOT NULL;
+ALTER TABLE newsletter_issues ALTER COLUMN status DROP DEFAULT;
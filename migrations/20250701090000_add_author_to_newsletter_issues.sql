@@ -0,0 +1,3 @@
+-- Add migration script here
+ALTER TABLE newsletter_issues
+    ADD COLUMN author_id uuid REFERENCES users (user_id) ON DELETE SET NULL;
@@ -0,0 +1,13 @@
+-- Add migration script here
+CREATE TABLE tags (
+    tag_id uuid NOT NULL PRIMARY KEY,
+    name TEXT NOT NULL UNIQUE,
+    -- Signup lists are the tags visitors can pick themselves when subscribing.
+    signup_list BOOLEAN NOT NULL DEFAULT false
+);
+CREATE TABLE subscription_tags (
+    subscriber_id uuid NOT NULL REFERENCES subscriptions (id) ON DELETE CASCADE,
+    tag_id uuid NOT NULL REFERENCES tags (tag_id) ON DELETE CASCADE,
+    PRIMARY KEY (subscriber_id, tag_id)
+);
+ALTER TABLE newsletter_issues ADD COLUMN segment TEXT;
@@ -0,0 +1,5 @@
+-- Add migration script here
+ALTER TABLE subscriptions
+    ADD COLUMN plain_text_only BOOLEAN NOT NULL DEFAULT false,
+    -- Delivery resumes automatically once this moment has passed.
+    ADD COLUMN paused_until timestamptz;
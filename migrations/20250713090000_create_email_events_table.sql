@@ -0,0 +1,14 @@
+-- Add migration script here
+CREATE TABLE email_events (
+    event_id uuid NOT NULL PRIMARY KEY,
+    provider TEXT NOT NULL,
+    -- 'bounce', 'spam_complaint' or 'delivery'.
+    event_type TEXT NOT NULL,
+    email TEXT NOT NULL,
+    -- The provider's classification, e.g. the bounce type.
+    detail TEXT,
+    occurred_at timestamptz,
+    received_at timestamptz NOT NULL DEFAULT now(),
+    payload TEXT NOT NULL
+);
+CREATE INDEX email_events_email_idx ON email_events (email);
@@ -0,0 +1,16 @@
+-- Add migration script here
+ALTER TABLE newsletter_issues
+    ADD COLUMN tracking BOOLEAN NOT NULL DEFAULT false,
+    -- Set when delivery tasks are enqueued.
+    ADD COLUMN recipient_count INTEGER;
+CREATE TABLE tracking_events (
+    event_id uuid NOT NULL PRIMARY KEY,
+    newsletter_issue_id uuid NOT NULL
+        REFERENCES newsletter_issues (newsletter_issue_id) ON DELETE CASCADE,
+    subscriber_id uuid NOT NULL REFERENCES subscriptions (id) ON DELETE CASCADE,
+    -- 'open' or 'click'.
+    event_type TEXT NOT NULL,
+    url TEXT,
+    occurred_at timestamptz NOT NULL DEFAULT now()
+);
+CREATE INDEX tracking_events_issue_idx ON tracking_events (newsletter_issue_id);
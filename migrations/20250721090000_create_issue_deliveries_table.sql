@@ -0,0 +1,13 @@
+-- Add migration script here
+CREATE TABLE issue_deliveries (
+    newsletter_issue_id uuid NOT NULL
+        REFERENCES newsletter_issues (newsletter_issue_id) ON DELETE CASCADE,
+    subscriber_email TEXT NOT NULL,
+    -- 'queued', 'sent', 'failed_retrying', 'failed_permanent' or 'skipped'.
+    status TEXT NOT NULL,
+    provider_message_id TEXT,
+    last_error TEXT,
+    n_attempts SMALLINT NOT NULL DEFAULT 0,
+    updated_at timestamptz NOT NULL DEFAULT now(),
+    PRIMARY KEY (newsletter_issue_id, subscriber_email)
+);
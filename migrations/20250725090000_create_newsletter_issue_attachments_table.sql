@@ -0,0 +1,11 @@
+-- Add migration script here
+CREATE TABLE newsletter_issue_attachments (
+    attachment_id uuid PRIMARY KEY,
+    newsletter_issue_id uuid NOT NULL
+        REFERENCES newsletter_issues (newsletter_issue_id) ON DELETE CASCADE,
+    file_name TEXT NOT NULL,
+    content_type TEXT NOT NULL,
+    content BYTEA NOT NULL,
+    created_at timestamptz NOT NULL DEFAULT now()
+);
+CREATE INDEX newsletter_issue_attachments_issue_idx ON newsletter_issue_attachments (newsletter_issue_id);
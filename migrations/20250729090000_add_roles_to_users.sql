@@ -0,0 +1,19 @@
+-- Existing users keep full access; new ones get the least privileges.
+ALTER TABLE users
+    ADD COLUMN role TEXT NOT NULL DEFAULT 'owner'
+        CHECK (role IN ('owner', 'editor', 'viewer')),
+    ADD COLUMN disabled_at timestamptz;
+ALTER TABLE users ALTER COLUMN role SET DEFAULT 'viewer';
+
+-- Users can now be deleted.
+ALTER TABLE idempotency
+    DROP CONSTRAINT idempotency_user_id_fkey,
+    ADD CONSTRAINT idempotency_user_id_fkey
+        FOREIGN KEY (user_id) REFERENCES users (user_id) ON DELETE CASCADE;
+
+-- Invited users cannot log in until they have chosen a password.
+CREATE TABLE user_invitations (
+    invitation_token TEXT PRIMARY KEY,
+    user_id uuid NOT NULL UNIQUE REFERENCES users (user_id) ON DELETE CASCADE,
+    expires_at timestamptz NOT NULL
+);
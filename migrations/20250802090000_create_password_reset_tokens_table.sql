@@ -0,0 +1,11 @@
+-- Stored in each session at login: bumping it logs the user out everywhere.
+ALTER TABLE users ADD COLUMN session_version INTEGER NOT NULL DEFAULT 0;
+
+-- Only a hash of each token is stored, as a token grants access to an account.
+CREATE TABLE password_reset_tokens (
+    token_hash TEXT PRIMARY KEY,
+    user_id uuid NOT NULL REFERENCES users (user_id) ON DELETE CASCADE,
+    expires_at timestamptz NOT NULL,
+    used_at timestamptz
+);
+CREATE INDEX password_reset_tokens_user_id_idx ON password_reset_tokens (user_id);
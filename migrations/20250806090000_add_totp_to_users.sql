@@ -0,0 +1,13 @@
+-- Base32, set once the user has confirmed enrolment with a valid code.
+ALTER TABLE users
+    ADD COLUMN totp_secret TEXT,
+    -- The time step of the last accepted code, so that a code cannot be replayed.
+    ADD COLUMN totp_last_step BIGINT;
+
+-- Only hashes are stored; each code can be used once instead of a TOTP code.
+CREATE TABLE recovery_codes (
+    user_id uuid NOT NULL REFERENCES users (user_id) ON DELETE CASCADE,
+    code_hash TEXT NOT NULL,
+    used_at timestamptz,
+    PRIMARY KEY (user_id, code_hash)
+);
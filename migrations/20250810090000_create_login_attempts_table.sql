@@ -0,0 +1,11 @@
+-- Failed logins, counted separately per username and per client IP, so that
+-- neither guessing one account's password nor spraying many accounts from
+-- one address goes unchecked. Rows exist for unknown usernames too.
+CREATE TABLE login_attempts (
+    scope TEXT NOT NULL,
+    subject TEXT NOT NULL,
+    failed_attempts INTEGER NOT NULL,
+    last_failed_at timestamptz NOT NULL,
+    locked_until timestamptz,
+    PRIMARY KEY (scope, subject)
+);
@@ -0,0 +1,22 @@
+-- Two accounts sharing an address would receive each other's reset links.
+-- There is no telling which account owns a duplicated address, so none keeps it.
+UPDATE users
+SET email = NULL
+WHERE lower(email) IN (
+    SELECT lower(email) FROM users WHERE email IS NOT NULL GROUP BY lower(email) HAVING count(*) > 1
+);
+CREATE UNIQUE INDEX users_email_key ON users (lower(email));
+
+-- Reset links are only sent to confirmed addresses. Addresses saved before
+-- this migration were never confirmed and have to be saved again.
+ALTER TABLE users ADD COLUMN email_confirmed_at timestamptz;
+
+-- A new address replaces the current one once its confirmation link is
+-- opened. Only a hash of each token is stored.
+CREATE TABLE email_confirmation_tokens (
+    token_hash TEXT PRIMARY KEY,
+    user_id uuid NOT NULL REFERENCES users (user_id) ON DELETE CASCADE,
+    email TEXT NOT NULL,
+    expires_at timestamptz NOT NULL
+);
+CREATE INDEX email_confirmation_tokens_user_id_idx ON email_confirmation_tokens (user_id);
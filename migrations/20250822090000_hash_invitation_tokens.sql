@@ -0,0 +1,4 @@
+-- Only a hash of each invitation token is stored, as for password reset
+-- tokens: a token lets whoever holds it choose the user's password.
+ALTER TABLE user_invitations RENAME COLUMN invitation_token TO token_hash;
+UPDATE user_invitations SET token_hash = encode(sha256(convert_to(token_hash, 'UTF8')), 'hex');
@@ -0,0 +1,3 @@
+-- Reset links can be requested without logging in: the time each token was
+-- issued caps how often a user gets one.
+ALTER TABLE password_reset_tokens ADD COLUMN created_at timestamptz NOT NULL DEFAULT now();
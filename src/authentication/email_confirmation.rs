@@ -0,0 +1,126 @@
+use std::ops::DerefMut;
+
+use anyhow::Context;
+use chrono::Utc;
+use sqlx::PgPool;
+use uuid::Uuid;
+
+use super::password_reset::{generate_token, hash_token};
+use crate::domain::SubscriberEmail;
+
+/// How long a confirmation link for a new email address stays valid.
+const EMAIL_CONFIRMATION_TOKEN_TTL_MINUTES: i64 = 60;
+
+/// Returns the token to send to the new address.
+#[tracing::instrument(name = "Create email confirmation token", skip(pool, email))]
+pub async fn create_email_confirmation_token(
+    user_id: Uuid,
+    email: &SubscriberEmail,
+    pool: &PgPool,
+) -> Result<String, anyhow::Error> {
+    let token = generate_token();
+    sqlx::query!(
+        r#"
+        INSERT INTO email_confirmation_tokens (token_hash, user_id, email, expires_at)
+        VALUES ($1, $2, $3, $4)
+        "#,
+        hash_token(&token),
+        user_id,
+        email.as_ref(),
+        Utc::now() + chrono::Duration::minutes(EMAIL_CONFIRMATION_TOKEN_TTL_MINUTES),
+    )
+    .execute(pool)
+    .await
+    .context("Failed to store an email confirmation token.")?;
+    Ok(token)
+}
+
+pub enum EmailConfirmation {
+    Confirmed(String),
+    /// The token is unknown, expired or was issued to another user.
+    InvalidToken,
+    /// Another user took the address after the link was sent.
+    AddressTaken,
+}
+
+/// Makes the address the token was sent to the confirmed address of the
+/// user. Every outstanding token of the user is used up, so an older link
+/// cannot bring back a previous address.
+#[tracing::instrument(name = "Confirm email address", skip(token, pool))]
+pub async fn confirm_email(
+    token: &str,
+    user_id: Uuid,
+    pool: &PgPool,
+) -> Result<EmailConfirmation, anyhow::Error> {
+    let mut transaction = pool
+        .begin()
+        .await
+        .context("Failed to acquire a Postgres connection from the pool.")?;
+    let email = sqlx::query_scalar!(
+        r#"
+        SELECT email
+        FROM email_confirmation_tokens
+        WHERE token_hash = $1 AND user_id = $2 AND expires_at > now()
+        "#,
+        hash_token(token),
+        user_id,
+    )
+    .fetch_optional(transaction.deref_mut())
+    .await
+    .context("Failed to retrieve an email confirmation token.")?;
+    let Some(email) = email else {
+        return Ok(EmailConfirmation::InvalidToken);
+    };
+    if email_is_taken(&email, Some(user_id), pool).await? {
+        return Ok(EmailConfirmation::AddressTaken);
+    }
+    sqlx::query!(
+        "DELETE FROM email_confirmation_tokens WHERE user_id = $1",
+        user_id,
+    )
+    .execute(transaction.deref_mut())
+    .await
+    .context("Failed to use up the email confirmation tokens of a user.")?;
+    sqlx::query!(
+        r#"
+        UPDATE users
+        SET email = $1, email_confirmed_at = now()
+        WHERE user_id = $2
+        "#,
+        email,
+        user_id,
+    )
+    .execute(transaction.deref_mut())
+    .await
+    .context("Failed to update the email address of a user.")?;
+    transaction
+        .commit()
+        .await
+        .context("Failed to commit SQL transaction to confirm an email address.")?;
+    Ok(EmailConfirmation::Confirmed(email))
+}
+
+/// Whether an account, other than `except_user_id` if given, already uses
+/// the address.
+#[tracing::instrument(name = "Check whether an email address is taken", skip(email, pool))]
+pub async fn email_is_taken(
+    email: &str,
+    except_user_id: Option<Uuid>,
+    pool: &PgPool,
+) -> Result<bool, anyhow::Error> {
+    let taken = sqlx::query_scalar!(
+        r#"
+        SELECT EXISTS (
+            SELECT 1
+            FROM users
+            WHERE lower(email) = lower($1) AND user_id IS DISTINCT FROM $2
+        ) AS "taken!"
+        "#,
+        email,
+        except_user_id,
+    )
+    .fetch_one(pool)
+    .await
+    .context("Failed to check whether an email address is taken.")?;
+    Ok(taken)
+}
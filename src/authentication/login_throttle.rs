@@ -0,0 +1,331 @@
+use std::time::Duration;
+
+use anyhow::Context;
+use chrono::{DateTime, Utc};
+use sqlx::PgPool;
+
+use crate::{configuration::Settings, startup::get_connection_pool};
+
+const USERNAME_SCOPE: &str = "username";
+const IP_SCOPE: &str = "ip";
+
+#[derive(Clone, Copy, Debug)]
+pub struct LoginThrottlePolicy {
+    /// Failures older than this are forgotten.
+    pub failure_window: Duration,
+    /// Failed attempts allowed before the next ones are slowed down.
+    pub free_attempts: u32,
+    pub base_delay: Duration,
+    pub max_delay: Duration,
+    pub username_lockout_threshold: u32,
+    pub ip_lockout_threshold: u32,
+    pub lockout: Duration,
+}
+
+impl LoginThrottlePolicy {
+    /// Doubles with every failure past the free ones, up to `max_delay`.
+    fn delay(&self, failed_attempts: u32) -> Duration {
+        let Some(extra_failures) = failed_attempts.checked_sub(self.free_attempts) else {
+            return Duration::ZERO;
+        };
+        self.base_delay
+            .saturating_mul(2_u32.saturating_pow(extra_failures))
+            .min(self.max_delay)
+    }
+}
+
+#[derive(Debug, PartialEq)]
+pub enum LoginThrottle {
+    /// Wait for this long before checking the credentials.
+    Allowed(Duration),
+    LockedOut,
+}
+
+/// Lifts the locks of the username and the client IP that have expired, in
+/// case the sweeper has not got to them yet, then looks at what is left.
+#[tracing::instrument(name = "Check login throttle", skip(policy, pool))]
+pub async fn check_login_throttle(
+    username: &str,
+    client_ip: &str,
+    policy: &LoginThrottlePolicy,
+    pool: &PgPool,
+) -> Result<LoginThrottle, anyhow::Error> {
+    check_throttle(Some(username), client_ip, policy, pool).await
+}
+
+/// For the endpoints that do not take a password but can still be abused,
+/// such as password reset requests: only the client IP is looked at.
+#[tracing::instrument(name = "Check client IP throttle", skip(policy, pool))]
+pub async fn check_ip_throttle(
+    client_ip: &str,
+    policy: &LoginThrottlePolicy,
+    pool: &PgPool,
+) -> Result<LoginThrottle, anyhow::Error> {
+    check_throttle(None, client_ip, policy, pool).await
+}
+
+/// Without a username, only the client IP's row can match.
+async fn check_throttle(
+    username: Option<&str>,
+    client_ip: &str,
+    policy: &LoginThrottlePolicy,
+    pool: &PgPool,
+) -> Result<LoginThrottle, anyhow::Error> {
+    let unlocked = sqlx::query!(
+        r#"
+        WITH expired AS (
+            SELECT scope, subject, locked_until
+            FROM login_attempts
+            WHERE ((scope = $1 AND subject = $2) OR (scope = $3 AND subject = $4))
+                AND locked_until <= now()
+            FOR UPDATE
+        )
+        UPDATE login_attempts
+        SET locked_until = NULL, failed_attempts = 0
+        FROM expired
+        WHERE login_attempts.scope = expired.scope AND login_attempts.subject = expired.subject
+        RETURNING expired.scope, expired.subject, expired.locked_until AS "unlocked_at!"
+        "#,
+        USERNAME_SCOPE,
+        username,
+        IP_SCOPE,
+        client_ip,
+    )
+    .fetch_all(pool)
+    .await
+    .context("Failed to lift expired login lockouts.")?;
+    for row in unlocked {
+        log_unlock(&row.scope, &row.subject, row.unlocked_at);
+    }
+
+    let rows = sqlx::query!(
+        r#"
+        SELECT failed_attempts, locked_until
+        FROM login_attempts
+        WHERE ((scope = $1 AND subject = $2) OR (scope = $3 AND subject = $4))
+            AND (last_failed_at > $5 OR locked_until IS NOT NULL)
+        "#,
+        USERNAME_SCOPE,
+        username,
+        IP_SCOPE,
+        client_ip,
+        Utc::now() - policy.failure_window,
+    )
+    .fetch_all(pool)
+    .await
+    .context("Failed to retrieve failed login attempts.")?;
+    if rows.iter().any(|row| row.locked_until.is_some()) {
+        return Ok(LoginThrottle::LockedOut);
+    }
+    let failed_attempts = rows.iter().map(|row| row.failed_attempts).max().unwrap_or_default();
+    Ok(LoginThrottle::Allowed(policy.delay(failed_attempts.max(0) as u32)))
+}
+
+/// Counts a failure against both the username and the client IP, and locks
+/// whichever reaches its threshold.
+#[tracing::instrument(name = "Record failed login", skip(policy, pool))]
+pub async fn record_failed_login(
+    username: &str,
+    client_ip: &str,
+    policy: &LoginThrottlePolicy,
+    pool: &PgPool,
+) -> Result<(), anyhow::Error> {
+    record_failure(USERNAME_SCOPE, username, policy.username_lockout_threshold, policy, pool).await?;
+    record_failure(IP_SCOPE, client_ip, policy.ip_lockout_threshold, policy, pool).await
+}
+
+/// Counts a request against the client IP, as if it were a failed login.
+#[tracing::instrument(name = "Record throttled request", skip(policy, pool))]
+pub async fn record_ip_request(
+    client_ip: &str,
+    policy: &LoginThrottlePolicy,
+    pool: &PgPool,
+) -> Result<(), anyhow::Error> {
+    record_failure(IP_SCOPE, client_ip, policy.ip_lockout_threshold, policy, pool).await
+}
+
+/// Locks the subject once it reaches `threshold`.
+async fn record_failure(
+    scope: &str,
+    subject: &str,
+    threshold: u32,
+    policy: &LoginThrottlePolicy,
+    pool: &PgPool,
+) -> Result<(), anyhow::Error> {
+    let failed_attempts = sqlx::query_scalar!(
+        r#"
+        INSERT INTO login_attempts (scope, subject, failed_attempts, last_failed_at)
+        VALUES ($1, $2, 1, now())
+        ON CONFLICT (scope, subject) DO UPDATE
+        SET failed_attempts = CASE
+                WHEN login_attempts.last_failed_at > $3 THEN login_attempts.failed_attempts + 1
+                ELSE 1
+            END,
+            last_failed_at = now()
+        RETURNING failed_attempts
+        "#,
+        scope,
+        subject,
+        Utc::now() - policy.failure_window,
+    )
+    .fetch_one(pool)
+    .await
+    .context("Failed to record a failed login attempt.")?;
+    if failed_attempts < threshold as i32 {
+        return Ok(());
+    }
+    let locked_until = Utc::now() + policy.lockout;
+    sqlx::query!(
+        r#"
+        UPDATE login_attempts
+        SET locked_until = $3
+        WHERE scope = $1 AND subject = $2 AND locked_until IS NULL
+        "#,
+        scope,
+        subject,
+        locked_until,
+    )
+    .execute(pool)
+    .await
+    .context("Failed to lock out a login subject.")?;
+    tracing::warn!(
+        lockout.scope = %scope,
+        lockout.subject = %subject,
+        lockout.failed_attempts = failed_attempts,
+        lockout.until = %locked_until,
+        "Login locked out after too many failed attempts."
+    );
+    Ok(())
+}
+
+pub async fn run_lockout_sweeper_until_stopped(configuration: Settings) -> Result<(), anyhow::Error> {
+    let connection_pool = get_connection_pool(&configuration.database);
+    sweeper_loop(connection_pool, configuration.login_throttle.policy()).await
+}
+
+async fn sweeper_loop(pool: PgPool, policy: LoginThrottlePolicy) -> Result<(), anyhow::Error> {
+    loop {
+        let swept = lift_expired_lockouts(&pool).await;
+        let forgotten = forget_stale_failures(&policy, &pool).await;
+        match (swept, forgotten) {
+            (Ok(_), Ok(_)) => tokio::time::sleep(Duration::from_secs(10)).await,
+            _ => tokio::time::sleep(Duration::from_secs(1)).await,
+        }
+    }
+}
+
+/// Lifts every expired lock, so that each unlock is logged even if nobody
+/// tries to log in again. Returns the number of lifted locks.
+#[tracing::instrument(skip_all, fields(n_unlocked = tracing::field::Empty), err)]
+pub async fn lift_expired_lockouts(pool: &PgPool) -> Result<usize, anyhow::Error> {
+    let unlocked = sqlx::query!(
+        r#"
+        WITH expired AS (
+            SELECT scope, subject, locked_until
+            FROM login_attempts
+            WHERE locked_until <= now()
+            FOR UPDATE SKIP LOCKED
+        )
+        UPDATE login_attempts
+        SET locked_until = NULL, failed_attempts = 0
+        FROM expired
+        WHERE login_attempts.scope = expired.scope AND login_attempts.subject = expired.subject
+        RETURNING expired.scope, expired.subject, expired.locked_until AS "unlocked_at!"
+        "#,
+    )
+    .fetch_all(pool)
+    .await
+    .context("Failed to lift expired login lockouts.")?;
+    for row in &unlocked {
+        log_unlock(&row.scope, &row.subject, row.unlocked_at);
+    }
+    tracing::Span::current().record("n_unlocked", unlocked.len());
+    Ok(unlocked.len())
+}
+
+/// Deletes the unlocked rows whose last failure is outside the window: they
+/// no longer count, and every username or IP ever tried would pile up
+/// otherwise. Returns the number of deleted rows.
+#[tracing::instrument(skip_all, fields(n_forgotten = tracing::field::Empty), err)]
+pub async fn forget_stale_failures(
+    policy: &LoginThrottlePolicy,
+    pool: &PgPool,
+) -> Result<u64, anyhow::Error> {
+    let forgotten = sqlx::query!(
+        r#"
+        DELETE FROM login_attempts
+        WHERE locked_until IS NULL AND last_failed_at <= $1
+        "#,
+        Utc::now() - policy.failure_window,
+    )
+    .execute(pool)
+    .await
+    .context("Failed to delete stale failed login attempts.")?
+    .rows_affected();
+    tracing::Span::current().record("n_forgotten", forgotten);
+    Ok(forgotten)
+}
+
+/// The event carries the time the lock ended, not the time it was noticed.
+fn log_unlock(scope: &str, subject: &str, unlocked_at: DateTime<Utc>) {
+    tracing::info!(
+        lockout.scope = %scope,
+        lockout.subject = %subject,
+        lockout.unlocked_at = %unlocked_at,
+        "Login lockout expired."
+    );
+}
+
+/// A successful login wipes the username's failures. The IP's are kept: one
+/// valid account must not let an attacker reset the counter of an address.
+#[tracing::instrument(name = "Clear failed logins", skip(pool))]
+pub async fn clear_failed_logins(username: &str, pool: &PgPool) -> Result<(), anyhow::Error> {
+    sqlx::query!(
+        "DELETE FROM login_attempts WHERE scope = $1 AND subject = $2",
+        USERNAME_SCOPE,
+        username,
+    )
+    .execute(pool)
+    .await
+    .context("Failed to clear failed login attempts.")?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::LoginThrottlePolicy;
+
+    fn policy() -> LoginThrottlePolicy {
+        LoginThrottlePolicy {
+            failure_window: Duration::from_secs(900),
+            free_attempts: 3,
+            base_delay: Duration::from_millis(500),
+            max_delay: Duration::from_secs(4),
+            username_lockout_threshold: 10,
+            ip_lockout_threshold: 50,
+            lockout: Duration::from_secs(900),
+        }
+    }
+
+    #[test]
+    fn the_first_failures_are_not_delayed() {
+        for failed_attempts in 0..3 {
+            assert_eq!(policy().delay(failed_attempts), Duration::ZERO);
+        }
+    }
+
+    #[test]
+    fn the_delay_doubles_with_every_further_failure() {
+        assert_eq!(policy().delay(3), Duration::from_millis(500));
+        assert_eq!(policy().delay(4), Duration::from_secs(1));
+        assert_eq!(policy().delay(5), Duration::from_secs(2));
+    }
+
+    #[test]
+    fn the_delay_is_capped() {
+        assert_eq!(policy().delay(7), Duration::from_secs(4));
+        assert_eq!(policy().delay(u32::MAX), Duration::from_secs(4));
+    }
+}
@@ -1,10 +1,15 @@
 use std::{fmt::Display, ops::Deref};
 
-use actix_web::{body::MessageBody, dev::{ServiceRequest, ServiceResponse}, error::InternalError, middleware::Next, FromRequest, HttpMessage};
+use actix_web::{body::{EitherBody, MessageBody}, dev::{ServiceRequest, ServiceResponse}, error::InternalError, middleware::Next, web, FromRequest, HttpMessage};
+use actix_web_flash_messages::FlashMessage;
+use anyhow::Context;
+use sqlx::{PgExecutor, PgPool};
 use uuid::Uuid;
 
 use crate::{session_state::TypedSession, utils::{e500, see_other}};
 
+use super::Role;
+
 #[derive(Debug, Clone, Copy)]
 pub struct UserId(Uuid);
 
@@ -22,6 +27,9 @@ impl Deref for UserId {
     }
 } 
 
+/// Also loads the user's role, for `require_editor` and `require_owner`.
+/// Disabled users, and sessions invalidated by a password reset, are logged out.
+/// Sessions still waiting for a second factor have no user id and count as anonymous.
 pub async fn reject_anonymous_users(
     mut req: ServiceRequest,
     next: Next<impl MessageBody>,
@@ -31,15 +39,111 @@ pub async fn reject_anonymous_users(
         TypedSession::from_request(http_request, payload).await
     }?;
 
-    match session.get_user_id().map_err(e500)? {
-        Some(user_id) => {
+    let Some(user_id) = session.get_user_id().map_err(e500)? else {
+        let response = see_other("/login");
+        let e = anyhow::anyhow!("The user has not logged in.");
+        return Err(InternalError::from_response(e, response).into());
+    };
+    let pool = req
+        .app_data::<web::Data<PgPool>>()
+        .expect("The database pool is missing from the application data.");
+    let session_version = session.get_session_version().map_err(e500)?;
+    match get_active_user_role(user_id, session_version, pool).await.map_err(e500)? {
+        Some(role) => {
             req.extensions_mut().insert(UserId(user_id));
+            req.extensions_mut().insert(role);
             next.call(req).await
         }
         None => {
+            session.login_out();
             let response = see_other("/login");
-            let e = anyhow::anyhow!("The user has not logged in.");
+            let e = anyhow::anyhow!("The user has been disabled or deleted, or the session is no longer valid.");
             Err(InternalError::from_response(e, response).into())
-        } 
+        }
     }
-}
\ No newline at end of file
+}
+
+/// For routes that draft or publish content. Must run after `reject_anonymous_users`.
+pub async fn require_editor(
+    req: ServiceRequest,
+    next: Next<impl MessageBody + 'static>,
+) -> Result<ServiceResponse<impl MessageBody>, actix_web::Error> {
+    require_role(Role::Editor, req, next).await
+}
+
+/// For routes that manage users. Must run after `reject_anonymous_users`.
+pub async fn require_owner(
+    req: ServiceRequest,
+    next: Next<impl MessageBody + 'static>,
+) -> Result<ServiceResponse<impl MessageBody>, actix_web::Error> {
+    require_role(Role::Owner, req, next).await
+}
+
+/// Redirects to the dashboard rather than failing, so that the flash message is kept.
+async fn require_role<B: MessageBody + 'static>(
+    minimum: Role,
+    req: ServiceRequest,
+    next: Next<B>,
+) -> Result<ServiceResponse<EitherBody<B>>, actix_web::Error> {
+    let role = req.extensions().get::<Role>().copied();
+    match role {
+        Some(role) if role >= minimum => next
+            .call(req)
+            .await
+            .map(ServiceResponse::map_into_left_body),
+        _ => {
+            tracing::warn!("The user's role does not allow this action.");
+            FlashMessage::error("You do not have permission to do that.").send();
+            Ok(req
+                .into_response(see_other("/admin/dashboard"))
+                .map_into_right_body())
+        }
+    }
+}
+
+#[tracing::instrument(name = "Get active user role", skip(pool))]
+async fn get_active_user_role(
+    user_id: Uuid,
+    session_version: i32,
+    pool: &PgPool,
+) -> Result<Option<Role>, anyhow::Error> {
+    let row = sqlx::query!(
+        r#"
+        SELECT role
+        FROM users
+        WHERE user_id = $1 AND disabled_at IS NULL AND session_version = $2
+        "#,
+        user_id,
+        session_version
+    )
+    .fetch_optional(pool)
+    .await
+    .context("Failed to retrieve the role of a user.")?;
+    row.map(|row| Role::parse(&row.role).map_err(anyhow::Error::msg))
+        .transpose()
+}
+
+/// The version to store in a new session of the user.
+#[tracing::instrument(name = "Get session version", skip(pool))]
+pub async fn get_session_version(user_id: Uuid, pool: &PgPool) -> Result<i32, anyhow::Error> {
+    sqlx::query_scalar!(
+        "SELECT session_version FROM users WHERE user_id = $1",
+        user_id
+    )
+    .fetch_one(pool)
+    .await
+    .context("Failed to retrieve the session version of a user.")
+}
+
+/// Logs the user out of every session they have open.
+#[tracing::instrument(name = "Invalidate sessions", skip(executor))]
+pub async fn invalidate_sessions(user_id: Uuid, executor: impl PgExecutor<'_>) -> Result<(), anyhow::Error> {
+    sqlx::query!(
+        "UPDATE users SET session_version = session_version + 1 WHERE user_id = $1",
+        user_id
+    )
+    .execute(executor)
+    .await
+    .context("Failed to invalidate the sessions of a user.")?;
+    Ok(())
+}
@@ -1,9 +1,59 @@
 mod password;
 pub use password::{
     change_password,
+    compute_password_hash,
+    is_current_password,
     validate_credentials,
     AuthError,
     Credentials,
 };
 mod middleware;
-pub use middleware::{reject_anonymous_users, UserId};
+pub use middleware::{
+    get_session_version,
+    invalidate_sessions,
+    reject_anonymous_users,
+    require_editor,
+    require_owner,
+    UserId,
+};
+mod role;
+pub use role::Role;
+mod password_reset;
+pub use password_reset::{
+    consume_reset_token,
+    create_reset_token,
+    get_last_reset_token_issued_at,
+    hash_token,
+    reset_token_is_valid,
+    RESET_COOLDOWN_MINUTES,
+};
+mod email_confirmation;
+pub use email_confirmation::{
+    confirm_email,
+    create_email_confirmation_token,
+    email_is_taken,
+    EmailConfirmation,
+};
+mod totp;
+pub use totp::{
+    disable_totp,
+    enable_totp,
+    generate_totp_secret,
+    get_totp_secret,
+    otpauth_uri,
+    verify_second_factor,
+    verify_totp,
+};
+mod login_throttle;
+pub use login_throttle::{
+    check_ip_throttle,
+    check_login_throttle,
+    clear_failed_logins,
+    forget_stale_failures,
+    lift_expired_lockouts,
+    record_failed_login,
+    record_ip_request,
+    run_lockout_sweeper_until_stopped,
+    LoginThrottle,
+    LoginThrottlePolicy,
+};
\ No newline at end of file
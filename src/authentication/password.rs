@@ -1,8 +1,8 @@
 use argon2::{password_hash::{rand_core::OsRng, SaltString}, Argon2, Params, PasswordHash, PasswordVerifier, PasswordHasher};
-use sqlx::PgPool;
+use sqlx::{PgExecutor, PgPool};
 use anyhow::Context;
 
-use crate::telemetry::spawn_blocking_with_tracing;
+use crate::{domain::AdminPassword, telemetry::spawn_blocking_with_tracing};
 
 pub struct Credentials {
     pub username: String,
@@ -85,7 +85,7 @@ async fn get_store_credentials(
         r#"
         SELECT user_id, password_hash
         FROM users
-        WHERE username = $1
+        WHERE username = $1 AND disabled_at IS NULL
         "#,
         username,
     )
@@ -97,12 +97,37 @@ async fn get_store_credentials(
     Ok(row)
 }
 
+/// Whether `password` is the one the user currently logs in with.
+#[tracing::instrument(name = "Check against the current password", skip(password, executor))]
+pub async fn is_current_password(
+    user_id: uuid::Uuid,
+    password: &AdminPassword,
+    executor: impl PgExecutor<'_>,
+) -> Result<bool, anyhow::Error> {
+    let password_hash = sqlx::query_scalar!(
+        "SELECT password_hash FROM users WHERE user_id = $1",
+        user_id
+    )
+    .fetch_one(executor)
+    .await
+    .context("Failed to retrieve the current password hash.")?;
+    let password = password.as_ref().to_owned();
+    match spawn_blocking_with_tracing(move || verify_password_hash(password_hash, password))
+        .await
+        .context("Failed to spawn blocking task")?
+    {
+        Ok(()) => Ok(true),
+        Err(AuthError::InvalidCredentials(_)) => Ok(false),
+        Err(AuthError::UnexpectedError(e)) => Err(e),
+    }
+}
+
 pub async fn change_password(
     user_id: uuid::Uuid,
-    password: String,
-    pool: &PgPool,
+    password: AdminPassword,
+    executor: impl PgExecutor<'_>,
 ) -> Result<(), anyhow::Error> {
-    let password_hash = spawn_blocking_with_tracing(move || compute_password_hash(password))
+    let password_hash = spawn_blocking_with_tracing(move || compute_password_hash(password.as_ref().to_owned()))
         .await?
         .context("Failed to hash password")?;
     sqlx::query!(
@@ -114,13 +139,13 @@ pub async fn change_password(
         password_hash,
         user_id
     )
-    .execute(pool)
+    .execute(executor)
     .await
     .context("Failed to change user's password in the database.")?;
     Ok(())
 }
 
-fn compute_password_hash(
+pub fn compute_password_hash(
     password: String,
 ) -> Result<String, anyhow::Error> {
     let salt = SaltString::generate(&mut OsRng);
@@ -0,0 +1,132 @@
+use std::ops::DerefMut;
+
+use anyhow::Context;
+use chrono::{DateTime, Utc};
+use rand::{distr::Alphanumeric, Rng};
+use sha2::{Digest, Sha256};
+use sqlx::{PgPool, Postgres, Transaction};
+use uuid::Uuid;
+
+/// How long a reset link stays valid.
+const RESET_TOKEN_TTL_MINUTES: i64 = 60;
+
+/// Reset links can be requested without logging in: this caps how often a
+/// user gets one.
+pub const RESET_COOLDOWN_MINUTES: i64 = 10;
+
+/// Only the hash is stored: a leaked database does not leak working links.
+pub fn hash_token(token: &str) -> String {
+    format!("{:x}", Sha256::digest(token.as_bytes()))
+}
+
+pub(super) fn generate_token() -> String {
+    rand::rng()
+        .sample_iter(Alphanumeric)
+        .map(char::from)
+        .take(32)
+        .collect()
+}
+
+/// Returns the token to send to the user.
+#[tracing::instrument(name = "Create password reset token", skip(pool))]
+pub async fn create_reset_token(user_id: Uuid, pool: &PgPool) -> Result<String, anyhow::Error> {
+    let token = generate_token();
+    sqlx::query!(
+        r#"
+        INSERT INTO password_reset_tokens (token_hash, user_id, expires_at)
+        VALUES ($1, $2, $3)
+        "#,
+        hash_token(&token),
+        user_id,
+        Utc::now() + chrono::Duration::minutes(RESET_TOKEN_TTL_MINUTES),
+    )
+    .execute(pool)
+    .await
+    .context("Failed to store a password reset token.")?;
+    Ok(token)
+}
+
+/// When the user's latest unused reset token was issued, if they have one.
+#[tracing::instrument(name = "Get the time the last password reset token was issued", skip(pool))]
+pub async fn get_last_reset_token_issued_at(
+    user_id: Uuid,
+    pool: &PgPool,
+) -> Result<Option<DateTime<Utc>>, anyhow::Error> {
+    sqlx::query_scalar!(
+        r#"
+        SELECT max(created_at)
+        FROM password_reset_tokens
+        WHERE user_id = $1 AND used_at IS NULL
+        "#,
+        user_id,
+    )
+    .fetch_one(pool)
+    .await
+    .context("Failed to retrieve the last password reset token of a user.")
+}
+
+#[tracing::instrument(name = "Check password reset token", skip_all)]
+pub async fn reset_token_is_valid(token: &str, pool: &PgPool) -> Result<bool, anyhow::Error> {
+    let row = sqlx::query!(
+        r#"
+        SELECT user_id
+        FROM password_reset_tokens
+        WHERE token_hash = $1 AND used_at IS NULL AND expires_at > now()
+        "#,
+        hash_token(token),
+    )
+    .fetch_optional(pool)
+    .await
+    .context("Failed to retrieve a password reset token.")?;
+    Ok(row.is_some())
+}
+
+/// Returns the user the token was issued to, if it is still valid. Every
+/// other outstanding token of the user is used up as well. Nothing is used
+/// up until the caller commits `transaction`.
+#[tracing::instrument(name = "Consume password reset token", skip_all)]
+pub async fn consume_reset_token(
+    token: &str,
+    transaction: &mut Transaction<'_, Postgres>,
+) -> Result<Option<Uuid>, anyhow::Error> {
+    let user_id = sqlx::query_scalar!(
+        r#"
+        UPDATE password_reset_tokens
+        SET used_at = now()
+        WHERE token_hash = $1 AND used_at IS NULL AND expires_at > now()
+        RETURNING user_id
+        "#,
+        hash_token(token),
+    )
+    .fetch_optional(transaction.deref_mut())
+    .await
+    .context("Failed to use up a password reset token.")?;
+    if let Some(user_id) = user_id {
+        sqlx::query!(
+            r#"
+            UPDATE password_reset_tokens
+            SET used_at = now()
+            WHERE user_id = $1 AND used_at IS NULL
+            "#,
+            user_id,
+        )
+        .execute(transaction.deref_mut())
+        .await
+        .context("Failed to use up the other password reset tokens of a user.")?;
+    }
+    Ok(user_id)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::hash_token;
+
+    #[test]
+    fn tokens_are_stored_as_sha256_hex_digests() {
+        let hash = hash_token("a-reset-token");
+
+        assert_eq!(hash.len(), 64);
+        assert!(hash.chars().all(|c| c.is_ascii_hexdigit()));
+        assert_ne!(hash, hash_token("another-reset-token"));
+    }
+}
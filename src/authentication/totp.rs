@@ -0,0 +1,278 @@
+use std::ops::DerefMut;
+
+use anyhow::Context;
+use data_encoding::BASE32_NOPAD;
+use hmac::{Hmac, Mac};
+use rand::{distr::Alphanumeric, Rng, RngCore};
+use sha1::Sha1;
+use sqlx::PgPool;
+use uuid::Uuid;
+
+use super::password_reset::hash_token;
+
+/// Shown by authenticator apps next to the account name.
+const ISSUER: &str = "Newsletter";
+const DIGITS: u32 = 6;
+const STEP_SECONDS: i64 = 30;
+/// Codes of the time steps around the current one are accepted too, to
+/// make up for clock drift between the server and the user's device.
+const ALLOWED_DRIFT_STEPS: i64 = 1;
+const N_RECOVERY_CODES: usize = 10;
+
+/// A random 160-bit secret, base32-encoded as authenticator apps expect it.
+pub fn generate_totp_secret() -> String {
+    let mut secret = [0u8; 20];
+    rand::rng().fill_bytes(&mut secret);
+    BASE32_NOPAD.encode(&secret)
+}
+
+/// The `otpauth://` URI authenticator apps enrol from, usually through a QR code.
+pub fn otpauth_uri(secret: &str, username: &str) -> String {
+    format!(
+        "otpauth://totp/{issuer}:{account}?secret={secret}&issuer={issuer}&algorithm=SHA1&digits={DIGITS}&period={STEP_SECONDS}",
+        issuer = urlencoding::encode(ISSUER),
+        account = urlencoding::encode(username),
+    )
+}
+
+/// RFC 4226: HMAC-SHA1 of the counter, dynamically truncated.
+fn hotp(key: &[u8], counter: u64) -> u32 {
+    let mut mac = Hmac::<Sha1>::new_from_slice(key).expect("HMAC accepts keys of any size");
+    mac.update(&counter.to_be_bytes());
+    let digest = mac.finalize().into_bytes();
+    let offset = (digest[19] & 0x0f) as usize;
+    let binary = u32::from_be_bytes(digest[offset..offset + 4].try_into().unwrap()) & 0x7fff_ffff;
+    binary % 10_u32.pow(DIGITS)
+}
+
+/// Returns the time step `code` belongs to, if it is valid at `unix_time`.
+pub fn verify_totp(secret: &str, code: &str, unix_time: i64) -> Option<i64> {
+    let key = BASE32_NOPAD.decode(secret.as_bytes()).ok()?;
+    let code: String = code.chars().filter(|c| !c.is_whitespace()).collect();
+    if code.len() != DIGITS as usize || !code.chars().all(|c| c.is_ascii_digit()) {
+        return None;
+    }
+    let code: u32 = code.parse().ok()?;
+    let current_step = unix_time.div_euclid(STEP_SECONDS);
+    (current_step - ALLOWED_DRIFT_STEPS..=current_step + ALLOWED_DRIFT_STEPS)
+        .filter(|step| *step >= 0)
+        .find(|step| hotp(&key, *step as u64) == code)
+}
+
+/// Recovery codes are case-insensitive and may be typed with or without the dash.
+fn normalize_recovery_code(code: &str) -> String {
+    code.chars()
+        .filter(|c| c.is_ascii_alphanumeric())
+        .map(|c| c.to_ascii_lowercase())
+        .collect()
+}
+
+fn generate_recovery_code() -> String {
+    let code: String = rand::rng()
+        .sample_iter(Alphanumeric)
+        .map(|c| char::from(c).to_ascii_lowercase())
+        .take(10)
+        .collect();
+    format!("{}-{}", &code[..5], &code[5..])
+}
+
+#[tracing::instrument(name = "Get TOTP secret", skip(pool))]
+pub async fn get_totp_secret(user_id: Uuid, pool: &PgPool) -> Result<Option<String>, anyhow::Error> {
+    sqlx::query_scalar!("SELECT totp_secret FROM users WHERE user_id = $1", user_id)
+        .fetch_one(pool)
+        .await
+        .context("Failed to retrieve the TOTP secret of a user.")
+}
+
+/// Replaces any previous secret and recovery codes. `confirmed_step` is the
+/// step of the code used to confirm enrolment, so that it cannot be replayed
+/// to log in. Returns the new recovery codes: only their hashes are stored.
+#[tracing::instrument(name = "Enable TOTP", skip(secret, pool))]
+pub async fn enable_totp(
+    user_id: Uuid,
+    secret: &str,
+    confirmed_step: i64,
+    pool: &PgPool,
+) -> Result<Vec<String>, anyhow::Error> {
+    let recovery_codes: Vec<String> = (0..N_RECOVERY_CODES).map(|_| generate_recovery_code()).collect();
+    let code_hashes: Vec<String> = recovery_codes
+        .iter()
+        .map(|code| hash_token(&normalize_recovery_code(code)))
+        .collect();
+    let mut transaction = pool
+        .begin()
+        .await
+        .context("Failed to acquire a Postgres connection from the pool.")?;
+    sqlx::query!(
+        "UPDATE users SET totp_secret = $1, totp_last_step = $2 WHERE user_id = $3",
+        secret,
+        confirmed_step,
+        user_id
+    )
+    .execute(transaction.deref_mut())
+    .await
+    .context("Failed to store the TOTP secret of a user.")?;
+    sqlx::query!("DELETE FROM recovery_codes WHERE user_id = $1", user_id)
+        .execute(transaction.deref_mut())
+        .await
+        .context("Failed to delete the previous recovery codes of a user.")?;
+    sqlx::query!(
+        r#"
+        INSERT INTO recovery_codes (user_id, code_hash)
+        SELECT $1, * FROM UNNEST($2::TEXT[])
+        "#,
+        user_id,
+        &code_hashes,
+    )
+    .execute(transaction.deref_mut())
+    .await
+    .context("Failed to store the recovery codes of a user.")?;
+    transaction
+        .commit()
+        .await
+        .context("Failed to commit SQL transaction to enable TOTP.")?;
+    Ok(recovery_codes)
+}
+
+#[tracing::instrument(name = "Disable TOTP", skip(pool))]
+pub async fn disable_totp(user_id: Uuid, pool: &PgPool) -> Result<(), anyhow::Error> {
+    let mut transaction = pool
+        .begin()
+        .await
+        .context("Failed to acquire a Postgres connection from the pool.")?;
+    sqlx::query!(
+        "UPDATE users SET totp_secret = NULL, totp_last_step = NULL WHERE user_id = $1",
+        user_id
+    )
+    .execute(transaction.deref_mut())
+    .await
+    .context("Failed to remove the TOTP secret of a user.")?;
+    sqlx::query!("DELETE FROM recovery_codes WHERE user_id = $1", user_id)
+        .execute(transaction.deref_mut())
+        .await
+        .context("Failed to delete the recovery codes of a user.")?;
+    transaction
+        .commit()
+        .await
+        .context("Failed to commit SQL transaction to disable TOTP.")?;
+    Ok(())
+}
+
+/// Accepts either a TOTP code, at most once per time step, or an unused
+/// recovery code, which is then used up.
+#[tracing::instrument(name = "Verify second factor", skip(code, pool))]
+pub async fn verify_second_factor(user_id: Uuid, code: &str, pool: &PgPool) -> Result<bool, anyhow::Error> {
+    let mut transaction = pool
+        .begin()
+        .await
+        .context("Failed to acquire a Postgres connection from the pool.")?;
+    let user = sqlx::query!(
+        "SELECT totp_secret, totp_last_step FROM users WHERE user_id = $1 FOR UPDATE",
+        user_id
+    )
+    .fetch_one(transaction.deref_mut())
+    .await
+    .context("Failed to retrieve the TOTP secret of a user.")?;
+    let Some(secret) = user.totp_secret else {
+        return Ok(false);
+    };
+
+    let step = verify_totp(&secret, code, chrono::Utc::now().timestamp())
+        .filter(|step| user.totp_last_step.is_none_or(|last_step| *step > last_step));
+    let verified = if let Some(step) = step {
+        sqlx::query!("UPDATE users SET totp_last_step = $1 WHERE user_id = $2", step, user_id)
+            .execute(transaction.deref_mut())
+            .await
+            .context("Failed to record the last TOTP step of a user.")?;
+        true
+    } else {
+        sqlx::query!(
+            r#"
+            UPDATE recovery_codes
+            SET used_at = now()
+            WHERE user_id = $1 AND code_hash = $2 AND used_at IS NULL
+            "#,
+            user_id,
+            hash_token(&normalize_recovery_code(code)),
+        )
+        .execute(transaction.deref_mut())
+        .await
+        .context("Failed to use up a recovery code.")?
+        .rows_affected()
+            > 0
+    };
+    transaction
+        .commit()
+        .await
+        .context("Failed to commit SQL transaction to verify a second factor.")?;
+    Ok(verified)
+}
+
+#[cfg(test)]
+mod tests {
+    use data_encoding::BASE32_NOPAD;
+
+    use super::{generate_recovery_code, hotp, normalize_recovery_code, otpauth_uri, verify_totp};
+
+    /// The SHA-1 seed of RFC 6238's test vectors.
+    fn rfc_secret() -> String {
+        BASE32_NOPAD.encode(b"12345678901234567890")
+    }
+
+    #[test]
+    fn hotp_matches_the_rfc_4226_test_vectors() {
+        let expected = [755224, 287082, 359152, 969429, 338314, 254676, 287922, 162583, 399871, 520489];
+        for (counter, code) in expected.into_iter().enumerate() {
+            assert_eq!(hotp(b"12345678901234567890", counter as u64), code);
+        }
+    }
+
+    #[test]
+    fn totp_matches_the_rfc_6238_test_vectors() {
+        // The RFC lists 8-digit codes: ours are their last 6 digits.
+        let test_cases = [
+            (59, "287082"),
+            (1111111109, "081804"),
+            (1111111111, "050471"),
+            (1234567890, "005924"),
+            (2000000000, "279037"),
+            (20000000000, "353130"),
+        ];
+        for (unix_time, code) in test_cases {
+            assert_eq!(verify_totp(&rfc_secret(), code, unix_time), Some(unix_time / 30));
+        }
+    }
+
+    #[test]
+    fn codes_from_neighbouring_time_steps_are_accepted() {
+        assert_eq!(verify_totp(&rfc_secret(), "287082", 59 + 30), Some(1));
+        assert_eq!(verify_totp(&rfc_secret(), "287082", 59 - 30), Some(1));
+        assert_eq!(verify_totp(&rfc_secret(), "287082", 59 + 60), None);
+    }
+
+    #[test]
+    fn malformed_codes_are_rejected() {
+        for code in ["", "28708", "2870823", "28708a", "-87082"] {
+            assert_eq!(verify_totp(&rfc_secret(), code, 59), None, "{code}");
+        }
+        assert_eq!(verify_totp(&rfc_secret(), " 287 082 ", 59), Some(1));
+    }
+
+    #[test]
+    fn the_otpauth_uri_carries_the_secret_and_the_account() {
+        let uri = otpauth_uri("JBSWY3DPEHPK3PXP", "ursula le guin");
+
+        assert!(uri.starts_with("otpauth://totp/Newsletter:ursula%20le%20guin?"));
+        assert!(uri.contains("secret=JBSWY3DPEHPK3PXP"));
+        assert!(uri.contains("issuer=Newsletter"));
+    }
+
+    #[test]
+    fn recovery_codes_are_normalized_before_hashing() {
+        let code = generate_recovery_code();
+
+        assert_eq!(code.len(), 11);
+        assert_eq!(normalize_recovery_code(&code.to_uppercase()), normalize_recovery_code(&code));
+        assert_eq!(normalize_recovery_code(&code).len(), 10);
+    }
+}
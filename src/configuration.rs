@@ -1,24 +1,229 @@
 use sqlx::postgres::PgConnectOptions;
 
-use crate::domain::SubscriberEmail;
+use crate::{
+    authentication::LoginThrottlePolicy,
+    domain::SubscriberEmail,
+    email_client::{CircuitBreakerPolicy, EmailClient, EmailProvider, FailoverProvider, FileSinkProvider, PostmarkProvider, RetryPolicy, SmtpProvider, SmtpTls},
+};
 
 #[derive(serde::Deserialize, Clone)]
 pub struct Settings {
     pub database: DatabaseSettings,
     pub application: ApplicationSettings,
     pub email_client: EmailClientSettings,
+    pub delivery: DeliverySettings,
+    pub login_throttle: LoginThrottleSettings,
     pub redis_url: String,
 }
 
+#[derive(serde::Deserialize, Clone)]
+pub struct DeliverySettings {
+    /// How many emails the worker sends in parallel. Each in-flight email
+    /// holds a database connection, so keep this below the pool size.
+    pub concurrency: usize,
+}
+
+#[derive(serde::Deserialize, Clone)]
+pub struct LoginThrottleSettings {
+    pub failure_window_seconds: u64,
+    pub free_attempts: u32,
+    pub base_delay_milliseconds: u64,
+    pub max_delay_milliseconds: u64,
+    pub username_lockout_threshold: u32,
+    pub ip_lockout_threshold: u32,
+    pub lockout_seconds: u64,
+}
+
+impl LoginThrottleSettings {
+    pub fn policy(&self) -> LoginThrottlePolicy {
+        LoginThrottlePolicy {
+            failure_window: std::time::Duration::from_secs(self.failure_window_seconds),
+            free_attempts: self.free_attempts,
+            base_delay: std::time::Duration::from_millis(self.base_delay_milliseconds),
+            max_delay: std::time::Duration::from_millis(self.max_delay_milliseconds),
+            username_lockout_threshold: self.username_lockout_threshold,
+            ip_lockout_threshold: self.ip_lockout_threshold,
+            lockout: std::time::Duration::from_secs(self.lockout_seconds),
+        }
+    }
+}
+
 #[derive(serde::Deserialize, Clone)]
 pub struct EmailClientSettings {
+    pub provider: EmailProviderKind,
     pub base_url: String,
     pub sender_email: String,
     pub auth_token: String,
+    /// The password the provider must send, with HTTP Basic auth, on webhook calls.
+    pub webhook_token: String,
     pub timeout_milliseconds: u64,
+    pub retry: EmailRetrySettings,
+    /// The provider's sending quota, if it has one. The API and the delivery
+    /// worker draw on the same budget.
+    pub max_emails_per_second: Option<u32>,
+    /// Caps batch sends below the provider's own limit; 1 disables batching.
+    pub max_batch_size: Option<usize>,
+    pub smtp: Option<SmtpSettings>,
+    pub file_sink: Option<FileSinkSettings>,
+    pub circuit_breaker: CircuitBreakerSettings,
+    /// Tried in order, after the provider above, when the ones before them fail.
+    #[serde(default)]
+    pub fallbacks: Vec<FallbackProviderSettings>,
+}
+
+/// A provider to fail over to. It shares the sender and timeout of the primary one.
+#[derive(serde::Deserialize, Clone)]
+pub struct FallbackProviderSettings {
+    pub provider: EmailProviderKind,
+    pub base_url: Option<String>,
+    pub auth_token: Option<String>,
+    pub smtp: Option<SmtpSettings>,
+    pub file_sink: Option<FileSinkSettings>,
+}
+
+#[derive(serde::Deserialize, Clone)]
+pub struct CircuitBreakerSettings {
+    pub failure_threshold: u32,
+    pub cooldown_seconds: u64,
+}
+
+impl CircuitBreakerSettings {
+    pub fn policy(&self) -> CircuitBreakerPolicy {
+        CircuitBreakerPolicy {
+            failure_threshold: self.failure_threshold,
+            cooldown: std::time::Duration::from_secs(self.cooldown_seconds),
+        }
+    }
+}
+
+#[derive(serde::Deserialize, Clone, Copy, Debug)]
+#[serde(rename_all = "snake_case")]
+pub enum EmailProviderKind {
+    Postmark,
+    Smtp,
+    FileSink,
+}
+
+impl EmailProviderKind {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            EmailProviderKind::Postmark => "postmark",
+            EmailProviderKind::Smtp => "smtp",
+            EmailProviderKind::FileSink => "file_sink",
+        }
+    }
+}
+
+#[derive(serde::Deserialize, Clone)]
+pub struct SmtpSettings {
+    pub host: String,
+    pub port: u16,
+    pub tls: SmtpTls,
+    pub username: Option<String>,
+    pub password: Option<String>,
+}
+
+#[derive(serde::Deserialize, Clone)]
+pub struct FileSinkSettings {
+    pub directory: String,
+}
+
+#[derive(serde::Deserialize, Clone)]
+pub struct EmailRetrySettings {
+    pub max_attempts: u32,
+    pub base_delay_milliseconds: u64,
+    pub max_delay_milliseconds: u64,
+    pub jitter: bool,
+}
+
+impl EmailRetrySettings {
+    pub fn policy(&self) -> RetryPolicy {
+        RetryPolicy {
+            max_attempts: self.max_attempts,
+            base_delay: std::time::Duration::from_millis(self.base_delay_milliseconds),
+            max_delay: std::time::Duration::from_millis(self.max_delay_milliseconds),
+            jitter: self.jitter,
+        }
+    }
+}
+
+impl FallbackProviderSettings {
+    fn build(self, sender_email: SubscriberEmail, timeout: std::time::Duration) -> Box<dyn EmailProvider> {
+        match self.provider {
+            EmailProviderKind::Postmark => Box::new(PostmarkProvider::new(
+                self.base_url.expect("Missing `base_url` for a Postmark provider."),
+                sender_email,
+                self.auth_token.expect("Missing `auth_token` for a Postmark provider."),
+                timeout,
+            )),
+            EmailProviderKind::Smtp => {
+                let smtp = self.smtp.expect("Missing `email_client.smtp` settings.");
+                let credentials = smtp.username.zip(smtp.password);
+                let provider = SmtpProvider::new(
+                    &smtp.host,
+                    smtp.port,
+                    smtp.tls,
+                    credentials,
+                    sender_email,
+                    timeout,
+                )
+                .expect("Failed to build the SMTP transport.");
+                Box::new(provider)
+            }
+            EmailProviderKind::FileSink => {
+                let file_sink = self.file_sink.expect("Missing `email_client.file_sink` settings.");
+                Box::new(FileSinkProvider::new(file_sink.directory.into(), sender_email))
+            }
+        }
+    }
 }
 
 impl EmailClientSettings {
+    pub fn client(self) -> EmailClient {
+        let retry_policy = self.retry.policy();
+        let max_emails_per_second = self.max_emails_per_second;
+        let max_batch_size = self.max_batch_size;
+        let mut client = EmailClient::new(self.provider(), retry_policy);
+        if let Some(max_emails_per_second) = max_emails_per_second {
+            client = client.with_rate_limit(max_emails_per_second);
+        }
+        if let Some(max_batch_size) = max_batch_size {
+            client = client.with_max_batch_size(max_batch_size);
+        }
+        client
+    }
+
+    /// The delivery worker's client. It goes through the circuit breakers of
+    /// `api_client`, so that the health endpoint reports the worker's failures.
+    ///
+    /// The delivery worker holds its tasks' row locks while it sends: rather
+    /// than sleeping through a backoff, it postpones the failed tasks.
+    pub fn worker_client(&self, api_client: &EmailClient) -> EmailClient {
+        api_client.with_retry_policy(RetryPolicy {
+            max_attempts: 1,
+            ..self.retry.policy()
+        })
+    }
+
+    /// The primary provider followed by the fallbacks, each behind its own circuit breaker.
+    pub fn provider(self) -> Box<dyn EmailProvider> {
+        let sender_email = self.sender().expect("Invalid sender email address.");
+        let timeout = self.timeout();
+        let primary = FallbackProviderSettings {
+            provider: self.provider,
+            base_url: Some(self.base_url),
+            auth_token: Some(self.auth_token),
+            smtp: self.smtp,
+            file_sink: self.file_sink,
+        };
+        let mut failover = FailoverProvider::new(self.circuit_breaker.policy());
+        for settings in std::iter::once(primary).chain(self.fallbacks) {
+            let name = settings.provider.as_str();
+            failover = failover.with_provider(name, settings.build(sender_email.clone(), timeout));
+        }
+        Box::new(failover)
+    }
+
     pub fn sender(&self) -> Result<SubscriberEmail, String> {
         SubscriberEmail::parse(self.sender_email.clone())
     }
@@ -33,6 +238,17 @@ pub struct ApplicationSettings {
     pub host: String,
     pub base_url: String,
     pub hmac_secret: String,
+    pub subscription_token_ttl_hours: u32,
+    /// Reverse proxies whose `X-Forwarded-For` header is believed. Requests
+    /// from any other peer are attributed to the peer itself.
+    #[serde(default)]
+    pub trusted_proxies: Vec<std::net::IpAddr>,
+}
+
+impl ApplicationSettings {
+    pub fn subscription_token_ttl(&self) -> chrono::Duration {
+        chrono::Duration::hours(self.subscription_token_ttl_hours.into())
+    }
 }
 
 #[derive(serde::Deserialize, Clone)]
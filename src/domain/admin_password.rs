@@ -0,0 +1,186 @@
+/// Passwords that meet the length rules but are still guessed first. Only
+/// entries of at least `MIN_LENGTH` characters matter: shorter ones are
+/// rejected anyway.
+const COMMON_PASSWORDS: &str = include_str!("common_passwords.txt");
+const MIN_LENGTH: usize = 12;
+/// Argon2 hashes whatever it gets: this keeps hashing cheap.
+const MAX_LENGTH: usize = 128;
+/// A random 12-character lowercase password scores about 56 bits.
+const MIN_ENTROPY_BITS: f64 = 50.0;
+
+/// A password an admin user may choose. The value is never printed.
+#[derive(Clone, PartialEq, Eq)]
+pub struct AdminPassword(String);
+
+impl AdminPassword {
+    /// `current_password` is the one being replaced, when it is known.
+    pub fn parse(s: String, current_password: Option<&str>) -> Result<AdminPassword, String> {
+        let length = s.chars().count();
+        if length < MIN_LENGTH {
+            return Err(format!("The new password must be at least {} characters long.", MIN_LENGTH));
+        }
+        if length > MAX_LENGTH {
+            return Err(format!("The new password must be at most {} characters long.", MAX_LENGTH));
+        }
+        if current_password == Some(s.as_str()) {
+            return Err("The new password must be different from the current one.".to_string());
+        }
+        let lowercase = s.to_lowercase();
+        if COMMON_PASSWORDS.lines().any(|common| common == lowercase) {
+            return Err("The new password is too common. Please choose another one.".to_string());
+        }
+        if estimated_entropy_bits(&s) < MIN_ENTROPY_BITS {
+            return Err(
+                "The new password is too predictable. Avoid repeated characters and sequences, or make it longer."
+                    .to_string(),
+            );
+        }
+        Ok(Self(s))
+    }
+}
+
+/// A rough estimate in the spirit of zxcvbn: each character is worth the
+/// size of the character classes in use, except when it repeats or
+/// continues a sequence from the previous one ("aa", "ab", "21").
+fn estimated_entropy_bits(password: &str) -> f64 {
+    let chars: Vec<char> = password.chars().collect();
+    let mut pool_size = 0;
+    if chars.iter().any(|c| c.is_ascii_lowercase()) {
+        pool_size += 26;
+    }
+    if chars.iter().any(|c| c.is_ascii_uppercase()) {
+        pool_size += 26;
+    }
+    if chars.iter().any(|c| c.is_ascii_digit()) {
+        pool_size += 10;
+    }
+    if chars.iter().any(|c| c.is_ascii() && !c.is_ascii_alphanumeric()) {
+        pool_size += 33;
+    }
+    if chars.iter().any(|c| !c.is_ascii()) {
+        pool_size += 100;
+    }
+    let bits_per_char = f64::from(pool_size).log2();
+    chars
+        .iter()
+        .enumerate()
+        .map(|(i, c)| {
+            let predictable = i > 0 && (*c as i64 - chars[i - 1] as i64).abs() <= 1;
+            if predictable { 1.0 } else { bits_per_char }
+        })
+        .sum()
+}
+
+impl AsRef<str> for AdminPassword {
+    fn as_ref(&self) -> &str {
+        &self.0
+    }
+}
+
+impl std::fmt::Debug for AdminPassword {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str("AdminPassword([REDACTED])")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use claim::{assert_err, assert_ok};
+    use quickcheck::{Arbitrary, Gen};
+
+    const PRINTABLE_ASCII: &str =
+        "abcdefghijklmnopqrstuvwxyzABCDEFGHIJKLMNOPQRSTUVWXYZ0123456789!\"#$%&'()*+,-./:;<=>?@[\\]^_`{|}~";
+
+    fn random_password(g: &mut Gen, min_length: usize, max_length: usize) -> String {
+        let chars: Vec<char> = PRINTABLE_ASCII.chars().collect();
+        let length = min_length + usize::arbitrary(g) % (max_length - min_length + 1);
+        (0..length).map(|_| *g.choose(&chars).unwrap()).collect()
+    }
+
+    #[derive(Debug, Clone)]
+    struct RandomPassword(String);
+
+    impl Arbitrary for RandomPassword {
+        fn arbitrary(g: &mut Gen) -> Self {
+            RandomPassword(random_password(g, MIN_LENGTH, MAX_LENGTH))
+        }
+    }
+
+    #[derive(Debug, Clone)]
+    struct ShortPassword(String);
+
+    impl Arbitrary for ShortPassword {
+        fn arbitrary(g: &mut Gen) -> Self {
+            ShortPassword(random_password(g, 0, MIN_LENGTH - 1))
+        }
+    }
+
+    #[derive(Debug, Clone)]
+    struct LongPassword(String);
+
+    impl Arbitrary for LongPassword {
+        fn arbitrary(g: &mut Gen) -> Self {
+            LongPassword(random_password(g, MAX_LENGTH + 1, 4 * MAX_LENGTH))
+        }
+    }
+
+    #[quickcheck_macros::quickcheck]
+    fn random_passwords_of_a_valid_length_are_accepted(password: RandomPassword) -> bool {
+        AdminPassword::parse(password.0, None).is_ok()
+    }
+
+    #[quickcheck_macros::quickcheck]
+    fn short_passwords_are_rejected(password: ShortPassword) -> bool {
+        AdminPassword::parse(password.0, None)
+            == Err("The new password must be at least 12 characters long.".to_string())
+    }
+
+    #[quickcheck_macros::quickcheck]
+    fn long_passwords_are_rejected(password: LongPassword) -> bool {
+        AdminPassword::parse(password.0, None)
+            == Err("The new password must be at most 128 characters long.".to_string())
+    }
+
+    #[quickcheck_macros::quickcheck]
+    fn the_current_password_is_rejected(password: RandomPassword) -> bool {
+        AdminPassword::parse(password.0.clone(), Some(&password.0))
+            == Err("The new password must be different from the current one.".to_string())
+    }
+
+    #[test]
+    fn length_is_counted_in_characters_not_bytes() {
+        assert_ok!(AdminPassword::parse("ünïcödé-pässwörd".to_string(), None));
+        assert_err!(AdminPassword::parse("éééééééééé".to_string(), None));
+    }
+
+    #[test]
+    fn common_passwords_are_rejected_whatever_their_case() {
+        for password in ["password1234", "Password1234", "QWERTY123456"] {
+            assert_eq!(
+                AdminPassword::parse(password.to_string(), None),
+                Err("The new password is too common. Please choose another one.".to_string()),
+                "{password}"
+            );
+        }
+    }
+
+    #[test]
+    fn repeated_characters_and_sequences_are_rejected() {
+        for password in ["aaaaaaaaaaaaaaaa", "abcdefghijklmnopq", "987654321098765", "abcabcabcabcabc"] {
+            assert_eq!(
+                AdminPassword::parse(password.to_string(), None),
+                Err("The new password is too predictable. Avoid repeated characters and sequences, or make it longer."
+                    .to_string()),
+                "{password}"
+            );
+        }
+    }
+
+    #[test]
+    fn the_value_is_not_printed() {
+        let password = AdminPassword::parse("a-long-enough-password".to_string(), None).unwrap();
+
+        assert_eq!(format!("{:?}", password), "AdminPassword([REDACTED])");
+    }
+}
@@ -1,7 +1,15 @@
+mod admin_password;
 mod new_subscriber;
+mod segment;
 mod subscriber_name;
 mod subscriber_email;
+mod subscriber_preferences;
+mod tag_name;
 
+pub use admin_password::AdminPassword;
 pub use subscriber_name::SubscriberName;
 pub use subscriber_email::SubscriberEmail;
-pub use new_subscriber::NewSubscriber;
\ No newline at end of file
+pub use new_subscriber::NewSubscriber;
+pub use subscriber_preferences::SubscriberPreferences;
+pub use segment::Segment;
+pub use tag_name::TagName;
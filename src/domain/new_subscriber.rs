@@ -1,8 +1,9 @@
-use crate::{domain::{SubscriberEmail, SubscriberName}, routes::FormData};
+use crate::{domain::{SubscriberEmail, SubscriberName, TagName}, routes::FormData};
 
 pub struct NewSubscriber {
     pub email: SubscriberEmail,
     pub name: SubscriberName,
+    pub lists: Vec<TagName>,
 }
 
 impl TryFrom<FormData> for NewSubscriber {
@@ -10,6 +11,10 @@ impl TryFrom<FormData> for NewSubscriber {
     fn try_from(value: FormData) -> Result<Self, Self::Error> {
         let name = SubscriberName::parse(value.name)?;
         let email = SubscriberEmail::parse(value.email)?;
-        Ok(NewSubscriber { email, name })
+        let lists = value.lists
+            .into_iter()
+            .map(TagName::parse)
+            .collect::<Result<Vec<_>, _>>()?;
+        Ok(NewSubscriber { email, name, lists })
     }
-}  
\ No newline at end of file
+}
@@ -0,0 +1,246 @@
+use crate::domain::TagName;
+
+/// A boolean expression over subscriber tags, e.g. `tag:rust AND NOT tag:beta`.
+///
+/// `NOT` binds tighter than `AND`, which binds tighter than `OR`.
+/// Parentheses can be used for grouping; keywords are case-insensitive.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Segment {
+    Tag(TagName),
+    Not(Box<Segment>),
+    And(Box<Segment>, Box<Segment>),
+    Or(Box<Segment>, Box<Segment>),
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Token {
+    Tag(TagName),
+    Not,
+    And,
+    Or,
+    LeftParen,
+    RightParen,
+}
+
+/// The parser, and every walk over the parsed tree, recurse: these bounds
+/// keep a hostile segment from overflowing the stack.
+const MAX_LENGTH: usize = 1024;
+const MAX_DEPTH: usize = 32;
+
+impl Segment {
+    pub fn parse(s: &str) -> Result<Segment, String> {
+        if s.chars().count() > MAX_LENGTH {
+            return Err(format!("The segment is longer than {} characters.", MAX_LENGTH));
+        }
+        let tokens = tokenize(s)?;
+        let mut parser = Parser { tokens: &tokens, position: 0, depth: 0 };
+        let segment = parser.or()?;
+        match parser.peek() {
+            None => Ok(segment),
+            Some(token) => Err(format!("Unexpected {} in the segment.", describe(token))),
+        }
+    }
+
+    /// Every tag the expression refers to.
+    pub fn tags(&self) -> Vec<&TagName> {
+        match self {
+            Segment::Tag(tag) => vec![tag],
+            Segment::Not(inner) => inner.tags(),
+            Segment::And(left, right) | Segment::Or(left, right) => {
+                let mut tags = left.tags();
+                tags.extend(right.tags());
+                tags
+            }
+        }
+    }
+}
+
+fn tokenize(s: &str) -> Result<Vec<Token>, String> {
+    let mut tokens = Vec::new();
+    let spaced = s.replace('(', " ( ").replace(')', " ) ");
+    for word in spaced.split_whitespace() {
+        let token = match word.to_uppercase().as_str() {
+            "(" => Token::LeftParen,
+            ")" => Token::RightParen,
+            "NOT" => Token::Not,
+            "AND" => Token::And,
+            "OR" => Token::Or,
+            _ => {
+                let name = word
+                    .strip_prefix("tag:")
+                    .ok_or_else(|| format!("`{}` is not a tag, tags are written as `tag:name`.", word))?;
+                Token::Tag(TagName::parse(name.to_string())?)
+            }
+        };
+        tokens.push(token);
+    }
+    Ok(tokens)
+}
+
+fn describe(token: &Token) -> String {
+    match token {
+        Token::Tag(tag) => format!("`tag:{}`", tag),
+        Token::Not => "`NOT`".into(),
+        Token::And => "`AND`".into(),
+        Token::Or => "`OR`".into(),
+        Token::LeftParen => "`(`".into(),
+        Token::RightParen => "`)`".into(),
+    }
+}
+
+struct Parser<'a> {
+    tokens: &'a [Token],
+    position: usize,
+    /// How many `NOT`s and parentheses enclose the current position.
+    depth: usize,
+}
+
+impl Parser<'_> {
+    fn peek(&self) -> Option<&Token> {
+        self.tokens.get(self.position)
+    }
+
+    fn next(&mut self) -> Option<&Token> {
+        let token = self.tokens.get(self.position);
+        self.position += 1;
+        token
+    }
+
+    /// Parses a nested expression with `parse`, one level deeper.
+    fn nested(&mut self, parse: fn(&mut Self) -> Result<Segment, String>) -> Result<Segment, String> {
+        if self.depth >= MAX_DEPTH {
+            return Err("The segment is nested too deeply.".into());
+        }
+        self.depth += 1;
+        let segment = parse(self);
+        self.depth -= 1;
+        segment
+    }
+
+    fn or(&mut self) -> Result<Segment, String> {
+        let mut segment = self.and()?;
+        while self.peek() == Some(&Token::Or) {
+            self.next();
+            segment = Segment::Or(Box::new(segment), Box::new(self.and()?));
+        }
+        Ok(segment)
+    }
+
+    fn and(&mut self) -> Result<Segment, String> {
+        let mut segment = self.not()?;
+        while self.peek() == Some(&Token::And) {
+            self.next();
+            segment = Segment::And(Box::new(segment), Box::new(self.not()?));
+        }
+        Ok(segment)
+    }
+
+    fn not(&mut self) -> Result<Segment, String> {
+        if self.peek() == Some(&Token::Not) {
+            self.next();
+            return Ok(Segment::Not(Box::new(self.nested(Self::not)?)));
+        }
+        self.primary()
+    }
+
+    fn primary(&mut self) -> Result<Segment, String> {
+        match self.next().cloned() {
+            Some(Token::Tag(tag)) => Ok(Segment::Tag(tag)),
+            Some(Token::LeftParen) => {
+                let segment = self.nested(Self::or)?;
+                match self.next() {
+                    Some(Token::RightParen) => Ok(segment),
+                    _ => Err("A `(` in the segment is never closed.".into()),
+                }
+            }
+            Some(token) => Err(format!("Unexpected {} in the segment.", describe(&token))),
+            None => Err("The segment ends unexpectedly.".into()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use claim::assert_err;
+
+    fn tag(name: &str) -> Box<Segment> {
+        Box::new(Segment::Tag(TagName::parse(name.to_string()).unwrap()))
+    }
+
+    #[test]
+    fn a_single_tag_is_parsed() {
+        assert_eq!(Segment::parse("tag:rust").unwrap(), *tag("rust"));
+    }
+
+    #[test]
+    fn not_binds_tighter_than_and() {
+        assert_eq!(
+            Segment::parse("tag:rust AND NOT tag:beta").unwrap(),
+            Segment::And(tag("rust"), Box::new(Segment::Not(tag("beta")))),
+        );
+    }
+
+    #[test]
+    fn and_binds_tighter_than_or() {
+        assert_eq!(
+            Segment::parse("tag:a or tag:b and tag:c").unwrap(),
+            Segment::Or(tag("a"), Box::new(Segment::And(tag("b"), tag("c")))),
+        );
+    }
+
+    #[test]
+    fn parentheses_group_expressions() {
+        assert_eq!(
+            Segment::parse("(tag:a OR tag:b) AND tag:c").unwrap(),
+            Segment::And(Box::new(Segment::Or(tag("a"), tag("b"))), tag("c")),
+        );
+    }
+
+    #[test]
+    fn tags_lists_every_tag_in_the_expression() {
+        let segment = Segment::parse("(tag:a OR tag:b) AND NOT tag:c").unwrap();
+        let tags: Vec<_> = segment.tags().into_iter().map(|t| t.to_string()).collect();
+        assert_eq!(tags, ["a", "b", "c"]);
+    }
+
+    #[test]
+    fn invalid_expressions_are_rejected() {
+        for segment in [
+            "",
+            "rust",
+            "tag:",
+            "tag:rust AND",
+            "tag:rust tag:beta",
+            "(tag:rust",
+            "tag:rust)",
+            "NOT",
+            "AND tag:rust",
+        ] {
+            assert_err!(Segment::parse(segment), "{:?} should be rejected", segment);
+        }
+    }
+
+    #[test]
+    fn deeply_nested_expressions_are_rejected() {
+        let parentheses = format!("{}tag:a{}", "(".repeat(100), ")".repeat(100));
+        let negations = format!("{}tag:a", "NOT ".repeat(100));
+        for segment in [parentheses, negations] {
+            assert_eq!(Segment::parse(&segment), Err("The segment is nested too deeply.".into()));
+        }
+    }
+
+    #[test]
+    fn long_expressions_are_rejected() {
+        let segment = vec!["tag:a"; 300].join(" OR ");
+
+        assert_err!(Segment::parse(&segment));
+    }
+
+    #[test]
+    fn expressions_within_the_limits_are_parsed() {
+        let segment = format!("{}tag:a{}", "(".repeat(32), ")".repeat(32));
+
+        assert_eq!(Segment::parse(&segment).unwrap(), *tag("a"));
+    }
+}
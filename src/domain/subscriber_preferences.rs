@@ -0,0 +1,85 @@
+use crate::{domain::{SubscriberName, TagName}, routes::PreferencesFormData};
+
+/// The longest pause a subscriber can ask for, in weeks.
+const MAX_PAUSE_WEEKS: u32 = 52;
+
+#[derive(Debug)]
+pub struct SubscriberPreferences {
+    pub name: SubscriberName,
+    pub lists: Vec<TagName>,
+    pub plain_text_only: bool,
+    /// `None` leaves the current pause untouched, `Some(0)` resumes delivery.
+    pub pause_weeks: Option<u32>,
+}
+
+impl TryFrom<PreferencesFormData> for SubscriberPreferences {
+    type Error = String;
+    fn try_from(value: PreferencesFormData) -> Result<Self, Self::Error> {
+        let name = SubscriberName::parse(value.name)?;
+        let lists = value.lists
+            .into_iter()
+            .map(TagName::parse)
+            .collect::<Result<Vec<_>, _>>()?;
+        let pause_weeks = match value.pause_weeks.as_deref().map(str::trim) {
+            None | Some("") => None,
+            Some(weeks) => match weeks.parse::<u32>() {
+                Ok(weeks) if weeks <= MAX_PAUSE_WEEKS => Some(weeks),
+                _ => {
+                    return Err(format!(
+                        "Delivery can be paused for 0 to {} weeks.",
+                        MAX_PAUSE_WEEKS
+                    ));
+                }
+            },
+        };
+        Ok(SubscriberPreferences {
+            name,
+            lists,
+            plain_text_only: value.plain_text_only.is_some(),
+            pause_weeks,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use claim::{assert_err, assert_none};
+
+    fn form(pause_weeks: Option<&str>) -> PreferencesFormData {
+        PreferencesFormData {
+            name: "Ursula".into(),
+            lists: vec!["Rust".into()],
+            plain_text_only: Some("on".into()),
+            pause_weeks: pause_weeks.map(Into::into),
+        }
+    }
+
+    #[test]
+    fn valid_preferences_are_parsed() {
+        let preferences = SubscriberPreferences::try_from(form(Some("4"))).unwrap();
+        assert_eq!(preferences.name.as_ref(), "Ursula");
+        assert_eq!(preferences.lists[0].as_ref(), "rust");
+        assert!(preferences.plain_text_only);
+        assert_eq!(preferences.pause_weeks, Some(4));
+    }
+
+    #[test]
+    fn an_empty_pause_leaves_delivery_untouched() {
+        assert_none!(SubscriberPreferences::try_from(form(Some(" "))).unwrap().pause_weeks);
+        assert_none!(SubscriberPreferences::try_from(form(None)).unwrap().pause_weeks);
+    }
+
+    #[test]
+    fn invalid_pauses_are_rejected() {
+        for weeks in ["-1", "53", "two"] {
+            assert_err!(SubscriberPreferences::try_from(form(Some(weeks))));
+        }
+    }
+
+    #[test]
+    fn invalid_names_are_rejected() {
+        let form = PreferencesFormData { name: " ".into(), ..form(None) };
+        assert_err!(SubscriberPreferences::try_from(form));
+    }
+}
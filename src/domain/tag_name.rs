@@ -0,0 +1,66 @@
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TagName(String);
+
+impl TagName {
+    /// Tag names are case-insensitive: they are stored in lowercase.
+    pub fn parse(s: String) -> Result<TagName, String> {
+        let name = s.trim().to_lowercase();
+
+        let is_empty = name.is_empty();
+        let is_too_long = name.chars().count() > 50;
+        let contains_forbidden_characters = name
+            .chars()
+            .any(|c| !(c.is_ascii_alphanumeric() || c == '-' || c == '_'));
+
+        if is_empty || is_too_long || contains_forbidden_characters {
+            Err(format!("{} is not a valid tag name.", s))
+        } else {
+            Ok(TagName(name))
+        }
+    }
+}
+
+impl AsRef<str> for TagName {
+    fn as_ref(&self) -> &str {
+        &self.0
+    }
+}
+
+impl std::fmt::Display for TagName {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        self.0.fmt(f)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn a_valid_tag_name_is_parsed_successfully() {
+        claim::assert_ok!(TagName::parse("rust-beta_2".to_string()));
+    }
+
+    #[test]
+    fn tag_names_are_lowercased() {
+        let tag = TagName::parse(" Rust ".to_string()).unwrap();
+        assert_eq!(tag.as_ref(), "rust");
+    }
+
+    #[test]
+    fn empty_tag_name_is_rejected() {
+        claim::assert_err!(TagName::parse(" ".to_string()));
+    }
+
+    #[test]
+    fn a_51_character_tag_name_is_rejected() {
+        claim::assert_err!(TagName::parse("a".repeat(51)));
+    }
+
+    #[test]
+    fn tag_name_with_forbidden_characters_is_rejected() {
+        for name in ["rust lang", "tag:rust", "<b>", "a(b)"] {
+            claim::assert_err!(TagName::parse(name.to_string()));
+        }
+    }
+}
@@ -0,0 +1,328 @@
+use std::{
+    sync::Mutex,
+    time::{Duration, Instant},
+};
+
+use futures::future::BoxFuture;
+
+use super::{BatchOutcome, EmailError, EmailMessage, EmailProvider, EmailReceipt};
+
+/// When to stop calling a failing provider, and for how long.
+#[derive(Debug, Clone)]
+pub struct CircuitBreakerPolicy {
+    /// Consecutive transient failures that open the circuit.
+    pub failure_threshold: u32,
+    /// How long an open circuit skips the provider before letting a trial request through.
+    pub cooldown: Duration,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, serde::Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum CircuitState {
+    Closed,
+    Open,
+    HalfOpen,
+}
+
+/// The state of one provider's circuit breaker, as reported by the health endpoint.
+#[derive(Debug, Clone, serde::Serialize)]
+pub struct ProviderHealth {
+    pub name: String,
+    pub circuit: CircuitState,
+    pub consecutive_failures: u32,
+}
+
+#[derive(Default)]
+struct BreakerState {
+    consecutive_failures: u32,
+    opened_at: Option<Instant>,
+}
+
+/// Opens after `failure_threshold` consecutive transient failures. Once the
+/// cool-down has passed, a single trial request goes through: a success
+/// closes the circuit, a failure opens it for another cool-down.
+struct CircuitBreaker {
+    policy: CircuitBreakerPolicy,
+    state: Mutex<BreakerState>,
+}
+
+impl CircuitBreaker {
+    fn new(policy: CircuitBreakerPolicy) -> Self {
+        Self {
+            policy,
+            state: Mutex::new(BreakerState::default()),
+        }
+    }
+
+    /// Whether a request may go through. Letting the trial request of a
+    /// half-open circuit through re-arms the cool-down, so that concurrent
+    /// callers keep skipping the provider until the trial is over.
+    fn try_acquire(&self) -> bool {
+        let mut state = self.state.lock().unwrap();
+        match state.opened_at {
+            None => true,
+            Some(opened_at) if opened_at.elapsed() < self.policy.cooldown => false,
+            Some(_) => {
+                state.opened_at = Some(Instant::now());
+                true
+            }
+        }
+    }
+
+    fn record_success(&self) {
+        *self.state.lock().unwrap() = BreakerState::default();
+    }
+
+    fn record_failure(&self) {
+        let mut state = self.state.lock().unwrap();
+        state.consecutive_failures += 1;
+        if state.consecutive_failures >= self.policy.failure_threshold.max(1) {
+            state.opened_at = Some(Instant::now());
+        }
+    }
+
+    fn health(&self, name: &str) -> ProviderHealth {
+        let state = self.state.lock().unwrap();
+        let circuit = match state.opened_at {
+            None => CircuitState::Closed,
+            Some(opened_at) if opened_at.elapsed() < self.policy.cooldown => CircuitState::Open,
+            Some(_) => CircuitState::HalfOpen,
+        };
+        ProviderHealth {
+            name: name.to_owned(),
+            circuit,
+            consecutive_failures: state.consecutive_failures,
+        }
+    }
+}
+
+struct FailoverMember {
+    name: String,
+    provider: Box<dyn EmailProvider>,
+    breaker: CircuitBreaker,
+}
+
+/// Sends through the first provider, in order, whose circuit is not open.
+/// A transient failure moves on to the next provider straight away, while
+/// a permanent one is returned as is: another provider would reject the
+/// same message.
+pub struct FailoverProvider {
+    policy: CircuitBreakerPolicy,
+    members: Vec<FailoverMember>,
+}
+
+impl FailoverProvider {
+    pub fn new(policy: CircuitBreakerPolicy) -> Self {
+        Self {
+            policy,
+            members: Vec::new(),
+        }
+    }
+
+    /// Adds a provider after the ones already configured.
+    pub fn with_provider(mut self, name: impl Into<String>, provider: Box<dyn EmailProvider>) -> Self {
+        self.members.push(FailoverMember {
+            name: name.into(),
+            provider,
+            breaker: CircuitBreaker::new(self.policy.clone()),
+        });
+        self
+    }
+
+    async fn call<'a, T>(
+        &'a self,
+        request: impl Fn(&'a dyn EmailProvider) -> BoxFuture<'a, Result<T, EmailError>>,
+    ) -> Result<T, EmailError> {
+        let mut last_error = None;
+        for member in &self.members {
+            if !member.breaker.try_acquire() {
+                continue;
+            }
+            match request(member.provider.as_ref()).await {
+                Err(e @ EmailError::Transient { .. }) => {
+                    member.breaker.record_failure();
+                    tracing::warn!(
+                        provider = %member.name,
+                        error.cause_chain = ?e,
+                        "The email provider failed. Trying the next one."
+                    );
+                    last_error = Some(e);
+                }
+                // The provider answered, even if only to reject the message.
+                outcome => {
+                    member.breaker.record_success();
+                    return outcome;
+                }
+            }
+        }
+        Err(last_error.unwrap_or_else(|| {
+            EmailError::transient(anyhow::anyhow!("The circuit of every email provider is open."))
+        }))
+    }
+}
+
+#[async_trait::async_trait]
+impl EmailProvider for FailoverProvider {
+    async fn send(&self, message: &EmailMessage) -> Result<EmailReceipt, EmailError> {
+        self.call(|provider| provider.send(message)).await
+    }
+
+    /// The primary provider's: a fallback without a batch API still goes
+    /// through the messages one by one.
+    fn max_batch_size(&self) -> usize {
+        self.members
+            .first()
+            .map_or(1, |member| member.provider.max_batch_size())
+    }
+
+    async fn send_batch(&self, messages: &[EmailMessage]) -> Result<BatchOutcome, EmailError> {
+        self.call(|provider| provider.send_batch(messages)).await
+    }
+
+    fn health(&self) -> Vec<ProviderHealth> {
+        self.members
+            .iter()
+            .map(|member| member.breaker.health(&member.name))
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use fake::faker::internet::en::SafeEmail;
+    use fake::{Fake, Faker};
+    use wiremock::matchers::any;
+    use wiremock::{Mock, MockServer, ResponseTemplate};
+
+    use crate::domain::SubscriberEmail;
+    use crate::email_client::{EmailError, EmailMessage, EmailProvider, PostmarkProvider};
+    use super::{CircuitBreakerPolicy, CircuitState, FailoverProvider};
+
+    fn email() -> SubscriberEmail {
+        SubscriberEmail::parse(SafeEmail().fake()).unwrap()
+    }
+
+    fn message() -> EmailMessage {
+        EmailMessage::builder(email(), "Subject")
+            .text_content("Body")
+            .build()
+    }
+
+    fn postmark(server: &MockServer) -> Box<dyn EmailProvider> {
+        Box::new(PostmarkProvider::new(
+            server.uri(),
+            email(),
+            Faker.fake(),
+            Duration::from_millis(200),
+        ))
+    }
+
+    fn failover(primary: &MockServer, fallback: &MockServer, cooldown: Duration) -> FailoverProvider {
+        let policy = CircuitBreakerPolicy {
+            failure_threshold: 2,
+            cooldown,
+        };
+        FailoverProvider::new(policy)
+            .with_provider("primary", postmark(primary))
+            .with_provider("fallback", postmark(fallback))
+    }
+
+    async fn respond(server: &MockServer, status: u16, n_requests: u64) {
+        Mock::given(any())
+            .respond_with(ResponseTemplate::new(status))
+            .expect(n_requests)
+            .mount(server)
+            .await;
+    }
+
+    #[tokio::test]
+    async fn a_transient_failure_fails_over_to_the_next_provider() {
+        let primary = MockServer::start().await;
+        let fallback = MockServer::start().await;
+        respond(&primary, 500, 1).await;
+        respond(&fallback, 200, 1).await;
+        let provider = failover(&primary, &fallback, Duration::from_secs(60));
+
+        claim::assert_ok!(provider.send(&message()).await);
+
+        let health = provider.health();
+        assert_eq!(health[0].circuit, CircuitState::Closed);
+        assert_eq!(health[0].consecutive_failures, 1);
+        assert_eq!(health[1].consecutive_failures, 0);
+    }
+
+    #[tokio::test]
+    async fn a_permanent_failure_does_not_fail_over() {
+        let primary = MockServer::start().await;
+        let fallback = MockServer::start().await;
+        respond(&primary, 422, 1).await;
+        respond(&fallback, 200, 0).await;
+        let provider = failover(&primary, &fallback, Duration::from_secs(60));
+
+        let outcome = provider.send(&message()).await;
+
+        assert!(matches!(outcome, Err(EmailError::Permanent(_))));
+        assert_eq!(provider.health()[0].consecutive_failures, 0);
+    }
+
+    #[tokio::test]
+    async fn an_open_circuit_skips_the_provider() {
+        let primary = MockServer::start().await;
+        let fallback = MockServer::start().await;
+        respond(&primary, 500, 2).await;
+        respond(&fallback, 200, 3).await;
+        let provider = failover(&primary, &fallback, Duration::from_secs(60));
+
+        for _ in 0..3 {
+            claim::assert_ok!(provider.send(&message()).await);
+        }
+
+        assert_eq!(provider.health()[0].circuit, CircuitState::Open);
+    }
+
+    #[tokio::test]
+    async fn the_circuit_closes_after_a_successful_trial_once_the_cooldown_is_over() {
+        let primary = MockServer::start().await;
+        let fallback = MockServer::start().await;
+        let provider = failover(&primary, &fallback, Duration::from_millis(100));
+        respond(&fallback, 200, 2).await;
+        let outage = Mock::given(any())
+            .respond_with(ResponseTemplate::new(500))
+            .expect(2)
+            .mount_as_scoped(&primary)
+            .await;
+        provider.send(&message()).await.unwrap();
+        provider.send(&message()).await.unwrap();
+        assert_eq!(provider.health()[0].circuit, CircuitState::Open);
+        drop(outage);
+
+        tokio::time::sleep(Duration::from_millis(150)).await;
+        assert_eq!(provider.health()[0].circuit, CircuitState::HalfOpen);
+        respond(&primary, 200, 1).await;
+        provider.send(&message()).await.unwrap();
+
+        assert_eq!(provider.health()[0].circuit, CircuitState::Closed);
+        assert_eq!(provider.health()[0].consecutive_failures, 0);
+    }
+
+    #[tokio::test]
+    async fn every_circuit_open_is_a_transient_error() {
+        let primary = MockServer::start().await;
+        let fallback = MockServer::start().await;
+        respond(&primary, 500, 2).await;
+        respond(&fallback, 500, 2).await;
+        let provider = failover(&primary, &fallback, Duration::from_secs(60));
+
+        for _ in 0..3 {
+            let outcome = provider.send(&message()).await;
+            assert!(matches!(outcome, Err(EmailError::Transient { .. })));
+        }
+
+        assert!(provider
+            .health()
+            .iter()
+            .all(|health| health.circuit == CircuitState::Open));
+    }
+}
@@ -0,0 +1,127 @@
+use std::path::PathBuf;
+
+use chrono::Utc;
+use uuid::Uuid;
+
+use crate::domain::SubscriberEmail;
+use super::{message_id, mime_message, EmailError, EmailMessage, EmailProvider, EmailReceipt};
+
+/// Writes every outgoing email to `directory` as an `.eml` file instead of sending it.
+/// Meant for local development.
+pub struct FileSinkProvider {
+    directory: PathBuf,
+    sender: SubscriberEmail,
+}
+
+impl FileSinkProvider {
+    pub fn new(directory: PathBuf, sender: SubscriberEmail) -> Self {
+        Self { directory, sender }
+    }
+}
+
+#[async_trait::async_trait]
+impl EmailProvider for FileSinkProvider {
+    async fn send(&self, message: &EmailMessage) -> Result<EmailReceipt, EmailError> {
+        let message = mime_message(&self.sender, message)?;
+        let file_name = format!("{}-{}.eml", Utc::now().format("%Y%m%dT%H%M%S"), Uuid::new_v4());
+
+        tokio::fs::create_dir_all(&self.directory)
+            .await
+            .map_err(EmailError::transient)?;
+        tokio::fs::write(self.directory.join(file_name), message.formatted())
+            .await
+            .map_err(EmailError::transient)?;
+        Ok(EmailReceipt {
+            message_id: message_id(&message),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use fake::faker::internet::en::SafeEmail;
+    use fake::Fake;
+
+    use crate::domain::SubscriberEmail;
+    use crate::email_client::{EmailAttachment, EmailMessage, EmailProvider};
+    use super::FileSinkProvider;
+
+    fn email() -> SubscriberEmail {
+        SubscriberEmail::parse(SafeEmail().fake()).unwrap()
+    }
+
+    #[tokio::test]
+    async fn send_email_writes_an_eml_file() {
+        let directory = std::env::temp_dir().join(uuid::Uuid::new_v4().to_string());
+        let provider = FileSinkProvider::new(directory.clone(), email());
+        let recipient = email();
+
+        let receipt = provider
+            .send_email(&recipient, "Hello there", "<p>HTML body</p>", "Plain body")
+            .await
+            .unwrap();
+
+        let files: Vec<_> = std::fs::read_dir(&directory)
+            .unwrap()
+            .map(|entry| entry.unwrap().path())
+            .collect();
+        assert_eq!(files.len(), 1);
+        assert_eq!(files[0].extension().unwrap(), "eml");
+        let content = std::fs::read_to_string(&files[0]).unwrap();
+        assert!(content.contains(&format!("To: {}", recipient)));
+        assert!(content.contains("Subject: Hello there"));
+        assert!(content.contains(&format!("Message-ID: {}", receipt.message_id.unwrap())));
+
+        std::fs::remove_dir_all(directory).unwrap();
+    }
+
+    #[tokio::test]
+    async fn an_empty_html_body_produces_a_plain_text_email() {
+        let directory = std::env::temp_dir().join(uuid::Uuid::new_v4().to_string());
+        let provider = FileSinkProvider::new(directory.clone(), email());
+
+        provider
+            .send_email(&email(), "Hello there", "", "Plain body")
+            .await
+            .unwrap();
+
+        let file = std::fs::read_dir(&directory).unwrap().next().unwrap().unwrap().path();
+        let content = std::fs::read_to_string(&file).unwrap();
+        assert!(content.contains("Content-Type: text/plain"));
+        assert!(!content.contains("multipart"));
+        assert!(!content.contains("text/html"));
+
+        std::fs::remove_dir_all(directory).unwrap();
+    }
+
+    #[tokio::test]
+    async fn attachments_and_display_names_end_up_in_the_message() {
+        let directory = std::env::temp_dir().join(uuid::Uuid::new_v4().to_string());
+        let provider = FileSinkProvider::new(directory.clone(), email());
+        let (reply_to, cc, bcc) = (email(), email(), email());
+
+        let message = EmailMessage::builder(email(), "Hello there")
+            .recipient_name("Ursula")
+            .sender_name("The Newsletter")
+            .reply_to(reply_to.clone())
+            .cc(cc.clone())
+            .bcc(bcc.clone())
+            .text_content("Plain body")
+            .attachment(EmailAttachment::new("issue.pdf", "application/pdf", b"%PDF-1.4".to_vec()))
+            .build();
+        provider.send(&message).await.unwrap();
+
+        let file = std::fs::read_dir(&directory).unwrap().next().unwrap().unwrap().path();
+        let content = std::fs::read_to_string(&file).unwrap();
+        assert!(content.contains("To: Ursula <"));
+        assert!(content.contains("From: \"The Newsletter\" <"));
+        assert!(content.contains(&format!("Reply-To: {}", reply_to)));
+        assert!(content.contains(&format!("Cc: {}", cc)));
+        assert!(!content.contains(bcc.as_ref()));
+        assert!(content.contains("Content-Type: multipart/mixed"));
+        assert!(content.contains("Content-Disposition: attachment; filename=\"issue.pdf\""));
+        assert!(content.contains("%PDF-1.4"));
+
+        std::fs::remove_dir_all(directory).unwrap();
+    }
+}
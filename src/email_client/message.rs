@@ -0,0 +1,181 @@
+use std::{collections::BTreeMap, sync::Arc};
+
+use crate::domain::SubscriberEmail;
+
+/// An outgoing email. Build one with [`EmailMessage::builder`].
+///
+/// An empty `html_content` means the email is sent as plain text only.
+/// BCC recipients only go into the envelope, never into a header.
+/// Tags, metadata and the message stream are provider features: the SMTP and
+/// file sink providers ignore them.
+#[derive(Debug, Clone)]
+pub struct EmailMessage {
+    pub(super) recipient: SubscriberEmail,
+    pub(super) recipient_name: Option<String>,
+    pub(super) sender_name: Option<String>,
+    pub(super) reply_to: Option<SubscriberEmail>,
+    pub(super) cc: Vec<SubscriberEmail>,
+    pub(super) bcc: Vec<SubscriberEmail>,
+    pub(super) subject: String,
+    pub(super) html_content: String,
+    pub(super) text_content: String,
+    pub(super) headers: Vec<EmailHeader>,
+    pub(super) tag: Option<String>,
+    pub(super) metadata: BTreeMap<String, String>,
+    pub(super) message_stream: Option<String>,
+    pub(super) attachments: Vec<EmailAttachment>,
+}
+
+impl EmailMessage {
+    pub fn builder(recipient: SubscriberEmail, subject: impl Into<String>) -> EmailMessageBuilder {
+        EmailMessageBuilder {
+            message: EmailMessage {
+                recipient,
+                recipient_name: None,
+                sender_name: None,
+                reply_to: None,
+                cc: Vec::new(),
+                bcc: Vec::new(),
+                subject: subject.into(),
+                html_content: String::new(),
+                text_content: String::new(),
+                headers: Vec::new(),
+                tag: None,
+                metadata: BTreeMap::new(),
+                message_stream: None,
+                attachments: Vec::new(),
+            },
+        }
+    }
+
+    /// The size of the attachments before encoding.
+    pub(super) fn attachments_size(&self) -> usize {
+        self.attachments.iter().map(|a| a.content.len()).sum()
+    }
+}
+
+pub struct EmailMessageBuilder {
+    message: EmailMessage,
+}
+
+impl EmailMessageBuilder {
+    /// The display name shown next to the recipient's address.
+    pub fn recipient_name(mut self, name: impl Into<String>) -> Self {
+        self.message.recipient_name = Some(name.into());
+        self
+    }
+
+    /// The display name shown next to the configured sender address.
+    pub fn sender_name(mut self, name: impl Into<String>) -> Self {
+        self.message.sender_name = Some(name.into());
+        self
+    }
+
+    pub fn reply_to(mut self, reply_to: SubscriberEmail) -> Self {
+        self.message.reply_to = Some(reply_to);
+        self
+    }
+
+    /// Adds a copy recipient, visible to every other recipient.
+    pub fn cc(mut self, cc: SubscriberEmail) -> Self {
+        self.message.cc.push(cc);
+        self
+    }
+
+    /// Adds a blind copy recipient, hidden from every other recipient.
+    pub fn bcc(mut self, bcc: SubscriberEmail) -> Self {
+        self.message.bcc.push(bcc);
+        self
+    }
+
+    pub fn html_content(mut self, html_content: impl Into<String>) -> Self {
+        self.message.html_content = html_content.into();
+        self
+    }
+
+    pub fn text_content(mut self, text_content: impl Into<String>) -> Self {
+        self.message.text_content = text_content.into();
+        self
+    }
+
+    pub fn header(mut self, name: impl Into<String>, value: impl Into<String>) -> Self {
+        self.message.headers.push(EmailHeader::new(name, value));
+        self
+    }
+
+    pub fn headers(mut self, headers: impl IntoIterator<Item = EmailHeader>) -> Self {
+        self.message.headers.extend(headers);
+        self
+    }
+
+    /// A label the provider uses to group messages in its statistics.
+    pub fn tag(mut self, tag: impl Into<String>) -> Self {
+        self.message.tag = Some(tag.into());
+        self
+    }
+
+    /// Key-value pairs the provider echoes back in its webhooks.
+    pub fn metadata(mut self, key: impl Into<String>, value: impl Into<String>) -> Self {
+        self.message.metadata.insert(key.into(), value.into());
+        self
+    }
+
+    /// The provider stream to send through, e.g. Postmark's `broadcast` stream.
+    pub fn message_stream(mut self, message_stream: impl Into<String>) -> Self {
+        self.message.message_stream = Some(message_stream.into());
+        self
+    }
+
+    pub fn attachment(mut self, attachment: EmailAttachment) -> Self {
+        self.message.attachments.push(attachment);
+        self
+    }
+
+    pub fn attachments(mut self, attachments: impl IntoIterator<Item = EmailAttachment>) -> Self {
+        self.message.attachments.extend(attachments);
+        self
+    }
+
+    pub fn build(self) -> EmailMessage {
+        self.message
+    }
+}
+
+/// An extra header to attach to an outgoing email, e.g. `List-Unsubscribe`.
+#[derive(Debug, Clone)]
+pub struct EmailHeader {
+    pub name: String,
+    pub value: String,
+}
+
+impl EmailHeader {
+    pub fn new(name: impl Into<String>, value: impl Into<String>) -> Self {
+        Self {
+            name: name.into(),
+            value: value.into(),
+        }
+    }
+}
+
+/// A file attached to an email. The content is shared, so that the same
+/// attachment can go out to many recipients without being copied.
+#[derive(Debug, Clone)]
+pub struct EmailAttachment {
+    pub file_name: String,
+    pub content_type: String,
+    pub content: Arc<[u8]>,
+}
+
+impl EmailAttachment {
+    pub fn new(
+        file_name: impl Into<String>,
+        content_type: impl Into<String>,
+        content: impl Into<Arc<[u8]>>,
+    ) -> Self {
+        Self {
+            file_name: file_name.into(),
+            content_type: content_type.into(),
+            content: content.into(),
+        }
+    }
+}
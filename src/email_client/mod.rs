@@ -0,0 +1,698 @@
+mod failover;
+mod file_sink;
+mod message;
+mod postmark;
+mod rate_limit;
+mod smtp;
+
+pub use failover::{CircuitBreakerPolicy, CircuitState, FailoverProvider, ProviderHealth};
+pub use file_sink::FileSinkProvider;
+pub use message::{EmailAttachment, EmailHeader, EmailMessage, EmailMessageBuilder};
+pub use postmark::PostmarkProvider;
+pub use rate_limit::RateLimiter;
+pub use smtp::{SmtpProvider, SmtpTls};
+
+use std::{future::Future, sync::Arc, time::Duration};
+
+use crate::domain::SubscriberEmail;
+use lettre::message::{
+    header::{ContentType, HeaderName, HeaderValue},
+    Attachment, Mailbox, MultiPart, SinglePart,
+};
+use rand::Rng;
+use tracing::Instrument;
+
+/// A backend capable of delivering a single email.
+#[async_trait::async_trait]
+pub trait EmailProvider: Send + Sync {
+    async fn send(&self, message: &EmailMessage) -> Result<EmailReceipt, EmailError>;
+
+    async fn send_email_with_headers(
+        &self,
+        recipient: &SubscriberEmail,
+        subject: &str,
+        html_content: &str,
+        text_content: &str,
+        headers: &[EmailHeader],
+    ) -> Result<EmailReceipt, EmailError> {
+        let message = EmailMessage::builder(recipient.clone(), subject)
+            .html_content(html_content)
+            .text_content(text_content)
+            .headers(headers.iter().cloned())
+            .build();
+        self.send(&message).await
+    }
+
+    async fn send_email(
+        &self,
+        recipient: &SubscriberEmail,
+        subject: &str,
+        html_content: &str,
+        text_content: &str,
+    ) -> Result<EmailReceipt, EmailError> {
+        self.send_email_with_headers(recipient, subject, html_content, text_content, &[])
+            .await
+    }
+
+    /// The largest batch `send_batch` accepts in one call. Providers without
+    /// a batch API keep the default of 1.
+    fn max_batch_size(&self) -> usize {
+        1
+    }
+
+    /// Sends several emails in one call. An `Err` means the whole batch
+    /// failed; otherwise there is one outcome per message, in order.
+    async fn send_batch(&self, messages: &[EmailMessage]) -> Result<BatchOutcome, EmailError> {
+        let mut outcomes = Vec::with_capacity(messages.len());
+        for message in messages {
+            outcomes.push(self.send(message).await);
+        }
+        Ok(outcomes)
+    }
+
+    /// The state of the circuit breakers guarding the provider, if any.
+    fn health(&self) -> Vec<ProviderHealth> {
+        Vec::new()
+    }
+}
+
+pub type BatchOutcome = Vec<Result<EmailReceipt, EmailError>>;
+
+/// What the provider tells us about an email it accepted.
+#[derive(Debug, Clone, Default)]
+pub struct EmailReceipt {
+    /// The provider's identifier for the message, if it returned one.
+    pub message_id: Option<String>,
+}
+
+#[derive(thiserror::Error, Debug)]
+pub enum EmailError {
+    #[error("A transient error occurred while sending an email.")]
+    Transient {
+        #[source]
+        source: anyhow::Error,
+        retry_after: Option<Duration>,
+    },
+    #[error("A permanent error occurred while sending an email.")]
+    Permanent(#[source] anyhow::Error),
+}
+
+impl EmailError {
+    pub fn transient(source: impl Into<anyhow::Error>) -> Self {
+        Self::Transient {
+            source: source.into(),
+            retry_after: None,
+        }
+    }
+
+    pub fn permanent(source: impl Into<anyhow::Error>) -> Self {
+        Self::Permanent(source.into())
+    }
+
+    /// The error reported to each message of a batch that failed as a whole.
+    fn for_batch_member(&self) -> Self {
+        match self {
+            Self::Transient { source, retry_after } => Self::Transient {
+                source: anyhow::anyhow!("{:#}", source),
+                retry_after: *retry_after,
+            },
+            Self::Permanent(source) => Self::Permanent(anyhow::anyhow!("{:#}", source)),
+        }
+    }
+}
+
+/// Attachments are base64-encoded in batch requests: this keeps a batch well
+/// below providers' request size limits (50 MB for Postmark).
+const MAX_BATCH_ATTACHMENTS_SIZE: usize = 20 * 1024 * 1024;
+
+/// Wraps the configured `EmailProvider`, retrying transient failures and,
+/// optionally, capping the send rate to the provider's quota.
+///
+/// Clones share the provider, with its circuit breakers, and the rate limiter.
+#[derive(Clone)]
+pub struct EmailClient {
+    provider: Arc<dyn EmailProvider>,
+    retry_policy: RetryPolicy,
+    rate_limiter: Option<Arc<RateLimiter>>,
+    max_batch_size: Option<usize>,
+}
+
+#[derive(Debug, Clone)]
+pub struct RetryPolicy {
+    pub max_attempts: u32,
+    pub base_delay: Duration,
+    pub max_delay: Duration,
+    pub jitter: bool,
+}
+
+impl RetryPolicy {
+    /// Exponential backoff capped at `max_delay`, with "full jitter" when enabled.
+    fn backoff(&self, attempt: u32) -> Duration {
+        let exponential = self
+            .base_delay
+            .saturating_mul(2_u32.saturating_pow(attempt.saturating_sub(1)));
+        let capped = exponential.min(self.max_delay);
+        if self.jitter {
+            capped.mul_f64(rand::rng().random_range(0.0..=1.0))
+        } else {
+            capped
+        }
+    }
+}
+
+impl EmailClient {
+    pub fn new(provider: Box<dyn EmailProvider>, retry_policy: RetryPolicy) -> Self {
+        Self {
+            provider: Arc::from(provider),
+            retry_policy,
+            rate_limiter: None,
+            max_batch_size: None,
+        }
+    }
+
+    /// Every attempt, retries included, counts towards the cap, which is
+    /// shared with the clients derived from this one.
+    pub fn with_rate_limit(mut self, max_emails_per_second: u32) -> Self {
+        self.rate_limiter = Some(Arc::new(RateLimiter::new(max_emails_per_second)));
+        self
+    }
+
+    /// A client for the same provider that retries as `retry_policy` says.
+    pub fn with_retry_policy(&self, retry_policy: RetryPolicy) -> Self {
+        Self {
+            retry_policy,
+            ..self.clone()
+        }
+    }
+
+    /// Sends at most `max_batch_size` messages per batch call, below the
+    /// provider's own limit. 1 disables batching.
+    pub fn with_max_batch_size(mut self, max_batch_size: usize) -> Self {
+        self.max_batch_size = Some(max_batch_size);
+        self
+    }
+
+    /// The number of messages worth handing to `send_batch` at once.
+    pub fn max_batch_size(&self) -> usize {
+        let provider_limit = self.provider.max_batch_size();
+        self.max_batch_size
+            .map_or(provider_limit, |limit| limit.min(provider_limit))
+            .max(1)
+    }
+
+    /// Reported by the health endpoint.
+    pub fn health(&self) -> Vec<ProviderHealth> {
+        self.provider.health()
+    }
+
+    /// Sends the messages in as few provider calls as the provider allows,
+    /// retrying calls that fail as a whole. Returns one outcome per message,
+    /// in order: a partially failed batch only fails the affected recipients.
+    pub async fn send_batch(&self, messages: &[EmailMessage]) -> BatchOutcome {
+        let mut outcomes = Vec::with_capacity(messages.len());
+        // A batch of one goes through the provider's single-send API.
+        if self.max_batch_size() == 1 || messages.len() == 1 {
+            for message in messages {
+                outcomes.push(self.send(message).await);
+            }
+            return outcomes;
+        }
+        for chunk in self.chunks(messages) {
+            match self.with_retries(chunk.len(), || self.provider.send_batch(chunk)).await {
+                Ok(chunk_outcomes) => outcomes.extend(chunk_outcomes),
+                Err(e) => outcomes.extend(chunk.iter().map(|_| Err(e.for_batch_member()))),
+            }
+        }
+        outcomes
+    }
+
+    /// Splits `messages` into batches that respect both the batch size and
+    /// `MAX_BATCH_ATTACHMENTS_SIZE`. A message whose attachments alone exceed
+    /// the limit goes out in a batch of its own.
+    fn chunks<'a>(&self, messages: &'a [EmailMessage]) -> Vec<&'a [EmailMessage]> {
+        let mut chunks = Vec::new();
+        let mut start = 0;
+        let mut attachments_size = 0;
+        for (i, message) in messages.iter().enumerate() {
+            let size = message.attachments_size();
+            let is_full = i - start >= self.max_batch_size()
+                || attachments_size + size > MAX_BATCH_ATTACHMENTS_SIZE;
+            if i > start && is_full {
+                chunks.push(&messages[start..i]);
+                start = i;
+                attachments_size = 0;
+            }
+            attachments_size += size;
+        }
+        if start < messages.len() {
+            chunks.push(&messages[start..]);
+        }
+        chunks
+    }
+
+    /// Runs `send` until it succeeds, fails permanently or runs out of attempts.
+    async fn with_retries<T, F, Fut>(&self, n_emails: usize, mut send: F) -> Result<T, EmailError>
+    where
+        F: FnMut() -> Fut,
+        Fut: Future<Output = Result<T, EmailError>>,
+    {
+        let max_attempts = self.retry_policy.max_attempts.max(1);
+        let mut attempt = 1;
+        loop {
+            let span = tracing::info_span!(
+                "Email delivery attempt",
+                attempt,
+                max_attempts,
+                n_emails,
+                http.status_code = tracing::field::Empty,
+            );
+            if let Some(rate_limiter) = &self.rate_limiter {
+                rate_limiter.acquire(n_emails).await;
+            }
+            let outcome = send().instrument(span).await;
+            let delay = match outcome {
+                Ok(sent) => return Ok(sent),
+                Err(e @ EmailError::Permanent(_)) => return Err(e),
+                Err(e) if attempt >= max_attempts => return Err(e),
+                Err(EmailError::Transient { source, retry_after }) => {
+                    let delay = match retry_after {
+                        Some(delay) if delay > self.retry_policy.max_delay => {
+                            tracing::warn!(
+                                retry_after = ?delay,
+                                "The email provider asked us to wait longer than our maximum delay. Giving up."
+                            );
+                            return Err(EmailError::Transient { source, retry_after });
+                        }
+                        Some(delay) => delay,
+                        None => self.retry_policy.backoff(attempt),
+                    };
+                    tracing::warn!(
+                        error.cause_chain = ?source,
+                        error.message = %source,
+                        retry_in = ?delay,
+                        "Failed to send email. Retrying."
+                    );
+                    delay
+                }
+            };
+            tokio::time::sleep(delay).await;
+            attempt += 1;
+        }
+    }
+}
+
+#[async_trait::async_trait]
+impl EmailProvider for EmailClient {
+    async fn send(&self, message: &EmailMessage) -> Result<EmailReceipt, EmailError> {
+        self.with_retries(1, || self.provider.send(message)).await
+    }
+}
+
+/// The `Message-ID` header generated for a MIME message.
+fn message_id(message: &lettre::Message) -> Option<String> {
+    message.headers().get_raw("Message-ID").map(str::to_owned)
+}
+
+fn mailbox(name: Option<&str>, email: &SubscriberEmail) -> Result<Mailbox, EmailError> {
+    let address = email.as_ref().parse().map_err(EmailError::permanent)?;
+    Ok(Mailbox::new(name.map(str::to_owned), address))
+}
+
+/// Builds the MIME message for the SMTP and file sink providers: a
+/// `multipart/alternative` body, wrapped in `multipart/mixed` with attachments.
+fn mime_message(
+    sender: &SubscriberEmail,
+    message: &EmailMessage,
+) -> Result<lettre::Message, EmailError> {
+    let mut builder = lettre::Message::builder()
+        .from(mailbox(message.sender_name.as_deref(), sender)?)
+        .to(mailbox(message.recipient_name.as_deref(), &message.recipient)?)
+        .subject(&message.subject)
+        // Generated here rather than by the relay, so that it can be reported back.
+        .message_id(None);
+    if let Some(reply_to) = &message.reply_to {
+        builder = builder.reply_to(mailbox(None, reply_to)?);
+    }
+    for cc in &message.cc {
+        builder = builder.cc(mailbox(None, cc)?);
+    }
+    // Lettre adds them to the envelope and leaves the `Bcc` header out.
+    for bcc in &message.bcc {
+        builder = builder.bcc(mailbox(None, bcc)?);
+    }
+    for header in &message.headers {
+        let name = HeaderName::new_from_ascii(header.name.clone()).map_err(EmailError::permanent)?;
+        builder = builder.raw_header(HeaderValue::new(name, header.value.clone()));
+    }
+    let text = SinglePart::plain(message.text_content.clone());
+    let alternative = || {
+        MultiPart::alternative_plain_html(message.text_content.clone(), message.html_content.clone())
+    };
+    if message.attachments.is_empty() {
+        let mime_message = if message.html_content.is_empty() {
+            builder.singlepart(text)
+        } else {
+            builder.multipart(alternative())
+        };
+        return mime_message.map_err(EmailError::permanent);
+    }
+    let mut body = if message.html_content.is_empty() {
+        MultiPart::mixed().singlepart(text)
+    } else {
+        MultiPart::mixed().multipart(alternative())
+    };
+    for attachment in &message.attachments {
+        let content_type =
+            ContentType::parse(&attachment.content_type).map_err(EmailError::permanent)?;
+        body = body.singlepart(
+            Attachment::new(attachment.file_name.clone())
+                .body(attachment.content.to_vec(), content_type),
+        );
+    }
+    builder.multipart(body).map_err(EmailError::permanent)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use fake::faker::internet::en::SafeEmail;
+    use fake::faker::lorem::en::{Sentence, Paragraph};
+    use fake::{Fake, Faker};
+    use wiremock::matchers::{any, path};
+    use wiremock::{Mock, MockServer, ResponseTemplate};
+
+    use crate::domain::SubscriberEmail;
+    use super::{EmailClient, EmailError, EmailMessage, EmailProvider, PostmarkProvider, RetryPolicy};
+
+    fn subject() -> String {
+        Sentence(1..2).fake()
+    }
+
+    fn content() -> String {
+        Paragraph(1..10).fake()
+    }
+
+    fn email() -> SubscriberEmail {
+        SubscriberEmail::parse(SafeEmail().fake()).unwrap()
+    }
+
+    fn retry_policy() -> RetryPolicy {
+        RetryPolicy {
+            max_attempts: 3,
+            base_delay: Duration::from_millis(1),
+            max_delay: Duration::from_millis(10),
+            jitter: true,
+        }
+    }
+
+    fn email_client(base_url: String) -> EmailClient {
+        let provider = PostmarkProvider::new(
+            base_url,
+            email(),
+            Faker.fake(),
+            Duration::from_millis(200),
+        );
+        EmailClient::new(Box::new(provider), retry_policy())
+    }
+
+    #[tokio::test]
+    async fn send_email_fails_if_the_server_returns_500() {
+        let mock_server = MockServer::start().await;
+        let email_client = email_client(mock_server.uri());
+
+        Mock::given(any())
+            .respond_with(ResponseTemplate::new(500))
+            .expect(3)
+            .mount(&mock_server)
+            .await;
+
+        let outcome = email_client
+            .send_email(&email(), &subject(), &content(), &content())
+            .await;
+
+        claim::assert_err!(outcome);
+    }
+
+    #[tokio::test]
+    async fn send_email_returns_the_provider_message_id() {
+        let mock_server = MockServer::start().await;
+        let email_client = email_client(mock_server.uri());
+
+        Mock::given(any())
+            .respond_with(ResponseTemplate::new(200).set_body_json(serde_json::json!({
+                "To": "receiver@example.com",
+                "MessageID": "b7bc2f4a-e38e-4336-af7d-e6c392c2f817",
+                "ErrorCode": 0,
+                "Message": "OK",
+            })))
+            .expect(1)
+            .mount(&mock_server)
+            .await;
+
+        let receipt = email_client
+            .send_email(&email(), &subject(), &content(), &content())
+            .await
+            .unwrap();
+
+        assert_eq!(
+            receipt.message_id.as_deref(),
+            Some("b7bc2f4a-e38e-4336-af7d-e6c392c2f817")
+        );
+    }
+
+    #[tokio::test]
+    async fn send_email_times_out_if_the_server_takes_too_long() {
+        let mock_server = MockServer::start().await;
+        let email_client = email_client(mock_server.uri());
+
+        Mock::given(any())
+            .respond_with(ResponseTemplate::new(200).set_delay(std::time::Duration::from_secs(180)))
+            .expect(3)
+            .mount(&mock_server)
+            .await;
+
+        let outcome = email_client
+            .send_email(&email(), &subject(), &content(), &content())
+            .await;
+
+        claim::assert_err!(outcome);
+    }
+
+    #[tokio::test]
+    async fn send_email_retries_after_a_transient_failure() {
+        let mock_server = MockServer::start().await;
+        let email_client = email_client(mock_server.uri());
+
+        Mock::given(any())
+            .respond_with(ResponseTemplate::new(503))
+            .up_to_n_times(1)
+            .expect(1)
+            .mount(&mock_server)
+            .await;
+        Mock::given(any())
+            .respond_with(ResponseTemplate::new(200))
+            .expect(1)
+            .mount(&mock_server)
+            .await;
+
+        let outcome = email_client
+            .send_email(&email(), &subject(), &content(), &content())
+            .await;
+
+        claim::assert_ok!(outcome);
+    }
+
+    #[tokio::test]
+    async fn send_email_retries_if_the_server_returns_429() {
+        let mock_server = MockServer::start().await;
+        let email_client = email_client(mock_server.uri());
+
+        Mock::given(any())
+            .respond_with(ResponseTemplate::new(429).insert_header("Retry-After", "0"))
+            .up_to_n_times(1)
+            .expect(1)
+            .mount(&mock_server)
+            .await;
+        Mock::given(any())
+            .respond_with(ResponseTemplate::new(200))
+            .expect(1)
+            .mount(&mock_server)
+            .await;
+
+        let outcome = email_client
+            .send_email(&email(), &subject(), &content(), &content())
+            .await;
+
+        claim::assert_ok!(outcome);
+    }
+
+    #[tokio::test]
+    async fn send_email_respects_the_rate_limit() {
+        let mock_server = MockServer::start().await;
+        let email_client = email_client(mock_server.uri()).with_rate_limit(10);
+
+        Mock::given(any())
+            .respond_with(ResponseTemplate::new(200))
+            .expect(4)
+            .mount(&mock_server)
+            .await;
+
+        let start = std::time::Instant::now();
+        for _ in 0..4 {
+            email_client
+                .send_email(&email(), &subject(), &content(), &content())
+                .await
+                .unwrap();
+        }
+
+        assert!(start.elapsed() >= Duration::from_millis(300));
+    }
+
+    #[tokio::test]
+    async fn a_client_with_another_retry_policy_shares_the_rate_limit() {
+        let mock_server = MockServer::start().await;
+        let email_client = email_client(mock_server.uri()).with_rate_limit(10);
+        let other_client = email_client.with_retry_policy(retry_policy());
+
+        Mock::given(any())
+            .respond_with(ResponseTemplate::new(200))
+            .expect(4)
+            .mount(&mock_server)
+            .await;
+
+        let start = std::time::Instant::now();
+        for client in [&email_client, &other_client, &email_client, &other_client] {
+            client
+                .send_email(&email(), &subject(), &content(), &content())
+                .await
+                .unwrap();
+        }
+
+        assert!(start.elapsed() >= Duration::from_millis(300));
+    }
+
+    fn message() -> EmailMessage {
+        EmailMessage::builder(email(), subject())
+            .html_content(content())
+            .text_content(content())
+            .build()
+    }
+
+    #[tokio::test]
+    async fn send_batch_chunks_messages_to_the_provider_limit() {
+        let mock_server = MockServer::start().await;
+        let email_client = email_client(mock_server.uri());
+
+        Mock::given(path("/email/batch"))
+            .respond_with(|request: &wiremock::Request| {
+                let body: Vec<serde_json::Value> = serde_json::from_slice(&request.body).unwrap();
+                let results: Vec<_> = body
+                    .iter()
+                    .map(|_| serde_json::json!({"ErrorCode": 0, "MessageID": "id"}))
+                    .collect();
+                ResponseTemplate::new(200).set_body_json(results)
+            })
+            .expect(2)
+            .mount(&mock_server)
+            .await;
+        let messages: Vec<_> = (0..501).map(|_| message()).collect();
+
+        let outcomes = email_client.send_batch(&messages).await;
+
+        assert_eq!(outcomes.len(), 501);
+        assert!(outcomes.iter().all(|outcome| outcome.is_ok()));
+    }
+
+    #[tokio::test]
+    async fn send_batch_retries_a_batch_that_failed_as_a_whole() {
+        let mock_server = MockServer::start().await;
+        let email_client = email_client(mock_server.uri());
+
+        Mock::given(any())
+            .respond_with(ResponseTemplate::new(500))
+            .up_to_n_times(1)
+            .expect(1)
+            .mount(&mock_server)
+            .await;
+        Mock::given(any())
+            .respond_with(ResponseTemplate::new(200).set_body_json(serde_json::json!([
+                {"ErrorCode": 0, "MessageID": "first"},
+                {"ErrorCode": 300, "Message": "Invalid email request"},
+            ])))
+            .expect(1)
+            .mount(&mock_server)
+            .await;
+
+        let outcomes = email_client.send_batch(&[message(), message()]).await;
+
+        assert!(outcomes[0].is_ok());
+        assert!(matches!(outcomes[1], Err(EmailError::Permanent(_))));
+    }
+
+    #[tokio::test]
+    async fn send_email_does_not_retry_if_the_server_returns_422() {
+        let mock_server = MockServer::start().await;
+        let email_client = email_client(mock_server.uri());
+
+        Mock::given(any())
+            .respond_with(ResponseTemplate::new(422))
+            .expect(1)
+            .mount(&mock_server)
+            .await;
+
+        let outcome = email_client
+            .send_email(&email(), &subject(), &content(), &content())
+            .await;
+
+        claim::assert_err!(outcome);
+    }
+
+    #[tokio::test]
+    async fn send_email_gives_up_if_retry_after_exceeds_the_maximum_delay() {
+        let mock_server = MockServer::start().await;
+        let email_client = email_client(mock_server.uri());
+
+        Mock::given(any())
+            .respond_with(ResponseTemplate::new(429).insert_header("Retry-After", "120"))
+            .expect(1)
+            .mount(&mock_server)
+            .await;
+
+        let outcome = email_client
+            .send_email(&email(), &subject(), &content(), &content())
+            .await;
+
+        claim::assert_err!(outcome);
+    }
+
+    #[test]
+    fn backoff_grows_exponentially_up_to_the_maximum_delay() {
+        let policy = RetryPolicy {
+            max_attempts: 10,
+            base_delay: Duration::from_millis(100),
+            max_delay: Duration::from_millis(1000),
+            jitter: false,
+        };
+
+        assert_eq!(policy.backoff(1), Duration::from_millis(100));
+        assert_eq!(policy.backoff(2), Duration::from_millis(200));
+        assert_eq!(policy.backoff(4), Duration::from_millis(800));
+        assert_eq!(policy.backoff(5), Duration::from_millis(1000));
+        assert_eq!(policy.backoff(64), Duration::from_millis(1000));
+    }
+
+    #[test]
+    fn jittered_backoff_never_exceeds_the_capped_delay() {
+        let policy = RetryPolicy {
+            max_attempts: 10,
+            base_delay: Duration::from_millis(100),
+            max_delay: Duration::from_millis(1000),
+            jitter: true,
+        };
+
+        for attempt in 1..10 {
+            assert!(policy.backoff(attempt) <= Duration::from_millis(1000));
+        }
+    }
+}
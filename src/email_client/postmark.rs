@@ -0,0 +1,522 @@
+use std::{collections::BTreeMap, time::Duration};
+
+use base64::{engine::general_purpose::STANDARD as BASE64_STANDARD, Engine};
+
+use reqwest::{header::RETRY_AFTER, Client, Response, StatusCode};
+
+use crate::domain::SubscriberEmail;
+use super::{mailbox, BatchOutcome, EmailError, EmailMessage, EmailProvider, EmailReceipt};
+
+/// Postmark accepts up to 500 messages per batch call.
+const MAX_BATCH_SIZE: usize = 500;
+/// The per-message error code Postmark reports while it is under maintenance.
+const MAINTENANCE_ERROR_CODE: i64 = 100;
+
+pub struct PostmarkProvider {
+    http_client: Client,
+    base_url: String,
+    sender: SubscriberEmail,
+    auth_token: String,
+}
+
+impl PostmarkProvider {
+    pub fn new(
+        base_url: String,
+        sender: SubscriberEmail,
+        auth_token: String,
+        timeout: std::time::Duration
+    ) -> Self {
+        let http_client = Client::builder()
+            .timeout(timeout)
+            .build()
+            .unwrap();
+        Self {
+            http_client,
+            base_url,
+            sender,
+            auth_token,
+        }
+    }
+
+    fn request<'a>(&self, message: &'a EmailMessage) -> Result<SendEmailRequest<'a>, EmailError> {
+        Ok(SendEmailRequest {
+            from: mailbox(message.sender_name.as_deref(), &self.sender)?.to_string(),
+            to: mailbox(message.recipient_name.as_deref(), &message.recipient)?.to_string(),
+            reply_to: message.reply_to.as_ref().map(AsRef::as_ref),
+            cc: address_list(&message.cc),
+            bcc: address_list(&message.bcc),
+            subject: &message.subject,
+            html_body: &message.html_content,
+            text_body: &message.text_content,
+            headers: message
+                .headers
+                .iter()
+                .map(|h| PostmarkHeader { name: &h.name, value: &h.value })
+                .collect(),
+            tag: message.tag.as_deref(),
+            metadata: Some(&message.metadata).filter(|metadata| !metadata.is_empty()),
+            message_stream: message.message_stream.as_deref(),
+            attachments: message
+                .attachments
+                .iter()
+                .map(|a| PostmarkAttachment {
+                    name: &a.file_name,
+                    content: BASE64_STANDARD.encode(&a.content),
+                    content_type: &a.content_type,
+                })
+                .collect(),
+        })
+    }
+
+    /// Posts `body` to `path`, mapping failed calls to transient or permanent errors.
+    async fn post(&self, path: &str, body: &impl serde::Serialize) -> Result<Response, EmailError> {
+        let url = format!("{}{}", self.base_url, path);
+        let response = self.http_client.post(url)
+            .header(
+            "X-Postmark-Server-Token",
+            &self.auth_token,
+            )
+            .json(body)
+            .send()
+            .await
+            .map_err(|e| {
+                if e.is_timeout() || e.is_connect() || e.is_request() {
+                    EmailError::transient(e)
+                } else {
+                    EmailError::permanent(e)
+                }
+            })?;
+        tracing::Span::current().record("http.status_code", response.status().as_u16());
+
+        let status = response.status();
+        let retry_after = retry_after(&response);
+        match response.error_for_status() {
+            Ok(response) => Ok(response),
+            Err(e) if is_retryable(status) => Err(EmailError::Transient {
+                source: e.into(),
+                retry_after,
+            }),
+            Err(e) => Err(EmailError::permanent(e)),
+        }
+    }
+}
+
+#[async_trait::async_trait]
+impl EmailProvider for PostmarkProvider {
+    async fn send(&self, message: &EmailMessage) -> Result<EmailReceipt, EmailError> {
+        let request_body = self.request(message)?;
+        let response = self.post("/email", &request_body).await?;
+        // A malformed body does not undo the delivery: the ID is informational.
+        let message_id = response
+            .json::<SendEmailResponse>()
+            .await
+            .ok()
+            .map(|body| body.message_id);
+        Ok(EmailReceipt { message_id })
+    }
+
+    fn max_batch_size(&self) -> usize {
+        MAX_BATCH_SIZE
+    }
+
+    async fn send_batch(&self, messages: &[EmailMessage]) -> Result<BatchOutcome, EmailError> {
+        let request_body = messages
+            .iter()
+            .map(|message| self.request(message))
+            .collect::<Result<Vec<_>, _>>()?;
+        let response = self.post("/email/batch", &request_body).await?;
+        let results = response.json::<Vec<BatchResult>>().await.ok();
+        let outcomes = match results {
+            Some(results) if results.len() == messages.len() => {
+                results.into_iter().map(BatchResult::into_outcome).collect()
+            }
+            // The batch was accepted, but we cannot tell which messages went
+            // out: reporting them as failed would send duplicates on retry.
+            _ => {
+                tracing::warn!("Could not parse the per-message results of a batch send.");
+                messages.iter().map(|_| Ok(EmailReceipt::default())).collect()
+            }
+        };
+        Ok(outcomes)
+    }
+}
+
+/// Postmark takes several recipients as a comma-separated list.
+fn address_list(addresses: &[SubscriberEmail]) -> Option<String> {
+    if addresses.is_empty() {
+        return None;
+    }
+    let addresses: Vec<&str> = addresses.iter().map(AsRef::as_ref).collect();
+    Some(addresses.join(", "))
+}
+
+fn is_retryable(status: StatusCode) -> bool {
+    status == StatusCode::TOO_MANY_REQUESTS || status.is_server_error()
+}
+
+/// Parses a `Retry-After` header, either as delay-seconds or as an HTTP-date.
+fn retry_after(response: &Response) -> Option<Duration> {
+    let value = response.headers().get(RETRY_AFTER)?.to_str().ok()?;
+    if let Ok(seconds) = value.trim().parse::<u64>() {
+        return Some(Duration::from_secs(seconds));
+    }
+    let date = chrono::DateTime::parse_from_rfc2822(value).ok()?;
+    (date.with_timezone(&chrono::Utc) - chrono::Utc::now())
+        .to_std()
+        .ok()
+        .or(Some(Duration::ZERO))
+}
+
+#[derive(serde::Serialize)]
+#[serde(rename_all = "PascalCase")]
+struct SendEmailRequest<'a> {
+    from: String,
+    to: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    reply_to: Option<&'a str>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    cc: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    bcc: Option<String>,
+    subject: &'a str,
+    #[serde(skip_serializing_if = "str::is_empty")]
+    html_body: &'a str,
+    text_body: &'a str,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    headers: Vec<PostmarkHeader<'a>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    tag: Option<&'a str>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    metadata: Option<&'a BTreeMap<String, String>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    message_stream: Option<&'a str>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    attachments: Vec<PostmarkAttachment<'a>>,
+}
+
+#[derive(serde::Serialize)]
+#[serde(rename_all = "PascalCase")]
+struct PostmarkAttachment<'a> {
+    name: &'a str,
+    /// Base64-encoded.
+    content: String,
+    content_type: &'a str,
+}
+
+#[derive(serde::Deserialize)]
+#[serde(rename_all = "PascalCase")]
+struct SendEmailResponse {
+    #[serde(rename = "MessageID")]
+    message_id: String,
+}
+
+/// The outcome of one message of a batch call.
+#[derive(serde::Deserialize)]
+#[serde(rename_all = "PascalCase")]
+struct BatchResult {
+    error_code: i64,
+    #[serde(default)]
+    message: String,
+    #[serde(rename = "MessageID", default)]
+    message_id: Option<String>,
+}
+
+impl BatchResult {
+    fn into_outcome(self) -> Result<EmailReceipt, EmailError> {
+        let error = || anyhow::anyhow!("Postmark error {}: {}", self.error_code, self.message);
+        match self.error_code {
+            0 => Ok(EmailReceipt { message_id: self.message_id.clone() }),
+            MAINTENANCE_ERROR_CODE => Err(EmailError::transient(error())),
+            _ => Err(EmailError::permanent(error())),
+        }
+    }
+}
+
+#[derive(serde::Serialize)]
+#[serde(rename_all = "PascalCase")]
+struct PostmarkHeader<'a> {
+    name: &'a str,
+    value: &'a str,
+}
+
+#[cfg(test)]
+mod tests {
+    use fake::faker::internet::en::SafeEmail;
+    use fake::faker::lorem::en::{Sentence, Paragraph};
+    use fake::{Fake, Faker};
+    use wiremock::matchers::{any, header, header_exists, method, path};
+    use wiremock::{Mock, MockServer, ResponseTemplate};
+
+    use crate::domain::SubscriberEmail;
+    use crate::email_client::{EmailAttachment, EmailError, EmailMessage, EmailProvider};
+    use super::PostmarkProvider;
+
+    struct SendEmailBodyMatcher;
+
+    impl wiremock::Match for SendEmailBodyMatcher {
+        fn matches(&self, request: &wiremock::Request) -> bool {
+            let result: serde_json::Result<serde_json::Value> = serde_json::from_slice(&request.body);
+            if let Ok(body) = result {
+                body.get("From").is_some()
+                    && body.get("To").is_some()
+                    && body.get("Subject").is_some()
+                    && body.get("HtmlBody").is_some()
+                    && body.get("TextBody").is_some()
+            } else {
+                false
+            }
+        }
+    }
+
+    fn subject() -> String {
+        Sentence(1..2).fake()
+    }
+
+    fn content() -> String {
+        Paragraph(1..10).fake()
+    }
+
+    fn email() -> SubscriberEmail {
+        SubscriberEmail::parse(SafeEmail().fake()).unwrap()
+    }
+
+    fn postmark_provider(base_url: String) -> PostmarkProvider {
+        PostmarkProvider::new(
+            base_url,
+            email(),
+            Faker.fake(),
+            std::time::Duration::from_millis(200),
+        )
+    }
+
+    #[tokio::test]
+    async fn send_email_sends_the_expected_request() {
+        let mock_server = MockServer::start().await;
+        let provider = postmark_provider(mock_server.uri());
+
+        Mock::given(header_exists("X-Postmark-Server-Token"))
+            .and(header("Content-Type", "application/json"))
+            .and(path("/email"))
+            .and(method("POST"))
+            .and(SendEmailBodyMatcher)
+            .respond_with(ResponseTemplate::new(200))
+            .expect(1)
+            .mount(&mock_server)
+            .await;
+
+        let outcome = provider
+            .send_email(&email(), &subject(), &content(), &content())
+            .await;
+
+        claim::assert_ok!(outcome);
+    }
+
+    #[tokio::test]
+    async fn send_email_succeeds_if_the_server_returns_200() {
+        let mock_server = MockServer::start().await;
+        let provider = postmark_provider(mock_server.uri());
+
+        Mock::given(any())
+            .respond_with(ResponseTemplate::new(200))
+            .expect(1)
+            .mount(&mock_server)
+            .await;
+
+        let outcome = provider
+            .send_email(&email(), &subject(), &content(), &content())
+            .await;
+
+        claim::assert_ok!(outcome);
+    }
+
+    #[tokio::test]
+    async fn a_500_is_reported_as_a_transient_error() {
+        let mock_server = MockServer::start().await;
+        let provider = postmark_provider(mock_server.uri());
+
+        Mock::given(any())
+            .respond_with(ResponseTemplate::new(500))
+            .expect(1)
+            .mount(&mock_server)
+            .await;
+
+        let outcome = provider
+            .send_email(&email(), &subject(), &content(), &content())
+            .await;
+
+        assert!(matches!(outcome, Err(EmailError::Transient { .. })));
+    }
+
+    #[tokio::test]
+    async fn a_422_is_reported_as_a_permanent_error() {
+        let mock_server = MockServer::start().await;
+        let provider = postmark_provider(mock_server.uri());
+
+        Mock::given(any())
+            .respond_with(ResponseTemplate::new(422))
+            .expect(1)
+            .mount(&mock_server)
+            .await;
+
+        let outcome = provider
+            .send_email(&email(), &subject(), &content(), &content())
+            .await;
+
+        assert!(matches!(outcome, Err(EmailError::Permanent(_))));
+    }
+
+    #[tokio::test]
+    async fn retry_after_is_forwarded_with_a_429() {
+        let mock_server = MockServer::start().await;
+        let provider = postmark_provider(mock_server.uri());
+
+        Mock::given(any())
+            .respond_with(ResponseTemplate::new(429).insert_header("Retry-After", "7"))
+            .expect(1)
+            .mount(&mock_server)
+            .await;
+
+        let outcome = provider
+            .send_email(&email(), &subject(), &content(), &content())
+            .await;
+
+        match outcome {
+            Err(EmailError::Transient { retry_after, .. }) => {
+                assert_eq!(retry_after, Some(std::time::Duration::from_secs(7)));
+            }
+            _ => panic!("Expected a transient error."),
+        }
+    }
+
+    fn message() -> EmailMessage {
+        EmailMessage::builder(email(), subject())
+            .html_content(content())
+            .text_content(content())
+            .build()
+    }
+
+    #[tokio::test]
+    async fn send_batch_reports_failures_per_recipient() {
+        let mock_server = MockServer::start().await;
+        let provider = postmark_provider(mock_server.uri());
+
+        Mock::given(path("/email/batch"))
+            .and(method("POST"))
+            .respond_with(ResponseTemplate::new(200).set_body_json(serde_json::json!([
+                {"ErrorCode": 0, "Message": "OK", "MessageID": "first-message-id"},
+                {"ErrorCode": 406, "Message": "Inactive recipient"},
+                {"ErrorCode": 100, "Message": "Maintenance"},
+            ])))
+            .expect(1)
+            .mount(&mock_server)
+            .await;
+
+        let outcomes = provider
+            .send_batch(&[message(), message(), message()])
+            .await
+            .unwrap();
+
+        assert_eq!(outcomes.len(), 3);
+        assert_eq!(outcomes[0].as_ref().unwrap().message_id.as_deref(), Some("first-message-id"));
+        assert!(matches!(outcomes[1], Err(EmailError::Permanent(_))));
+        assert!(matches!(outcomes[2], Err(EmailError::Transient { .. })));
+    }
+
+    #[tokio::test]
+    async fn send_batch_sends_every_message_in_one_request() {
+        let mock_server = MockServer::start().await;
+        let provider = postmark_provider(mock_server.uri());
+
+        Mock::given(path("/email/batch"))
+            .respond_with(ResponseTemplate::new(200))
+            .expect(1)
+            .mount(&mock_server)
+            .await;
+
+        let outcomes = provider.send_batch(&[message(), message()]).await.unwrap();
+
+        assert_eq!(outcomes.len(), 2);
+        let request = &mock_server.received_requests().await.unwrap()[0];
+        let body: serde_json::Value = serde_json::from_slice(&request.body).unwrap();
+        assert_eq!(body.as_array().unwrap().len(), 2);
+    }
+
+    #[tokio::test]
+    async fn a_500_fails_the_whole_batch_as_transient() {
+        let mock_server = MockServer::start().await;
+        let provider = postmark_provider(mock_server.uri());
+
+        Mock::given(any())
+            .respond_with(ResponseTemplate::new(500))
+            .expect(1)
+            .mount(&mock_server)
+            .await;
+
+        let outcome = provider.send_batch(&[message(), message()]).await;
+
+        assert!(matches!(outcome, Err(EmailError::Transient { .. })));
+    }
+
+    #[tokio::test]
+    async fn send_serializes_the_optional_message_fields() {
+        let mock_server = MockServer::start().await;
+        let provider = postmark_provider(mock_server.uri());
+        let reply_to = email();
+        let (cc, first_bcc, second_bcc) = (email(), email(), email());
+
+        Mock::given(path("/email"))
+            .respond_with(ResponseTemplate::new(200))
+            .expect(1)
+            .mount(&mock_server)
+            .await;
+
+        let message = EmailMessage::builder(email(), subject())
+            .recipient_name("Ursula Le Guin")
+            .reply_to(reply_to.clone())
+            .cc(cc.clone())
+            .bcc(first_bcc.clone())
+            .bcc(second_bcc.clone())
+            .text_content(content())
+            .header("X-Campaign", "spring")
+            .tag("newsletter")
+            .metadata("newsletter_issue_id", "42")
+            .message_stream("broadcast")
+            .attachment(EmailAttachment::new("issue.pdf", "application/pdf", b"%PDF-1.4".to_vec()))
+            .build();
+        provider.send(&message).await.unwrap();
+
+        let request = &mock_server.received_requests().await.unwrap()[0];
+        let body: serde_json::Value = serde_json::from_slice(&request.body).unwrap();
+        assert!(body["To"].as_str().unwrap().starts_with("Ursula Le Guin <"));
+        assert_eq!(body["ReplyTo"], reply_to.as_ref());
+        assert_eq!(body["Cc"], cc.as_ref());
+        assert_eq!(body["Bcc"], format!("{}, {}", first_bcc, second_bcc));
+        assert_eq!(body["Headers"][0]["Name"], "X-Campaign");
+        assert_eq!(body["Tag"], "newsletter");
+        assert_eq!(body["Metadata"]["newsletter_issue_id"], "42");
+        assert_eq!(body["MessageStream"], "broadcast");
+        assert_eq!(body["Attachments"][0]["Name"], "issue.pdf");
+        assert_eq!(body["Attachments"][0]["ContentType"], "application/pdf");
+        assert_eq!(body["Attachments"][0]["Content"], "JVBERi0xLjQ=");
+    }
+
+    #[tokio::test]
+    async fn optional_message_fields_are_omitted_when_unset() {
+        let mock_server = MockServer::start().await;
+        let provider = postmark_provider(mock_server.uri());
+
+        Mock::given(path("/email"))
+            .respond_with(ResponseTemplate::new(200))
+            .expect(1)
+            .mount(&mock_server)
+            .await;
+
+        provider.send(&message()).await.unwrap();
+
+        let request = &mock_server.received_requests().await.unwrap()[0];
+        let body: serde_json::Value = serde_json::from_slice(&request.body).unwrap();
+        for field in ["ReplyTo", "Cc", "Bcc", "Tag", "Metadata", "MessageStream", "Attachments"] {
+            assert!(body.get(field).is_none(), "{field} should be omitted");
+        }
+    }
+}
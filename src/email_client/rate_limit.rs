@@ -0,0 +1,62 @@
+use std::{sync::Mutex, time::Duration};
+
+use tokio::time::Instant;
+
+/// Spaces out sends evenly so that no more than `max_per_second` emails
+/// are handed to the provider in any second, however many tasks share it.
+pub struct RateLimiter {
+    interval: Duration,
+    next_slot: Mutex<Instant>,
+}
+
+impl RateLimiter {
+    pub fn new(max_per_second: u32) -> Self {
+        Self {
+            interval: Duration::from_secs(1) / max_per_second.max(1),
+            next_slot: Mutex::new(Instant::now()),
+        }
+    }
+
+    /// Waits until the caller may send the next `n_emails` emails.
+    pub async fn acquire(&self, n_emails: usize) {
+        let slot = {
+            let mut next_slot = self.next_slot.lock().unwrap();
+            let slot = (*next_slot).max(Instant::now());
+            *next_slot = slot + self.interval * u32::try_from(n_emails).unwrap_or(u32::MAX);
+            slot
+        };
+        tokio::time::sleep_until(slot).await;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use tokio::time::Instant;
+
+    use super::RateLimiter;
+
+    #[tokio::test]
+    async fn sends_are_spaced_out_to_the_rate_cap() {
+        let limiter = RateLimiter::new(20);
+        let start = Instant::now();
+
+        futures::future::join_all((0..5).map(|_| limiter.acquire(1))).await;
+
+        // The first send goes out immediately, the other four wait 50ms each.
+        assert!(start.elapsed() >= Duration::from_millis(200));
+        assert!(start.elapsed() < Duration::from_millis(400));
+    }
+
+    #[tokio::test]
+    async fn a_batch_takes_one_slot_per_email() {
+        let limiter = RateLimiter::new(20);
+        let start = Instant::now();
+
+        limiter.acquire(5).await;
+        limiter.acquire(1).await;
+
+        assert!(start.elapsed() >= Duration::from_millis(250));
+    }
+}
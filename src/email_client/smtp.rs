@@ -0,0 +1,209 @@
+use lettre::{
+    transport::smtp::authentication::Credentials,
+    AsyncSmtpTransport,
+    AsyncTransport,
+    Tokio1Executor,
+};
+
+use crate::domain::SubscriberEmail;
+use super::{message_id, mime_message, EmailError, EmailMessage, EmailProvider, EmailReceipt};
+
+#[derive(serde::Deserialize, Clone, Copy, Debug)]
+#[serde(rename_all = "lowercase")]
+pub enum SmtpTls {
+    None,
+    Starttls,
+    Tls,
+}
+
+pub struct SmtpProvider {
+    transport: AsyncSmtpTransport<Tokio1Executor>,
+    sender: SubscriberEmail,
+}
+
+impl SmtpProvider {
+    pub fn new(
+        host: &str,
+        port: u16,
+        tls: SmtpTls,
+        credentials: Option<(String, String)>,
+        sender: SubscriberEmail,
+        timeout: std::time::Duration,
+    ) -> Result<Self, anyhow::Error> {
+        let builder = match tls {
+            SmtpTls::None => AsyncSmtpTransport::<Tokio1Executor>::builder_dangerous(host),
+            SmtpTls::Starttls => AsyncSmtpTransport::<Tokio1Executor>::starttls_relay(host)?,
+            SmtpTls::Tls => AsyncSmtpTransport::<Tokio1Executor>::relay(host)?,
+        };
+        let mut builder = builder.port(port).timeout(Some(timeout));
+        if let Some((username, password)) = credentials {
+            builder = builder.credentials(Credentials::new(username, password));
+        }
+        Ok(Self {
+            transport: builder.build(),
+            sender,
+        })
+    }
+}
+
+#[async_trait::async_trait]
+impl EmailProvider for SmtpProvider {
+    async fn send(&self, message: &EmailMessage) -> Result<EmailReceipt, EmailError> {
+        let message = mime_message(&self.sender, message)?;
+        let message_id = message_id(&message);
+        self.transport
+            .send(message)
+            .await
+            .map_err(|e| {
+                if e.is_permanent() || e.is_client() {
+                    EmailError::permanent(e)
+                } else {
+                    EmailError::transient(e)
+                }
+            })?;
+        Ok(EmailReceipt { message_id })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::{Arc, Mutex};
+
+    use fake::faker::internet::en::SafeEmail;
+    use fake::Fake;
+    use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader};
+    use tokio::net::TcpListener;
+
+    use crate::domain::SubscriberEmail;
+    use crate::email_client::{EmailError, EmailMessage, EmailProvider};
+    use super::{SmtpProvider, SmtpTls};
+
+    fn email() -> SubscriberEmail {
+        SubscriberEmail::parse(SafeEmail().fake()).unwrap()
+    }
+
+    type Received = Arc<Mutex<Vec<String>>>;
+
+    /// A minimal SMTP stand-in: it accepts a single message and records the
+    /// DATA payload and the `RCPT TO` commands of the envelope.
+    /// Every `RCPT TO` is answered with `rcpt_reply`.
+    async fn spawn_smtp_server(rcpt_reply: &'static str) -> (u16, Received, Received) {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let port = listener.local_addr().unwrap().port();
+        let messages = Arc::new(Mutex::new(Vec::new()));
+        let received = messages.clone();
+        let recipients = Arc::new(Mutex::new(Vec::new()));
+        let envelope = recipients.clone();
+        tokio::spawn(async move {
+            let (stream, _) = listener.accept().await.unwrap();
+            let (reader, mut writer) = stream.into_split();
+            let mut lines = BufReader::new(reader).lines();
+            writer.write_all(b"220 localhost ESMTP\r\n").await.unwrap();
+            while let Ok(Some(line)) = lines.next_line().await {
+                let command = line.to_uppercase();
+                let reply = if command.starts_with("EHLO") || command.starts_with("HELO") {
+                    "250 localhost\r\n"
+                } else if command.starts_with("RCPT") {
+                    envelope.lock().unwrap().push(line);
+                    rcpt_reply
+                } else if command.starts_with("DATA") {
+                    writer.write_all(b"354 End data with <CR><LF>.<CR><LF>\r\n").await.unwrap();
+                    let mut data = String::new();
+                    while let Ok(Some(line)) = lines.next_line().await {
+                        if line == "." {
+                            break;
+                        }
+                        data.push_str(&line);
+                        data.push('\n');
+                    }
+                    received.lock().unwrap().push(data);
+                    "250 OK\r\n"
+                } else if command.starts_with("QUIT") {
+                    writer.write_all(b"221 Bye\r\n").await.unwrap();
+                    break;
+                } else {
+                    "250 OK\r\n"
+                };
+                writer.write_all(reply.as_bytes()).await.unwrap();
+            }
+        });
+        (port, messages, recipients)
+    }
+
+    fn smtp_provider(port: u16) -> SmtpProvider {
+        SmtpProvider::new(
+            "127.0.0.1",
+            port,
+            SmtpTls::None,
+            None,
+            email(),
+            std::time::Duration::from_secs(1),
+        )
+        .unwrap()
+    }
+
+    #[tokio::test]
+    async fn send_email_delivers_a_multipart_message() {
+        let (port, messages, _) = spawn_smtp_server("250 OK\r\n").await;
+        let provider = smtp_provider(port);
+
+        let outcome = provider
+            .send_email(&email(), "Hello there", "<p>HTML body</p>", "Plain body")
+            .await;
+
+        claim::assert_ok!(outcome);
+        let messages = messages.lock().unwrap();
+        assert_eq!(messages.len(), 1);
+        assert!(messages[0].contains("Subject: Hello there"));
+        assert!(messages[0].contains("multipart/alternative"));
+        assert!(messages[0].contains("Plain body"));
+        assert!(messages[0].contains("<p>HTML body</p>"));
+    }
+
+    #[tokio::test]
+    async fn a_5xx_reply_is_reported_as_a_permanent_error() {
+        let (port, _, _) = spawn_smtp_server("550 No such user\r\n").await;
+        let provider = smtp_provider(port);
+
+        let outcome = provider
+            .send_email(&email(), "Hello there", "<p>HTML body</p>", "Plain body")
+            .await;
+
+        assert!(matches!(outcome, Err(EmailError::Permanent(_))));
+    }
+
+    #[tokio::test]
+    async fn a_4xx_reply_is_reported_as_a_transient_error() {
+        let (port, _, _) = spawn_smtp_server("451 Try again later\r\n").await;
+        let provider = smtp_provider(port);
+
+        let outcome = provider
+            .send_email(&email(), "Hello there", "<p>HTML body</p>", "Plain body")
+            .await;
+
+        assert!(matches!(outcome, Err(EmailError::Transient { .. })));
+    }
+
+    #[tokio::test]
+    async fn bcc_recipients_are_in_the_envelope_but_not_in_the_headers() {
+        let (port, messages, recipients) = spawn_smtp_server("250 OK\r\n").await;
+        let provider = smtp_provider(port);
+        let (recipient, cc, bcc) = (email(), email(), email());
+
+        let message = EmailMessage::builder(recipient.clone(), "Hello there")
+            .cc(cc.clone())
+            .bcc(bcc.clone())
+            .text_content("Plain body")
+            .build();
+        provider.send(&message).await.unwrap();
+
+        let recipients = recipients.lock().unwrap();
+        for address in [&recipient, &cc, &bcc] {
+            assert!(recipients.iter().any(|rcpt| rcpt.contains(address.as_ref())));
+        }
+        let messages = messages.lock().unwrap();
+        assert!(messages[0].contains(&format!("Cc: {}", cc)));
+        assert!(!messages[0].contains("Bcc:"));
+        assert!(!messages[0].contains(bcc.as_ref()));
+    }
+}
@@ -0,0 +1,634 @@
+use std::{collections::HashMap, ops::DerefMut, time::Duration};
+
+use anyhow::Context;
+use sqlx::{PgPool, Postgres, QueryBuilder, Transaction};
+use tracing::Span;
+use uuid::Uuid;
+
+use crate::{
+    configuration::Settings,
+    domain::{Segment, SubscriberEmail},
+    email_client::{EmailAttachment, EmailClient, EmailError, EmailMessage, EmailReceipt},
+    issue_template::{render_html, render_text, TemplateValues},
+    routes::{preferences_link, unsubscribe_link},
+    startup::get_connection_pool,
+    tracking::instrument_html,
+};
+
+const MAX_DELIVERY_ATTEMPTS: i16 = 5;
+/// Postmark's default broadcast stream: bulk mail must not share a sending
+/// reputation with the transactional emails of the outbound stream.
+const NEWSLETTER_MESSAGE_STREAM: &str = "broadcast";
+
+pub enum ExecutionOutcome {
+    TasksCompleted(usize),
+    EmptyQueue,
+}
+
+pub async fn run_worker_until_stopped(
+    configuration: Settings,
+    email_client: EmailClient,
+) -> Result<(), anyhow::Error> {
+    let connection_pool = get_connection_pool(&configuration.database);
+    worker_loop(
+        connection_pool,
+        email_client,
+        configuration.application.base_url,
+        configuration.application.hmac_secret,
+        configuration.delivery.concurrency,
+    )
+    .await
+}
+
+async fn worker_loop(
+    pool: PgPool,
+    email_client: EmailClient,
+    base_url: String,
+    hmac_secret: String,
+    concurrency: usize,
+) -> Result<(), anyhow::Error> {
+    loop {
+        match dispatch_pending_emails(&pool, &email_client, &base_url, &hmac_secret, concurrency).await {
+            Ok(0) => {
+                tokio::time::sleep(Duration::from_secs(10)).await;
+            }
+            Err(_) => {
+                tokio::time::sleep(Duration::from_secs(1)).await;
+            }
+            Ok(_) => {}
+        }
+    }
+}
+
+/// Drains the delivery queue with up to `concurrency` emails in flight.
+///
+/// Each lane claims its own tasks with `SKIP LOCKED`, so lanes never send the
+/// same email twice. Recipients are never loaded in bulk: the fan-out to
+/// subscribers happens inside Postgres when the issue is enqueued, and each
+/// lane fetches at most one provider batch at a time. Returns the number of
+/// completed tasks; a lane stops at the first error, the others carry on.
+pub async fn dispatch_pending_emails(
+    pool: &PgPool,
+    email_client: &EmailClient,
+    base_url: &str,
+    hmac_secret: &str,
+    concurrency: usize,
+) -> Result<usize, anyhow::Error> {
+    let lane = || async {
+        let mut n_completed = 0;
+        loop {
+            match try_execute_task(pool, email_client, base_url, hmac_secret).await? {
+                ExecutionOutcome::TasksCompleted(n) => n_completed += n,
+                ExecutionOutcome::EmptyQueue => return Ok::<_, anyhow::Error>(n_completed),
+            }
+        }
+    };
+    let outcomes = futures::future::join_all((0..concurrency.max(1)).map(|_| lane())).await;
+    let mut n_completed = 0;
+    for outcome in outcomes {
+        n_completed += outcome?;
+    }
+    Ok(n_completed)
+}
+
+/// Claims as many tasks as the provider can send in one call (a single one
+/// for providers without a batch API) and delivers them.
+#[tracing::instrument(skip_all, fields(n_tasks = tracing::field::Empty), err)]
+pub async fn try_execute_task(
+    pool: &PgPool,
+    email_client: &EmailClient,
+    base_url: &str,
+    hmac_secret: &str,
+) -> Result<ExecutionOutcome, anyhow::Error> {
+    let Some((mut transaction, tasks)) = dequeue_tasks(pool, email_client.max_batch_size()).await?
+    else {
+        return Ok(ExecutionOutcome::EmptyQueue);
+    };
+    let n_tasks = tasks.len();
+    Span::current().record("n_tasks", n_tasks);
+
+    let mut issue_ids: Vec<Uuid> = tasks.iter().map(|task| task.newsletter_issue_id).collect();
+    issue_ids.sort();
+    issue_ids.dedup();
+    let attachments = get_attachments(&mut transaction, &issue_ids).await?;
+
+    let mut ready_tasks = Vec::with_capacity(tasks.len());
+    let mut messages = Vec::with_capacity(tasks.len());
+    for task in tasks {
+        let issue_attachments = attachments
+            .get(&task.newsletter_issue_id)
+            .map_or(&[][..], Vec::as_slice);
+        match prepare_email(&mut transaction, &task, issue_attachments, base_url, hmac_secret).await? {
+            Ok(message) => {
+                ready_tasks.push(task);
+                messages.push(message);
+            }
+            Err(outcome) => {
+                record_delivery(&mut transaction, &task, &outcome).await?;
+                delete_task(&mut transaction, &task).await?;
+            }
+        }
+    }
+
+    let outcomes = email_client.send_batch(&messages).await;
+    for (task, outcome) in ready_tasks.iter().zip(outcomes) {
+        complete_task(&mut transaction, task, outcome).await?;
+    }
+    transaction.commit().await?;
+    Ok(ExecutionOutcome::TasksCompleted(n_tasks))
+}
+
+/// Renders the issue for the task's subscriber, or explains why they are skipped.
+#[tracing::instrument(
+    skip_all,
+    fields(
+        newsletter_issue_id = %task.newsletter_issue_id,
+        subscriber_email = %task.subscriber_email,
+    )
+)]
+async fn prepare_email(
+    transaction: &mut PgTransaction,
+    task: &DeliveryTask,
+    attachments: &[EmailAttachment],
+    base_url: &str,
+    hmac_secret: &str,
+) -> Result<Result<EmailMessage, DeliveryOutcome>, anyhow::Error> {
+    let email = match SubscriberEmail::parse(task.subscriber_email.clone()) {
+        Ok(email) => email,
+        Err(e) => {
+            tracing::error!(
+                error.message = %e,
+                "Skipping a confirmed subscriber. Their stored contact details are invalid."
+            );
+            return Ok(Err(DeliveryOutcome::Skipped(format!("Invalid email address: {}", e))));
+        }
+    };
+
+    let Some(recipient) = get_recipient(transaction, &task.subscriber_email).await? else {
+        tracing::info!("Skipping a subscriber who is no longer confirmed or has paused delivery.");
+        return Ok(Err(DeliveryOutcome::Skipped(
+            "The subscriber is no longer confirmed or has paused delivery.".into(),
+        )));
+    };
+    let unsubscribe_link = unsubscribe_link(base_url, &recipient.unsubscribe_token);
+    let preferences_link = preferences_link(base_url, &recipient.unsubscribe_token);
+    let issue_url = format!("{}/issues/{}", base_url, task.newsletter_issue_id);
+
+    let issue = get_issue(transaction, task.newsletter_issue_id).await?;
+    let values = TemplateValues {
+        name: Some(&recipient.name),
+        unsubscribe_url: Some(&unsubscribe_link),
+        issue_url: &issue_url,
+    };
+    let rendered = render_html(&issue.html_content, &values)
+        .and_then(|html| Ok((html, render_text(&issue.text_content, &values)?)));
+    let (html_content, text_content) = match rendered {
+        Ok(rendered) => rendered,
+        Err(e) => {
+            tracing::error!(
+                error.message = %e,
+                "Skipping a subscriber. The issue template failed to render."
+            );
+            return Ok(Err(DeliveryOutcome::FailedPermanent(format!(
+                "Failed to render the issue: {}",
+                e
+            ))));
+        }
+    };
+    let html_content = if issue.tracking {
+        instrument_html(
+            &html_content,
+            base_url,
+            hmac_secret,
+            task.newsletter_issue_id,
+            recipient.id,
+        )
+    } else {
+        html_content
+    };
+    // An empty HTML body makes the provider send a plain-text-only email.
+    let html_content = if recipient.plain_text_only {
+        String::new()
+    } else {
+        format!(
+            "{}<p><a href=\"{}\">Manage your preferences</a> or \
+            <a href=\"{}\">unsubscribe here</a> to stop receiving these emails.</p>",
+            html_content,
+            preferences_link,
+            unsubscribe_link
+        )
+    };
+    let text_content = format!(
+        "{}\n\n--\nManage your preferences: {}\nTo stop receiving these emails, unsubscribe here: {}",
+        text_content,
+        preferences_link,
+        unsubscribe_link
+    );
+    let message = EmailMessage::builder(email, issue.title)
+        .recipient_name(recipient.name)
+        .html_content(html_content)
+        .text_content(text_content)
+        .header("List-Unsubscribe", format!("<{}>", unsubscribe_link))
+        .header("List-Unsubscribe-Post", "List-Unsubscribe=One-Click")
+        .tag("newsletter")
+        .metadata("newsletter_issue_id", task.newsletter_issue_id.to_string())
+        .message_stream(NEWSLETTER_MESSAGE_STREAM)
+        .attachments(attachments.iter().cloned())
+        .build();
+    Ok(Ok(message))
+}
+
+/// Records what the provider did with the task's email and removes it from
+/// the queue, unless it failed transiently and has attempts left.
+#[tracing::instrument(
+    skip_all,
+    fields(
+        newsletter_issue_id = %task.newsletter_issue_id,
+        subscriber_email = %task.subscriber_email,
+    )
+)]
+async fn complete_task(
+    transaction: &mut PgTransaction,
+    task: &DeliveryTask,
+    outcome: Result<EmailReceipt, EmailError>,
+) -> Result<(), anyhow::Error> {
+    match outcome {
+        Ok(receipt) => {
+            let outcome = DeliveryOutcome::Sent(receipt.message_id);
+            record_delivery(transaction, task, &outcome).await?;
+            delete_task(transaction, task).await?;
+        }
+        Err(e @ EmailError::Permanent(_)) => {
+            tracing::error!(
+                error.cause_chain = ?e,
+                error.message = %e,
+                "The email provider rejected the issue for a confirmed subscriber. Giving up."
+            );
+            let outcome = DeliveryOutcome::FailedPermanent(e.to_string());
+            record_delivery(transaction, task, &outcome).await?;
+            delete_task(transaction, task).await?;
+        }
+        Err(e) if task.n_retries + 1 >= MAX_DELIVERY_ATTEMPTS => {
+            tracing::error!(
+                error.cause_chain = ?e,
+                error.message = %e,
+                "Failed to deliver issue to a confirmed subscriber. Giving up."
+            );
+            let outcome = DeliveryOutcome::FailedPermanent(e.to_string());
+            record_delivery(transaction, task, &outcome).await?;
+            delete_task(transaction, task).await?;
+        }
+        Err(e) => {
+            tracing::warn!(
+                error.cause_chain = ?e,
+                error.message = %e,
+                "Failed to deliver issue to a confirmed subscriber. Retrying later."
+            );
+            let retry_after = match &e {
+                EmailError::Transient { retry_after, .. } => *retry_after,
+                EmailError::Permanent(_) => None,
+            };
+            let outcome = DeliveryOutcome::FailedRetrying(e.to_string());
+            record_delivery(transaction, task, &outcome).await?;
+            postpone_task(transaction, task, retry_after).await?;
+        }
+    }
+    Ok(())
+}
+
+type PgTransaction = Transaction<'static, Postgres>;
+
+/// Queues the issue for every confirmed, unpaused subscriber in its segment,
+/// or for all of them if it has none.
+#[tracing::instrument(
+    name = "Enqueue delivery tasks",
+    skip(transaction)
+)]
+pub async fn enqueue_delivery_tasks(
+    transaction: &mut Transaction<'_, sqlx::Postgres>,
+    newsletter_issue_id: Uuid,
+) -> Result<(), anyhow::Error> {
+    let segment = sqlx::query!(
+        r#"
+        SELECT segment
+        FROM newsletter_issues
+        WHERE newsletter_issue_id = $1
+        "#,
+        newsletter_issue_id,
+    )
+    .fetch_one(transaction.deref_mut())
+    .await
+    .context("Failed to retrieve the segment of the newsletter issue.")?
+    .segment
+    .as_deref()
+    .map(Segment::parse)
+    .transpose()
+    .map_err(anyhow::Error::msg)
+    .context("The newsletter issue has an invalid segment.")?;
+
+    let mut query = QueryBuilder::new(
+        "INSERT INTO issue_delivery_queue (newsletter_issue_id, subscriber_email) SELECT ",
+    );
+    query
+        .push_bind(newsletter_issue_id)
+        .push(
+            ", s.email FROM subscriptions s WHERE s.status = 'confirmed' \
+            AND (s.paused_until IS NULL OR s.paused_until <= now())",
+        );
+    if let Some(segment) = &segment {
+        query.push(" AND ");
+        push_segment(&mut query, segment);
+    }
+    let recipient_count = query
+        .build()
+        .execute(transaction.deref_mut())
+        .await
+        .context("Failed to insert delivery tasks.")?
+        .rows_affected();
+
+    sqlx::query!(
+        r#"
+        UPDATE newsletter_issues
+        SET recipient_count = $2
+        WHERE newsletter_issue_id = $1
+        "#,
+        newsletter_issue_id,
+        i32::try_from(recipient_count).unwrap_or(i32::MAX),
+    )
+    .execute(transaction.deref_mut())
+    .await
+    .context("Failed to record the number of recipients.")?;
+
+    sqlx::query!(
+        r#"
+        INSERT INTO issue_deliveries (newsletter_issue_id, subscriber_email, status)
+        SELECT newsletter_issue_id, subscriber_email, 'queued'
+        FROM issue_delivery_queue
+        WHERE newsletter_issue_id = $1
+        ON CONFLICT DO NOTHING
+        "#,
+        newsletter_issue_id,
+    )
+    .execute(transaction.deref_mut())
+    .await
+    .context("Failed to record the queued deliveries.")?;
+    Ok(())
+}
+
+fn push_segment(query: &mut QueryBuilder<'_, Postgres>, segment: &Segment) {
+    match segment {
+        Segment::Tag(tag) => {
+            query
+                .push(
+                    "EXISTS (SELECT 1 FROM subscription_tags st \
+                    JOIN tags t ON t.tag_id = st.tag_id \
+                    WHERE st.subscriber_id = s.id AND t.name = ",
+                )
+                .push_bind(tag.as_ref().to_owned())
+                .push(")");
+        }
+        Segment::Not(inner) => {
+            query.push("NOT (");
+            push_segment(query, inner);
+            query.push(")");
+        }
+        Segment::And(left, right) | Segment::Or(left, right) => {
+            let operator = if matches!(segment, Segment::And(..)) { " AND " } else { " OR " };
+            query.push("(");
+            push_segment(query, left);
+            query.push(operator);
+            push_segment(query, right);
+            query.push(")");
+        }
+    }
+}
+
+struct DeliveryTask {
+    newsletter_issue_id: Uuid,
+    subscriber_email: String,
+    n_retries: i16,
+}
+
+#[tracing::instrument(skip_all)]
+async fn dequeue_tasks(
+    pool: &PgPool,
+    max_tasks: usize,
+) -> Result<Option<(PgTransaction, Vec<DeliveryTask>)>, anyhow::Error> {
+    let mut transaction = pool.begin().await?;
+    let tasks = sqlx::query_as!(
+        DeliveryTask,
+        r#"
+        SELECT newsletter_issue_id, subscriber_email, n_retries
+        FROM issue_delivery_queue
+        WHERE execute_after <= now()
+        FOR UPDATE
+        SKIP LOCKED
+        LIMIT $1
+        "#,
+        i64::try_from(max_tasks).unwrap_or(i64::MAX),
+    )
+    .fetch_all(transaction.deref_mut())
+    .await
+    .context("Failed to dequeue delivery tasks.")?;
+
+    if tasks.is_empty() {
+        return Ok(None);
+    }
+    Ok(Some((transaction, tasks)))
+}
+
+/// What happened to a single recipient of an issue, as shown on the
+/// delivery report.
+enum DeliveryOutcome {
+    Sent(Option<String>),
+    FailedRetrying(String),
+    FailedPermanent(String),
+    Skipped(String),
+}
+
+impl DeliveryOutcome {
+    fn status(&self) -> &'static str {
+        match self {
+            Self::Sent(_) => "sent",
+            Self::FailedRetrying(_) => "failed_retrying",
+            Self::FailedPermanent(_) => "failed_permanent",
+            Self::Skipped(_) => "skipped",
+        }
+    }
+}
+
+/// Upserts, so that tasks queued before deliveries were tracked are recorded too.
+#[tracing::instrument(skip_all)]
+async fn record_delivery(
+    transaction: &mut PgTransaction,
+    task: &DeliveryTask,
+    outcome: &DeliveryOutcome,
+) -> Result<(), anyhow::Error> {
+    let (provider_message_id, last_error) = match outcome {
+        DeliveryOutcome::Sent(message_id) => (message_id.as_deref(), None),
+        DeliveryOutcome::FailedRetrying(error)
+        | DeliveryOutcome::FailedPermanent(error)
+        | DeliveryOutcome::Skipped(error) => (None, Some(error.as_str())),
+    };
+    sqlx::query!(
+        r#"
+        INSERT INTO issue_deliveries (
+            newsletter_issue_id, subscriber_email, status,
+            provider_message_id, last_error, n_attempts, updated_at
+        )
+        VALUES ($1, $2, $3, $4, $5, $6, now())
+        ON CONFLICT (newsletter_issue_id, subscriber_email) DO UPDATE
+        SET
+            status = EXCLUDED.status,
+            provider_message_id = EXCLUDED.provider_message_id,
+            last_error = EXCLUDED.last_error,
+            n_attempts = issue_deliveries.n_attempts + 1,
+            updated_at = EXCLUDED.updated_at
+        "#,
+        task.newsletter_issue_id,
+        task.subscriber_email,
+        outcome.status(),
+        provider_message_id,
+        last_error,
+        1_i16,
+    )
+    .execute(transaction.deref_mut())
+    .await
+    .context("Failed to record the delivery status.")?;
+    Ok(())
+}
+
+#[tracing::instrument(skip_all)]
+async fn delete_task(
+    transaction: &mut PgTransaction,
+    task: &DeliveryTask,
+) -> Result<(), anyhow::Error> {
+    sqlx::query!(
+        r#"
+        DELETE FROM issue_delivery_queue
+        WHERE
+            newsletter_issue_id = $1 AND
+            subscriber_email = $2
+        "#,
+        task.newsletter_issue_id,
+        task.subscriber_email
+    )
+    .execute(transaction.deref_mut())
+    .await
+    .context("Failed to delete a delivery task.")?;
+    Ok(())
+}
+
+/// Waits at least as long as the provider asked to, through `retry_after`.
+#[tracing::instrument(skip_all)]
+async fn postpone_task(
+    transaction: &mut PgTransaction,
+    task: &DeliveryTask,
+    retry_after: Option<Duration>,
+) -> Result<(), anyhow::Error> {
+    let backoff_seconds = 2_f64
+        .powi(task.n_retries.into())
+        .max(retry_after.unwrap_or_default().as_secs_f64());
+    sqlx::query!(
+        r#"
+        UPDATE issue_delivery_queue
+        SET
+            n_retries = n_retries + 1,
+            execute_after = now() + make_interval(secs => $3)
+        WHERE
+            newsletter_issue_id = $1 AND
+            subscriber_email = $2
+        "#,
+        task.newsletter_issue_id,
+        task.subscriber_email,
+        backoff_seconds
+    )
+    .execute(transaction.deref_mut())
+    .await
+    .context("Failed to postpone a delivery task.")?;
+    Ok(())
+}
+
+struct NewsletterIssue {
+    title: String,
+    text_content: String,
+    html_content: String,
+    tracking: bool,
+}
+
+#[tracing::instrument(skip_all)]
+async fn get_issue(
+    transaction: &mut PgTransaction,
+    issue_id: Uuid,
+) -> Result<NewsletterIssue, anyhow::Error> {
+    let issue = sqlx::query_as!(
+        NewsletterIssue,
+        r#"
+        SELECT title, text_content, html_content, tracking
+        FROM newsletter_issues
+        WHERE newsletter_issue_id = $1
+        "#,
+        issue_id
+    )
+    .fetch_one(transaction.deref_mut())
+    .await
+    .context("Failed to retrieve the newsletter issue.")?;
+    Ok(issue)
+}
+
+/// Loaded once per batch and shared by every message of an issue.
+#[tracing::instrument(skip_all)]
+async fn get_attachments(
+    transaction: &mut PgTransaction,
+    issue_ids: &[Uuid],
+) -> Result<HashMap<Uuid, Vec<EmailAttachment>>, anyhow::Error> {
+    let rows = sqlx::query!(
+        r#"
+        SELECT newsletter_issue_id, file_name, content_type, content
+        FROM newsletter_issue_attachments
+        WHERE newsletter_issue_id = ANY($1)
+        ORDER BY created_at
+        "#,
+        issue_ids,
+    )
+    .fetch_all(transaction.deref_mut())
+    .await
+    .context("Failed to retrieve the attachments of the newsletter issues.")?;
+    let mut attachments: HashMap<Uuid, Vec<EmailAttachment>> = HashMap::new();
+    for row in rows {
+        attachments
+            .entry(row.newsletter_issue_id)
+            .or_default()
+            .push(EmailAttachment::new(row.file_name, row.content_type, row.content));
+    }
+    Ok(attachments)
+}
+
+struct Recipient {
+    id: Uuid,
+    name: String,
+    unsubscribe_token: String,
+    plain_text_only: bool,
+}
+
+#[tracing::instrument(skip_all)]
+async fn get_recipient(
+    transaction: &mut PgTransaction,
+    subscriber_email: &str,
+) -> Result<Option<Recipient>, anyhow::Error> {
+    let recipient = sqlx::query_as!(
+        Recipient,
+        r#"
+        SELECT id, name, unsubscribe_token, plain_text_only
+        FROM subscriptions
+        WHERE email = $1
+            AND status = 'confirmed'
+            AND (paused_until IS NULL OR paused_until <= now())
+        "#,
+        subscriber_email
+    )
+    .fetch_optional(transaction.deref_mut())
+    .await
+    .context("Failed to retrieve the recipient's details.")?;
+    Ok(recipient)
+}
@@ -0,0 +1,74 @@
+use std::{ops::DerefMut, time::Duration};
+
+use anyhow::Context;
+use sqlx::PgPool;
+use uuid::Uuid;
+
+use crate::{
+    configuration::Settings,
+    issue_delivery_worker::enqueue_delivery_tasks,
+    startup::get_connection_pool,
+};
+
+pub async fn run_scheduler_until_stopped(configuration: Settings) -> Result<(), anyhow::Error> {
+    let connection_pool = get_connection_pool(&configuration.database);
+    scheduler_loop(connection_pool).await
+}
+
+async fn scheduler_loop(pool: PgPool) -> Result<(), anyhow::Error> {
+    loop {
+        match promote_due_issues(&pool).await {
+            Ok(_) => tokio::time::sleep(Duration::from_secs(10)).await,
+            Err(_) => tokio::time::sleep(Duration::from_secs(1)).await,
+        }
+    }
+}
+
+/// Moves every scheduled issue whose `send_at` has passed into the delivery queue.
+/// Returns the number of promoted issues.
+#[tracing::instrument(skip_all, fields(n_promoted = tracing::field::Empty), err)]
+pub async fn promote_due_issues(pool: &PgPool) -> Result<usize, anyhow::Error> {
+    let mut transaction = pool.begin().await?;
+    let due_issues = sqlx::query!(
+        r#"
+        SELECT newsletter_issue_id
+        FROM newsletter_issues
+        WHERE status = 'scheduled' AND send_at <= now()
+        FOR UPDATE
+        SKIP LOCKED
+        "#,
+    )
+    .fetch_all(transaction.deref_mut())
+    .await
+    .context("Failed to retrieve due scheduled issues.")?;
+
+    for issue in &due_issues {
+        mark_issue_as_published(&mut transaction, issue.newsletter_issue_id).await?;
+        enqueue_delivery_tasks(&mut transaction, issue.newsletter_issue_id)
+            .await
+            .context("Failed to enqueue delivery tasks for a scheduled issue.")?;
+    }
+    transaction.commit().await?;
+
+    tracing::Span::current().record("n_promoted", due_issues.len());
+    Ok(due_issues.len())
+}
+
+#[tracing::instrument(skip(transaction))]
+async fn mark_issue_as_published(
+    transaction: &mut sqlx::Transaction<'_, sqlx::Postgres>,
+    newsletter_issue_id: Uuid,
+) -> Result<(), anyhow::Error> {
+    sqlx::query!(
+        r#"
+        UPDATE newsletter_issues
+        SET status = 'published', published_at = now()
+        WHERE newsletter_issue_id = $1
+        "#,
+        newsletter_issue_id
+    )
+    .execute(transaction.deref_mut())
+    .await
+    .context("Failed to mark a scheduled issue as published.")?;
+    Ok(())
+}
@@ -0,0 +1,127 @@
+//! Newsletter issues are templates, rendered once per recipient at send time.
+//!
+//! Placeholders (`{{ name }}`, `{{ unsubscribe_url }}`, `{{ issue_url }}`),
+//! conditionals (`{% if name %}...{% endif %}`) and defaults
+//! (`{{ name | default("reader") }}`) are supported. Values substituted into
+//! the HTML body are escaped.
+use std::collections::BTreeMap;
+
+use minijinja::{AutoEscape, Environment, UndefinedBehavior, Value};
+
+#[derive(Debug, thiserror::Error)]
+#[error("{0}")]
+pub struct TemplateError(#[from] minijinja::Error);
+
+/// The values available to a template. `None` leaves the variable undefined,
+/// so that `default` and `{% if %}` behave as expected.
+pub struct TemplateValues<'a> {
+    pub name: Option<&'a str>,
+    pub unsubscribe_url: Option<&'a str>,
+    pub issue_url: &'a str,
+}
+
+impl TemplateValues<'_> {
+    fn context(&self) -> BTreeMap<&'static str, Value> {
+        let mut context = BTreeMap::new();
+        context.insert("issue_url", Value::from(self.issue_url));
+        if let Some(name) = self.name {
+            context.insert("name", Value::from(name));
+        }
+        if let Some(unsubscribe_url) = self.unsubscribe_url {
+            context.insert("unsubscribe_url", Value::from(unsubscribe_url));
+        }
+        context
+    }
+}
+
+/// Checks that both bodies parse and only use known variables.
+pub fn validate(text_content: &str, html_content: &str) -> Result<(), TemplateError> {
+    let values = TemplateValues {
+        name: Some("Ursula"),
+        unsubscribe_url: Some("https://example.com/unsubscribe"),
+        issue_url: "https://example.com/issues",
+    };
+    render(text_content, &values, AutoEscape::None, UndefinedBehavior::Strict)?;
+    render(html_content, &values, AutoEscape::Html, UndefinedBehavior::Strict)?;
+    Ok(())
+}
+
+pub fn render_text(template: &str, values: &TemplateValues) -> Result<String, TemplateError> {
+    render(template, values, AutoEscape::None, UndefinedBehavior::Lenient)
+}
+
+pub fn render_html(template: &str, values: &TemplateValues) -> Result<String, TemplateError> {
+    render(template, values, AutoEscape::Html, UndefinedBehavior::Lenient)
+}
+
+fn render(
+    template: &str,
+    values: &TemplateValues,
+    auto_escape: AutoEscape,
+    undefined_behavior: UndefinedBehavior,
+) -> Result<String, TemplateError> {
+    let mut environment = Environment::new();
+    environment.set_auto_escape_callback(move |_| auto_escape);
+    environment.set_undefined_behavior(undefined_behavior);
+    environment.set_keep_trailing_newline(true);
+    Ok(environment.render_str(template, values.context())?)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use claim::{assert_err, assert_ok};
+
+    fn values() -> TemplateValues<'static> {
+        TemplateValues {
+            name: Some("Tom & Jerry"),
+            unsubscribe_url: Some("https://example.com/unsubscribe?token=abc"),
+            issue_url: "https://example.com/issues/1",
+        }
+    }
+
+    #[test]
+    fn placeholders_are_substituted() {
+        let rendered = render_text("Hi {{ name }}, read it at {{ issue_url }}", &values()).unwrap();
+        assert_eq!(rendered, "Hi Tom & Jerry, read it at https://example.com/issues/1");
+    }
+
+    #[test]
+    fn substituted_values_are_escaped_in_html() {
+        let rendered = render_html("<p>Hi {{ name }}</p>", &values()).unwrap();
+        assert_eq!(rendered, "<p>Hi Tom &amp; Jerry</p>");
+    }
+
+    #[test]
+    fn defaults_apply_to_missing_values() {
+        let values = TemplateValues { name: None, ..values() };
+        let rendered = render_text("Hi {{ name | default(\"reader\") }}", &values).unwrap();
+        assert_eq!(rendered, "Hi reader");
+    }
+
+    #[test]
+    fn conditionals_are_supported() {
+        let template = "{% if unsubscribe_url %}Leave: {{ unsubscribe_url }}{% else %}Public copy{% endif %}";
+        let values = TemplateValues { unsubscribe_url: None, ..values() };
+        assert_eq!(render_text(template, &values).unwrap(), "Public copy");
+    }
+
+    #[test]
+    fn valid_templates_pass_validation() {
+        assert_ok!(validate(
+            "Hi {{ name | default(\"reader\") }}",
+            "{% if name %}<p>Hi {{ name }}</p>{% endif %}<a href=\"{{ unsubscribe_url }}\">Bye</a>",
+        ));
+    }
+
+    #[test]
+    fn syntax_errors_are_rejected() {
+        assert_err!(validate("Hi {{ name ", "<p>Hello</p>"));
+        assert_err!(validate("Hello", "{% if name %}<p>Hello</p>"));
+    }
+
+    #[test]
+    fn unknown_variables_are_rejected() {
+        assert_err!(validate("Hi {{ nickname }}", "<p>Hello</p>"));
+    }
+}
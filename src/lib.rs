@@ -7,6 +7,11 @@ pub mod email_client;
 pub mod authentication;
 pub mod session_state;
 pub mod utils;
+pub mod issue_delivery_worker;
+pub mod issue_scheduler;
+pub mod idempotency;
+pub mod issue_template;
+pub mod tracking;
 
 
 
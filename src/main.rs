@@ -1,4 +1,10 @@
+use std::fmt::{Debug, Display};
+
+use tokio::task::JoinError;
+use zero2prod::authentication::run_lockout_sweeper_until_stopped;
 use zero2prod::configuration;
+use zero2prod::issue_delivery_worker::run_worker_until_stopped;
+use zero2prod::issue_scheduler::run_scheduler_until_stopped;
 use zero2prod::startup::Application;
 use zero2prod::telemetry;
 
@@ -13,7 +19,47 @@ async fn main() -> anyhow::Result<()> {
 
     let configuration = configuration::get_configuration().expect("Failed to read configuration.");
 
-    let application = Application::build(configuration).await?;
-    application.run_until_stopped().await?;
+    let email_client = configuration.email_client.clone().client();
+    let worker_email_client = configuration.email_client.worker_client(&email_client);
+
+    let application = Application::build(configuration.clone(), email_client).await?;
+    let application_task = tokio::spawn(application.run_until_stopped());
+    let worker_task = tokio::spawn(run_worker_until_stopped(configuration.clone(), worker_email_client));
+    let scheduler_task = tokio::spawn(run_scheduler_until_stopped(configuration.clone()));
+    let sweeper_task = tokio::spawn(run_lockout_sweeper_until_stopped(configuration));
+
+    tokio::select! {
+        o = application_task => report_exit("API", o),
+        o = worker_task => report_exit("Background worker", o),
+        o = scheduler_task => report_exit("Issue scheduler", o),
+        o = sweeper_task => report_exit("Login lockout sweeper", o),
+    };
     Ok(())
 }
+
+fn report_exit(
+    task_name: &str,
+    outcome: Result<Result<(), impl Debug + Display>, JoinError>,
+) {
+    match outcome {
+        Ok(Ok(())) => {
+            tracing::info!("{} has exited", task_name)
+        }
+        Ok(Err(e)) => {
+            tracing::error!(
+                error.cause_chain = ?e,
+                error.message = %e,
+                "{} failed",
+                task_name
+            )
+        }
+        Err(e) => {
+            tracing::error!(
+                error.cause_chain = ?e,
+                error.message = %e,
+                "{} task failed to complete",
+                task_name
+            )
+        }
+    }
+}
@@ -1,16 +1,48 @@
 use actix_web::{http::header::ContentType, web, HttpResponse};
+use actix_web_flash_messages::IncomingFlashMessages;
 use anyhow::Context;
 use sqlx::PgPool;
+use std::fmt::Write;
 use uuid::Uuid;
-use crate::{authentication::UserId, utils::e500};
+use crate::{authentication::{Role, UserId}, utils::e500};
 
 
+/// Only lists the actions the user's role allows.
 pub async fn admin_dashboard(
     user_id: web::ReqData<UserId>,
-    pool: web::Data<PgPool>
+    role: web::ReqData<Role>,
+    pool: web::Data<PgPool>,
+    flash_messages: IncomingFlashMessages,
 ) -> Result<HttpResponse, actix_web::Error> {
     let user_id = user_id.into_inner();
+    let role = role.into_inner();
+    let mut msg_html = String::new();
+    for m in flash_messages.iter() {
+        writeln!(msg_html, "<p><i>{}</i></p>", m.content()).unwrap();
+    }
     let username = get_username(*user_id, &pool).await.map_err(e500)?;
+    let username = htmlescape::encode_minimal(&username);
+    let mut actions = String::new();
+    if role >= Role::Editor {
+        actions.push_str(
+            r#"<li><a href="/admin/newsletter/drafts">Newsletter drafts</a></li>
+        <li><a href="/admin/newsletter/scheduled">Scheduled issues</a></li>
+        "#,
+        );
+    }
+    actions.push_str(r#"<li><a href="/admin/issues">Sent issues</a></li>"#);
+    if role >= Role::Editor {
+        actions.push_str(
+            r#"
+        <li><a href="/admin/tags">Subscriber tags</a></li>"#,
+        );
+    }
+    if role >= Role::Owner {
+        actions.push_str(
+            r#"
+        <li><a href="/admin/users">Users</a></li>"#,
+        );
+    }
     Ok(HttpResponse::Ok()
         .content_type(ContentType::html())
         .body(format!(
@@ -22,11 +54,14 @@ pub async fn admin_dashboard(
     <title>Admin Dashboard</title>
 </head>
 <body>
-    <p>Welcome {username}</p>
+    {msg_html}
+    <p>Welcome {username} ({role})</p>
     <p>Available actions:</p>
     <ol>
         <li><a href="/admin/password">Change password</a></li>
-        <li><a href="/admin/newsletter">Publish newsletter</a></li>
+        <li><a href="/admin/email">Change email address</a></li>
+        <li><a href="/admin/2fa">Two-factor authentication</a></li>
+        {actions}
         <li>
             <form action="/admin/logout" method="post">
                 <input type="submit" value="Logout">
@@ -56,4 +91,24 @@ pub async fn get_username(
     .context("Failed to perform query to retrieve username")?;
     
     Ok(row.username)
-}
\ No newline at end of file
+}
+
+#[tracing::instrument(name = "Get user email", skip(pool))]
+pub async fn get_user_email(
+    user_id: Uuid,
+    pool: &PgPool
+) -> Result<Option<String>, anyhow::Error> {
+    let row = sqlx::query!(
+        r#"
+        SELECT email
+        FROM users
+        WHERE user_id = $1
+        "#,
+        user_id
+    )
+    .fetch_one(pool)
+    .await
+    .context("Failed to perform query to retrieve the user email")?;
+
+    Ok(row.email)
+}
@@ -0,0 +1,64 @@
+use actix_web::{http::header::ContentType, web, HttpResponse};
+use actix_web_flash_messages::IncomingFlashMessages;
+use anyhow::Context;
+use sqlx::PgPool;
+use std::fmt::Write;
+
+use crate::{authentication::UserId, utils::e500};
+
+pub async fn change_email_form(
+    flash_messages: IncomingFlashMessages,
+    pool: web::Data<PgPool>,
+    user_id: web::ReqData<UserId>,
+) -> Result<HttpResponse, actix_web::Error> {
+    let mut msg_html = String::new();
+    for m in flash_messages.iter() {
+        writeln!(msg_html, "<p><i>{}</i></p>", m.content()).unwrap();
+    }
+    let user = sqlx::query!(
+        "SELECT email, email_confirmed_at FROM users WHERE user_id = $1",
+        *user_id.into_inner()
+    )
+    .fetch_one(pool.get_ref())
+    .await
+    .context("Failed to retrieve the admin email address.")
+    .map_err(e500)?;
+    let status_html = match (&user.email, user.email_confirmed_at) {
+        (Some(_), None) => {
+            "<p>This address is not confirmed: save it again to get a confirmation link. \
+            Password reset links are only sent to confirmed addresses.</p>"
+        }
+        _ => "",
+    };
+    let email = htmlescape::encode_attribute(user.email.as_deref().unwrap_or_default());
+
+    Ok(HttpResponse::Ok()
+    .content_type(ContentType::html())
+    .body(format!(
+        r#"<!DOCTYPE html>
+<html lang="en">
+<head>
+    <meta charset="UTF-8">
+    <meta name="viewport" content="width=device-width, initial-scale=1.0">
+    <title>Change Email Address</title>
+</head>
+<body>
+    {msg_html}
+    {status_html}
+    <form action="/admin/email" method="POST">
+        <label>Email address
+            <input
+                type="email"
+                placeholder="Enter your email address"
+                name="email"
+                value="{email}"
+            >
+        </label>
+        <br>
+        <button type="submit">Save</button>
+    </form>
+    <p><a href="/admin/dashboard"><- Back</a></p>
+</body>
+</html>"#
+    )))
+}
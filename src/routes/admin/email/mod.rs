@@ -0,0 +1,5 @@
+mod get;
+pub use get::change_email_form;
+
+mod post;
+pub use post::{change_email, confirm_email_change};
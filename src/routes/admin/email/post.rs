@@ -0,0 +1,98 @@
+use actix_web::{web, HttpResponse};
+use actix_web_flash_messages::FlashMessage;
+use anyhow::Context;
+use sqlx::PgPool;
+
+use crate::{
+    authentication::{confirm_email, create_email_confirmation_token, email_is_taken, EmailConfirmation, UserId},
+    domain::SubscriberEmail,
+    email_client::{EmailClient, EmailMessage, EmailProvider},
+    startup::ApplicationBaseUrl,
+    utils::{e500, see_other},
+};
+
+#[derive(serde::Deserialize)]
+pub struct FormData {
+    email: String,
+}
+
+/// The address only changes once the link sent to it is opened, as password
+/// reset links go to it.
+#[tracing::instrument(
+    name = "Change the admin email address",
+    skip(form, pool, email_client, base_url, user_id)
+)]
+pub async fn change_email(
+    form: web::Form<FormData>,
+    pool: web::Data<PgPool>,
+    email_client: web::Data<EmailClient>,
+    base_url: web::Data<ApplicationBaseUrl>,
+    user_id: web::ReqData<UserId>,
+) -> Result<HttpResponse, actix_web::Error> {
+    let user_id = *user_id.into_inner();
+    let Ok(email) = SubscriberEmail::parse(form.0.email) else {
+        FlashMessage::error("The email address is not valid.").send();
+        return Ok(see_other("/admin/email"));
+    };
+    if email_is_taken(email.as_ref(), Some(user_id), &pool).await.map_err(e500)? {
+        FlashMessage::error("Another account already uses that email address.").send();
+        return Ok(see_other("/admin/email"));
+    }
+    let token = create_email_confirmation_token(user_id, &email, &pool)
+        .await
+        .map_err(e500)?;
+    let confirmation_link = format!("{}/admin/email/confirm?token={}", base_url.0, token);
+    let message = EmailMessage::builder(email.clone(), "Confirm your new email address")
+        .html_content(format!(
+            "Click <a href=\"{}\">here</a> within an hour to make this the email address \
+            of your newsletter account.",
+            confirmation_link
+        ))
+        .text_content(format!(
+            "Visit {} within an hour to make this the email address of your newsletter account.",
+            confirmation_link
+        ))
+        .tag("email-confirmation")
+        .build();
+    email_client
+        .send(&message)
+        .await
+        .context("Failed to send an email address confirmation.")
+        .map_err(e500)?;
+    FlashMessage::info(format!(
+        "We have sent a confirmation link to {}. Your email address changes once you open it.",
+        htmlescape::encode_minimal(email.as_ref())
+    ))
+    .send();
+    Ok(see_other("/admin/email"))
+}
+
+#[derive(serde::Deserialize)]
+pub struct ConfirmParameters {
+    token: String,
+}
+
+#[tracing::instrument(name = "Confirm the admin email address", skip(parameters, pool, user_id))]
+pub async fn confirm_email_change(
+    parameters: web::Query<ConfirmParameters>,
+    pool: web::Data<PgPool>,
+    user_id: web::ReqData<UserId>,
+) -> Result<HttpResponse, actix_web::Error> {
+    match confirm_email(&parameters.token, *user_id.into_inner(), &pool)
+        .await
+        .map_err(e500)?
+    {
+        EmailConfirmation::Confirmed(email) => FlashMessage::info(format!(
+            "Your email address has been changed to {}.",
+            htmlescape::encode_minimal(&email)
+        )),
+        EmailConfirmation::InvalidToken => FlashMessage::error(
+            "This confirmation link is not valid. It may have expired or been replaced by a newer one.",
+        ),
+        EmailConfirmation::AddressTaken => {
+            FlashMessage::error("Another account already uses that email address.")
+        }
+    }
+    .send();
+    Ok(see_other("/admin/email"))
+}
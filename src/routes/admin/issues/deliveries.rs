@@ -0,0 +1,278 @@
+use actix_web::{http::header::ContentType, web, HttpResponse};
+use actix_web_flash_messages::{FlashMessage, IncomingFlashMessages};
+use anyhow::Context;
+use sqlx::PgPool;
+use std::{fmt::Write, ops::DerefMut};
+use uuid::Uuid;
+
+use crate::utils::{e500, see_other};
+
+const STATUSES: [&str; 5] = ["queued", "sent", "failed_retrying", "failed_permanent", "skipped"];
+
+struct FailedDelivery {
+    subscriber_email: String,
+    status: String,
+    last_error: Option<String>,
+    n_attempts: i16,
+}
+
+pub async fn issue_deliveries(
+    issue_id: web::Path<Uuid>,
+    pool: web::Data<PgPool>,
+    flash_messages: IncomingFlashMessages,
+) -> Result<HttpResponse, actix_web::Error> {
+    let issue_id = issue_id.into_inner();
+    let mut msg_html = String::new();
+    for m in flash_messages.iter() {
+        writeln!(msg_html, "<p><i>{}</i></p>", m.content()).unwrap();
+    }
+    let Some(title) = get_issue_title(&pool, issue_id).await.map_err(e500)? else {
+        return Ok(HttpResponse::NotFound().finish());
+    };
+
+    let counts = sqlx::query!(
+        r#"
+        SELECT status, COUNT(*) AS "count!"
+        FROM issue_deliveries
+        WHERE newsletter_issue_id = $1
+        GROUP BY status
+        "#,
+        issue_id,
+    )
+    .fetch_all(pool.get_ref())
+    .await
+    .context("Failed to count the deliveries of a newsletter issue.")
+    .map_err(e500)?;
+    let mut counts_html = String::new();
+    for status in STATUSES {
+        let count = counts
+            .iter()
+            .find(|c| c.status == status)
+            .map_or(0, |c| c.count);
+        writeln!(counts_html, "<li>{}: {}</li>", status_label(status), count).unwrap();
+    }
+
+    let failures = get_failed_deliveries(&pool, issue_id).await.map_err(e500)?;
+    let mut failures_html = String::new();
+    for failure in &failures {
+        writeln!(
+            failures_html,
+            "<tr><td>{}</td><td>{}</td><td>{}</td><td>{}</td></tr>",
+            htmlescape::encode_minimal(&failure.subscriber_email),
+            status_label(&failure.status),
+            failure.n_attempts,
+            htmlescape::encode_minimal(failure.last_error.as_deref().unwrap_or("")),
+        )
+        .unwrap();
+    }
+    if failures.is_empty() {
+        failures_html.push_str(r#"<tr><td colspan="4">No failed deliveries.</td></tr>"#);
+    }
+
+    Ok(HttpResponse::Ok()
+        .content_type(ContentType::html())
+        .body(format!(
+            r#"<!DOCTYPE html>
+<html lang="en">
+<head>
+    <meta charset="UTF-8">
+    <meta name="viewport" content="width=device-width, initial-scale=1.0">
+    <title>Deliveries: {title}</title>
+</head>
+<body>
+    {msg_html}
+    <h1>{title}</h1>
+    <ul>
+        {counts_html}
+    </ul>
+    <table>
+        <tr><th>Email</th><th>Status</th><th>Attempts</th><th>Last error</th></tr>
+        {failures_html}
+    </table>
+    <form action="/admin/issues/{issue_id}/retry" method="post">
+        <button type="submit">Retry permanently failed deliveries</button>
+    </form>
+    <p><a href="/admin/issues/{issue_id}/failures.csv">Export failures as CSV</a></p>
+    <p><a href="/admin/issues/{issue_id}/stats">Engagement</a></p>
+    <p><a href="/admin/issues"><- Back</a></p>
+</body>
+</html>"#,
+            title = htmlescape::encode_minimal(&title),
+        )))
+}
+
+/// Puts every permanently failed delivery back on the queue. Deliveries that
+/// are still being retried are already queued and are left alone.
+#[tracing::instrument(name = "Retry failed deliveries", skip(pool))]
+pub async fn retry_failed_deliveries(
+    issue_id: web::Path<Uuid>,
+    pool: web::Data<PgPool>,
+) -> Result<HttpResponse, actix_web::Error> {
+    let issue_id = issue_id.into_inner();
+    let report_url = format!("/admin/issues/{}", issue_id);
+    let mut transaction = pool
+        .begin()
+        .await
+        .context("Failed to acquire a Postgres connection from the pool.")
+        .map_err(e500)?;
+    sqlx::query!(
+        r#"
+        INSERT INTO issue_delivery_queue (newsletter_issue_id, subscriber_email)
+        SELECT newsletter_issue_id, subscriber_email
+        FROM issue_deliveries
+        WHERE newsletter_issue_id = $1 AND status = 'failed_permanent'
+        ON CONFLICT DO NOTHING
+        "#,
+        issue_id,
+    )
+    .execute(transaction.deref_mut())
+    .await
+    .context("Failed to requeue failed deliveries.")
+    .map_err(e500)?;
+    let retried = sqlx::query!(
+        r#"
+        UPDATE issue_deliveries
+        SET status = 'queued', updated_at = now()
+        WHERE newsletter_issue_id = $1 AND status = 'failed_permanent'
+        "#,
+        issue_id,
+    )
+    .execute(transaction.deref_mut())
+    .await
+    .context("Failed to mark failed deliveries as queued.")
+    .map_err(e500)?
+    .rows_affected();
+    transaction
+        .commit()
+        .await
+        .context("Failed to commit SQL transaction to retry failed deliveries.")
+        .map_err(e500)?;
+
+    if retried > 0 {
+        FlashMessage::info(format!("{} failed deliveries have been queued again.", retried)).send();
+    } else {
+        FlashMessage::info("There are no failed deliveries to retry.").send();
+    }
+    Ok(see_other(&report_url))
+}
+
+pub async fn failed_deliveries_csv(
+    issue_id: web::Path<Uuid>,
+    pool: web::Data<PgPool>,
+) -> Result<HttpResponse, actix_web::Error> {
+    let issue_id = issue_id.into_inner();
+    if get_issue_title(&pool, issue_id).await.map_err(e500)?.is_none() {
+        return Ok(HttpResponse::NotFound().finish());
+    }
+    let failures = get_failed_deliveries(&pool, issue_id).await.map_err(e500)?;
+    let mut csv = String::from("email,status,attempts,last_error\r\n");
+    for failure in &failures {
+        write!(
+            csv,
+            "{},{},{},{}\r\n",
+            csv_field(&failure.subscriber_email),
+            failure.status,
+            failure.n_attempts,
+            csv_field(failure.last_error.as_deref().unwrap_or("")),
+        )
+        .unwrap();
+    }
+
+    Ok(HttpResponse::Ok()
+        .content_type("text/csv; charset=utf-8")
+        .insert_header((
+            "Content-Disposition",
+            format!(r#"attachment; filename="issue-{}-failures.csv""#, issue_id),
+        ))
+        .body(csv))
+}
+
+fn status_label(status: &str) -> &str {
+    match status {
+        "queued" => "Queued",
+        "sent" => "Sent",
+        "failed_retrying" => "Failed, retrying",
+        "failed_permanent" => "Failed permanently",
+        "skipped" => "Skipped",
+        other => other,
+    }
+}
+
+/// Quotes a field if it contains a separator, a quote or a line break (RFC 4180).
+/// Fields that a spreadsheet would run as a formula are prefixed with `'`:
+/// error messages come from the provider and addresses from the public.
+fn csv_field(value: &str) -> String {
+    let value = if value.starts_with(['=', '+', '-', '@', '\t', '\r']) {
+        format!("'{}", value)
+    } else {
+        value.to_string()
+    };
+    if value.contains([',', '"', '\r', '\n']) {
+        format!("\"{}\"", value.replace('"', "\"\""))
+    } else {
+        value
+    }
+}
+
+#[tracing::instrument(name = "Get newsletter issue title", skip(pool))]
+async fn get_issue_title(pool: &PgPool, issue_id: Uuid) -> Result<Option<String>, anyhow::Error> {
+    let issue = sqlx::query!(
+        r#"
+        SELECT title
+        FROM newsletter_issues
+        WHERE newsletter_issue_id = $1
+        "#,
+        issue_id,
+    )
+    .fetch_optional(pool)
+    .await
+    .context("Failed to retrieve a newsletter issue.")?;
+    Ok(issue.map(|issue| issue.title))
+}
+
+#[tracing::instrument(name = "Get failed deliveries", skip(pool))]
+async fn get_failed_deliveries(
+    pool: &PgPool,
+    issue_id: Uuid,
+) -> Result<Vec<FailedDelivery>, anyhow::Error> {
+    let failures = sqlx::query_as!(
+        FailedDelivery,
+        r#"
+        SELECT subscriber_email, status, last_error, n_attempts
+        FROM issue_deliveries
+        WHERE newsletter_issue_id = $1
+            AND status IN ('failed_retrying', 'failed_permanent')
+        ORDER BY subscriber_email
+        "#,
+        issue_id,
+    )
+    .fetch_all(pool)
+    .await
+    .context("Failed to retrieve the failed deliveries of a newsletter issue.")?;
+    Ok(failures)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::csv_field;
+
+    #[test]
+    fn plain_csv_fields_are_left_alone() {
+        assert_eq!(csv_field("ursula@example.com"), "ursula@example.com");
+    }
+
+    #[test]
+    fn csv_fields_with_separators_or_quotes_are_quoted() {
+        assert_eq!(csv_field("550, \"mailbox\"\nfull"), "\"550, \"\"mailbox\"\"\nfull\"");
+    }
+
+    #[test]
+    fn csv_fields_that_look_like_formulas_are_neutralised() {
+        assert_eq!(csv_field("=HYPERLINK(\"http://evil.example\")"), "\"'=HYPERLINK(\"\"http://evil.example\"\")\"");
+        assert_eq!(csv_field("+1+1"), "'+1+1");
+        assert_eq!(csv_field("-2+3"), "'-2+3");
+        assert_eq!(csv_field("@SUM(A1)"), "'@SUM(A1)");
+        assert_eq!(csv_field("\t=1"), "'\t=1");
+        assert_eq!(csv_field("\r=1"), "\"'\r=1\"");
+    }
+}
@@ -0,0 +1,64 @@
+use actix_web::{http::header::ContentType, web, HttpResponse};
+use anyhow::Context;
+use sqlx::PgPool;
+use std::fmt::Write;
+
+use crate::utils::e500;
+
+pub async fn sent_issues(pool: web::Data<PgPool>) -> Result<HttpResponse, actix_web::Error> {
+    let issues = sqlx::query!(
+        r#"
+        SELECT newsletter_issue_id, title, published_at AS "published_at!", tracking
+        FROM newsletter_issues
+        WHERE status = 'published'
+        ORDER BY published_at DESC
+        "#,
+    )
+    .fetch_all(pool.get_ref())
+    .await
+    .context("Failed to retrieve published newsletter issues.")
+    .map_err(e500)?;
+    let mut rows_html = String::new();
+    for issue in &issues {
+        write!(
+            rows_html,
+            r#"<li>{} - {} - <a href="/admin/issues/{}">Deliveries</a>"#,
+            htmlescape::encode_minimal(&issue.title),
+            issue.published_at.format("%Y-%m-%d %H:%M UTC"),
+            issue.newsletter_issue_id,
+        )
+        .unwrap();
+        if issue.tracking {
+            write!(
+                rows_html,
+                r#" - <a href="/admin/issues/{}/stats">Engagement</a>"#,
+                issue.newsletter_issue_id,
+            )
+            .unwrap();
+        }
+        rows_html.push_str("</li>\n");
+    }
+    if issues.is_empty() {
+        rows_html.push_str("<li>No issues have been sent yet.</li>");
+    }
+
+    Ok(HttpResponse::Ok()
+        .content_type(ContentType::html())
+        .body(format!(
+            r#"<!DOCTYPE html>
+<html lang="en">
+<head>
+    <meta charset="UTF-8">
+    <meta name="viewport" content="width=device-width, initial-scale=1.0">
+    <title>Sent Newsletter Issues</title>
+</head>
+<body>
+    <p>Sent issues:</p>
+    <ul>
+        {rows_html}
+    </ul>
+    <p><a href="/admin/dashboard"><- Back</a></p>
+</body>
+</html>"#,
+        )))
+}
@@ -0,0 +1,7 @@
+mod deliveries;
+mod list;
+mod stats;
+
+pub use deliveries::{failed_deliveries_csv, issue_deliveries, retry_failed_deliveries};
+pub use list::sent_issues;
+pub use stats::issue_stats;
@@ -0,0 +1,113 @@
+use actix_web::{http::header::ContentType, web, HttpResponse};
+use anyhow::Context;
+use sqlx::PgPool;
+use std::fmt::Write;
+use uuid::Uuid;
+
+use crate::utils::e500;
+
+/// Unique opens and clicks count subscribers, not events: a subscriber
+/// opening an issue twice is one open.
+pub async fn issue_stats(
+    issue_id: web::Path<Uuid>,
+    pool: web::Data<PgPool>,
+) -> Result<HttpResponse, actix_web::Error> {
+    let issue_id = issue_id.into_inner();
+    let issue = sqlx::query!(
+        r#"
+        SELECT
+            i.title,
+            i.tracking,
+            i.recipient_count,
+            COUNT(DISTINCT e.subscriber_id) FILTER (WHERE e.event_type = 'open') AS "unique_opens!",
+            COUNT(DISTINCT e.subscriber_id) FILTER (WHERE e.event_type = 'click') AS "unique_clicks!"
+        FROM newsletter_issues i
+        LEFT JOIN tracking_events e ON e.newsletter_issue_id = i.newsletter_issue_id
+        WHERE i.newsletter_issue_id = $1
+        GROUP BY i.newsletter_issue_id
+        "#,
+        issue_id,
+    )
+    .fetch_optional(pool.get_ref())
+    .await
+    .context("Failed to retrieve the engagement of a newsletter issue.")
+    .map_err(e500)?;
+    let Some(issue) = issue else {
+        return Ok(HttpResponse::NotFound().finish());
+    };
+
+    let links = sqlx::query!(
+        r#"
+        SELECT
+            url AS "url!",
+            COUNT(*) AS "clicks!",
+            COUNT(DISTINCT subscriber_id) AS "unique_clicks!"
+        FROM tracking_events
+        WHERE newsletter_issue_id = $1 AND event_type = 'click'
+        GROUP BY url
+        ORDER BY COUNT(*) DESC, url
+        "#,
+        issue_id,
+    )
+    .fetch_all(pool.get_ref())
+    .await
+    .context("Failed to retrieve the clicks of a newsletter issue.")
+    .map_err(e500)?;
+    let mut links_html = String::new();
+    for link in &links {
+        writeln!(
+            links_html,
+            "<tr><td>{}</td><td>{}</td><td>{}</td></tr>",
+            htmlescape::encode_minimal(&link.url),
+            link.clicks,
+            link.unique_clicks,
+        )
+        .unwrap();
+    }
+    if links.is_empty() {
+        links_html.push_str(r#"<tr><td colspan="3">No clicks yet.</td></tr>"#);
+    }
+
+    let recipients = issue.recipient_count.unwrap_or(0);
+    let click_through_rate = if recipients > 0 {
+        format!("{:.1}%", 100.0 * issue.unique_clicks as f64 / f64::from(recipients))
+    } else {
+        "n/a".into()
+    };
+    let tracking_note = if issue.tracking {
+        ""
+    } else {
+        "<p>Engagement tracking was not enabled for this issue.</p>"
+    };
+
+    Ok(HttpResponse::Ok()
+        .content_type(ContentType::html())
+        .body(format!(
+            r#"<!DOCTYPE html>
+<html lang="en">
+<head>
+    <meta charset="UTF-8">
+    <meta name="viewport" content="width=device-width, initial-scale=1.0">
+    <title>Engagement: {title}</title>
+</head>
+<body>
+    <h1>{title}</h1>
+    {tracking_note}
+    <ul>
+        <li>Recipients: {recipients}</li>
+        <li>Unique opens: {unique_opens}</li>
+        <li>Unique clicks: {unique_clicks}</li>
+        <li>Click-through rate: {click_through_rate}</li>
+    </ul>
+    <table>
+        <tr><th>Link</th><th>Clicks</th><th>Unique clicks</th></tr>
+        {links_html}
+    </table>
+    <p><a href="/admin/issues"><- Back</a></p>
+</body>
+</html>"#,
+            title = htmlescape::encode_minimal(&issue.title),
+            unique_opens = issue.unique_opens,
+            unique_clicks = issue.unique_clicks,
+        )))
+}
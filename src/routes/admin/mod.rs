@@ -1,9 +1,19 @@
 mod dashboard;
 mod logout;
 mod password;
+mod email;
+mod issues;
 mod newsletter;
+mod tags;
+mod two_factor;
+mod users;
 
-pub use dashboard::admin_dashboard;
+pub use dashboard::{admin_dashboard, get_username};
 pub use logout::logout;
 pub use password::*;
-pub use newsletter::*;
\ No newline at end of file
+pub use email::*;
+pub use issues::*;
+pub use newsletter::*;
+pub use tags::*;
+pub use two_factor::*;
+pub use users::*;
\ No newline at end of file
@@ -0,0 +1,244 @@
+use actix_web::{http::header::ContentType, web, HttpResponse};
+use actix_web_flash_messages::IncomingFlashMessages;
+use anyhow::Context;
+use sqlx::PgPool;
+use std::fmt::Write;
+use uuid::Uuid;
+
+use crate::{startup::ApplicationBaseUrl, utils::e500};
+use super::{get_draft, get_draft_attachment_name, render_sample};
+
+pub async fn list_drafts(
+    pool: web::Data<PgPool>,
+    flash_messages: IncomingFlashMessages,
+) -> Result<HttpResponse, actix_web::Error> {
+    let mut msg_html = String::new();
+    for m in flash_messages.iter() {
+        writeln!(msg_html, "<p><i>{}</i></p>", m.content()).unwrap();
+    }
+
+    let drafts = sqlx::query!(
+        r#"
+        SELECT newsletter_issue_id, title
+        FROM newsletter_issues
+        WHERE status = 'draft'
+        ORDER BY title
+        "#,
+    )
+    .fetch_all(pool.get_ref())
+    .await
+    .context("Failed to retrieve newsletter drafts.")
+    .map_err(e500)?;
+    let mut rows_html = String::new();
+    for draft in &drafts {
+        writeln!(
+            rows_html,
+            r#"<li><a href="/admin/newsletter/drafts/{}">{}</a></li>"#,
+            draft.newsletter_issue_id,
+            htmlescape::encode_minimal(&draft.title),
+        )
+        .unwrap();
+    }
+    if drafts.is_empty() {
+        rows_html.push_str("<li>There are no drafts.</li>");
+    }
+
+    Ok(HttpResponse::Ok()
+        .content_type(ContentType::html())
+        .body(format!(
+            r#"<!DOCTYPE html>
+<html lang="en">
+<head>
+    <meta charset="UTF-8">
+    <meta name="viewport" content="width=device-width, initial-scale=1.0">
+    <title>Newsletter Drafts</title>
+</head>
+<body>
+    {msg_html}
+    <p>Drafts:</p>
+    <ul>
+        {rows_html}
+    </ul>
+    <p>New draft:</p>
+    {form_html}
+    <p><a href="/admin/dashboard"><- Back</a></p>
+</body>
+</html>"#,
+            form_html = draft_form("/admin/newsletter/drafts", "", "", "", None, "Save draft"),
+        )))
+}
+
+pub async fn edit_draft_form(
+    draft_id: web::Path<Uuid>,
+    pool: web::Data<PgPool>,
+    flash_messages: IncomingFlashMessages,
+) -> Result<HttpResponse, actix_web::Error> {
+    let mut msg_html = String::new();
+    for m in flash_messages.iter() {
+        writeln!(msg_html, "<p><i>{}</i></p>", m.content()).unwrap();
+    }
+    let Some(draft) = get_draft(&pool, draft_id.into_inner()).await.map_err(e500)? else {
+        return Ok(HttpResponse::NotFound().finish());
+    };
+    let attachment_name = get_draft_attachment_name(&pool, draft.newsletter_issue_id)
+        .await
+        .map_err(e500)?;
+    let draft_url = format!("/admin/newsletter/drafts/{}", draft.newsletter_issue_id);
+    let idempotency_key = Uuid::new_v4();
+
+    Ok(HttpResponse::Ok()
+        .content_type(ContentType::html())
+        .body(format!(
+            r#"<!DOCTYPE html>
+<html lang="en">
+<head>
+    <meta charset="UTF-8">
+    <meta name="viewport" content="width=device-width, initial-scale=1.0">
+    <title>Edit Newsletter Draft</title>
+</head>
+<body>
+    {msg_html}
+    {form_html}
+    <p><a href="{draft_url}/preview">Preview</a></p>
+    <form action="{draft_url}/test" method="post">
+        <button type="submit">Send test to me</button>
+    </form>
+    <form action="{draft_url}/publish" method="post">
+        <label>Send at (leave empty to send now):<br>
+            <input type="datetime-local" name="send_at">
+        </label>
+        <label>Timezone:
+            <input type="text" name="timezone" value="UTC" placeholder="e.g. Europe/Rome">
+        </label>
+        <br>
+        <label>Segment (leave empty to send to everyone):<br>
+            <input type="text" name="segment" placeholder="e.g. tag:rust AND NOT tag:beta">
+        </label>
+        <br>
+        <label>
+            <input type="checkbox" name="tracking" value="on">
+            Track opens and clicks
+        </label>
+        <br>
+        <input hidden type="text" name="idempotency_key" value="{idempotency_key}">
+        <button type="submit">Publish</button>
+    </form>
+    <p><a href="/admin/newsletter/drafts"><- Back</a></p>
+</body>
+</html>"#,
+            form_html = draft_form(
+                &draft_url,
+                &draft.title,
+                &draft.text_content,
+                &draft.html_content,
+                attachment_name.as_deref(),
+                "Save changes",
+            ),
+        )))
+}
+
+/// The HTML body goes into a sandboxed `iframe` so that its styles and scripts
+/// cannot interfere with the admin area.
+pub async fn draft_preview(
+    draft_id: web::Path<Uuid>,
+    pool: web::Data<PgPool>,
+    base_url: web::Data<ApplicationBaseUrl>,
+) -> Result<HttpResponse, actix_web::Error> {
+    let Some(draft) = get_draft(&pool, draft_id.into_inner()).await.map_err(e500)? else {
+        return Ok(HttpResponse::NotFound().finish());
+    };
+    let (html_content, text_content) = match render_sample(&draft, &base_url.0) {
+        Ok(rendered) => rendered,
+        Err(e) => {
+            let error = format!("The draft is not a valid template: {}", e);
+            (error.clone(), error)
+        }
+    };
+
+    Ok(HttpResponse::Ok()
+        .content_type(ContentType::html())
+        .body(format!(
+            r#"<!DOCTYPE html>
+<html lang="en">
+<head>
+    <meta charset="UTF-8">
+    <meta name="viewport" content="width=device-width, initial-scale=1.0">
+    <title>Preview: {title}</title>
+</head>
+<body>
+    <h1>{title}</h1>
+    <h2>HTML</h2>
+    <iframe sandbox srcdoc="{html_content}" width="100%" height="600"></iframe>
+    <h2>Plain text</h2>
+    <pre>{text_content}</pre>
+    <p><a href="/admin/newsletter/drafts/{draft_id}"><- Back</a></p>
+</body>
+</html>"#,
+            title = htmlescape::encode_minimal(&draft.title),
+            html_content = htmlescape::encode_attribute(&html_content),
+            text_content = htmlescape::encode_minimal(&text_content),
+            draft_id = draft.newsletter_issue_id,
+        )))
+}
+
+fn draft_form(
+    action: &str,
+    title: &str,
+    text_content: &str,
+    html_content: &str,
+    attachment_name: Option<&str>,
+    submit_label: &str,
+) -> String {
+    let attachment_html = match attachment_name {
+        Some(file_name) => format!(
+            r#"<p>Attachment: {}</p>
+        <label>
+            <input type="checkbox" name="remove_attachment" value="on">
+            Remove the attachment
+        </label>
+        <br>
+        <label>Replace the attachment (PDF, up to 5 MB):"#,
+            htmlescape::encode_minimal(file_name)
+        ),
+        None => "<label>Attachment (PDF, up to 5 MB):".to_string(),
+    };
+    format!(
+        r#"<form action="{action}" method="post" enctype="multipart/form-data">
+        <label>Title:<br>
+            <input
+                type="text"
+                placeholder="Enter the issue title"
+                name="title"
+                value="{title}"
+            >
+        </label>
+        <br>
+        <label>Plain text content:<br>
+            <textarea
+                placeholder="Enter the content in plain text"
+                name="text_content"
+                rows="20"
+                cols="50"
+            >{text_content}</textarea>
+        </label>
+        <br>
+        <label>HTML content:<br>
+            <textarea
+                placeholder="Enter the content in HTML format"
+                name="html_content"
+                rows="20"
+                cols="50"
+            >{html_content}</textarea>
+        </label>
+        <br>
+        {attachment_html}
+            <input type="file" name="attachment" accept="application/pdf">
+        </label>
+        <br>
+        <button type="submit">{submit_label}</button>
+    </form>"#,
+        title = htmlescape::encode_attribute(title),
+        text_content = htmlescape::encode_minimal(text_content),
+        html_content = htmlescape::encode_minimal(html_content),
+    )
+}
@@ -0,0 +1,65 @@
+mod get;
+mod post;
+
+pub use get::{draft_preview, edit_draft_form, list_drafts};
+pub use post::{create_draft, publish_draft, send_test_draft, update_draft};
+
+use anyhow::Context;
+use sqlx::PgPool;
+use uuid::Uuid;
+
+use crate::issue_template::{render_html, render_text, TemplateValues};
+
+struct Draft {
+    newsletter_issue_id: Uuid,
+    title: String,
+    text_content: String,
+    html_content: String,
+}
+
+/// Returns `None` if the issue does not exist or is no longer a draft.
+#[tracing::instrument(name = "Get newsletter draft", skip(pool))]
+async fn get_draft(pool: &PgPool, draft_id: Uuid) -> Result<Option<Draft>, anyhow::Error> {
+    let draft = sqlx::query_as!(
+        Draft,
+        r#"
+        SELECT newsletter_issue_id, title, text_content, html_content
+        FROM newsletter_issues
+        WHERE newsletter_issue_id = $1 AND status = 'draft'
+        "#,
+        draft_id
+    )
+    .fetch_optional(pool)
+    .await
+    .context("Failed to retrieve a newsletter draft.")?;
+    Ok(draft)
+}
+
+#[tracing::instrument(name = "Get newsletter draft attachment name", skip(pool))]
+async fn get_draft_attachment_name(pool: &PgPool, draft_id: Uuid) -> Result<Option<String>, anyhow::Error> {
+    let file_name = sqlx::query_scalar!(
+        "SELECT file_name FROM newsletter_issue_attachments WHERE newsletter_issue_id = $1",
+        draft_id
+    )
+    .fetch_optional(pool)
+    .await
+    .context("Failed to retrieve the attachment of a newsletter draft.")?;
+    Ok(file_name)
+}
+
+/// Stands in for the subscriber's name in previews and test emails, so that
+/// no account name ends up in them.
+const SAMPLE_NAME: &str = "Subscriber";
+
+/// Renders the draft the way a subscriber called `SAMPLE_NAME` would receive it.
+fn render_sample(draft: &Draft, base_url: &str) -> Result<(String, String), anyhow::Error> {
+    let issue_url = format!("{}/issues/{}", base_url, draft.newsletter_issue_id);
+    let values = TemplateValues {
+        name: Some(SAMPLE_NAME),
+        unsubscribe_url: None,
+        issue_url: &issue_url,
+    };
+    let html_content = render_html(&draft.html_content, &values)?;
+    let text_content = render_text(&draft.text_content, &values)?;
+    Ok((html_content, text_content))
+}
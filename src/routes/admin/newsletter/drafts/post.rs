@@ -0,0 +1,378 @@
+use std::ops::DerefMut;
+
+use actix_multipart::form::{bytes::Bytes, text::Text, MultipartForm};
+use actix_web::{dev::Payload, http::header::CONTENT_TYPE, web, FromRequest, HttpRequest, HttpResponse};
+use actix_web_flash_messages::FlashMessage;
+use anyhow::Context;
+use chrono::{DateTime, Utc};
+use sqlx::{PgPool, Transaction};
+use uuid::Uuid;
+
+use crate::{
+    authentication::UserId,
+    domain::SubscriberEmail,
+    email_client::{EmailClient, EmailProvider},
+    idempotency::{save_response, try_processing, IdempotencyKey, NextAction},
+    issue_delivery_worker::enqueue_delivery_tasks,
+    routes::admin::dashboard::get_user_email,
+    startup::ApplicationBaseUrl,
+    utils::{e400, e500, see_other},
+};
+use super::{
+    get_draft,
+    render_sample,
+    super::post::{
+        insert_attachment,
+        parse_send_at,
+        success_message,
+        validate_attachment,
+        validate_segment,
+        validate_template,
+        NewAttachment,
+    },
+};
+
+#[derive(serde::Deserialize)]
+pub struct DraftFormData {
+    title: String,
+    text_content: String,
+    html_content: String,
+    remove_attachment: Option<String>,
+}
+
+/// The draft forms are sent as `multipart/form-data`, so that they can carry
+/// an attachment. URL-encoded submissions without one are still accepted.
+#[derive(MultipartForm)]
+pub struct DraftUpload {
+    title: Text<String>,
+    text_content: Text<String>,
+    html_content: Text<String>,
+    remove_attachment: Option<Text<String>>,
+    #[multipart(limit = "10 MiB")]
+    attachment: Option<Bytes>,
+}
+
+impl DraftUpload {
+    fn into_parts(self) -> (DraftFormData, Option<Bytes>) {
+        let form = DraftFormData {
+            title: self.title.into_inner(),
+            text_content: self.text_content.into_inner(),
+            html_content: self.html_content.into_inner(),
+            remove_attachment: self.remove_attachment.map(Text::into_inner),
+        };
+        (form, self.attachment)
+    }
+}
+
+async fn extract_draft_form(
+    request: &HttpRequest,
+    mut payload: Payload,
+) -> Result<(DraftFormData, Option<Bytes>), actix_web::Error> {
+    let is_multipart = request
+        .headers()
+        .get(CONTENT_TYPE)
+        .and_then(|value| value.to_str().ok())
+        .is_some_and(|value| value.starts_with("multipart/form-data"));
+    if is_multipart {
+        let upload = MultipartForm::<DraftUpload>::from_request(request, &mut payload).await?;
+        Ok(upload.into_inner().into_parts())
+    } else {
+        let form = web::Form::<DraftFormData>::from_request(request, &mut payload).await?;
+        Ok((form.into_inner(), None))
+    }
+}
+
+#[tracing::instrument(name = "Create a newsletter draft", skip(request, payload, pool, user_id))]
+pub async fn create_draft(
+    request: HttpRequest,
+    payload: web::Payload,
+    pool: web::Data<PgPool>,
+    user_id: web::ReqData<UserId>,
+) -> Result<HttpResponse, actix_web::Error> {
+    let (form, attachment) = extract_draft_form(&request, payload.into_inner()).await?;
+    let attachment = match validate_attachment(attachment) {
+        Ok(attachment) => attachment,
+        Err(e) => {
+            FlashMessage::error(e).send();
+            return Ok(see_other("/admin/newsletter/drafts"));
+        }
+    };
+    let draft_id = Uuid::new_v4();
+    let mut transaction = pool.begin().await.map_err(e500)?;
+    sqlx::query!(
+        r#"
+        INSERT INTO newsletter_issues (
+            newsletter_issue_id,
+            title,
+            text_content,
+            html_content,
+            status,
+            author_id
+        )
+        VALUES ($1, $2, $3, $4, 'draft', $5)
+        "#,
+        draft_id,
+        form.title,
+        form.text_content,
+        form.html_content,
+        *user_id.into_inner(),
+    )
+    .execute(transaction.deref_mut())
+    .await
+    .context("Failed to store a newsletter draft.")
+    .map_err(e500)?;
+    if let Some(attachment) = &attachment {
+        insert_attachment(&mut transaction, draft_id, attachment)
+            .await
+            .context("Failed to store the attachment of a newsletter draft.")
+            .map_err(e500)?;
+    }
+    transaction
+        .commit()
+        .await
+        .context("Failed to commit SQL transaction to store a newsletter draft.")
+        .map_err(e500)?;
+
+    FlashMessage::info("The draft has been saved.").send();
+    Ok(see_other(&format!("/admin/newsletter/drafts/{}", draft_id)))
+}
+
+/// A new upload replaces the draft's attachment; without one, the attachment
+/// is kept unless `remove_attachment` is ticked.
+#[tracing::instrument(name = "Update a newsletter draft", skip(request, payload, pool))]
+pub async fn update_draft(
+    draft_id: web::Path<Uuid>,
+    request: HttpRequest,
+    payload: web::Payload,
+    pool: web::Data<PgPool>,
+) -> Result<HttpResponse, actix_web::Error> {
+    let draft_id = draft_id.into_inner();
+    let draft_url = format!("/admin/newsletter/drafts/{}", draft_id);
+    let (form, attachment) = extract_draft_form(&request, payload.into_inner()).await?;
+    let attachment = match validate_attachment(attachment) {
+        Ok(attachment) => attachment,
+        Err(e) => {
+            FlashMessage::error(e).send();
+            return Ok(see_other(&draft_url));
+        }
+    };
+    let mut transaction = pool.begin().await.map_err(e500)?;
+    let updated = sqlx::query!(
+        r#"
+        UPDATE newsletter_issues
+        SET title = $2, text_content = $3, html_content = $4
+        WHERE newsletter_issue_id = $1 AND status = 'draft'
+        "#,
+        draft_id,
+        form.title,
+        form.text_content,
+        form.html_content,
+    )
+    .execute(transaction.deref_mut())
+    .await
+    .context("Failed to update a newsletter draft.")
+    .map_err(e500)?
+    .rows_affected();
+    if updated == 0 {
+        return Ok(HttpResponse::NotFound().finish());
+    }
+    if attachment.is_some() || form.remove_attachment.is_some() {
+        replace_draft_attachment(&mut transaction, draft_id, attachment.as_ref())
+            .await
+            .context("Failed to replace the attachment of a newsletter draft.")
+            .map_err(e500)?;
+    }
+    transaction
+        .commit()
+        .await
+        .context("Failed to commit SQL transaction to update a newsletter draft.")
+        .map_err(e500)?;
+
+    FlashMessage::info("The draft has been saved.").send();
+    Ok(see_other(&draft_url))
+}
+
+/// Drafts are issues too: their attachments are stored against the issue id,
+/// which publishing keeps, so they go out with the issue.
+async fn replace_draft_attachment(
+    transaction: &mut Transaction<'_, sqlx::Postgres>,
+    draft_id: Uuid,
+    attachment: Option<&NewAttachment>,
+) -> Result<(), sqlx::Error> {
+    sqlx::query!(
+        "DELETE FROM newsletter_issue_attachments WHERE newsletter_issue_id = $1",
+        draft_id
+    )
+    .execute(transaction.deref_mut())
+    .await?;
+    if let Some(attachment) = attachment {
+        insert_attachment(transaction, draft_id, attachment).await?;
+    }
+    Ok(())
+}
+
+/// Delivers the draft to the logged-in admin only, bypassing the delivery queue.
+#[tracing::instrument(
+    name = "Send a test email for a newsletter draft",
+    skip(pool, email_client, base_url, user_id)
+)]
+pub async fn send_test_draft(
+    draft_id: web::Path<Uuid>,
+    pool: web::Data<PgPool>,
+    email_client: web::Data<EmailClient>,
+    base_url: web::Data<ApplicationBaseUrl>,
+    user_id: web::ReqData<UserId>,
+) -> Result<HttpResponse, actix_web::Error> {
+    let user_id = user_id.into_inner();
+    let Some(draft) = get_draft(&pool, draft_id.into_inner()).await.map_err(e500)? else {
+        return Ok(HttpResponse::NotFound().finish());
+    };
+    let draft_url = format!("/admin/newsletter/drafts/{}", draft.newsletter_issue_id);
+    if let Err(e) = validate_template(&draft.text_content, &draft.html_content) {
+        FlashMessage::error(e).send();
+        return Ok(see_other(&draft_url));
+    }
+
+    let recipient = get_user_email(*user_id, &pool)
+        .await
+        .map_err(e500)?
+        .and_then(|email| SubscriberEmail::parse(email).ok());
+    let Some(recipient) = recipient else {
+        FlashMessage::error("Set your email address before sending a test email.").send();
+        return Ok(see_other(&draft_url));
+    };
+
+    let (html_content, text_content) = render_sample(&draft, &base_url.0).map_err(e500)?;
+    email_client
+        .send_email(
+            &recipient,
+            &format!("[TEST] {}", draft.title),
+            &html_content,
+            &text_content,
+        )
+        .await
+        .context("Failed to send a test email for a newsletter draft.")
+        .map_err(e500)?;
+
+    FlashMessage::info(format!("A test email has been sent to {}.", recipient)).send();
+    Ok(see_other(&draft_url))
+}
+
+#[derive(serde::Deserialize)]
+pub struct PublishFormData {
+    idempotency_key: String,
+    send_at: Option<String>,
+    timezone: Option<String>,
+    segment: Option<String>,
+    tracking: Option<String>,
+}
+
+/// A retried or concurrent submission with the same idempotency key gets the
+/// first one's response replayed. Publishing also flips the draft's status,
+/// so a fresh submission finds no draft and cannot enqueue the issue again.
+#[tracing::instrument(name = "Publish a newsletter draft", skip(form, pool, user_id), fields(user_id = %*user_id))]
+pub async fn publish_draft(
+    draft_id: web::Path<Uuid>,
+    form: web::Form<PublishFormData>,
+    pool: web::Data<PgPool>,
+    user_id: web::ReqData<UserId>,
+) -> Result<HttpResponse, actix_web::Error> {
+    let user_id = user_id.into_inner();
+    let PublishFormData {
+        idempotency_key,
+        send_at,
+        timezone,
+        segment,
+        tracking,
+    } = form.0;
+    let idempotency_key: IdempotencyKey = idempotency_key.try_into().map_err(e400)?;
+    let draft_id = draft_id.into_inner();
+    let draft_url = format!("/admin/newsletter/drafts/{}", draft_id);
+    let send_at = match parse_send_at(send_at.as_deref(), timezone.as_deref()) {
+        Ok(send_at) => send_at,
+        Err(e) => {
+            FlashMessage::error(e).send();
+            return Ok(see_other(&draft_url));
+        }
+    };
+    let segment = match validate_segment(&pool, segment.as_deref()).await.map_err(e500)? {
+        Ok(segment) => segment,
+        Err(e) => {
+            FlashMessage::error(e).send();
+            return Ok(see_other(&draft_url));
+        }
+    };
+
+    let mut transaction = match try_processing(&pool, &idempotency_key, *user_id)
+        .await
+        .map_err(e500)?
+    {
+        NextAction::StartProcessing(transaction) => transaction,
+        NextAction::ReturnSavedResponse(saved_response) => {
+            // Describes what the first submission did, not what this one asked for.
+            let send_at = get_send_at(&pool, draft_id).await.map_err(e500)?;
+            success_message(send_at).send();
+            return Ok(saved_response);
+        }
+    };
+    let Some(draft) = get_draft(&pool, draft_id).await.map_err(e500)? else {
+        FlashMessage::error("The draft has already been published.").send();
+        return Ok(see_other("/admin/newsletter/drafts"));
+    };
+    if let Err(e) = validate_template(&draft.text_content, &draft.html_content) {
+        FlashMessage::error(e).send();
+        return Ok(see_other(&draft_url));
+    }
+    let (status, published_at) = match send_at {
+        Some(_) => ("scheduled", None),
+        None => ("published", Some(Utc::now())),
+    };
+    let published = sqlx::query!(
+        r#"
+        UPDATE newsletter_issues
+        SET status = $2, send_at = $3, published_at = $4, segment = $5, tracking = $6
+        WHERE newsletter_issue_id = $1 AND status = 'draft'
+        "#,
+        draft_id,
+        status,
+        send_at,
+        published_at,
+        segment,
+        tracking.is_some(),
+    )
+    .execute(transaction.deref_mut())
+    .await
+    .context("Failed to publish a newsletter draft.")
+    .map_err(e500)?
+    .rows_affected();
+    if published == 0 {
+        FlashMessage::error("The draft has already been published.").send();
+        return Ok(see_other("/admin/newsletter/drafts"));
+    }
+    // Scheduled issues are enqueued by the scheduler once `send_at` is reached.
+    if send_at.is_none() {
+        enqueue_delivery_tasks(&mut transaction, draft_id)
+            .await
+            .context("Failed to enqueue delivery tasks.")
+            .map_err(e500)?;
+    }
+
+    let response = see_other("/admin/newsletter/drafts");
+    let response = save_response(transaction, &idempotency_key, *user_id, response)
+        .await
+        .map_err(e500)?;
+    success_message(send_at).send();
+    Ok(response)
+}
+
+#[tracing::instrument(name = "Get the send time of an issue", skip(pool))]
+async fn get_send_at(pool: &PgPool, issue_id: Uuid) -> Result<Option<DateTime<Utc>>, anyhow::Error> {
+    let send_at = sqlx::query_scalar!(
+        "SELECT send_at FROM newsletter_issues WHERE newsletter_issue_id = $1",
+        issue_id,
+    )
+    .fetch_optional(pool)
+    .await
+    .context("Failed to retrieve the send time of a newsletter issue.")?;
+    Ok(send_at.flatten())
+}
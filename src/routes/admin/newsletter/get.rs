@@ -1,59 +0,0 @@
-use actix_web::{http::header::ContentType, HttpResponse};
-use actix_web_flash_messages::IncomingFlashMessages;
-use std::fmt::Write;
-
-
-pub async fn publish_newsletter_form(
-    flash_messages: IncomingFlashMessages,
-) -> Result<HttpResponse, actix_web::Error> {
-    let mut msg_html = String::new();
-    for m in flash_messages.iter() {
-        writeln!(msg_html, "<p><i>{}</i></p>", m.content()).unwrap();
-    }
-
-    Ok(HttpResponse::Ok()
-        .content_type(ContentType::html())
-        .body(format!(
-            r#"<!DOCTYPE html>
-<html lang="en">
-<head>
-    <meta charset="UTF-8">
-    <meta name="viewport" content="width=device-width, initial-scale=1.0">
-    <title>Publish Newsletter Issue</title>
-</head>
-<body>
-    {msg_html}
-    <form action="/admin/newsletter" method="post">
-        <label>Title:<br>
-            <input
-                type="text"
-                placeholder="Enter the issue title"
-                name="title"
-            >
-        </label>
-        <br>
-        <label>Plain text content:<br>
-            <textarea
-                placeholder="Enter the content in plain text"
-                name="text_content"
-                rows="20"
-                cols="50"
-            ></textarea>
-        </label>
-        <br>
-        <label>HTML content:<br>
-            <textarea
-                placeholder="Enter the content in HTML format"
-                name="html_content"
-                rows="20"
-                cols="50"
-            ></textarea>
-        </label>
-        <br>
-        <button type="submit">Publish</button>
-    </form>
-    <p><a href="/admin/dashboard"><- Back</a></p>
-</body>
-</html>"#,
-        )))
-}
\ No newline at end of file
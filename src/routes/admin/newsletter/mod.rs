@@ -1,5 +1,6 @@
-mod get;
+mod drafts;
 mod post;
+mod scheduled;
 
-pub use get::publish_newsletter_form;
-pub use post::publish_newsletter;
\ No newline at end of file
+pub use drafts::*;
+pub use scheduled::{cancel_scheduled_issue, scheduled_issues};
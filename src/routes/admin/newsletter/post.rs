@@ -1,82 +1,162 @@
-use actix_web::{web, HttpResponse};
+use std::ops::DerefMut;
+
+use actix_multipart::form::bytes::Bytes;
 use actix_web_flash_messages::FlashMessage;
 use anyhow::Context;
-use sqlx::PgPool;
-use crate::{authentication::UserId, domain::SubscriberEmail, email_client::EmailClient, utils::{e500, see_other}};
+use chrono::{DateTime, NaiveDateTime, TimeZone, Utc};
+use chrono_tz::Tz;
+use sqlx::{PgPool, Transaction};
+use uuid::Uuid;
+use crate::{domain::Segment, issue_template};
+
 
+/// Attachments are capped well below providers' message size limits.
+const MAX_ATTACHMENT_SIZE: usize = 5 * 1024 * 1024;
 
-#[derive(serde::Deserialize)]
-pub struct FormData {
-    title: String,
-    text_content: String,
-    html_content: String,
+/// A validated PDF attachment, ready to be stored with the issue.
+pub(super) struct NewAttachment {
+    file_name: String,
+    content: Vec<u8>,
 }
 
-#[tracing::instrument(
-    name = "Publish a newsletter",
-    skip(form, pool, email_client, user_id),
-    fields(use_id = %*user_id)
-)]
-pub async fn publish_newsletter(
-    form: web::Form<FormData>,
-    pool: web::Data<PgPool>,
-    email_client: web::Data<EmailClient>,
-    user_id: web::ReqData<UserId>,
-) -> Result<HttpResponse, actix_web::Error> {
-    let subscirbers = get_confirmed_subscriber(&pool).await.map_err(e500)?;
-    for subscriber in subscirbers {
-        match subscriber {
-            Ok(subscriber) => {
-                email_client
-                    .send_email(
-                        &subscriber.email,
-                        &form.title,
-                        &form.text_content,
-                        &form.html_content,
-                    )
-                    .await
-                    .with_context(|| {
-                        format!("Failed to send email to {}", subscriber.email)
-                    })
-                    .map_err(e500)?;
-            }
-            Err(err) => {
-                tracing::warn!("Failed to send email to subscriber: {:?}", err);
-            }
-            
+/// Browsers send an empty file field when no file is chosen: that is no attachment.
+pub(super) fn validate_attachment(attachment: Option<Bytes>) -> Result<Option<NewAttachment>, String> {
+    let Some(attachment) = attachment.filter(|a| !a.data.is_empty()) else {
+        return Ok(None);
+    };
+    let is_pdf = attachment
+        .content_type
+        .as_ref()
+        .is_some_and(|content_type| content_type.essence_str() == "application/pdf")
+        && attachment.data.starts_with(b"%PDF-");
+    if !is_pdf {
+        return Err("Attachments must be PDF files.".into());
+    }
+    if attachment.data.len() > MAX_ATTACHMENT_SIZE {
+        return Err("Attachments must be smaller than 5 MB.".into());
+    }
+    // Keep the base name only, as some browsers send the full path.
+    let file_name = attachment
+        .file_name
+        .as_deref()
+        .and_then(|name| name.rsplit(['/', '\\']).next())
+        .map(|name| name.chars().filter(|c| !c.is_control()).collect::<String>())
+        .filter(|name| !name.trim().is_empty())
+        .unwrap_or_else(|| "attachment.pdf".into());
+    Ok(Some(NewAttachment {
+        file_name,
+        content: attachment.data.to_vec(),
+    }))
+}
+
+pub(super) fn success_message(send_at: Option<DateTime<Utc>>) -> FlashMessage {
+    match send_at {
+        Some(send_at) => FlashMessage::info(format!(
+            "The newsletter issue has been scheduled for {}.",
+            send_at.format("%Y-%m-%d %H:%M UTC")
+        )),
+        None => FlashMessage::info("The newsletter issue has been published!"),
+    }
+}
+
+pub(super) fn validate_template(text_content: &str, html_content: &str) -> Result<(), String> {
+    issue_template::validate(text_content, html_content).map_err(|e| {
+        format!(
+            "The issue content is not a valid template: {}",
+            htmlescape::encode_minimal(&e.to_string())
+        )
+    })
+}
+
+/// An empty segment targets every confirmed subscriber. The `Ok` value is the
+/// trimmed expression to store, the `Err` value a message for the admin.
+pub(super) async fn validate_segment(
+    pool: &PgPool,
+    segment: Option<&str>,
+) -> Result<Result<Option<String>, String>, anyhow::Error> {
+    let raw = match segment.map(str::trim) {
+        None | Some("") => return Ok(Ok(None)),
+        Some(raw) => raw,
+    };
+    let segment = match Segment::parse(raw) {
+        Ok(segment) => segment,
+        Err(e) => {
+            return Ok(Err(format!(
+                "The segment is not valid: {}",
+                htmlescape::encode_minimal(&e)
+            )));
         }
+    };
+    let names: Vec<String> = segment.tags().iter().map(|tag| tag.to_string()).collect();
+    let known = sqlx::query_scalar!("SELECT name FROM tags WHERE name = ANY($1)", &names)
+        .fetch_all(pool)
+        .await
+        .context("Failed to look up the tags of a segment.")?;
+    if let Some(unknown) = names.iter().find(|name| !known.contains(name)) {
+        return Ok(Err(format!(
+            "The segment refers to an unknown tag: {}",
+            htmlescape::encode_minimal(unknown)
+        )));
     }
-    FlashMessage::info("The newsletter issue has been published!").send();
-    Ok(see_other("/admin/newsletter"))
+    Ok(Ok(Some(raw.to_string())))
 }
 
-struct ConfirmedSubscriber {
-    email: SubscriberEmail,
+/// `send_at` comes from a `datetime-local` input, which carries no offset:
+/// it is interpreted in `timezone` (an IANA name, UTC if omitted).
+pub(super) fn parse_send_at(
+    send_at: Option<&str>,
+    timezone: Option<&str>,
+) -> Result<Option<DateTime<Utc>>, String> {
+    let send_at = match send_at.map(str::trim) {
+        None | Some("") => return Ok(None),
+        Some(send_at) => send_at,
+    };
+    let timezone: Tz = match timezone.map(str::trim) {
+        None | Some("") => Tz::UTC,
+        Some(timezone) => timezone
+            .parse()
+            .map_err(|_| "The timezone is not a valid IANA timezone name.".to_string())?,
+    };
+    let local = NaiveDateTime::parse_from_str(send_at, "%Y-%m-%dT%H:%M")
+        .or_else(|_| NaiveDateTime::parse_from_str(send_at, "%Y-%m-%dT%H:%M:%S"))
+        .map_err(|_| "The scheduled time is not a valid date and time.".to_string())?;
+    let send_at = timezone
+        .from_local_datetime(&local)
+        .earliest()
+        .ok_or_else(|| "The scheduled time does not exist in the chosen timezone.".to_string())?
+        .with_timezone(&Utc);
+    if send_at <= Utc::now() {
+        return Err("The scheduled time must be in the future.".into());
+    }
+    Ok(Some(send_at))
 }
 
 #[tracing::instrument(
-    name = "Get confirmed subscribers",
-    skip(pool)
+    name = "Insert newsletter issue attachment",
+    skip_all
 )]
-async fn get_confirmed_subscriber(
-    pool: &PgPool
-) -> Result<Vec<Result<ConfirmedSubscriber, anyhow::Error>>, anyhow::Error> {
-    let confirmd_subscribers = sqlx::query!(
+pub(super) async fn insert_attachment(
+    transaction: &mut Transaction<'_, sqlx::Postgres>,
+    newsletter_issue_id: Uuid,
+    attachment: &NewAttachment,
+) -> Result<(), sqlx::Error> {
+    sqlx::query!(
         r#"
-        SELECT email
-        FROM subscriptions
-        WHERE status = 'confirmed'
+        INSERT INTO newsletter_issue_attachments (
+            attachment_id,
+            newsletter_issue_id,
+            file_name,
+            content_type,
+            content
+        )
+        VALUES ($1, $2, $3, 'application/pdf', $4)
         "#,
+        Uuid::new_v4(),
+        newsletter_issue_id,
+        attachment.file_name,
+        attachment.content,
     )
-    .fetch_all(pool)
-    .await?
-    .into_iter()
-    .map(|r| {
-        match SubscriberEmail::parse(r.email) {
-            Ok(email) => Ok(ConfirmedSubscriber { email }),
-            Err(err) => Err(anyhow::anyhow!(err)), 
-        }
-    })
-    .collect();
-    Ok(confirmd_subscribers)
-}
\ No newline at end of file
+    .execute(transaction.deref_mut())
+    .await?;
+    Ok(())
+}
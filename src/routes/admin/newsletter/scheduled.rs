@@ -0,0 +1,114 @@
+use actix_web::{http::header::ContentType, web, HttpResponse};
+use actix_web_flash_messages::{FlashMessage, IncomingFlashMessages};
+use anyhow::Context;
+use chrono::{DateTime, Utc};
+use sqlx::PgPool;
+use std::fmt::Write;
+use uuid::Uuid;
+
+use crate::utils::{e500, see_other};
+
+struct ScheduledIssue {
+    newsletter_issue_id: Uuid,
+    title: String,
+    send_at: Option<DateTime<Utc>>,
+}
+
+pub async fn scheduled_issues(
+    pool: web::Data<PgPool>,
+    flash_messages: IncomingFlashMessages,
+) -> Result<HttpResponse, actix_web::Error> {
+    let mut msg_html = String::new();
+    for m in flash_messages.iter() {
+        writeln!(msg_html, "<p><i>{}</i></p>", m.content()).unwrap();
+    }
+
+    let issues = get_scheduled_issues(&pool).await.map_err(e500)?;
+    let mut rows_html = String::new();
+    for issue in &issues {
+        let send_at = issue
+            .send_at
+            .map(|send_at| send_at.format("%Y-%m-%d %H:%M UTC").to_string())
+            .unwrap_or_default();
+        writeln!(
+            rows_html,
+            r#"<li>{} - {}
+            <form action="/admin/newsletter/scheduled/{}/cancel" method="post">
+                <button type="submit">Cancel</button>
+            </form>
+        </li>"#,
+            htmlescape::encode_minimal(&issue.title),
+            send_at,
+            issue.newsletter_issue_id,
+        )
+        .unwrap();
+    }
+    if issues.is_empty() {
+        rows_html.push_str("<li>There are no scheduled issues.</li>");
+    }
+
+    Ok(HttpResponse::Ok()
+        .content_type(ContentType::html())
+        .body(format!(
+            r#"<!DOCTYPE html>
+<html lang="en">
+<head>
+    <meta charset="UTF-8">
+    <meta name="viewport" content="width=device-width, initial-scale=1.0">
+    <title>Scheduled Newsletter Issues</title>
+</head>
+<body>
+    {msg_html}
+    <p>Issues waiting to be sent:</p>
+    <ul>
+        {rows_html}
+    </ul>
+    <p><a href="/admin/newsletter/drafts"><- Back</a></p>
+</body>
+</html>"#,
+        )))
+}
+
+#[tracing::instrument(name = "Cancel a scheduled newsletter issue", skip(pool))]
+pub async fn cancel_scheduled_issue(
+    issue_id: web::Path<Uuid>,
+    pool: web::Data<PgPool>,
+) -> Result<HttpResponse, actix_web::Error> {
+    let cancelled = sqlx::query!(
+        r#"
+        UPDATE newsletter_issues
+        SET status = 'cancelled'
+        WHERE newsletter_issue_id = $1 AND status = 'scheduled'
+        "#,
+        issue_id.into_inner()
+    )
+    .execute(pool.get_ref())
+    .await
+    .context("Failed to cancel a scheduled newsletter issue.")
+    .map_err(e500)?
+    .rows_affected();
+
+    if cancelled > 0 {
+        FlashMessage::info("The scheduled issue has been cancelled.").send();
+    } else {
+        FlashMessage::error("The issue is no longer scheduled, it could not be cancelled.").send();
+    }
+    Ok(see_other("/admin/newsletter/scheduled"))
+}
+
+#[tracing::instrument(name = "Get scheduled newsletter issues", skip(pool))]
+async fn get_scheduled_issues(pool: &PgPool) -> Result<Vec<ScheduledIssue>, anyhow::Error> {
+    let issues = sqlx::query_as!(
+        ScheduledIssue,
+        r#"
+        SELECT newsletter_issue_id, title, send_at
+        FROM newsletter_issues
+        WHERE status = 'scheduled'
+        ORDER BY send_at
+        "#,
+    )
+    .fetch_all(pool)
+    .await
+    .context("Failed to retrieve scheduled newsletter issues.")?;
+    Ok(issues)
+}
@@ -2,7 +2,7 @@ use actix_web::{web, HttpResponse};
 use actix_web_flash_messages::FlashMessage;
 use sqlx::PgPool;
 
-use crate::{authentication::{validate_credentials, AuthError, Credentials, UserId}, routes::admin::dashboard::get_username, utils::{e500, see_other}};
+use crate::{authentication::{validate_credentials, AuthError, Credentials, UserId}, domain::AdminPassword, routes::admin::dashboard::get_username, utils::{e500, see_other}};
 
 
 #[derive(serde::Deserialize)]
@@ -25,9 +25,10 @@ pub async fn change_password(
     }
     let username = get_username(*user_id, &pool).await.map_err(e500)?;
 
+    let current_password = form.0.current_password;
     let credentials= Credentials {
         username,
-        password: form.0.current_password,
+        password: current_password.clone(),
     };
     if let Err(e) = validate_credentials(credentials, &pool).await {
         return match e {
@@ -38,7 +39,14 @@ pub async fn change_password(
             AuthError::UnexpectedError(_) => Err(e500(e)),
         };
     }
-    crate::authentication::change_password(*user_id, form.0.new_password, &pool)
+    let new_password = match AdminPassword::parse(form.0.new_password, Some(&current_password)) {
+        Ok(new_password) => new_password,
+        Err(e) => {
+            FlashMessage::error(e).send();
+            return Ok(see_other("/admin/password"));
+        }
+    };
+    crate::authentication::change_password(*user_id, new_password, pool.get_ref())
         .await
         .map_err(e500)?;
     FlashMessage::error("Your password has been changed").send();
@@ -0,0 +1,90 @@
+use actix_web::{http::header::ContentType, web, HttpResponse};
+use actix_web_flash_messages::IncomingFlashMessages;
+use anyhow::Context;
+use sqlx::PgPool;
+use std::fmt::Write;
+
+use crate::utils::e500;
+
+pub async fn list_tags(
+    pool: web::Data<PgPool>,
+    flash_messages: IncomingFlashMessages,
+) -> Result<HttpResponse, actix_web::Error> {
+    let mut msg_html = String::new();
+    for m in flash_messages.iter() {
+        writeln!(msg_html, "<p><i>{}</i></p>", m.content()).unwrap();
+    }
+
+    let tags = sqlx::query!(
+        r#"
+        SELECT t.name, t.signup_list, COUNT(st.subscriber_id) AS "subscribers!"
+        FROM tags t
+        LEFT JOIN subscription_tags st ON st.tag_id = t.tag_id
+        GROUP BY t.tag_id
+        ORDER BY t.name
+        "#,
+    )
+    .fetch_all(pool.get_ref())
+    .await
+    .context("Failed to retrieve tags.")
+    .map_err(e500)?;
+    let mut rows_html = String::new();
+    for tag in &tags {
+        writeln!(
+            rows_html,
+            "<li>{}{} - {} subscriber(s)</li>",
+            tag.name,
+            if tag.signup_list { " (signup list)" } else { "" },
+            tag.subscribers,
+        )
+        .unwrap();
+    }
+    if tags.is_empty() {
+        rows_html.push_str("<li>There are no tags.</li>");
+    }
+
+    Ok(HttpResponse::Ok()
+        .content_type(ContentType::html())
+        .body(format!(
+            r#"<!DOCTYPE html>
+<html lang="en">
+<head>
+    <meta charset="UTF-8">
+    <meta name="viewport" content="width=device-width, initial-scale=1.0">
+    <title>Subscriber Tags</title>
+</head>
+<body>
+    {msg_html}
+    <p>Tags:</p>
+    <ul>
+        {rows_html}
+    </ul>
+    <p>New tag:</p>
+    <form action="/admin/tags" method="post">
+        <label>Name:
+            <input type="text" placeholder="e.g. rust" name="name">
+        </label>
+        <label>
+            <input type="checkbox" name="signup_list" value="on">
+            Offer as a list on the signup form
+        </label>
+        <br>
+        <button type="submit">Create tag</button>
+    </form>
+    <p>Tag a subscriber:</p>
+    <form action="/admin/tags/subscribers" method="post">
+        <label>Subscriber email:
+            <input type="text" name="email">
+        </label>
+        <label>Tag:
+            <input type="text" placeholder="e.g. rust" name="tag">
+        </label>
+        <br>
+        <button type="submit" name="action" value="add">Add tag</button>
+        <button type="submit" name="action" value="remove">Remove tag</button>
+    </form>
+    <p><a href="/admin/dashboard"><- Back</a></p>
+</body>
+</html>"#,
+        )))
+}
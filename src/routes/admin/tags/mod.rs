@@ -0,0 +1,5 @@
+mod get;
+pub use get::list_tags;
+
+mod post;
+pub use post::{create_tag, tag_subscriber};
@@ -0,0 +1,132 @@
+use actix_web::{web, HttpResponse};
+use actix_web_flash_messages::FlashMessage;
+use anyhow::Context;
+use sqlx::PgPool;
+use uuid::Uuid;
+
+use crate::{
+    domain::{SubscriberEmail, TagName},
+    utils::{e500, see_other},
+};
+
+#[derive(serde::Deserialize)]
+pub struct TagFormData {
+    name: String,
+    signup_list: Option<String>,
+}
+
+#[tracing::instrument(name = "Create a subscriber tag", skip(form, pool))]
+pub async fn create_tag(
+    form: web::Form<TagFormData>,
+    pool: web::Data<PgPool>,
+) -> Result<HttpResponse, actix_web::Error> {
+    let TagFormData { name, signup_list } = form.0;
+    let Ok(name) = TagName::parse(name) else {
+        FlashMessage::error(
+            "Tag names are 1 to 50 lowercase letters, digits, dashes or underscores.",
+        )
+        .send();
+        return Ok(see_other("/admin/tags"));
+    };
+    let created = sqlx::query!(
+        r#"
+        INSERT INTO tags (tag_id, name, signup_list)
+        VALUES ($1, $2, $3)
+        ON CONFLICT (name) DO NOTHING
+        "#,
+        Uuid::new_v4(),
+        name.as_ref(),
+        signup_list.is_some(),
+    )
+    .execute(pool.get_ref())
+    .await
+    .context("Failed to create a subscriber tag.")
+    .map_err(e500)?
+    .rows_affected();
+
+    if created > 0 {
+        FlashMessage::info(format!("The tag {} has been created.", name)).send();
+    } else {
+        FlashMessage::error(format!("The tag {} already exists.", name)).send();
+    }
+    Ok(see_other("/admin/tags"))
+}
+
+#[derive(serde::Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum TagAction {
+    Add,
+    Remove,
+}
+
+#[derive(serde::Deserialize)]
+pub struct TagSubscriberFormData {
+    email: String,
+    tag: String,
+    action: TagAction,
+}
+
+#[tracing::instrument(name = "Tag a subscriber", skip(form, pool))]
+pub async fn tag_subscriber(
+    form: web::Form<TagSubscriberFormData>,
+    pool: web::Data<PgPool>,
+) -> Result<HttpResponse, actix_web::Error> {
+    let TagSubscriberFormData { email, tag, action } = form.0;
+    let (Ok(email), Ok(tag)) = (SubscriberEmail::parse(email), TagName::parse(tag)) else {
+        FlashMessage::error("Enter a valid email address and tag name.").send();
+        return Ok(see_other("/admin/tags"));
+    };
+    let ids = sqlx::query!(
+        r#"
+        SELECT s.id AS subscriber_id, t.tag_id
+        FROM subscriptions s, tags t
+        WHERE s.email = $1 AND t.name = $2
+        "#,
+        email.as_ref(),
+        tag.as_ref(),
+    )
+    .fetch_optional(pool.get_ref())
+    .await
+    .context("Failed to look up the subscriber and the tag.")
+    .map_err(e500)?;
+    let email = htmlescape::encode_minimal(email.as_ref());
+    let Some(ids) = ids else {
+        FlashMessage::error("There is no such subscriber or tag.").send();
+        return Ok(see_other("/admin/tags"));
+    };
+
+    match action {
+        TagAction::Add => {
+            sqlx::query!(
+                r#"
+                INSERT INTO subscription_tags (subscriber_id, tag_id)
+                VALUES ($1, $2)
+                ON CONFLICT DO NOTHING
+                "#,
+                ids.subscriber_id,
+                ids.tag_id,
+            )
+            .execute(pool.get_ref())
+            .await
+            .context("Failed to tag a subscriber.")
+            .map_err(e500)?;
+            FlashMessage::info(format!("{} is now tagged {}.", email, tag)).send();
+        }
+        TagAction::Remove => {
+            sqlx::query!(
+                r#"
+                DELETE FROM subscription_tags
+                WHERE subscriber_id = $1 AND tag_id = $2
+                "#,
+                ids.subscriber_id,
+                ids.tag_id,
+            )
+            .execute(pool.get_ref())
+            .await
+            .context("Failed to untag a subscriber.")
+            .map_err(e500)?;
+            FlashMessage::info(format!("{} is no longer tagged {}.", email, tag)).send();
+        }
+    }
+    Ok(see_other("/admin/tags"))
+}
@@ -0,0 +1,86 @@
+use actix_web::{http::header::ContentType, web, HttpResponse};
+use actix_web_flash_messages::IncomingFlashMessages;
+use qrcode::{render::svg, QrCode};
+use sqlx::PgPool;
+use std::fmt::Write;
+
+use crate::{
+    authentication::{generate_totp_secret, get_totp_secret, otpauth_uri, UserId},
+    routes::admin::dashboard::get_username,
+    session_state::TypedSession,
+    utils::e500,
+};
+
+/// Either the enrolment form, with a fresh secret kept in the session until
+/// it is confirmed, or the form to turn two-factor authentication off.
+pub async fn two_factor_settings(
+    user_id: web::ReqData<UserId>,
+    pool: web::Data<PgPool>,
+    session: TypedSession,
+    flash_messages: IncomingFlashMessages,
+) -> Result<HttpResponse, actix_web::Error> {
+    let user_id = user_id.into_inner();
+    let mut msg_html = String::new();
+    for m in flash_messages.iter() {
+        writeln!(msg_html, "<p><i>{}</i></p>", m.content()).unwrap();
+    }
+
+    let body = if get_totp_secret(*user_id, &pool).await.map_err(e500)?.is_some() {
+        r#"<p>Two-factor authentication is enabled.</p>
+    <form action="/admin/2fa/disable" method="post">
+        <label>Authentication or recovery code
+            <input type="text" autocomplete="one-time-code" name="code">
+        </label>
+        <button type="submit">Disable two-factor authentication</button>
+    </form>"#
+            .to_string()
+    } else {
+        // Reloading the page keeps the secret the user may already have scanned.
+        let secret = match session.get_pending_totp_secret().map_err(e500)? {
+            Some(secret) => secret,
+            None => {
+                let secret = generate_totp_secret();
+                session.insert_pending_totp_secret(&secret).map_err(e500)?;
+                secret
+            }
+        };
+        let username = get_username(*user_id, &pool).await.map_err(e500)?;
+        let uri = otpauth_uri(&secret, &username);
+        let qr_code = QrCode::new(uri.as_bytes())
+            .map_err(e500)?
+            .render::<svg::Color>()
+            .min_dimensions(200, 200)
+            .build();
+        format!(
+            r#"<p>Scan this QR code with your authenticator app, or enter the key by hand.</p>
+    {qr_code}
+    <p>Key: <code>{secret}</code></p>
+    <p><a href="{uri}">Open in an authenticator app</a></p>
+    <form action="/admin/2fa" method="post">
+        <label>Code from the app
+            <input type="text" inputmode="numeric" autocomplete="one-time-code" placeholder="123456" name="code">
+        </label>
+        <button type="submit">Enable two-factor authentication</button>
+    </form>"#,
+            uri = htmlescape::encode_minimal(&uri),
+        )
+    };
+
+    Ok(HttpResponse::Ok()
+        .content_type(ContentType::html())
+        .body(format!(
+            r#"<!DOCTYPE html>
+<html lang="en">
+<head>
+    <meta charset="UTF-8">
+    <meta name="viewport" content="width=device-width, initial-scale=1.0">
+    <title>Two-factor authentication</title>
+</head>
+<body>
+    {msg_html}
+    {body}
+    <p><a href="/admin/dashboard"><- Back</a></p>
+</body>
+</html>"#
+        )))
+}
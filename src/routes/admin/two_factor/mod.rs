@@ -0,0 +1,5 @@
+mod get;
+pub use get::two_factor_settings;
+
+mod post;
+pub use post::{disable_two_factor, enable_two_factor};
@@ -0,0 +1,80 @@
+use actix_web::{http::header::ContentType, web, HttpResponse};
+use actix_web_flash_messages::FlashMessage;
+use sqlx::PgPool;
+use std::fmt::Write;
+
+use crate::{
+    authentication::{disable_totp, enable_totp, verify_second_factor, verify_totp, UserId},
+    session_state::TypedSession,
+    utils::{e500, see_other},
+};
+
+#[derive(serde::Deserialize)]
+pub struct CodeFormData {
+    code: String,
+}
+
+/// Confirms the pending secret with a code from the app. The recovery codes
+/// are shown on this page only: they cannot be retrieved later.
+#[tracing::instrument(name = "Enable two-factor authentication", skip(form, pool, session))]
+pub async fn enable_two_factor(
+    form: web::Form<CodeFormData>,
+    user_id: web::ReqData<UserId>,
+    pool: web::Data<PgPool>,
+    session: TypedSession,
+) -> Result<HttpResponse, actix_web::Error> {
+    let user_id = user_id.into_inner();
+    let Some(secret) = session.get_pending_totp_secret().map_err(e500)? else {
+        return Ok(see_other("/admin/2fa"));
+    };
+    let Some(step) = verify_totp(&secret, &form.0.code, chrono::Utc::now().timestamp()) else {
+        FlashMessage::error("The code is invalid. Check your device's clock and try again.").send();
+        return Ok(see_other("/admin/2fa"));
+    };
+    let recovery_codes = enable_totp(*user_id, &secret, step, &pool)
+        .await
+        .map_err(e500)?;
+    session.remove_pending_totp_secret();
+
+    let mut codes_html = String::new();
+    for code in recovery_codes {
+        writeln!(codes_html, "<li><code>{code}</code></li>").unwrap();
+    }
+    Ok(HttpResponse::Ok()
+        .content_type(ContentType::html())
+        .body(format!(
+            r#"<!DOCTYPE html>
+<html lang="en">
+<head>
+    <meta charset="UTF-8">
+    <meta name="viewport" content="width=device-width, initial-scale=1.0">
+    <title>Recovery codes</title>
+</head>
+<body>
+    <p>Two-factor authentication is enabled.</p>
+    <p>Keep these recovery codes somewhere safe. Each of them lets you log in once without your device. They will not be shown again.</p>
+    <ul>
+        {codes_html}
+    </ul>
+    <p><a href="/admin/dashboard"><- Back</a></p>
+</body>
+</html>"#
+        )))
+}
+
+/// Asks for a second factor, so that an unattended session cannot turn it off.
+#[tracing::instrument(name = "Disable two-factor authentication", skip(form, pool))]
+pub async fn disable_two_factor(
+    form: web::Form<CodeFormData>,
+    user_id: web::ReqData<UserId>,
+    pool: web::Data<PgPool>,
+) -> Result<HttpResponse, actix_web::Error> {
+    let user_id = user_id.into_inner();
+    if !verify_second_factor(*user_id, &form.0.code, &pool).await.map_err(e500)? {
+        FlashMessage::error("The code is invalid.").send();
+        return Ok(see_other("/admin/2fa"));
+    }
+    disable_totp(*user_id, &pool).await.map_err(e500)?;
+    FlashMessage::info("Two-factor authentication has been disabled.").send();
+    Ok(see_other("/admin/2fa"))
+}
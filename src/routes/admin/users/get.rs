@@ -0,0 +1,117 @@
+use actix_web::{http::header::ContentType, web, HttpResponse};
+use actix_web_flash_messages::IncomingFlashMessages;
+use anyhow::Context;
+use sqlx::PgPool;
+use std::fmt::Write;
+
+use crate::{authentication::UserId, utils::e500};
+
+pub async fn list_users(
+    pool: web::Data<PgPool>,
+    user_id: web::ReqData<UserId>,
+    flash_messages: IncomingFlashMessages,
+) -> Result<HttpResponse, actix_web::Error> {
+    let user_id = user_id.into_inner();
+    let mut msg_html = String::new();
+    for m in flash_messages.iter() {
+        writeln!(msg_html, "<p><i>{}</i></p>", m.content()).unwrap();
+    }
+
+    let users = sqlx::query!(
+        r#"
+        SELECT
+            u.user_id,
+            u.username,
+            u.email,
+            u.role,
+            u.disabled_at IS NOT NULL AS "disabled!",
+            i.user_id IS NOT NULL AS "invited!"
+        FROM users u
+        LEFT JOIN user_invitations i ON i.user_id = u.user_id
+        ORDER BY u.username
+        "#,
+    )
+    .fetch_all(pool.get_ref())
+    .await
+    .context("Failed to retrieve users.")
+    .map_err(e500)?;
+    let mut rows_html = String::new();
+    for user in &users {
+        let status = if user.disabled {
+            "disabled"
+        } else if user.invited {
+            "invited"
+        } else {
+            "active"
+        };
+        let actions = if user.user_id == *user_id {
+            "(you)".to_string()
+        } else {
+            let toggle = if user.disabled { "enable" } else { "disable" };
+            format!(
+                r#"<form action="/admin/users/{id}/{toggle}" method="post" style="display:inline">
+                <button type="submit">{toggle}</button>
+            </form>
+            <form action="/admin/users/{id}/delete" method="post" style="display:inline">
+                <button type="submit">delete</button>
+            </form>"#,
+                id = user.user_id,
+            )
+        };
+        writeln!(
+            rows_html,
+            r#"<tr>
+            <td>{}</td>
+            <td>{}</td>
+            <td>{}</td>
+            <td>{}</td>
+            <td>{}</td>
+        </tr>"#,
+            htmlescape::encode_minimal(&user.username),
+            htmlescape::encode_minimal(user.email.as_deref().unwrap_or("")),
+            user.role,
+            status,
+            actions,
+        )
+        .unwrap();
+    }
+
+    Ok(HttpResponse::Ok()
+        .content_type(ContentType::html())
+        .body(format!(
+            r#"<!DOCTYPE html>
+<html lang="en">
+<head>
+    <meta charset="UTF-8">
+    <meta name="viewport" content="width=device-width, initial-scale=1.0">
+    <title>Users</title>
+</head>
+<body>
+    {msg_html}
+    <table>
+        <tr><th>Username</th><th>Email</th><th>Role</th><th>Status</th><th></th></tr>
+        {rows_html}
+    </table>
+    <p>Invite a user:</p>
+    <form action="/admin/users" method="post">
+        <label>Username:
+            <input type="text" name="username">
+        </label>
+        <label>Email:
+            <input type="text" name="email">
+        </label>
+        <label>Role:
+            <select name="role">
+                <option value="viewer">Viewer - reads issues and stats</option>
+                <option value="editor">Editor - also drafts and publishes</option>
+                <option value="owner">Owner - also manages users</option>
+            </select>
+        </label>
+        <br>
+        <button type="submit">Send invitation</button>
+    </form>
+    <p><a href="/admin/dashboard"><- Back</a></p>
+</body>
+</html>"#,
+        )))
+}
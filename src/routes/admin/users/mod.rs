@@ -0,0 +1,5 @@
+mod get;
+pub use get::list_users;
+
+mod post;
+pub use post::{delete_user, disable_user, enable_user, invite_user};
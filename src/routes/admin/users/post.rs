@@ -0,0 +1,323 @@
+use std::ops::DerefMut;
+
+use actix_web::{web, HttpResponse};
+use actix_web_flash_messages::FlashMessage;
+use anyhow::Context;
+use chrono::Utc;
+use sqlx::{PgPool, Transaction};
+use uuid::Uuid;
+
+use crate::{
+    authentication::{compute_password_hash, email_is_taken, hash_token, Role, UserId},
+    domain::SubscriberEmail,
+    email_client::{EmailClient, EmailError, EmailMessage, EmailProvider},
+    routes::generate_subscription_token,
+    startup::ApplicationBaseUrl,
+    telemetry::spawn_blocking_with_tracing,
+    utils::{e500, see_other},
+};
+
+/// How long an invitation link stays valid.
+const INVITATION_TTL_DAYS: i64 = 7;
+
+#[derive(serde::Deserialize)]
+pub struct InviteFormData {
+    username: String,
+    email: String,
+    role: String,
+}
+
+/// Creates the user straight away, with a random password nobody knows:
+/// they can log in once they have chosen their own from the invitation link.
+#[tracing::instrument(
+    name = "Invite a user",
+    skip(form, pool, email_client, base_url),
+    fields(username = %form.username)
+)]
+pub async fn invite_user(
+    form: web::Form<InviteFormData>,
+    pool: web::Data<PgPool>,
+    email_client: web::Data<EmailClient>,
+    base_url: web::Data<ApplicationBaseUrl>,
+) -> Result<HttpResponse, actix_web::Error> {
+    let InviteFormData { username, email, role } = form.0;
+    let username = username.trim().to_string();
+    if username.is_empty() || username.chars().count() > 50 {
+        FlashMessage::error("Usernames are 1 to 50 characters long.").send();
+        return Ok(see_other("/admin/users"));
+    }
+    let Ok(email) = SubscriberEmail::parse(email) else {
+        FlashMessage::error("Enter a valid email address.").send();
+        return Ok(see_other("/admin/users"));
+    };
+    let Ok(role) = Role::parse(&role) else {
+        FlashMessage::error("Choose a role for the new user.").send();
+        return Ok(see_other("/admin/users"));
+    };
+    if email_is_taken(email.as_ref(), None, &pool).await.map_err(e500)? {
+        FlashMessage::error("Another account already uses that email address.").send();
+        return Ok(see_other("/admin/users"));
+    }
+
+    let unusable_password = generate_subscription_token();
+    let password_hash = spawn_blocking_with_tracing(move || compute_password_hash(unusable_password))
+        .await
+        .context("Failed to spawn blocking task")
+        .map_err(e500)?
+        .map_err(e500)?;
+    let mut transaction = pool
+        .begin()
+        .await
+        .context("Failed to acquire a Postgres connection from the pool.")
+        .map_err(e500)?;
+    let user_id = match insert_user(&mut transaction, &username, &email, role, &password_hash)
+        .await
+        .context("Failed to store the invited user.")
+        .map_err(e500)?
+    {
+        InsertedUser::Inserted(user_id) => user_id,
+        InsertedUser::UsernameTaken => {
+            FlashMessage::error(format!(
+                "The username {} is already taken.",
+                htmlescape::encode_minimal(&username)
+            ))
+            .send();
+            return Ok(see_other("/admin/users"));
+        }
+        InsertedUser::EmailTaken => {
+            FlashMessage::error("Another account already uses that email address.").send();
+            return Ok(see_other("/admin/users"));
+        }
+    };
+    let invitation_token = generate_subscription_token();
+    store_invitation(&mut transaction, user_id, &invitation_token)
+        .await
+        .context("Failed to store the invitation token.")
+        .map_err(e500)?;
+
+    transaction
+        .commit()
+        .await
+        .context("Failed to commit SQL transaction to invite a user.")
+        .map_err(e500)?;
+
+    // Sent once the user is stored, so that the link never points to a user
+    // that does not exist, and without holding the transaction open while
+    // the email client retries.
+    if let Err(e) = send_invitation_email(&email_client, &email, &username, &base_url.0, &invitation_token).await {
+        tracing::error!(
+            error.cause_chain = ?e,
+            error.message = %e,
+            "Failed to send an invitation email."
+        );
+        // Frees the username and the email address for another attempt.
+        delete_uninvited_user(user_id, &pool)
+            .await
+            .context("Failed to delete a user whose invitation could not be sent.")
+            .map_err(e500)?;
+        FlashMessage::error("The invitation email could not be sent. Please try again.").send();
+        return Ok(see_other("/admin/users"));
+    }
+
+    FlashMessage::info(format!(
+        "{} has been invited with the {} role.",
+        htmlescape::encode_minimal(&username),
+        role
+    ))
+    .send();
+    Ok(see_other("/admin/users"))
+}
+
+#[tracing::instrument(name = "Disable a user", skip(pool, current_user))]
+pub async fn disable_user(
+    user_id: web::Path<Uuid>,
+    pool: web::Data<PgPool>,
+    current_user: web::ReqData<UserId>,
+) -> Result<HttpResponse, actix_web::Error> {
+    set_disabled(*user_id, true, &pool, *current_user.into_inner()).await
+}
+
+#[tracing::instrument(name = "Enable a user", skip(pool, current_user))]
+pub async fn enable_user(
+    user_id: web::Path<Uuid>,
+    pool: web::Data<PgPool>,
+    current_user: web::ReqData<UserId>,
+) -> Result<HttpResponse, actix_web::Error> {
+    set_disabled(*user_id, false, &pool, *current_user.into_inner()).await
+}
+
+/// Issues keep their content when their author is deleted.
+#[tracing::instrument(name = "Delete a user", skip(pool, current_user))]
+pub async fn delete_user(
+    user_id: web::Path<Uuid>,
+    pool: web::Data<PgPool>,
+    current_user: web::ReqData<UserId>,
+) -> Result<HttpResponse, actix_web::Error> {
+    let user_id = user_id.into_inner();
+    // Owners cannot lock themselves out, which also keeps at least one owner around.
+    if user_id == *current_user.into_inner() {
+        FlashMessage::error("You cannot delete your own account.").send();
+        return Ok(see_other("/admin/users"));
+    }
+    let username = sqlx::query_scalar!(
+        "DELETE FROM users WHERE user_id = $1 RETURNING username",
+        user_id,
+    )
+    .fetch_optional(pool.get_ref())
+    .await
+    .context("Failed to delete a user.")
+    .map_err(e500)?;
+    match username {
+        Some(username) => FlashMessage::info(format!(
+            "{} has been deleted.",
+            htmlescape::encode_minimal(&username)
+        )),
+        None => FlashMessage::error("The user does not exist."),
+    }
+    .send();
+    Ok(see_other("/admin/users"))
+}
+
+async fn set_disabled(
+    user_id: Uuid,
+    disabled: bool,
+    pool: &PgPool,
+    current_user: Uuid,
+) -> Result<HttpResponse, actix_web::Error> {
+    if user_id == current_user {
+        FlashMessage::error("You cannot disable your own account.").send();
+        return Ok(see_other("/admin/users"));
+    }
+    let username = sqlx::query_scalar!(
+        r#"
+        UPDATE users
+        SET disabled_at = CASE WHEN $2 THEN COALESCE(disabled_at, now()) END
+        WHERE user_id = $1
+        RETURNING username
+        "#,
+        user_id,
+        disabled,
+    )
+    .fetch_optional(pool)
+    .await
+    .context("Failed to update the status of a user.")
+    .map_err(e500)?;
+    match username {
+        Some(username) => FlashMessage::info(format!(
+            "{} has been {}.",
+            htmlescape::encode_minimal(&username),
+            if disabled { "disabled" } else { "enabled" }
+        )),
+        None => FlashMessage::error("The user does not exist."),
+    }
+    .send();
+    Ok(see_other("/admin/users"))
+}
+
+/// The unique index on email addresses, see the `confirm_user_emails` migration.
+const EMAIL_UNIQUE_INDEX: &str = "users_email_key";
+
+enum InsertedUser {
+    Inserted(Uuid),
+    UsernameTaken,
+    /// Only if another invitation took the address after `email_is_taken` was checked.
+    EmailTaken,
+}
+
+#[tracing::instrument(name = "Insert an invited user", skip(transaction, email, password_hash))]
+async fn insert_user(
+    transaction: &mut Transaction<'_, sqlx::Postgres>,
+    username: &str,
+    email: &SubscriberEmail,
+    role: Role,
+    password_hash: &str,
+) -> Result<InsertedUser, sqlx::Error> {
+    let inserted = sqlx::query_scalar!(
+        r#"
+        INSERT INTO users (user_id, username, password_hash, email, role)
+        VALUES ($1, $2, $3, $4, $5)
+        ON CONFLICT (username) DO NOTHING
+        RETURNING user_id
+        "#,
+        Uuid::new_v4(),
+        username,
+        password_hash,
+        email.as_ref(),
+        role.as_str(),
+    )
+    .fetch_optional(transaction.deref_mut())
+    .await;
+    match inserted {
+        Ok(Some(user_id)) => Ok(InsertedUser::Inserted(user_id)),
+        Ok(None) => Ok(InsertedUser::UsernameTaken),
+        Err(sqlx::Error::Database(e)) if e.constraint() == Some(EMAIL_UNIQUE_INDEX) => {
+            Ok(InsertedUser::EmailTaken)
+        }
+        Err(e) => Err(e),
+    }
+}
+
+#[tracing::instrument(name = "Store an invitation token", skip(transaction, invitation_token))]
+async fn store_invitation(
+    transaction: &mut Transaction<'_, sqlx::Postgres>,
+    user_id: Uuid,
+    invitation_token: &str,
+) -> Result<(), sqlx::Error> {
+    sqlx::query!(
+        r#"
+        INSERT INTO user_invitations (token_hash, user_id, expires_at)
+        VALUES ($1, $2, $3)
+        "#,
+        hash_token(invitation_token),
+        user_id,
+        Utc::now() + chrono::Duration::days(INVITATION_TTL_DAYS),
+    )
+    .execute(transaction.deref_mut())
+    .await?;
+    Ok(())
+}
+
+/// The invitation goes with the user.
+#[tracing::instrument(name = "Delete an uninvited user", skip(pool))]
+async fn delete_uninvited_user(user_id: Uuid, pool: &PgPool) -> Result<(), sqlx::Error> {
+    sqlx::query!("DELETE FROM users WHERE user_id = $1", user_id)
+        .execute(pool)
+        .await?;
+    Ok(())
+}
+
+#[tracing::instrument(
+    name = "Send an invitation email",
+    skip(email_client, recipient, base_url, invitation_token)
+)]
+async fn send_invitation_email(
+    email_client: &EmailClient,
+    recipient: &SubscriberEmail,
+    username: &str,
+    base_url: &str,
+    invitation_token: &str,
+) -> Result<(), EmailError> {
+    let invitation_link = format!(
+        "{}/invitations/accept?invitation_token={}",
+        base_url, invitation_token
+    );
+    let plain_body = format!(
+        "You have been invited to help run our newsletter, with the username {}.\n\
+        Visit {} within {} days to choose your password.",
+        username, invitation_link, INVITATION_TTL_DAYS
+    );
+    let html_body = format!(
+        "You have been invited to help run our newsletter, with the username {}.<br />\
+        Click <a href=\"{}\">here</a> within {} days to choose your password.",
+        htmlescape::encode_minimal(username),
+        invitation_link,
+        INVITATION_TTL_DAYS
+    );
+    let message = EmailMessage::builder(recipient.clone(), "You have been invited")
+        .html_content(html_body)
+        .text_content(plain_body)
+        .tag("invitation")
+        .build();
+    email_client.send(&message).await?;
+    Ok(())
+}
@@ -0,0 +1,273 @@
+use std::ops::DerefMut;
+
+use actix_web::{
+    http::{header, StatusCode},
+    web, HttpRequest, HttpResponse, ResponseError,
+};
+use anyhow::Context;
+use base64::Engine;
+use chrono::{DateTime, Utc};
+use sqlx::{PgPool, Transaction};
+use uuid::Uuid;
+
+use crate::startup::EmailWebhookToken;
+
+/// Bounce types after which an address will never accept mail again.
+const HARD_BOUNCE_TYPES: [&str; 2] = ["HardBounce", "BadEmailAddress"];
+
+/// A Postmark webhook payload. Record types we do not track are acknowledged
+/// and ignored, so that the provider does not keep retrying them.
+#[derive(serde::Deserialize)]
+#[serde(tag = "RecordType")]
+enum PostmarkEvent {
+    #[serde(rename_all = "PascalCase")]
+    Bounce {
+        r#type: String,
+        email: String,
+        bounced_at: Option<String>,
+    },
+    #[serde(rename_all = "PascalCase")]
+    SpamComplaint {
+        email: String,
+        bounced_at: Option<String>,
+    },
+    #[serde(rename_all = "PascalCase")]
+    Delivery {
+        recipient: String,
+        delivered_at: Option<String>,
+    },
+    #[serde(other)]
+    Other,
+}
+
+struct EmailEvent {
+    event_type: &'static str,
+    email: String,
+    detail: Option<String>,
+    occurred_at: Option<DateTime<Utc>>,
+    suppress: bool,
+}
+
+impl PostmarkEvent {
+    fn into_email_event(self) -> Option<EmailEvent> {
+        let event = match self {
+            PostmarkEvent::Bounce { r#type, email, bounced_at } => EmailEvent {
+                event_type: "bounce",
+                email,
+                suppress: HARD_BOUNCE_TYPES.contains(&r#type.as_str()),
+                detail: Some(r#type),
+                occurred_at: parse_timestamp(bounced_at),
+            },
+            PostmarkEvent::SpamComplaint { email, bounced_at } => EmailEvent {
+                event_type: "spam_complaint",
+                email,
+                detail: None,
+                occurred_at: parse_timestamp(bounced_at),
+                suppress: true,
+            },
+            PostmarkEvent::Delivery { recipient, delivered_at } => EmailEvent {
+                event_type: "delivery",
+                email: recipient,
+                detail: None,
+                occurred_at: parse_timestamp(delivered_at),
+                suppress: false,
+            },
+            PostmarkEvent::Other => return None,
+        };
+        Some(event)
+    }
+}
+
+fn parse_timestamp(timestamp: Option<String>) -> Option<DateTime<Utc>> {
+    DateTime::parse_from_rfc3339(&timestamp?)
+        .ok()
+        .map(|timestamp| timestamp.with_timezone(&Utc))
+}
+
+/// Receives delivery feedback from the email provider. Hard bounces and spam
+/// complaints move the subscriber to `suppressed`, so they are never mailed again.
+#[tracing::instrument(
+    name = "Ingest an email provider webhook.",
+    skip(request, body, pool, webhook_token),
+    fields(event_type = tracing::field::Empty)
+)]
+pub async fn email_webhook(
+    provider: web::Path<String>,
+    request: HttpRequest,
+    body: web::Bytes,
+    pool: web::Data<PgPool>,
+    webhook_token: web::Data<EmailWebhookToken>,
+) -> Result<HttpResponse, WebhookError> {
+    if !is_authorized(&request, &webhook_token.0) {
+        return Err(WebhookError::Unauthorized);
+    }
+    if provider.as_str() != "postmark" {
+        return Err(WebhookError::UnknownProvider);
+    }
+    let event: PostmarkEvent = serde_json::from_slice(&body)
+        .map_err(|e| WebhookError::InvalidPayload(e.to_string()))?;
+    let Some(event) = event.into_email_event() else {
+        tracing::info!("Ignoring an untracked webhook record type.");
+        return Ok(HttpResponse::Ok().finish());
+    };
+    tracing::Span::current().record("event_type", event.event_type);
+
+    let payload = String::from_utf8_lossy(&body);
+    let mut transaction = pool.begin().await
+        .context("Failed to acquire a Postgres connection from the pool.")?;
+    store_email_event(&mut transaction, &provider, &event, &payload).await
+        .context("Failed to store an email event.")?;
+    if event.suppress {
+        suppress_subscriber(&mut transaction, &event.email).await
+            .context("Failed to suppress a subscriber.")?;
+    }
+    transaction.commit().await
+        .context("Failed to commit SQL transaction to store an email event.")?;
+
+    Ok(HttpResponse::Ok().finish())
+}
+
+/// Webhooks authenticate with HTTP Basic auth: the username is ignored,
+/// the password must match the configured webhook token.
+fn is_authorized(request: &HttpRequest, webhook_token: &str) -> bool {
+    let password = request
+        .headers()
+        .get(header::AUTHORIZATION)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.strip_prefix("Basic "))
+        .and_then(|encoded| base64::engine::general_purpose::STANDARD.decode(encoded).ok())
+        .and_then(|decoded| String::from_utf8(decoded).ok())
+        .and_then(|credentials| {
+            credentials
+                .split_once(':')
+                .map(|(_, password)| password.to_owned())
+        });
+    match password {
+        Some(password) => constant_time_eq(password.as_bytes(), webhook_token.as_bytes()),
+        None => false,
+    }
+}
+
+fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    a.len() == b.len() && a.iter().zip(b).fold(0, |acc, (x, y)| acc | (x ^ y)) == 0
+}
+
+#[tracing::instrument(
+    name = "Store an email event.",
+    skip(transaction, event, payload),
+)]
+async fn store_email_event(
+    transaction: &mut Transaction<'_, sqlx::Postgres>,
+    provider: &str,
+    event: &EmailEvent,
+    payload: &str,
+) -> Result<(), sqlx::Error> {
+    sqlx::query!(
+        r#"
+        INSERT INTO email_events (event_id, provider, event_type, email, detail, occurred_at, payload)
+        VALUES ($1, $2, $3, $4, $5, $6, $7)
+        "#,
+        Uuid::new_v4(),
+        provider,
+        event.event_type,
+        event.email,
+        event.detail,
+        event.occurred_at,
+        payload,
+    )
+    .execute(transaction.deref_mut())
+    .await?;
+    Ok(())
+}
+
+#[tracing::instrument(
+    name = "Suppress a subscriber.",
+    skip(transaction, email),
+)]
+async fn suppress_subscriber(
+    transaction: &mut Transaction<'_, sqlx::Postgres>,
+    email: &str,
+) -> Result<(), sqlx::Error> {
+    sqlx::query!(
+        r#"
+        UPDATE subscriptions
+        SET status = 'suppressed'
+        WHERE email = $1"#,
+        email
+    )
+    .execute(transaction.deref_mut())
+    .await?;
+    Ok(())
+}
+
+#[derive(Debug, thiserror::Error)]
+pub enum WebhookError {
+    #[error("The webhook credentials are missing or invalid.")]
+    Unauthorized,
+    #[error("Webhooks are not supported for this email provider.")]
+    UnknownProvider,
+    #[error("{0}")]
+    InvalidPayload(String),
+    #[error(transparent)]
+    UnexpectedError(#[from] anyhow::Error),
+}
+
+impl ResponseError for WebhookError {
+    fn status_code(&self) -> StatusCode {
+        match self {
+            Self::Unauthorized => StatusCode::UNAUTHORIZED,
+            Self::UnknownProvider => StatusCode::NOT_FOUND,
+            Self::InvalidPayload(_) => StatusCode::BAD_REQUEST,
+            Self::UnexpectedError(_) => StatusCode::INTERNAL_SERVER_ERROR,
+        }
+    }
+
+    fn error_response(&self) -> HttpResponse {
+        let mut response = HttpResponse::build(self.status_code());
+        if let Self::Unauthorized = self {
+            response.insert_header((header::WWW_AUTHENTICATE, r#"Basic realm="webhooks""#));
+        }
+        response.body(self.to_string())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(payload: &str) -> Option<EmailEvent> {
+        serde_json::from_str::<PostmarkEvent>(payload).unwrap().into_email_event()
+    }
+
+    #[test]
+    fn hard_bounces_suppress_the_address() {
+        let event = parse(
+            r#"{"RecordType": "Bounce", "Type": "HardBounce", "Email": "a@example.com",
+            "BouncedAt": "2019-11-05T16:33:54.9070259Z"}"#,
+        )
+        .unwrap();
+        assert_eq!(event.event_type, "bounce");
+        assert!(event.suppress);
+        assert!(event.occurred_at.is_some());
+    }
+
+    #[test]
+    fn soft_bounces_do_not_suppress_the_address() {
+        let event = parse(r#"{"RecordType": "Bounce", "Type": "SoftBounce", "Email": "a@example.com"}"#)
+            .unwrap();
+        assert!(!event.suppress);
+        assert_eq!(event.detail.as_deref(), Some("SoftBounce"));
+    }
+
+    #[test]
+    fn untracked_record_types_are_ignored() {
+        assert!(parse(r#"{"RecordType": "Open", "Recipient": "a@example.com"}"#).is_none());
+    }
+
+    #[test]
+    fn tokens_are_compared_in_full() {
+        assert!(constant_time_eq(b"secret", b"secret"));
+        assert!(!constant_time_eq(b"secret", b"secreT"));
+        assert!(!constant_time_eq(b"secret", b"secret2"));
+    }
+}
@@ -1,5 +1,22 @@
-use actix_web::HttpResponse;
+use actix_web::{web, HttpResponse};
 
-pub async fn health_check() -> HttpResponse {
-    HttpResponse::Ok().finish()
-}
\ No newline at end of file
+use crate::email_client::{CircuitState, EmailClient};
+
+/// Always `200 OK` while the application is up: an email provider outage
+/// degrades the service, it does not make it unhealthy. The state of each
+/// provider's circuit breaker is reported in the body.
+pub async fn health_check(email_client: web::Data<EmailClient>) -> HttpResponse {
+    let email_providers = email_client.health();
+    let status = if email_providers
+        .iter()
+        .all(|provider| provider.circuit == CircuitState::Closed)
+    {
+        "ok"
+    } else {
+        "degraded"
+    };
+    HttpResponse::Ok().json(serde_json::json!({
+        "status": status,
+        "email_providers": email_providers,
+    }))
+}
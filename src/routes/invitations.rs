@@ -0,0 +1,215 @@
+use std::{fmt::Write, ops::DerefMut};
+
+use actix_web::{http::{header::ContentType, StatusCode}, web, HttpResponse, ResponseError};
+use actix_web_flash_messages::{FlashMessage, IncomingFlashMessages};
+use anyhow::Context;
+use chrono::{DateTime, Utc};
+use sqlx::{PgPool, Transaction};
+use uuid::Uuid;
+
+use crate::{authentication::{compute_password_hash, hash_token}, domain::AdminPassword, telemetry::spawn_blocking_with_tracing, utils::see_other};
+
+#[derive(serde::Deserialize)]
+pub struct InvitationParameters {
+    invitation_token: String,
+}
+
+/// Where invited users choose their password.
+#[tracing::instrument(name = "Show the invitation form", skip_all)]
+pub async fn accept_invitation_form(
+    parameters: web::Query<InvitationParameters>,
+    pool: web::Data<PgPool>,
+    flash_messages: IncomingFlashMessages,
+) -> Result<HttpResponse, InvitationError> {
+    let mut transaction = pool.begin().await
+        .context("Failed to acquire a Postgres connection from the pool.")?;
+    let invitation = get_invitation(&mut transaction, &parameters.invitation_token).await
+        .context("Failed to retrieve the invitation.")?;
+    check_invitation(invitation.as_ref())?;
+
+    let mut msg_html = String::new();
+    for m in flash_messages.iter() {
+        writeln!(msg_html, "<p><i>{}</i></p>", m.content()).unwrap();
+    }
+    let invitation_token = htmlescape::encode_attribute(&parameters.invitation_token);
+    Ok(HttpResponse::Ok()
+        .content_type(ContentType::html())
+        .body(invitation_page(
+            "Choose your password",
+            &format!(
+                r#"{msg_html}
+    <form action="/invitations/accept" method="post">
+        <input type="hidden" name="invitation_token" value="{invitation_token}">
+        <label>Password
+            <input type="password" placeholder="Enter a password" name="password">
+        </label>
+        <br>
+        <label>Confirm password
+            <input type="password" placeholder="Type the password again" name="password_check">
+        </label>
+        <br>
+        <button type="submit">Set password</button>
+    </form>"#
+            ),
+        )))
+}
+
+#[derive(serde::Deserialize)]
+pub struct AcceptFormData {
+    invitation_token: String,
+    password: String,
+    password_check: String,
+}
+
+#[tracing::instrument(name = "Accept an invitation", skip_all)]
+pub async fn accept_invitation(
+    form: web::Form<AcceptFormData>,
+    pool: web::Data<PgPool>,
+) -> Result<HttpResponse, InvitationError> {
+    let AcceptFormData { invitation_token, password, password_check } = form.0;
+    let mut transaction = pool.begin().await
+        .context("Failed to acquire a Postgres connection from the pool.")?;
+    let invitation = get_invitation(&mut transaction, &invitation_token).await
+        .context("Failed to retrieve the invitation.")?;
+    let user_id = check_invitation(invitation.as_ref())?;
+
+    let retry_url = format!(
+        "/invitations/accept?invitation_token={}",
+        urlencoding::encode(&invitation_token)
+    );
+    if password != password_check {
+        FlashMessage::error("You entered two different passwords.").send();
+        return Ok(see_other(&retry_url));
+    }
+    let password = match AdminPassword::parse(password, None) {
+        Ok(password) => password,
+        Err(e) => {
+            FlashMessage::error(e).send();
+            return Ok(see_other(&retry_url));
+        }
+    };
+    let password_hash = spawn_blocking_with_tracing(move || compute_password_hash(password.as_ref().to_owned()))
+        .await
+        .context("Failed to spawn blocking task")?
+        .context("Failed to hash password")?;
+    sqlx::query!(
+        r#"
+        UPDATE users
+        SET password_hash = $1, email_confirmed_at = now()
+        WHERE user_id = $2
+        "#,
+        password_hash,
+        user_id
+    )
+    .execute(transaction.deref_mut())
+    .await
+    .context("Failed to set the password of an invited user.")?;
+    sqlx::query!("DELETE FROM user_invitations WHERE user_id = $1", user_id)
+        .execute(transaction.deref_mut())
+        .await
+        .context("Failed to delete an accepted invitation.")?;
+    transaction.commit().await
+        .context("Failed to commit SQL transaction to accept an invitation.")?;
+
+    FlashMessage::info("Your password has been set. You can now log in.").send();
+    Ok(see_other("/login"))
+}
+
+struct Invitation {
+    user_id: Uuid,
+    expires_at: DateTime<Utc>,
+    user_disabled: bool,
+}
+
+fn check_invitation(invitation: Option<&Invitation>) -> Result<Uuid, InvitationError> {
+    let invitation = invitation.ok_or(InvitationError::UnknownToken)?;
+    if invitation.user_disabled {
+        return Err(InvitationError::Revoked);
+    }
+    if invitation.expires_at < Utc::now() {
+        return Err(InvitationError::Expired);
+    }
+    Ok(invitation.user_id)
+}
+
+#[tracing::instrument(name = "Get invitation details", skip_all)]
+async fn get_invitation(
+    transaction: &mut Transaction<'_, sqlx::Postgres>,
+    invitation_token: &str,
+) -> Result<Option<Invitation>, sqlx::Error> {
+    sqlx::query_as!(
+        Invitation,
+        r#"
+        SELECT i.user_id, i.expires_at, u.disabled_at IS NOT NULL AS "user_disabled!"
+        FROM user_invitations i
+        JOIN users u ON u.user_id = i.user_id
+        WHERE i.token_hash = $1
+        FOR UPDATE OF i
+        "#,
+        hash_token(invitation_token),
+    )
+    .fetch_optional(transaction.deref_mut())
+    .await
+}
+
+fn invitation_page(title: &str, body: &str) -> String {
+    format!(
+        r#"<!DOCTYPE html>
+<html lang="en">
+<head>
+    <meta charset="UTF-8">
+    <meta name="viewport" content="width=device-width, initial-scale=1.0">
+    <title>{title}</title>
+</head>
+<body>
+    {body}
+</body>
+</html>"#,
+    )
+}
+
+#[derive(thiserror::Error, Debug)]
+pub enum InvitationError {
+    #[error("There is no invitation associated with the provided token.")]
+    UnknownToken,
+    #[error("The invitation has expired.")]
+    Expired,
+    #[error("The invited user has been disabled.")]
+    Revoked,
+    #[error(transparent)]
+    UnexpectedError(#[from] anyhow::Error),
+}
+
+impl ResponseError for InvitationError {
+    fn status_code(&self) -> StatusCode {
+        match self {
+            Self::UnknownToken => StatusCode::UNAUTHORIZED,
+            Self::Expired | Self::Revoked => StatusCode::GONE,
+            Self::UnexpectedError(_) => StatusCode::INTERNAL_SERVER_ERROR,
+        }
+    }
+
+    fn error_response(&self) -> HttpResponse {
+        let page = match self {
+            Self::UnknownToken => invitation_page(
+                "Invalid link",
+                "<p>This invitation link is not valid. It may already have been used.</p>",
+            ),
+            Self::Expired => invitation_page(
+                "Link expired",
+                "<p>This invitation has expired. Please ask for a new one.</p>",
+            ),
+            Self::Revoked => invitation_page(
+                "Invitation withdrawn",
+                "<p>This invitation has been withdrawn.</p>",
+            ),
+            Self::UnexpectedError(_) => invitation_page(
+                "Something went wrong",
+                "<p>We could not process your invitation. Please try again later.</p>",
+            ),
+        };
+        HttpResponse::build(self.status_code())
+            .content_type(ContentType::html())
+            .body(page)
+    }
+}
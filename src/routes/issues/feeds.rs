@@ -0,0 +1,108 @@
+use actix_web::{web, HttpResponse};
+use sqlx::PgPool;
+use std::fmt::Write;
+
+use crate::{startup::ApplicationBaseUrl, utils::e500};
+use super::get_published_issues;
+
+const FEED_SIZE: i64 = 20;
+const FEED_TITLE: &str = "Newsletter";
+
+pub async fn atom_feed(
+    pool: web::Data<PgPool>,
+    base_url: web::Data<ApplicationBaseUrl>,
+) -> Result<HttpResponse, actix_web::Error> {
+    let issues = get_published_issues(&pool, FEED_SIZE).await.map_err(e500)?;
+    let base_url = &base_url.0;
+    let updated = issues
+        .first()
+        .map(|issue| issue.published_at)
+        .unwrap_or_default()
+        .to_rfc3339();
+
+    let mut entries_xml = String::new();
+    for issue in &issues {
+        let link = format!("{}/issues/{}", base_url, issue.newsletter_issue_id);
+        writeln!(
+            entries_xml,
+            r#"  <entry>
+    <id>urn:uuid:{id}</id>
+    <title>{title}</title>
+    <link href="{link}"/>
+    <updated>{published_at}</updated>
+    <published>{published_at}</published>
+    <author><name>{FEED_TITLE}</name></author>
+    <content type="html">{content}</content>
+  </entry>"#,
+            id = issue.newsletter_issue_id,
+            title = xml_escape(&issue.title),
+            link = xml_escape(&link),
+            published_at = issue.published_at.to_rfc3339(),
+            content = xml_escape(&issue.sanitised_html(base_url)),
+        )
+        .unwrap();
+    }
+
+    Ok(HttpResponse::Ok()
+        .content_type("application/atom+xml; charset=utf-8")
+        .body(format!(
+            r#"<?xml version="1.0" encoding="utf-8"?>
+<feed xmlns="http://www.w3.org/2005/Atom">
+  <id>{base_url}/issues</id>
+  <title>{FEED_TITLE}</title>
+  <link href="{base_url}/issues"/>
+  <link rel="self" href="{base_url}/feed.xml"/>
+  <updated>{updated}</updated>
+{entries_xml}</feed>
+"#,
+            base_url = xml_escape(base_url),
+        )))
+}
+
+pub async fn rss_feed(
+    pool: web::Data<PgPool>,
+    base_url: web::Data<ApplicationBaseUrl>,
+) -> Result<HttpResponse, actix_web::Error> {
+    let issues = get_published_issues(&pool, FEED_SIZE).await.map_err(e500)?;
+    let base_url = &base_url.0;
+
+    let mut items_xml = String::new();
+    for issue in &issues {
+        let link = format!("{}/issues/{}", base_url, issue.newsletter_issue_id);
+        writeln!(
+            items_xml,
+            r#"    <item>
+      <guid isPermaLink="false">{id}</guid>
+      <title>{title}</title>
+      <link>{link}</link>
+      <pubDate>{published_at}</pubDate>
+      <description>{content}</description>
+    </item>"#,
+            id = issue.newsletter_issue_id,
+            title = xml_escape(&issue.title),
+            link = xml_escape(&link),
+            published_at = issue.published_at.to_rfc2822(),
+            content = xml_escape(&issue.sanitised_html(base_url)),
+        )
+        .unwrap();
+    }
+
+    Ok(HttpResponse::Ok()
+        .content_type("application/rss+xml; charset=utf-8")
+        .body(format!(
+            r#"<?xml version="1.0" encoding="utf-8"?>
+<rss version="2.0">
+  <channel>
+    <title>{FEED_TITLE}</title>
+    <link>{base_url}/issues</link>
+    <description>Every issue of the newsletter.</description>
+{items_xml}  </channel>
+</rss>
+"#,
+            base_url = xml_escape(base_url),
+        )))
+}
+
+fn xml_escape(s: &str) -> String {
+    htmlescape::encode_minimal(s)
+}
@@ -0,0 +1,90 @@
+mod feeds;
+mod pages;
+
+pub use feeds::{atom_feed, rss_feed};
+pub use pages::{issue_page, issues_index};
+
+use anyhow::Context;
+use chrono::{DateTime, Utc};
+use sqlx::PgPool;
+use uuid::Uuid;
+
+use crate::issue_template::{render_html, TemplateValues};
+
+struct PublishedIssue {
+    newsletter_issue_id: Uuid,
+    title: String,
+    html_content: String,
+    published_at: DateTime<Utc>,
+}
+
+impl PublishedIssue {
+    /// Issues are authored as trusted HTML for email clients: anything that
+    /// could run in a reader's browser is stripped before serving it publicly.
+    /// The template is rendered without any subscriber-specific values.
+    fn sanitised_html(&self, base_url: &str) -> String {
+        let issue_url = format!("{}/issues/{}", base_url, self.newsletter_issue_id);
+        let values = TemplateValues {
+            name: None,
+            unsubscribe_url: None,
+            issue_url: &issue_url,
+        };
+        let html_content = render_html(&self.html_content, &values).unwrap_or_else(|e| {
+            tracing::warn!(error.message = %e, "Failed to render a published issue.");
+            self.html_content.clone()
+        });
+        ammonia::clean(&html_content)
+    }
+}
+
+/// Public pages never name the author: usernames are admin login names.
+/// Issues sent to a segment only are left out: they were not meant for everyone.
+#[tracing::instrument(name = "Get published newsletter issues", skip(pool))]
+async fn get_published_issues(
+    pool: &PgPool,
+    limit: i64,
+) -> Result<Vec<PublishedIssue>, anyhow::Error> {
+    let issues = sqlx::query_as!(
+        PublishedIssue,
+        r#"
+        SELECT
+            i.newsletter_issue_id,
+            i.title,
+            i.html_content,
+            i.published_at AS "published_at!"
+        FROM newsletter_issues i
+        WHERE i.status = 'published' AND i.segment IS NULL
+        ORDER BY i.published_at DESC
+        LIMIT $1
+        "#,
+        limit
+    )
+    .fetch_all(pool)
+    .await
+    .context("Failed to retrieve published newsletter issues.")?;
+    Ok(issues)
+}
+
+#[tracing::instrument(name = "Get a published newsletter issue", skip(pool))]
+async fn get_published_issue(
+    pool: &PgPool,
+    issue_id: Uuid,
+) -> Result<Option<PublishedIssue>, anyhow::Error> {
+    let issue = sqlx::query_as!(
+        PublishedIssue,
+        r#"
+        SELECT
+            i.newsletter_issue_id,
+            i.title,
+            i.html_content,
+            i.published_at AS "published_at!"
+        FROM newsletter_issues i
+        WHERE i.newsletter_issue_id = $1 AND i.status = 'published' AND i.segment IS NULL
+        "#,
+        issue_id
+    )
+    .fetch_optional(pool)
+    .await
+    .context("Failed to retrieve a published newsletter issue.")?;
+    Ok(issue)
+}
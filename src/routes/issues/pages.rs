@@ -0,0 +1,87 @@
+use actix_web::{http::header::ContentType, web, HttpResponse};
+use sqlx::PgPool;
+use std::fmt::Write;
+use uuid::Uuid;
+
+use crate::{startup::ApplicationBaseUrl, utils::e500};
+use super::{get_published_issue, get_published_issues, PublishedIssue};
+
+const INDEX_SIZE: i64 = 100;
+
+pub async fn issues_index(pool: web::Data<PgPool>) -> Result<HttpResponse, actix_web::Error> {
+    let issues = get_published_issues(&pool, INDEX_SIZE).await.map_err(e500)?;
+    let mut rows_html = String::new();
+    for issue in &issues {
+        writeln!(
+            rows_html,
+            r#"<li>{} - <a href="/issues/{}">{}</a></li>"#,
+            issue.published_at.format("%Y-%m-%d"),
+            issue.newsletter_issue_id,
+            htmlescape::encode_minimal(&issue.title),
+        )
+        .unwrap();
+    }
+    if issues.is_empty() {
+        rows_html.push_str("<li>No issues have been published yet.</li>");
+    }
+
+    Ok(HttpResponse::Ok()
+        .content_type(ContentType::html())
+        .body(format!(
+            r#"<!DOCTYPE html>
+<html lang="en">
+<head>
+    <meta charset="UTF-8">
+    <meta name="viewport" content="width=device-width, initial-scale=1.0">
+    <title>Newsletter Archive</title>
+    <link rel="alternate" type="application/atom+xml" title="Atom feed" href="/feed.xml">
+    <link rel="alternate" type="application/rss+xml" title="RSS feed" href="/rss.xml">
+</head>
+<body>
+    <h1>Newsletter archive</h1>
+    <ul>
+        {rows_html}
+    </ul>
+    <p>Follow along with the <a href="/feed.xml">Atom</a> or <a href="/rss.xml">RSS</a> feed.</p>
+</body>
+</html>"#,
+        )))
+}
+
+pub async fn issue_page(
+    issue_id: web::Path<Uuid>,
+    pool: web::Data<PgPool>,
+    base_url: web::Data<ApplicationBaseUrl>,
+) -> Result<HttpResponse, actix_web::Error> {
+    let Some(issue) = get_published_issue(&pool, issue_id.into_inner()).await.map_err(e500)? else {
+        return Ok(HttpResponse::NotFound().finish());
+    };
+
+    Ok(HttpResponse::Ok()
+        .content_type(ContentType::html())
+        .body(format!(
+            r#"<!DOCTYPE html>
+<html lang="en">
+<head>
+    <meta charset="UTF-8">
+    <meta name="viewport" content="width=device-width, initial-scale=1.0">
+    <title>{title}</title>
+</head>
+<body>
+    <h1>{title}</h1>
+    <p><i>{byline}</i></p>
+    <article>
+        {html_content}
+    </article>
+    <p><a href="/issues"><- All issues</a></p>
+</body>
+</html>"#,
+            title = htmlescape::encode_minimal(&issue.title),
+            byline = byline(&issue),
+            html_content = issue.sanitised_html(&base_url.0),
+        )))
+}
+
+fn byline(issue: &PublishedIssue) -> String {
+    format!("Published on {}", issue.published_at.format("%Y-%m-%d"))
+}
@@ -0,0 +1,174 @@
+use actix_web::{http::header::ContentType, web, HttpRequest, HttpResponse};
+use actix_web_flash_messages::{FlashMessage, IncomingFlashMessages};
+use anyhow::Context;
+use chrono::{Duration, Utc};
+use sqlx::PgPool;
+use std::fmt::Write;
+use tracing::Instrument;
+
+use crate::{
+    authentication::{
+        check_ip_throttle,
+        create_reset_token,
+        get_last_reset_token_issued_at,
+        record_ip_request,
+        LoginThrottle,
+        LoginThrottlePolicy,
+        RESET_COOLDOWN_MINUTES,
+    },
+    domain::SubscriberEmail,
+    email_client::{EmailClient, EmailMessage, EmailProvider},
+    startup::{ApplicationBaseUrl, TrustedProxies},
+    utils::{client_ip, e500, see_other},
+};
+
+pub async fn forgot_password_form(flash_messages: IncomingFlashMessages) -> HttpResponse {
+    let mut msg_html = String::new();
+    for m in flash_messages.iter() {
+        writeln!(msg_html, "<p><i>{}</i></p>", m.content()).unwrap();
+    }
+
+    HttpResponse::Ok()
+        .content_type(ContentType::html())
+        .body(format!(
+            r#"<!DOCTYPE html>
+<html lang="en">
+<head>
+    <meta charset="UTF-8">
+    <meta name="viewport" content="width=device-width, initial-scale=1.0">
+    <title>Forgot password</title>
+</head>
+<body>
+    {msg_html}
+    <p>Enter your username: we will email you a link to choose a new password.</p>
+    <form action="/login/forgot" method="post">
+        <label>Username
+            <input type="text" placeholder="Enter your username" name="username">
+        </label>
+        <button type="submit">Send reset link</button>
+    </form>
+    <p><a href="/login"><- Back to login</a></p>
+</body>
+</html>"#,
+        ))
+}
+
+#[derive(serde::Deserialize)]
+pub struct ForgotFormData {
+    username: String,
+}
+
+/// Responds the same way, and as quickly, whether the username exists or
+/// not: the link is sent in the background. Each request counts against the
+/// client IP in the login throttle, so that nobody can flood an inbox.
+#[tracing::instrument(
+    name = "Request a password reset",
+    skip(form, request, pool, email_client, base_url, throttle_policy, trusted_proxies),
+    fields(username = %form.username)
+)]
+pub async fn forgot_password(
+    form: web::Form<ForgotFormData>,
+    request: HttpRequest,
+    pool: web::Data<PgPool>,
+    email_client: web::Data<EmailClient>,
+    base_url: web::Data<ApplicationBaseUrl>,
+    throttle_policy: web::Data<LoginThrottlePolicy>,
+    trusted_proxies: web::Data<TrustedProxies>,
+) -> Result<HttpResponse, actix_web::Error> {
+    let client_ip = client_ip(&request, &trusted_proxies.0);
+    match check_ip_throttle(&client_ip, &throttle_policy, &pool)
+        .await
+        .map_err(e500)?
+    {
+        LoginThrottle::LockedOut => {
+            tracing::warn!("Not sending a password reset link to a locked out client.");
+            return Ok(reset_requested());
+        }
+        LoginThrottle::Allowed(delay) if !delay.is_zero() => tokio::time::sleep(delay).await,
+        LoginThrottle::Allowed(_) => {}
+    }
+    record_ip_request(&client_ip, &throttle_policy, &pool)
+        .await
+        .map_err(e500)?;
+
+    let username = form.0.username;
+    let span = tracing::info_span!("Send a password reset link", %username);
+    tokio::spawn(
+        async move {
+            if let Err(e) = send_reset_link(&username, &pool, &email_client, &base_url.0).await {
+                tracing::error!(
+                    error.cause_chain = ?e,
+                    error.message = %e,
+                    "Failed to send a password reset link."
+                );
+            }
+        }
+        .instrument(span),
+    );
+
+    Ok(reset_requested())
+}
+
+/// The same whatever happened: it must not tell whether the username exists.
+fn reset_requested() -> HttpResponse {
+    FlashMessage::info(
+        "If that account exists and has an email address, \
+        we have sent it a link to reset the password.",
+    )
+    .send();
+    see_other("/login")
+}
+
+async fn send_reset_link(
+    username: &str,
+    pool: &PgPool,
+    email_client: &EmailClient,
+    base_url: &str,
+) -> Result<(), anyhow::Error> {
+    let user = sqlx::query!(
+        r#"
+        SELECT user_id, email
+        FROM users
+        WHERE username = $1 AND disabled_at IS NULL AND email_confirmed_at IS NOT NULL
+        "#,
+        username,
+    )
+    .fetch_optional(pool)
+    .await
+    .context("Failed to retrieve the user asking for a password reset.")?;
+    let Some((user_id, Some(email))) = user.map(|user| (user.user_id, user.email)) else {
+        tracing::info!("There is no active user with a confirmed email address under that username.");
+        return Ok(());
+    };
+    let email = SubscriberEmail::parse(email)
+        .map_err(anyhow::Error::msg)
+        .context("The stored user email is invalid.")?;
+
+    let last_sent_at = get_last_reset_token_issued_at(user_id, pool).await?;
+    if last_sent_at.is_some_and(|sent_at| Utc::now() - sent_at < Duration::minutes(RESET_COOLDOWN_MINUTES)) {
+        tracing::info!("Not resending a password reset link that was sent moments ago.");
+        return Ok(());
+    }
+    let token = create_reset_token(user_id, pool).await?;
+    let reset_link = format!("{}/login/reset?token={}", base_url, token);
+    let message = EmailMessage::builder(email, "Reset your password")
+        .html_content(format!(
+            "Someone asked to reset the password of your newsletter account.<br />\
+            Click <a href=\"{}\">here</a> within an hour to choose a new one. \
+            If it was not you, you can ignore this email.",
+            reset_link
+        ))
+        .text_content(format!(
+            "Someone asked to reset the password of your newsletter account.\n\
+            Visit {} within an hour to choose a new one. \
+            If it was not you, you can ignore this email.",
+            reset_link
+        ))
+        .tag("password-reset")
+        .build();
+    email_client
+        .send(&message)
+        .await
+        .context("Failed to send a password reset email.")?;
+    Ok(())
+}
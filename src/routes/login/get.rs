@@ -41,6 +41,7 @@ pub async fn login_form(
 
         <button type="submit">Login</button>
     </form>
+    <p><a href="/login/forgot">Forgot your password?</a></p>
     
 </body>
 </html>"#,
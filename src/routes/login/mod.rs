@@ -1,5 +1,11 @@
+mod forgot;
 mod get;
 mod post;
+mod reset;
+mod two_factor;
 
+pub use forgot::{forgot_password, forgot_password_form};
 pub use get::login_form;
-pub use post::login;
\ No newline at end of file
+pub use post::login;
+pub use reset::{reset_password, reset_password_form};
+pub use two_factor::{two_factor, two_factor_form};
@@ -1,8 +1,22 @@
-use actix_web::{error::InternalError, http::header::LOCATION, web, HttpResponse};
+use actix_web::{error::InternalError, http::header::LOCATION, web, HttpRequest, HttpResponse};
 use actix_web_flash_messages::FlashMessage;
 use sqlx::PgPool;
+use uuid::Uuid;
 use crate::session_state::TypedSession;
-use crate::authentication::{validate_credentials, AuthError, Credentials};
+use crate::startup::TrustedProxies;
+use crate::utils::client_ip;
+use crate::authentication::{
+    check_login_throttle,
+    clear_failed_logins,
+    get_session_version,
+    get_totp_secret,
+    record_failed_login,
+    validate_credentials,
+    AuthError,
+    Credentials,
+    LoginThrottle,
+    LoginThrottlePolicy,
+};
 
 #[derive(serde::Deserialize)]
 pub struct FormData {
@@ -14,12 +28,15 @@ pub struct FormData {
 pub enum LoginError {
     #[error("Authentication failed")]
     AuthError(#[source] anyhow::Error),
+    /// Deliberately vague: it must not tell whether the username exists.
+    #[error("Too many failed login attempts. Please try again later.")]
+    TooManyAttempts,
     #[error(transparent)]
     UnexpectedError(#[from] anyhow::Error),
 }
 
 #[tracing::instrument(
-    skip(form, pool, session),
+    skip(form, request, pool, session, throttle_policy, trusted_proxies),
     fields(
         username = tracing::field::Empty,
         user_id = tracing::field::Empty
@@ -27,27 +44,64 @@ pub enum LoginError {
 )]
 pub async fn login(
     form: web::Form<FormData>,
+    request: HttpRequest,
     pool: web::Data<PgPool>,
     session: TypedSession,
+    throttle_policy: web::Data<LoginThrottlePolicy>,
+    trusted_proxies: web::Data<TrustedProxies>,
 ) -> Result<HttpResponse, InternalError<LoginError>> {
+    let username = form.0.username;
     let credentials = Credentials {
-        username: form.0.username,
+        username: username.clone(),
         password: form.0.password,
     };
-    tracing::Span::current().record("username", &tracing::field::display(&credentials.username));
+    tracing::Span::current().record("username", tracing::field::display(&credentials.username));
+    let client_ip = client_ip(&request, &trusted_proxies.0);
+
+    match check_login_throttle(&username, &client_ip, &throttle_policy, &pool)
+        .await
+        .map_err(|e| login_redirect(LoginError::UnexpectedError(e)))?
+    {
+        LoginThrottle::LockedOut => return Err(login_redirect(LoginError::TooManyAttempts)),
+        LoginThrottle::Allowed(delay) if !delay.is_zero() => tokio::time::sleep(delay).await,
+        LoginThrottle::Allowed(_) => {}
+    }
 
     match validate_credentials(credentials, &pool).await {
         Ok(user_id) => {
-            tracing::Span::current().record("user_id", &tracing::field::display(&user_id));
+            tracing::Span::current().record("user_id", tracing::field::display(&user_id));
+            let totp_enabled = get_totp_secret(user_id, &pool)
+                .await
+                .map_err(|e| login_redirect(LoginError::UnexpectedError(e)))?
+                .is_some();
             session.renew();
-            session.insert_user_id(user_id)
-                .map_err(|e| login_redirect(LoginError::UnexpectedError(e.into())))?;
+            if totp_enabled {
+                // Half-authenticated: `user_id` is only set once the code has been checked.
+                session.insert_two_factor_user_id(user_id)
+                    .map_err(|e| login_redirect(LoginError::UnexpectedError(e.into())))?;
+                return Ok(HttpResponse::SeeOther()
+                    .insert_header((LOCATION, "/login/2fa"))
+                    .finish());
+            }
+            start_session(&session, user_id, &pool)
+                .await
+                .map_err(|e| login_redirect(LoginError::UnexpectedError(e)))?;
+            // Only a complete login wipes the failures: with two-factor
+            // authentication, that happens once the code has been checked.
+            clear_failed_logins(&username, &pool)
+                .await
+                .map_err(|e| login_redirect(LoginError::UnexpectedError(e)))?;
             Ok(HttpResponse::SeeOther()
                 .insert_header((LOCATION, "/admin/dashboard"))
                 .finish()
             )
         }
         Err(e) => {
+            if let AuthError::InvalidCredentials(_) = e {
+                record_failed_login(&username, &client_ip, &throttle_policy, &pool)
+                    .await
+                    .map_err(|e| login_redirect(LoginError::UnexpectedError(e)))?;
+            }
             let e = match e {
                 AuthError::InvalidCredentials(_) => LoginError::AuthError(e.into()),
                 AuthError::UnexpectedError(_) => LoginError::UnexpectedError(e.into()), 
@@ -73,3 +127,15 @@ fn login_redirect(e: LoginError) -> InternalError<LoginError> {
         .finish();
     InternalError::from_response(e, response)
 }
+
+/// Marks the session as fully authenticated. The caller renews the session first.
+pub(super) async fn start_session(
+    session: &TypedSession,
+    user_id: Uuid,
+    pool: &PgPool,
+) -> Result<(), anyhow::Error> {
+    let session_version = get_session_version(user_id, pool).await?;
+    session.insert_user_id(user_id)?;
+    session.insert_session_version(session_version)?;
+    Ok(())
+}
@@ -0,0 +1,129 @@
+use std::ops::DerefMut;
+
+use actix_web::{http::header::ContentType, web, HttpResponse};
+use actix_web_flash_messages::{FlashMessage, IncomingFlashMessages};
+use anyhow::Context;
+use sqlx::PgPool;
+use std::fmt::Write;
+
+use crate::{
+    authentication::{change_password, consume_reset_token, invalidate_sessions, is_current_password, reset_token_is_valid},
+    domain::AdminPassword,
+    utils::{e500, see_other},
+};
+
+const INVALID_LINK: &str = "This reset link is invalid or has expired. Please ask for a new one.";
+
+#[derive(serde::Deserialize)]
+pub struct ResetParameters {
+    token: String,
+}
+
+pub async fn reset_password_form(
+    parameters: web::Query<ResetParameters>,
+    pool: web::Data<PgPool>,
+    flash_messages: IncomingFlashMessages,
+) -> Result<HttpResponse, actix_web::Error> {
+    if !reset_token_is_valid(&parameters.token, &pool).await.map_err(e500)? {
+        FlashMessage::error(INVALID_LINK).send();
+        return Ok(see_other("/login/forgot"));
+    }
+    let mut msg_html = String::new();
+    for m in flash_messages.iter() {
+        writeln!(msg_html, "<p><i>{}</i></p>", m.content()).unwrap();
+    }
+    let token = htmlescape::encode_attribute(&parameters.token);
+
+    Ok(HttpResponse::Ok()
+        .content_type(ContentType::html())
+        .body(format!(
+            r#"<!DOCTYPE html>
+<html lang="en">
+<head>
+    <meta charset="UTF-8">
+    <meta name="viewport" content="width=device-width, initial-scale=1.0">
+    <title>Reset password</title>
+</head>
+<body>
+    {msg_html}
+    <form action="/login/reset" method="post">
+        <input type="hidden" name="token" value="{token}">
+        <label>New password
+            <input type="password" placeholder="Enter a new password" name="new_password">
+        </label>
+        <br>
+        <label>Confirm new password
+            <input type="password" placeholder="Type the new password again" name="new_password_check">
+        </label>
+        <br>
+        <button type="submit">Reset password</button>
+    </form>
+</body>
+</html>"#,
+        )))
+}
+
+#[derive(serde::Deserialize)]
+pub struct ResetFormData {
+    token: String,
+    new_password: String,
+    new_password_check: String,
+}
+
+/// The token is used up, the password changed and every open session logged
+/// out in one transaction: a failure part way leaves the link usable and the
+/// old password in place. The token's row lock keeps a link from being
+/// replayed concurrently.
+#[tracing::instrument(name = "Reset a password", skip_all, fields(user_id = tracing::field::Empty))]
+pub async fn reset_password(
+    form: web::Form<ResetFormData>,
+    pool: web::Data<PgPool>,
+) -> Result<HttpResponse, actix_web::Error> {
+    let ResetFormData { token, new_password, new_password_check } = form.0;
+    let retry_url = format!("/login/reset?token={}", urlencoding::encode(&token));
+    if new_password != new_password_check {
+        FlashMessage::error("You entered two different passwords.").send();
+        return Ok(see_other(&retry_url));
+    }
+    // Checked before the token is used up, so that the link can be retried.
+    let new_password = match AdminPassword::parse(new_password, None) {
+        Ok(new_password) => new_password,
+        Err(e) => {
+            FlashMessage::error(e).send();
+            return Ok(see_other(&retry_url));
+        }
+    };
+    let mut transaction = pool
+        .begin()
+        .await
+        .context("Failed to acquire a Postgres connection from the pool.")
+        .map_err(e500)?;
+    let Some(user_id) = consume_reset_token(&token, &mut transaction).await.map_err(e500)? else {
+        FlashMessage::error(INVALID_LINK).send();
+        return Ok(see_other("/login/forgot"));
+    };
+    tracing::Span::current().record("user_id", tracing::field::display(&user_id));
+    // Only the stored hash is known here: the comparison `AdminPassword::parse`
+    // makes on a password change has to go through it. Dropping the
+    // transaction keeps the link usable.
+    if is_current_password(user_id, &new_password, transaction.deref_mut())
+        .await
+        .map_err(e500)?
+    {
+        FlashMessage::error("The new password must be different from the current one.").send();
+        return Ok(see_other(&retry_url));
+    }
+
+    change_password(user_id, new_password, transaction.deref_mut())
+        .await
+        .map_err(e500)?;
+    invalidate_sessions(user_id, transaction.deref_mut()).await.map_err(e500)?;
+    transaction
+        .commit()
+        .await
+        .context("Failed to commit SQL transaction to reset a password.")
+        .map_err(e500)?;
+
+    FlashMessage::info("Your password has been reset. You can now log in.").send();
+    Ok(see_other("/login"))
+}
@@ -0,0 +1,117 @@
+use actix_web::{http::header::ContentType, web, HttpRequest, HttpResponse};
+use actix_web_flash_messages::{FlashMessage, IncomingFlashMessages};
+use sqlx::PgPool;
+use std::fmt::Write;
+
+use crate::{
+    authentication::{
+        check_login_throttle,
+        clear_failed_logins,
+        record_failed_login,
+        verify_second_factor,
+        LoginThrottle,
+        LoginThrottlePolicy,
+    },
+    routes::admin::get_username,
+    session_state::TypedSession,
+    startup::TrustedProxies,
+    utils::{client_ip, e500, see_other},
+};
+
+use super::post::{start_session, LoginError};
+
+/// After this many wrong codes the password has to be entered again. Wrong
+/// codes also count towards the login throttle, which outlives the session.
+const MAX_ATTEMPTS: u32 = 5;
+
+pub async fn two_factor_form(
+    session: TypedSession,
+    flash_messages: IncomingFlashMessages,
+) -> Result<HttpResponse, actix_web::Error> {
+    if session.get_two_factor_user_id().map_err(e500)?.is_none() {
+        return Ok(see_other("/login"));
+    }
+    let mut msg_html = String::new();
+    for m in flash_messages.iter() {
+        writeln!(msg_html, "<p><i>{}</i></p>", m.content()).unwrap();
+    }
+
+    Ok(HttpResponse::Ok()
+        .content_type(ContentType::html())
+        .body(format!(
+            r#"<!DOCTYPE html>
+<html lang="en">
+<head>
+    <meta charset="UTF-8">
+    <meta name="viewport" content="width=device-width, initial-scale=1.0">
+    <title>Two-factor authentication</title>
+</head>
+<body>
+    {msg_html}
+    <form action="/login/2fa" method="post">
+        <label>Authentication code
+            <input type="text" inputmode="numeric" autocomplete="one-time-code" placeholder="123456" name="code">
+        </label>
+        <button type="submit">Verify</button>
+    </form>
+    <p>Lost your device? Enter one of your recovery codes instead.</p>
+</body>
+</html>"#,
+        )))
+}
+
+#[derive(serde::Deserialize)]
+pub struct TwoFactorFormData {
+    code: String,
+}
+
+/// Second step of the login: the session only becomes authenticated here.
+#[tracing::instrument(name = "Verify a login code", skip_all, fields(user_id = tracing::field::Empty))]
+pub async fn two_factor(
+    form: web::Form<TwoFactorFormData>,
+    request: HttpRequest,
+    pool: web::Data<PgPool>,
+    session: TypedSession,
+    throttle_policy: web::Data<LoginThrottlePolicy>,
+    trusted_proxies: web::Data<TrustedProxies>,
+) -> Result<HttpResponse, actix_web::Error> {
+    let Some(user_id) = session.get_two_factor_user_id().map_err(e500)? else {
+        return Ok(see_other("/login"));
+    };
+    tracing::Span::current().record("user_id", tracing::field::display(&user_id));
+    let username = get_username(user_id, &pool).await.map_err(e500)?;
+    let client_ip = client_ip(&request, &trusted_proxies.0);
+
+    match check_login_throttle(&username, &client_ip, &throttle_policy, &pool)
+        .await
+        .map_err(e500)?
+    {
+        LoginThrottle::LockedOut => {
+            session.login_out();
+            FlashMessage::error(LoginError::TooManyAttempts.to_string()).send();
+            return Ok(see_other("/login"));
+        }
+        LoginThrottle::Allowed(delay) if !delay.is_zero() => tokio::time::sleep(delay).await,
+        LoginThrottle::Allowed(_) => {}
+    }
+
+    if !verify_second_factor(user_id, &form.0.code, &pool).await.map_err(e500)? {
+        record_failed_login(&username, &client_ip, &throttle_policy, &pool)
+            .await
+            .map_err(e500)?;
+        let attempts = session.record_failed_two_factor_attempt().map_err(e500)?;
+        if attempts >= MAX_ATTEMPTS {
+            session.login_out();
+            FlashMessage::error("Too many invalid codes. Please log in again.").send();
+            return Ok(see_other("/login"));
+        }
+        FlashMessage::error("The code is invalid.").send();
+        return Ok(see_other("/login/2fa"));
+    }
+
+    session.renew();
+    session.remove_two_factor_state();
+    start_session(&session, user_id, &pool).await.map_err(e500)?;
+    clear_failed_logins(&username, &pool).await.map_err(e500)?;
+    Ok(see_other("/admin/dashboard"))
+}
@@ -1,13 +1,25 @@
 mod subscriptions;
 mod health_check;
 mod subscriptions_confirm;
+mod subscriptions_unsubscribe;
+mod subscriptions_preferences;
 mod home;
 mod login;
 mod admin;
+mod issues;
+mod email_webhooks;
+mod tracking;
+mod invitations;
 
 pub use subscriptions::*;
 pub use health_check::*;
 pub use subscriptions_confirm::*;
+pub use subscriptions_unsubscribe::*;
+pub use subscriptions_preferences::*;
 pub use home::*;
 pub use login::*;
-pub use admin::*;
\ No newline at end of file
+pub use admin::*;
+pub use issues::*;
+pub use email_webhooks::*;
+pub use tracking::*;
+pub use invitations::*;
\ No newline at end of file
@@ -6,37 +6,79 @@ use serde::Deserialize;
 use actix_web::{http::StatusCode, web, HttpResponse, ResponseError};
 use sqlx::{PgPool, Transaction};
 use uuid::Uuid;
-use chrono::Utc;
-use crate::{domain::NewSubscriber, email_client::EmailClient, startup::ApplicationBaseUrl};
+use chrono::{DateTime, Duration, Utc};
+use crate::{domain::{NewSubscriber, SubscriberEmail, TagName}, email_client::{EmailClient, EmailError, EmailMessage, EmailProvider}, startup::ApplicationBaseUrl};
 
 #[derive(Deserialize)]
 pub struct FormData {
     pub email: String,
     pub name: String,
+    /// Signup lists, one `lists` field per list.
+    #[serde(default)]
+    pub lists: Vec<String>,
 }
 
+/// The body is decoded with `serde_html_form` rather than `web::Form`, which
+/// cannot deserialize repeated fields such as `lists`.
 #[tracing::instrument(
     name = "Adding a new subscriber.",
-    skip(form, db_pool, email_client, base_url),
+    skip(body, db_pool, email_client, base_url),
     fields(
-        subscriber_email = %form.email,
-        subscriber_name = %form.name
+        subscriber_email = tracing::field::Empty,
+        subscriber_name = tracing::field::Empty
     )
 )]
 pub async fn subscriptions(
-    form: web::Form<FormData>,
+    body: web::Bytes,
     db_pool: web::Data<PgPool>,
     email_client: web::Data<EmailClient>,
     base_url: web::Data<ApplicationBaseUrl>
 ) -> Result<HttpResponse, SubscirbeError> {
+    let form: FormData = serde_html_form::from_bytes(&body)
+        .map_err(|e| SubscirbeError::ValidationError(e.to_string()))?;
+    tracing::Span::current()
+        .record("subscriber_email", tracing::field::display(&form.email))
+        .record("subscriber_name", tracing::field::display(&form.name));
+
     let mut transaction = db_pool.begin().await
         .context("Failed to acquire a Postgres connection from the pool.")?;
 
-    let new_subscriber = form.0.try_into()
+    let new_subscriber: NewSubscriber = form.try_into()
         .map_err(SubscirbeError::ValidationError)?;
 
-    let subscriber_id = insert_subscriber(&new_subscriber, &mut transaction).await
+    let inserted_id = insert_subscriber(&new_subscriber, &mut transaction).await
         .context("Failed to insert new subscriber in the database.")?;
+    let subscriber_id = match inserted_id {
+        Some(subscriber_id) => subscriber_id,
+        None => {
+            let existing = get_existing_subscriber(&new_subscriber, &mut transaction).await
+                .context("Failed to retrieve an existing subscriber from the database.")?;
+            match existing.status.as_str() {
+                "confirmed" => {
+                    tracing::info!("The subscriber has already confirmed their subscription.");
+                    return Ok(HttpResponse::Ok().finish());
+                }
+                "suppressed" => {
+                    tracing::info!("Not mailing an address that bounced or complained.");
+                    return Ok(HttpResponse::Ok().finish());
+                }
+                "unsubscribed" => {
+                    mark_subscriber_as_pending(existing.id, &mut transaction).await
+                        .context("Failed to re-subscribe a subscriber who had unsubscribed.")?;
+                }
+                _ => {
+                    let last_sent_at = get_last_token_issued_at(&mut transaction, existing.id).await
+                        .context("Failed to retrieve the last confirmation token of a subscriber.")?;
+                    if last_sent_at.is_some_and(|sent_at| Utc::now() - sent_at < Duration::minutes(RESEND_COOLDOWN_MINUTES)) {
+                        tracing::info!("Not resending a confirmation link that was sent moments ago.");
+                        return Ok(HttpResponse::Ok().finish());
+                    }
+                }
+            }
+            existing.id
+        }
+    };
+    add_to_signup_lists(&new_subscriber.lists, subscriber_id, &mut transaction).await?;
     let subscriber_token = generate_subscription_token();
     
     store_token(&mut transaction, subscriber_id, &subscriber_token).await
@@ -44,18 +86,29 @@ pub async fn subscriptions(
 
     transaction.commit().await.context("Failed to commit SQL transaction to store a new subscriber")?;
 
-    send_confirmation_email(
-        &email_client,
-        new_subscriber,
+    // The subscriber is already stored: if every email provider is down,
+    // signing up again after the cooldown resends the confirmation email.
+    if let Err(e) = send_confirmation_email(
+        email_client.get_ref(),
+        &new_subscriber.email,
         &base_url.0,
         &subscriber_token
-    ).await
-    .context("Failed to send a confirmation email.")?;
+    ).await {
+        tracing::error!(
+            error.cause_chain = ?e,
+            error.message = %e,
+            "Failed to send a confirmation email."
+        );
+    }
 
     Ok(HttpResponse::Ok().finish())
 }
 
-fn generate_subscription_token() -> String {
+/// Confirmation links can be requested without logging in, by signing up
+/// again or from the resend form: this caps how often an address gets one.
+pub const RESEND_COOLDOWN_MINUTES: i64 = 10;
+
+pub fn generate_subscription_token() -> String {
     let mut rng = rand::rng();
     std::iter::repeat_with(|| rng.sample(Alphanumeric))
         .map(char::from)
@@ -92,16 +145,35 @@ pub async fn store_token(
     Ok(())
 }
 
+#[tracing::instrument(
+    name = "Get the time the last subscription token was issued.",
+    skip(subscriber_id, transaction),
+)]
+pub async fn get_last_token_issued_at(
+    transaction: &mut Transaction<'_, sqlx::Postgres>,
+    subscriber_id: Uuid,
+) -> Result<Option<DateTime<Utc>>, sqlx::Error> {
+    sqlx::query_scalar!(
+        r#"
+        SELECT max(created_at)
+        FROM subscription_tokens
+        WHERE subscription_id = $1"#,
+        subscriber_id
+    )
+    .fetch_one(transaction.deref_mut())
+    .await
+}
+
 #[tracing::instrument(
     name = "Sending confirmation email to new subscriber.",
-    skip(email_client, new_subscriber, base_url, subscription_token),
+    skip(email_client, recipient, base_url, subscription_token),
 )]
-async fn send_confirmation_email(
-    email_client: &EmailClient,
-    new_subscriber: NewSubscriber,
+pub async fn send_confirmation_email(
+    email_client: &dyn EmailProvider,
+    recipient: &SubscriberEmail,
     base_url: &str,
     subscription_token: &str
-) -> Result<(), reqwest::Error> {
+) -> Result<(), EmailError> {
     let confirmation_link = format!("{}/subscriptions/confirm?subscription_token={}", base_url, subscription_token);
 
     let plain_body = format!(
@@ -115,16 +187,17 @@ async fn send_confirmation_email(
             confirmation_link
     );
         
-    email_client.send_email(
-        &new_subscriber.email,
-        "Welcome!",
-        &html_body,
-        &plain_body
-    ).await
-
+    let message = EmailMessage::builder(recipient.clone(), "Welcome!")
+        .html_content(html_body)
+        .text_content(plain_body)
+        .tag("confirmation")
+        .build();
+    email_client.send(&message).await?;
+    Ok(())
 }
 
 
+/// Returns `None` if a subscriber with the same email address already exists.
 #[tracing::instrument(
     name = "Saving new subscriber details in the database.",
     skip(new_subscriber, transaction),
@@ -132,26 +205,121 @@ async fn send_confirmation_email(
 pub async fn insert_subscriber(
     new_subscriber: &NewSubscriber,
     transaction: &mut Transaction<'_, sqlx::Postgres> 
-) -> Result<Uuid, sqlx::Error> {
+) -> Result<Option<Uuid>, sqlx::Error> {
     let subscriber_id = Uuid::new_v4();
-    sqlx::query!(
+    let unsubscribe_token = generate_subscription_token();
+    let inserted = sqlx::query!(
         r#"
-        insert into subscriptions (id, email, name, subscribed_at, status)
-        values ($1, $2, $3, $4, 'pending_confirmation')
+        insert into subscriptions (id, email, name, subscribed_at, status, unsubscribe_token)
+        values ($1, $2, $3, $4, 'pending_confirmation', $5)
+        on conflict (email) do nothing
         "#,
         subscriber_id,
         new_subscriber.email.as_ref(),
         new_subscriber.name.as_ref(),
-        Utc::now() 
+        Utc::now(),
+        unsubscribe_token
     ) 
     .execute(transaction.deref_mut())
     .await
     .map_err(|e| {
         tracing::error!("Failed to execute query: {:?}", e);
         e
-    })?;
+    })?
+    .rows_affected();
+
+    Ok((inserted > 0).then_some(subscriber_id))
+}
+
+struct ExistingSubscriber {
+    id: Uuid,
+    status: String,
+}
+
+#[tracing::instrument(
+    name = "Fetching an existing subscriber from the database.",
+    skip(new_subscriber, transaction),
+)]
+async fn get_existing_subscriber(
+    new_subscriber: &NewSubscriber,
+    transaction: &mut Transaction<'_, sqlx::Postgres>,
+) -> Result<ExistingSubscriber, sqlx::Error> {
+    sqlx::query_as!(
+        ExistingSubscriber,
+        r#"
+        select id, status from subscriptions
+        where email = $1
+        for update
+        "#,
+        new_subscriber.email.as_ref(),
+    )
+    .fetch_one(transaction.deref_mut())
+    .await
+}
+
+#[tracing::instrument(
+    name = "Moving an unsubscribed subscriber back to pending confirmation.",
+    skip(transaction),
+)]
+async fn mark_subscriber_as_pending(
+    subscriber_id: Uuid,
+    transaction: &mut Transaction<'_, sqlx::Postgres>,
+) -> Result<(), sqlx::Error> {
+    sqlx::query!(
+        r#"
+        update subscriptions
+        set status = 'pending_confirmation'
+        where id = $1
+        "#,
+        subscriber_id,
+    )
+    .execute(transaction.deref_mut())
+    .await?;
+    Ok(())
+}
+
+/// Only tags flagged as signup lists can be picked by subscribers themselves.
+#[tracing::instrument(
+    name = "Adding a subscriber to signup lists.",
+    skip(transaction),
+)]
+pub async fn add_to_signup_lists(
+    lists: &[TagName],
+    subscriber_id: Uuid,
+    transaction: &mut Transaction<'_, sqlx::Postgres>,
+) -> Result<(), SubscirbeError> {
+    let mut names: Vec<String> = lists.iter().map(|l| l.as_ref().to_owned()).collect();
+    names.sort();
+    names.dedup();
+
+    let tag_ids = sqlx::query!(
+        r#"
+        select tag_id from tags
+        where name = any($1) and signup_list
+        "#,
+        &names,
+    )
+    .fetch_all(transaction.deref_mut())
+    .await
+    .context("Failed to look up the signup lists.")?;
+    if tag_ids.len() < names.len() {
+        return Err(SubscirbeError::ValidationError("Unknown signup list.".into()));
+    }
 
-    Ok(subscriber_id)
+    let tag_ids: Vec<Uuid> = tag_ids.into_iter().map(|r| r.tag_id).collect();
+    sqlx::query!(
+        r#"
+        insert into subscription_tags (subscriber_id, tag_id)
+        select $1, unnest($2::uuid[])
+        on conflict do nothing
+        "#,
+        subscriber_id,
+        &tag_ids,
+    )
+    .execute(transaction.deref_mut())
+    .await
+    .context("Failed to add the subscriber to the signup lists.")?;
+    Ok(())
 }
 
 #[derive(Debug, thiserror::Error)]
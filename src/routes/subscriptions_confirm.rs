@@ -1,7 +1,24 @@
-use actix_web::{web, HttpResponse};
-use sqlx::PgPool;
+use std::ops::DerefMut;
+
+use actix_web::{http::{header::ContentType, StatusCode}, web, HttpResponse, ResponseError};
+use anyhow::Context;
+use chrono::{DateTime, Duration, Utc};
+use sqlx::{PgPool, Transaction};
 use uuid::Uuid;
 
+use crate::{
+    domain::SubscriberEmail,
+    email_client::EmailClient,
+    routes::{
+        generate_subscription_token,
+        get_last_token_issued_at,
+        send_confirmation_email,
+        store_token,
+        RESEND_COOLDOWN_MINUTES,
+    },
+    startup::{ApplicationBaseUrl, SubscriptionTokenTtl},
+};
+
 #[derive(serde::Deserialize)]
 pub struct Parameters {
     subscription_token: String,
@@ -9,57 +26,181 @@ pub struct Parameters {
 
 #[tracing::instrument(
     name = "Confirm a pending subscriber.",
-    skip(parameters, pool),
+    skip(parameters, pool, ttl),
 )]
 pub async fn confirm(
     parameters: web::Query<Parameters>,
     pool: web::Data<PgPool>,
-) -> HttpResponse {
-    let Ok(id) = get_subscriber_id_from_token(&pool, &parameters.subscription_token).await else {
-        return HttpResponse::InternalServerError().finish();
-    };
-
-    match id {
-        None => HttpResponse::Unauthorized().finish(),
-        Some(subscriber_id) => {
-            if confirm_subscriber(&pool, subscriber_id).await.is_err() {
-                return HttpResponse::InternalServerError().finish();
-            }
-            HttpResponse::Ok().finish()
-        }
+    ttl: web::Data<SubscriptionTokenTtl>,
+) -> Result<HttpResponse, ConfirmError> {
+    let mut transaction = pool.begin().await
+        .context("Failed to acquire a Postgres connection from the pool.")?;
+
+    let token = get_token(&mut transaction, &parameters.subscription_token).await
+        .context("Failed to retrieve the subscription token.")?
+        .ok_or(ConfirmError::UnknownToken)?;
+    if token.consumed_at.is_some() || token.status != "pending_confirmation" {
+        return Err(ConfirmError::not_pending(&token.status));
+    }
+    if Utc::now() - token.created_at > ttl.0 {
+        return Err(ConfirmError::Expired(parameters.0.subscription_token));
+    }
+
+    confirm_subscriber(&mut transaction, token.subscriber_id).await
+        .context("Failed to update the subscriber status to `confirmed`.")?;
+    consume_tokens(&mut transaction, token.subscriber_id).await
+        .context("Failed to mark the subscription tokens as used.")?;
+    transaction.commit().await
+        .context("Failed to commit SQL transaction to confirm a subscriber.")?;
+
+    Ok(HttpResponse::Ok()
+        .content_type(ContentType::html())
+        .body(confirmation_page(
+            "Subscription confirmed",
+            "<p>Thanks! Your subscription has been confirmed.</p>",
+        )))
+}
+
+#[derive(serde::Deserialize)]
+pub struct ResendFormData {
+    subscription_token: String,
+}
+
+/// Sends a fresh confirmation link to the subscriber an (usually expired) token belongs to,
+/// unless one was sent recently. The reply is the same either way.
+#[tracing::instrument(
+    name = "Resend a confirmation email.",
+    skip(form, pool, email_client, base_url),
+)]
+pub async fn resend_confirmation(
+    form: web::Form<ResendFormData>,
+    pool: web::Data<PgPool>,
+    email_client: web::Data<EmailClient>,
+    base_url: web::Data<ApplicationBaseUrl>,
+) -> Result<HttpResponse, ConfirmError> {
+    let mut transaction = pool.begin().await
+        .context("Failed to acquire a Postgres connection from the pool.")?;
+
+    let subscriber = get_subscriber_from_token(&mut transaction, &form.subscription_token).await
+        .context("Failed to retrieve the subscriber associated with the subscription token.")?
+        .ok_or(ConfirmError::UnknownToken)?;
+    if subscriber.status != "pending_confirmation" {
+        return Err(ConfirmError::not_pending(&subscriber.status));
+    }
+    let check_your_inbox = HttpResponse::Ok()
+        .content_type(ContentType::html())
+        .body(confirmation_page(
+            "Check your inbox",
+            "<p>We have sent you a new confirmation link. Please check your inbox.</p>",
+        ));
+    let last_sent_at = get_last_token_issued_at(&mut transaction, subscriber.id).await
+        .context("Failed to retrieve the last confirmation token of a subscriber.")?;
+    if last_sent_at.is_some_and(|sent_at| Utc::now() - sent_at < Duration::minutes(RESEND_COOLDOWN_MINUTES)) {
+        tracing::info!("Not resending a confirmation link that was sent moments ago.");
+        return Ok(check_your_inbox);
+    }
+    let email = SubscriberEmail::parse(subscriber.email)
+        .map_err(|e| anyhow::anyhow!(e))
+        .context("The stored subscriber email is invalid.")?;
+
+    let subscription_token = generate_subscription_token();
+    store_token(&mut transaction, subscriber.id, &subscription_token).await
+        .context("Failed to store a new confirmation token.")?;
+    transaction.commit().await
+        .context("Failed to commit SQL transaction to store a new confirmation token.")?;
+
+    // As for signups: the token is already stored, and resending once the
+    // cooldown is over sends a new link.
+    if let Err(e) = send_confirmation_email(email_client.get_ref(), &email, &base_url.0, &subscription_token).await {
+        tracing::error!(
+            error.cause_chain = ?e,
+            error.message = %e,
+            "Failed to send a confirmation email."
+        );
     }
+
+    Ok(check_your_inbox)
+}
+
+fn confirmation_page(title: &str, body: &str) -> String {
+    format!(
+        r#"<!DOCTYPE html>
+<html lang="en">
+<head>
+    <meta charset="UTF-8">
+    <meta name="viewport" content="width=device-width, initial-scale=1.0">
+    <title>{title}</title>
+</head>
+<body>
+    {body}
+</body>
+</html>"#,
+    )
+}
+
+struct SubscriptionToken {
+    subscriber_id: Uuid,
+    created_at: DateTime<Utc>,
+    consumed_at: Option<DateTime<Utc>>,
+    status: String,
 }
 
 #[tracing::instrument(
-    name = "Get subscriber_id from token.",
-    skip(token, pool),
+    name = "Get subscription token details.",
+    skip(token, transaction),
 )]
-async fn get_subscriber_id_from_token(
-    pool: &PgPool,
+async fn get_token(
+    transaction: &mut Transaction<'_, sqlx::Postgres>,
     token: &str,
-) -> Result<Option<Uuid>, sqlx::Error> {
-    let result = sqlx::query!(
+) -> Result<Option<SubscriptionToken>, sqlx::Error> {
+    sqlx::query_as!(
+        SubscriptionToken,
         r#"
-        SELECT subscription_id FROM subscription_tokens
-        WHERE subscription_token = $1"#,
+        SELECT t.subscription_id AS subscriber_id, t.created_at, t.consumed_at, s.status
+        FROM subscription_tokens t
+        JOIN subscriptions s ON s.id = t.subscription_id
+        WHERE t.subscription_token = $1
+        FOR UPDATE OF t"#,
         token
     )
-    .fetch_optional(pool)
+    .fetch_optional(transaction.deref_mut())
     .await
-    .map_err(|e| {
-        tracing::error!("Failed to execute query: {:?}", e);
-        e
-    })?;
+}
 
-    Ok(result.map(|r| r.subscription_id))
+struct TokenSubscriber {
+    id: Uuid,
+    email: String,
+    status: String,
+}
+
+#[tracing::instrument(
+    name = "Get subscriber from subscription token.",
+    skip(token, transaction),
+)]
+async fn get_subscriber_from_token(
+    transaction: &mut Transaction<'_, sqlx::Postgres>,
+    token: &str,
+) -> Result<Option<TokenSubscriber>, sqlx::Error> {
+    sqlx::query_as!(
+        TokenSubscriber,
+        r#"
+        SELECT s.id, s.email, s.status
+        FROM subscription_tokens t
+        JOIN subscriptions s ON s.id = t.subscription_id
+        WHERE t.subscription_token = $1
+        FOR UPDATE OF s"#,
+        token
+    )
+    .fetch_optional(transaction.deref_mut())
+    .await
 }
 
 #[tracing::instrument(
     name = "Update status of subscriber.",
-    skip(subscriber_id, pool),
+    skip(subscriber_id, transaction),
 )]
 async fn confirm_subscriber(
-    pool: &PgPool,
+    transaction: &mut Transaction<'_, sqlx::Postgres>,
     subscriber_id: Uuid,
 ) -> Result<(), sqlx::Error> {
     sqlx::query!(
@@ -69,13 +210,114 @@ async fn confirm_subscriber(
         WHERE id = $1"#,
         subscriber_id
     )
-    .execute(pool)
-    .await
-    .map_err(|e| {
-        tracing::error!("Failed to execute query: {:?}", e);
-        e
-    })?;
+    .execute(transaction.deref_mut())
+    .await?;
 
     Ok(())
 }
 
+/// Every outstanding token of the subscriber is spent, not just the one that was clicked:
+/// older links from re-sent emails must not keep working either.
+#[tracing::instrument(
+    name = "Mark subscription tokens as used.",
+    skip(subscriber_id, transaction),
+)]
+async fn consume_tokens(
+    transaction: &mut Transaction<'_, sqlx::Postgres>,
+    subscriber_id: Uuid,
+) -> Result<(), sqlx::Error> {
+    sqlx::query!(
+        r#"
+        UPDATE subscription_tokens
+        SET consumed_at = now()
+        WHERE subscription_id = $1 AND consumed_at IS NULL"#,
+        subscriber_id
+    )
+    .execute(transaction.deref_mut())
+    .await?;
+
+    Ok(())
+}
+
+#[derive(Debug, thiserror::Error)]
+pub enum ConfirmError {
+    #[error("There is no subscriber associated with the provided token.")]
+    UnknownToken,
+    #[error("The subscription token has expired.")]
+    Expired(String),
+    #[error("The subscription has already been confirmed.")]
+    AlreadyConfirmed,
+    #[error("The subscription is no longer active.")]
+    Inactive,
+    #[error("The subscription token was used before the subscriber signed up again.")]
+    Superseded,
+    #[error(transparent)]
+    UnexpectedError(#[from] anyhow::Error),
+}
+
+impl ConfirmError {
+    /// For used links, or links of a subscriber that is no longer waiting for
+    /// confirmation: they may have unsubscribed, or bounced, since. A used
+    /// link of a pending subscriber predates their latest signup.
+    fn not_pending(status: &str) -> Self {
+        match status {
+            "confirmed" => Self::AlreadyConfirmed,
+            "pending_confirmation" => Self::Superseded,
+            _ => Self::Inactive,
+        }
+    }
+}
+
+impl ResponseError for ConfirmError {
+    fn status_code(&self) -> StatusCode {
+        match self {
+            Self::UnknownToken => StatusCode::UNAUTHORIZED,
+            Self::Expired(_) => StatusCode::GONE,
+            Self::AlreadyConfirmed | Self::Inactive | Self::Superseded => StatusCode::CONFLICT,
+            Self::UnexpectedError(_) => StatusCode::INTERNAL_SERVER_ERROR,
+        }
+    }
+
+    fn error_response(&self) -> HttpResponse {
+        let page = match self {
+            Self::UnknownToken => confirmation_page(
+                "Invalid link",
+                "<p>This confirmation link is not valid. \
+                Please check that you copied the whole link from the email.</p>",
+            ),
+            Self::Expired(token) => confirmation_page(
+                "Link expired",
+                &format!(
+                    r#"<p>This confirmation link has expired.</p>
+    <form action="/subscriptions/confirm/resend" method="post">
+        <input type="hidden" name="subscription_token" value="{}">
+        <button type="submit">Send me a new link</button>
+    </form>"#,
+                    htmlescape::encode_attribute(token)
+                ),
+            ),
+            Self::AlreadyConfirmed => confirmation_page(
+                "Already confirmed",
+                "<p>This confirmation link has already been used. \
+                Your subscription is confirmed, there is nothing else to do.</p>",
+            ),
+            Self::Inactive => confirmation_page(
+                "Subscription inactive",
+                "<p>This subscription is no longer active, so this link cannot be used. \
+                Subscribe again to start receiving the newsletter.</p>",
+            ),
+            Self::Superseded => confirmation_page(
+                "Link replaced",
+                "<p>This confirmation link was replaced when you subscribed again. \
+                Please check your inbox for the latest link.</p>",
+            ),
+            Self::UnexpectedError(_) => confirmation_page(
+                "Something went wrong",
+                "<p>We could not confirm your subscription. Please try again later.</p>",
+            ),
+        };
+        HttpResponse::build(self.status_code())
+            .content_type(ContentType::html())
+            .body(page)
+    }
+}
@@ -0,0 +1,296 @@
+use std::{fmt::Write, ops::DerefMut};
+
+use actix_web::{http::{header::ContentType, StatusCode}, web, HttpResponse, ResponseError};
+use actix_web_flash_messages::{FlashMessage, IncomingFlashMessages};
+use anyhow::Context;
+use chrono::{DateTime, Duration, Utc};
+use sqlx::{PgPool, Transaction};
+use uuid::Uuid;
+
+use crate::{
+    domain::SubscriberPreferences,
+    routes::{add_to_signup_lists, unsubscribe_link, SubscirbeError},
+    utils::see_other,
+};
+
+/// Subscribers manage their preferences with the same long-lived token
+/// that authorises the unsubscribe link.
+#[derive(serde::Deserialize)]
+pub struct PreferencesParameters {
+    token: String,
+}
+
+#[derive(serde::Deserialize)]
+pub struct PreferencesFormData {
+    pub name: String,
+    /// Signup lists, one `lists` field per list.
+    #[serde(default)]
+    pub lists: Vec<String>,
+    pub plain_text_only: Option<String>,
+    pub pause_weeks: Option<String>,
+}
+
+pub fn preferences_link(base_url: &str, token: &str) -> String {
+    format!("{}/subscriptions/preferences?token={}", base_url, token)
+}
+
+struct CurrentPreferences {
+    id: Uuid,
+    name: String,
+    plain_text_only: bool,
+    paused_until: Option<DateTime<Utc>>,
+}
+
+#[tracing::instrument(
+    name = "Show the subscriber preferences page.",
+    skip(parameters, pool, flash_messages),
+)]
+pub async fn preferences_form(
+    parameters: web::Query<PreferencesParameters>,
+    pool: web::Data<PgPool>,
+    flash_messages: IncomingFlashMessages,
+) -> Result<HttpResponse, PreferencesError> {
+    let mut msg_html = String::new();
+    for m in flash_messages.iter() {
+        writeln!(msg_html, "<p><i>{}</i></p>", m.content()).unwrap();
+    }
+    let subscriber = get_current_preferences(&pool, &parameters.token)
+        .await
+        .context("Failed to retrieve the subscriber associated with the token.")?
+        .ok_or(PreferencesError::UnknownToken)?;
+
+    let lists = sqlx::query!(
+        r#"
+        SELECT t.name, st.subscriber_id IS NOT NULL AS "selected!"
+        FROM tags t
+        LEFT JOIN subscription_tags st ON st.tag_id = t.tag_id AND st.subscriber_id = $1
+        WHERE t.signup_list
+        ORDER BY t.name
+        "#,
+        subscriber.id,
+    )
+    .fetch_all(pool.get_ref())
+    .await
+    .context("Failed to retrieve the signup lists.")?;
+    let mut lists_html = String::new();
+    for list in &lists {
+        writeln!(
+            lists_html,
+            r#"<label><input type="checkbox" name="lists" value="{}"{}> {}</label><br>"#,
+            list.name,
+            if list.selected { " checked" } else { "" },
+            list.name,
+        )
+        .unwrap();
+    }
+    if lists.is_empty() {
+        lists_html.push_str("<p>There are no lists to choose from.</p>");
+    }
+    let pause_html = match subscriber.paused_until.filter(|until| *until > Utc::now()) {
+        Some(until) => format!(
+            "<p>Delivery is paused until {}. Pause for 0 weeks to resume it now.</p>",
+            until.format("%Y-%m-%d %H:%M UTC")
+        ),
+        None => String::new(),
+    };
+
+    Ok(HttpResponse::Ok()
+        .content_type(ContentType::html())
+        .body(format!(
+            r#"<!DOCTYPE html>
+<html lang="en">
+<head>
+    <meta charset="UTF-8">
+    <meta name="viewport" content="width=device-width, initial-scale=1.0">
+    <title>Your Preferences</title>
+</head>
+<body>
+    {msg_html}
+    <form action="{action}" method="post">
+        <label>Name:<br>
+            <input type="text" name="name" value="{name}">
+        </label>
+        <p>Lists:</p>
+        {lists_html}
+        <label>
+            <input type="checkbox" name="plain_text_only" value="on"{plain_text_checked}>
+            Send me plain-text emails only
+        </label>
+        {pause_html}
+        <label>Pause delivery for (weeks, leave empty to keep receiving issues):<br>
+            <input type="number" name="pause_weeks" min="0" max="52">
+        </label>
+        <br>
+        <button type="submit">Save preferences</button>
+    </form>
+    <p><a href="{unsubscribe}">Unsubscribe</a></p>
+</body>
+</html>"#,
+            action = htmlescape::encode_attribute(&preferences_link("", &parameters.token)),
+            name = htmlescape::encode_attribute(&subscriber.name),
+            plain_text_checked = if subscriber.plain_text_only { " checked" } else { "" },
+            unsubscribe = htmlescape::encode_attribute(&unsubscribe_link("", &parameters.token)),
+        )))
+}
+
+/// The body is decoded with `serde_html_form`, as `lists` is a repeated field.
+#[tracing::instrument(
+    name = "Update the subscriber preferences.",
+    skip(parameters, body, pool),
+)]
+pub async fn update_preferences(
+    parameters: web::Query<PreferencesParameters>,
+    body: web::Bytes,
+    pool: web::Data<PgPool>,
+) -> Result<HttpResponse, PreferencesError> {
+    let preferences_url = preferences_link("", &parameters.token);
+    let mut transaction = pool.begin().await
+        .context("Failed to acquire a Postgres connection from the pool.")?;
+    let subscriber_id = get_subscriber_id(&mut transaction, &parameters.token)
+        .await
+        .context("Failed to retrieve the subscriber associated with the token.")?
+        .ok_or(PreferencesError::UnknownToken)?;
+
+    let preferences = serde_html_form::from_bytes::<PreferencesFormData>(&body)
+        .map_err(|e| e.to_string())
+        .and_then(SubscriberPreferences::try_from);
+    let preferences = match preferences {
+        Ok(preferences) => preferences,
+        Err(e) => {
+            FlashMessage::error(htmlescape::encode_minimal(&e)).send();
+            return Ok(see_other(&preferences_url));
+        }
+    };
+
+    update_subscriber(&mut transaction, subscriber_id, &preferences).await
+        .context("Failed to update the subscriber preferences.")?;
+    clear_signup_lists(&mut transaction, subscriber_id).await
+        .context("Failed to clear the subscriber's signup lists.")?;
+    match add_to_signup_lists(&preferences.lists, subscriber_id, &mut transaction).await {
+        Ok(()) => {}
+        Err(SubscirbeError::ValidationError(e)) => {
+            FlashMessage::error(e).send();
+            return Ok(see_other(&preferences_url));
+        }
+        Err(SubscirbeError::UnexpectedError(e)) => return Err(e.into()),
+    }
+    transaction.commit().await
+        .context("Failed to commit SQL transaction to update the subscriber preferences.")?;
+
+    FlashMessage::info("Your preferences have been saved.").send();
+    Ok(see_other(&preferences_url))
+}
+
+#[tracing::instrument(
+    name = "Get the current subscriber preferences.",
+    skip(token, pool),
+)]
+async fn get_current_preferences(
+    pool: &PgPool,
+    token: &str,
+) -> Result<Option<CurrentPreferences>, sqlx::Error> {
+    sqlx::query_as!(
+        CurrentPreferences,
+        r#"
+        SELECT id, name, plain_text_only, paused_until
+        FROM subscriptions
+        WHERE unsubscribe_token = $1
+        "#,
+        token
+    )
+    .fetch_optional(pool)
+    .await
+}
+
+#[tracing::instrument(
+    name = "Get subscriber_id from preferences token.",
+    skip(token, transaction),
+)]
+async fn get_subscriber_id(
+    transaction: &mut Transaction<'_, sqlx::Postgres>,
+    token: &str,
+) -> Result<Option<Uuid>, sqlx::Error> {
+    let result = sqlx::query!(
+        r#"
+        SELECT id FROM subscriptions
+        WHERE unsubscribe_token = $1
+        FOR UPDATE
+        "#,
+        token
+    )
+    .fetch_optional(transaction.deref_mut())
+    .await?;
+    Ok(result.map(|r| r.id))
+}
+
+#[tracing::instrument(
+    name = "Save the subscriber preferences.",
+    skip(transaction, preferences),
+)]
+async fn update_subscriber(
+    transaction: &mut Transaction<'_, sqlx::Postgres>,
+    subscriber_id: Uuid,
+    preferences: &SubscriberPreferences,
+) -> Result<(), sqlx::Error> {
+    let paused_until = preferences
+        .pause_weeks
+        .filter(|weeks| *weeks > 0)
+        .map(|weeks| Utc::now() + Duration::weeks(weeks.into()));
+    sqlx::query!(
+        r#"
+        UPDATE subscriptions
+        SET
+            name = $2,
+            plain_text_only = $3,
+            paused_until = CASE WHEN $4 THEN $5 ELSE paused_until END
+        WHERE id = $1
+        "#,
+        subscriber_id,
+        preferences.name.as_ref(),
+        preferences.plain_text_only,
+        preferences.pause_weeks.is_some(),
+        paused_until,
+    )
+    .execute(transaction.deref_mut())
+    .await?;
+    Ok(())
+}
+
+/// Tags set by admins are kept: subscribers only manage signup lists.
+#[tracing::instrument(
+    name = "Remove a subscriber from all signup lists.",
+    skip(transaction),
+)]
+async fn clear_signup_lists(
+    transaction: &mut Transaction<'_, sqlx::Postgres>,
+    subscriber_id: Uuid,
+) -> Result<(), sqlx::Error> {
+    sqlx::query!(
+        r#"
+        DELETE FROM subscription_tags
+        WHERE subscriber_id = $1
+            AND tag_id IN (SELECT tag_id FROM tags WHERE signup_list)
+        "#,
+        subscriber_id
+    )
+    .execute(transaction.deref_mut())
+    .await?;
+    Ok(())
+}
+
+#[derive(Debug, thiserror::Error)]
+pub enum PreferencesError {
+    #[error("There is no subscriber associated with the provided token.")]
+    UnknownToken,
+    #[error(transparent)]
+    UnexpectedError(#[from] anyhow::Error),
+}
+
+impl ResponseError for PreferencesError {
+    fn status_code(&self) -> StatusCode {
+        match self {
+            Self::UnknownToken => StatusCode::UNAUTHORIZED,
+            Self::UnexpectedError(_) => StatusCode::INTERNAL_SERVER_ERROR,
+        }
+    }
+}
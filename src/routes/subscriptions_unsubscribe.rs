@@ -0,0 +1,141 @@
+use actix_web::{http::{header::ContentType, StatusCode}, web, HttpResponse, ResponseError};
+use anyhow::Context;
+use sqlx::PgPool;
+use uuid::Uuid;
+
+#[derive(serde::Deserialize)]
+pub struct UnsubscribeParameters {
+    token: String,
+}
+
+pub fn unsubscribe_link(base_url: &str, unsubscribe_token: &str) -> String {
+    format!("{}/subscriptions/unsubscribe?token={}", base_url, unsubscribe_token)
+}
+
+#[tracing::instrument(
+    name = "Show the unsubscribe confirmation page.",
+    skip(parameters, pool),
+)]
+pub async fn unsubscribe_form(
+    parameters: web::Query<UnsubscribeParameters>,
+    pool: web::Data<PgPool>,
+) -> Result<HttpResponse, UnsubscribeError> {
+    get_subscriber_id_from_unsubscribe_token(&pool, &parameters.token)
+        .await
+        .context("Failed to retrieve the subscriber associated with the unsubscribe token.")?
+        .ok_or(UnsubscribeError::UnknownToken)?;
+
+    let action = htmlescape::encode_attribute(&unsubscribe_link("", &parameters.token));
+    Ok(HttpResponse::Ok()
+        .content_type(ContentType::html())
+        .body(format!(
+            r#"<!DOCTYPE html>
+<html lang="en">
+<head>
+    <meta charset="UTF-8">
+    <meta name="viewport" content="width=device-width, initial-scale=1.0">
+    <title>Unsubscribe</title>
+</head>
+<body>
+    <p>Do you want to stop receiving our newsletter?</p>
+    <form action="{action}" method="post">
+        <button type="submit">Unsubscribe</button>
+    </form>
+</body>
+</html>"#,
+        )))
+}
+
+/// Handles both the confirmation form and RFC 8058 one-click requests,
+/// which POST `List-Unsubscribe=One-Click` to the `List-Unsubscribe` URL.
+#[tracing::instrument(
+    name = "Unsubscribe a subscriber.",
+    skip(parameters, pool),
+)]
+pub async fn unsubscribe(
+    parameters: web::Query<UnsubscribeParameters>,
+    pool: web::Data<PgPool>,
+) -> Result<HttpResponse, UnsubscribeError> {
+    let subscriber_id = get_subscriber_id_from_unsubscribe_token(&pool, &parameters.token)
+        .await
+        .context("Failed to retrieve the subscriber associated with the unsubscribe token.")?
+        .ok_or(UnsubscribeError::UnknownToken)?;
+    mark_subscriber_as_unsubscribed(&pool, subscriber_id)
+        .await
+        .context("Failed to update the subscriber status to `unsubscribed`.")?;
+
+    Ok(HttpResponse::Ok()
+        .content_type(ContentType::html())
+        .body(
+            r#"<!DOCTYPE html>
+<html lang="en">
+<head>
+    <meta charset="UTF-8">
+    <meta name="viewport" content="width=device-width, initial-scale=1.0">
+    <title>Unsubscribed</title>
+</head>
+<body>
+    <p>You have been unsubscribed. You will not receive any more emails from us.</p>
+</body>
+</html>"#,
+        ))
+}
+
+#[tracing::instrument(
+    name = "Get subscriber_id from unsubscribe token.",
+    skip(token, pool),
+)]
+async fn get_subscriber_id_from_unsubscribe_token(
+    pool: &PgPool,
+    token: &str,
+) -> Result<Option<Uuid>, sqlx::Error> {
+    let result = sqlx::query!(
+        r#"
+        SELECT id FROM subscriptions
+        WHERE unsubscribe_token = $1"#,
+        token
+    )
+    .fetch_optional(pool)
+    .await?;
+
+    Ok(result.map(|r| r.id))
+}
+
+/// Suppressed addresses stay suppressed: signing up again would mail them.
+#[tracing::instrument(
+    name = "Mark subscriber as unsubscribed.",
+    skip(pool),
+)]
+async fn mark_subscriber_as_unsubscribed(
+    pool: &PgPool,
+    subscriber_id: Uuid,
+) -> Result<(), sqlx::Error> {
+    sqlx::query!(
+        r#"
+        UPDATE subscriptions
+        SET status = 'unsubscribed'
+        WHERE id = $1 AND status <> 'suppressed'"#,
+        subscriber_id
+    )
+    .execute(pool)
+    .await?;
+
+    Ok(())
+}
+
+#[derive(Debug, thiserror::Error)]
+pub enum UnsubscribeError {
+    #[error("There is no subscriber associated with the provided token.")]
+    UnknownToken,
+    #[error(transparent)]
+    UnexpectedError(#[from] anyhow::Error),
+}
+
+impl ResponseError for UnsubscribeError {
+    fn status_code(&self) -> StatusCode {
+        match self {
+            Self::UnknownToken => StatusCode::UNAUTHORIZED,
+            Self::UnexpectedError(_) => StatusCode::INTERNAL_SERVER_ERROR,
+        }
+    }
+}
@@ -0,0 +1,84 @@
+use actix_web::{http::header::{self, CacheControl, CacheDirective}, web, HttpResponse};
+use anyhow::Context;
+use sqlx::PgPool;
+use uuid::Uuid;
+
+use crate::{startup::HmacSecret, tracking::TrackingEvent};
+
+/// A transparent 1x1 GIF.
+const PIXEL: &[u8] = &[
+    0x47, 0x49, 0x46, 0x38, 0x39, 0x61, 0x01, 0x00, 0x01, 0x00, 0x80, 0x00, 0x00, 0x00, 0x00,
+    0x00, 0xff, 0xff, 0xff, 0x21, 0xf9, 0x04, 0x01, 0x00, 0x00, 0x00, 0x00, 0x2c, 0x00, 0x00,
+    0x00, 0x00, 0x01, 0x00, 0x01, 0x00, 0x00, 0x02, 0x02, 0x44, 0x01, 0x00, 0x3b,
+];
+
+/// The pixel is served whatever the token, so that broken tokens never show
+/// up as broken images.
+#[tracing::instrument(name = "Track an email open.", skip(token, pool, hmac_secret))]
+pub async fn track_open(
+    token: web::Path<String>,
+    pool: web::Data<PgPool>,
+    hmac_secret: web::Data<HmacSecret>,
+) -> HttpResponse {
+    match TrackingEvent::verify(&token, &hmac_secret.0) {
+        Some(event @ TrackingEvent::Open { .. }) => record_event(&pool, &event).await,
+        _ => tracing::warn!("Ignoring an open with an invalid tracking token."),
+    }
+    HttpResponse::Ok()
+        .content_type("image/gif")
+        .insert_header(CacheControl(vec![CacheDirective::NoStore]))
+        .body(PIXEL)
+}
+
+#[tracing::instrument(name = "Track a link click.", skip(token, pool, hmac_secret))]
+pub async fn track_click(
+    token: web::Path<String>,
+    pool: web::Data<PgPool>,
+    hmac_secret: web::Data<HmacSecret>,
+) -> HttpResponse {
+    let Some(event) = TrackingEvent::verify(&token, &hmac_secret.0) else {
+        return HttpResponse::NotFound().finish();
+    };
+    let Some(url) = event.url() else {
+        return HttpResponse::NotFound().finish();
+    };
+    record_event(&pool, &event).await;
+    HttpResponse::Found()
+        .insert_header((header::LOCATION, url))
+        .finish()
+}
+
+/// Failing to record an event must not break the link or the image,
+/// so errors are only logged.
+async fn record_event(pool: &PgPool, event: &TrackingEvent) {
+    if let Err(e) = insert_event(pool, event).await {
+        tracing::error!(
+            error.cause_chain = ?e,
+            error.message = %e,
+            "Failed to record a tracking event."
+        );
+    }
+}
+
+#[tracing::instrument(name = "Store a tracking event.", skip(pool))]
+async fn insert_event(pool: &PgPool, event: &TrackingEvent) -> Result<(), anyhow::Error> {
+    let event_type = match event {
+        TrackingEvent::Open { .. } => "open",
+        TrackingEvent::Click { .. } => "click",
+    };
+    sqlx::query!(
+        r#"
+        INSERT INTO tracking_events (event_id, newsletter_issue_id, subscriber_id, event_type, url)
+        VALUES ($1, $2, $3, $4, $5)
+        "#,
+        Uuid::new_v4(),
+        event.issue_id(),
+        event.subscriber_id(),
+        event_type,
+        event.url(),
+    )
+    .execute(pool)
+    .await
+    .context("Failed to insert a tracking event.")?;
+    Ok(())
+}
@@ -8,6 +8,10 @@ pub struct TypedSession(Session);
 
 impl TypedSession {
     const USER_ID_KEY: &'static str = "user_id";
+    const SESSION_VERSION_KEY: &'static str = "session_version";
+    const TWO_FACTOR_USER_ID_KEY: &'static str = "two_factor_user_id";
+    const TWO_FACTOR_ATTEMPTS_KEY: &'static str = "two_factor_attempts";
+    const PENDING_TOTP_SECRET_KEY: &'static str = "pending_totp_secret";
 
     pub fn renew(&self) {
         self.0.renew();
@@ -21,6 +25,52 @@ impl TypedSession {
         self.0.get(Self::USER_ID_KEY)
     }
 
+    pub fn insert_session_version(&self, session_version: i32) -> Result<(), SessionInsertError> {
+        self.0.insert(Self::SESSION_VERSION_KEY, session_version)
+    }
+
+    /// Sessions created before versions were introduced count as version 0.
+    pub fn get_session_version(&self) -> Result<i32, SessionGetError> {
+        Ok(self.0.get(Self::SESSION_VERSION_KEY)?.unwrap_or_default())
+    }
+
+    /// The password has been checked but the second factor has not: the
+    /// session stays anonymous until `/login/2fa` calls `remove_two_factor_state`
+    /// and then sets the user id.
+    pub fn insert_two_factor_user_id(&self, user_id: Uuid) -> Result<(), SessionInsertError> {
+        self.0.insert(Self::TWO_FACTOR_USER_ID_KEY, user_id)
+    }
+
+    pub fn get_two_factor_user_id(&self) -> Result<Option<Uuid>, SessionGetError> {
+        self.0.get(Self::TWO_FACTOR_USER_ID_KEY)
+    }
+
+    /// Returns the number of failed attempts so far, this one included.
+    pub fn record_failed_two_factor_attempt(&self) -> Result<u32, anyhow::Error> {
+        let attempts = self.0.get::<u32>(Self::TWO_FACTOR_ATTEMPTS_KEY)?.unwrap_or_default() + 1;
+        self.0.insert(Self::TWO_FACTOR_ATTEMPTS_KEY, attempts)?;
+        Ok(attempts)
+    }
+
+    /// Forgets the half-authenticated state, e.g. once the second factor has been checked.
+    pub fn remove_two_factor_state(&self) {
+        self.0.remove(Self::TWO_FACTOR_USER_ID_KEY);
+        self.0.remove(Self::TWO_FACTOR_ATTEMPTS_KEY);
+    }
+
+    /// A secret shown during enrolment, kept until the user confirms it with a code.
+    pub fn insert_pending_totp_secret(&self, secret: &str) -> Result<(), SessionInsertError> {
+        self.0.insert(Self::PENDING_TOTP_SECRET_KEY, secret)
+    }
+
+    pub fn get_pending_totp_secret(&self) -> Result<Option<String>, SessionGetError> {
+        self.0.get(Self::PENDING_TOTP_SECRET_KEY)
+    }
+
+    pub fn remove_pending_totp_secret(&self) {
+        self.0.remove(Self::PENDING_TOTP_SECRET_KEY);
+    }
+
     pub fn login_out(&self) {
         self.0.purge();
     }
@@ -1,13 +1,14 @@
+use actix_multipart::form::MultipartFormConfig;
 use actix_session::SessionMiddleware;
 use actix_web::{cookie::Key, dev::Server, middleware::from_fn, web, App, HttpServer};
 use actix_web_flash_messages::{storage::CookieMessageStore, FlashMessagesFramework};
 use sqlx::{PgPool, postgres::PgPoolOptions};
-use std::net::TcpListener;
+use std::net::{IpAddr, TcpListener};
 use crate::{
-    authentication::reject_anonymous_users,
-    configuration::{DatabaseSettings, Settings},
+    authentication::{reject_anonymous_users, LoginThrottlePolicy, require_editor, require_owner},
+    configuration::{ApplicationSettings, DatabaseSettings, Settings},
     email_client::EmailClient,
-    routes::{admin_dashboard, change_password, change_password_form, confirm, health_check, home, login, login_form, logout, publish_newsletter, publish_newsletter_form, subscriptions}
+    routes::{accept_invitation, accept_invitation_form, admin_dashboard, atom_feed, cancel_scheduled_issue, change_email, change_email_form, confirm_email_change, change_password, change_password_form, confirm, create_draft, create_tag, delete_user, disable_two_factor, disable_user, draft_preview, edit_draft_form, email_webhook, enable_two_factor, forgot_password, forgot_password_form, enable_user, failed_deliveries_csv, health_check, home, invite_user, issue_deliveries, issue_page, issue_stats, issues_index, list_drafts, list_tags, list_users, login, login_form, logout, preferences_form, publish_draft, reset_password, reset_password_form, resend_confirmation, retry_failed_deliveries, rss_feed, scheduled_issues, send_test_draft, sent_issues, subscriptions, tag_subscriber, track_click, track_open, unsubscribe, unsubscribe_form, two_factor, two_factor_form, two_factor_settings, update_draft, update_preferences}
 };
 use tracing_actix_web::TracingLogger;
 
@@ -17,15 +18,10 @@ pub struct Application {
 }
 
 impl Application {
-    pub async fn build(configuration: Settings) -> Result<Self, anyhow::Error> {
-        let sender_email = configuration.email_client.sender().expect("Invalid email");
-        let timeout = configuration.email_client.timeout();
-        let email_client = EmailClient::new(
-            configuration.email_client.base_url,
-            sender_email,
-            configuration.email_client.auth_token,
-            timeout,
-        );
+    /// `email_client` is shared with the delivery worker: the health endpoint
+    /// reports the state of its circuit breakers.
+    pub async fn build(configuration: Settings, email_client: EmailClient) -> Result<Self, anyhow::Error> {
+        let webhook_token = configuration.email_client.webhook_token.clone();
 
         let address = format!(
             "{}:{}",
@@ -39,8 +35,9 @@ impl Application {
             listener,
             connection_pool,
             email_client,
-            configuration.application.base_url,
-            configuration.application.hmac_secret,
+            webhook_token,
+            configuration.application,
+            configuration.login_throttle.policy(),
             configuration.redis_url,
         ).await?;
 
@@ -65,19 +62,29 @@ pub fn get_connection_pool(database_config: &DatabaseSettings) -> PgPool {
 
 pub struct ApplicationBaseUrl(pub String);
 pub struct HmacSecret(pub String);
+pub struct SubscriptionTokenTtl(pub chrono::Duration);
+pub struct EmailWebhookToken(pub String);
+pub struct TrustedProxies(pub Vec<IpAddr>);
 
 async fn run(
     listener: TcpListener,
     db_pool: PgPool,
     email_client: EmailClient,
-    base_url: String,
-    hmac_secret: String,
+    webhook_token: String,
+    application: ApplicationSettings,
+    login_throttle: LoginThrottlePolicy,
     redis_url: String,
 ) -> Result<Server, anyhow::Error> {
     let db_pool = web::Data::new(db_pool);
     let email_client = web::Data::new(email_client);
-    let base_url = web::Data::new(ApplicationBaseUrl(base_url));
-    let secret_key = Key::from(hmac_secret.as_bytes());
+    let base_url = web::Data::new(ApplicationBaseUrl(application.base_url.clone()));
+    let subscription_token_ttl =
+        web::Data::new(SubscriptionTokenTtl(application.subscription_token_ttl()));
+    let webhook_token = web::Data::new(EmailWebhookToken(webhook_token));
+    let secret_key = Key::from(application.hmac_secret.as_bytes());
+    let trusted_proxies = web::Data::new(TrustedProxies(application.trusted_proxies));
+    let hmac_secret = web::Data::new(HmacSecret(application.hmac_secret));
+    let login_throttle = web::Data::new(login_throttle);
     let message_store = CookieMessageStore::builder(
         secret_key.clone() 
     ).build();
@@ -94,20 +101,72 @@ async fn run(
                 web::scope("/admin")
                         .wrap(from_fn(reject_anonymous_users))
                         .route("/dashboard", web::get().to(admin_dashboard))
-                        .route("/newsletter", web::get().to(publish_newsletter_form))
-                        .route("/newsletter", web::post().to(publish_newsletter))
+                        .route("/newsletter/scheduled", web::get().to(scheduled_issues).wrap(from_fn(require_editor)))
+                        .route("/newsletter/scheduled/{issue_id}/cancel", web::post().to(cancel_scheduled_issue).wrap(from_fn(require_editor)))
+                        .route("/newsletter/drafts", web::get().to(list_drafts).wrap(from_fn(require_editor)))
+                        .route("/newsletter/drafts", web::post().to(create_draft).wrap(from_fn(require_editor)))
+                        .route("/newsletter/drafts/{draft_id}", web::get().to(edit_draft_form).wrap(from_fn(require_editor)))
+                        .route("/newsletter/drafts/{draft_id}", web::post().to(update_draft).wrap(from_fn(require_editor)))
+                        .route("/newsletter/drafts/{draft_id}/preview", web::get().to(draft_preview).wrap(from_fn(require_editor)))
+                        .route("/newsletter/drafts/{draft_id}/test", web::post().to(send_test_draft).wrap(from_fn(require_editor)))
+                        .route("/newsletter/drafts/{draft_id}/publish", web::post().to(publish_draft).wrap(from_fn(require_editor)))
+                        .route("/issues", web::get().to(sent_issues))
+                        .route("/issues/{issue_id}", web::get().to(issue_deliveries))
+                        .route("/issues/{issue_id}/retry", web::post().to(retry_failed_deliveries).wrap(from_fn(require_editor)))
+                        .route("/issues/{issue_id}/failures.csv", web::get().to(failed_deliveries_csv).wrap(from_fn(require_editor)))
+                        .route("/issues/{issue_id}/stats", web::get().to(issue_stats))
+                        .route("/tags", web::get().to(list_tags).wrap(from_fn(require_editor)))
+                        .route("/tags", web::post().to(create_tag).wrap(from_fn(require_editor)))
+                        .route("/tags/subscribers", web::post().to(tag_subscriber).wrap(from_fn(require_editor)))
                         .route("/password", web::get().to(change_password_form))
                         .route("/password", web::post().to(change_password))
+                        .route("/email", web::get().to(change_email_form))
+                        .route("/email", web::post().to(change_email))
+                        .route("/email/confirm", web::get().to(confirm_email_change))
+                        .route("/2fa", web::get().to(two_factor_settings))
+                        .route("/2fa", web::post().to(enable_two_factor))
+                        .route("/2fa/disable", web::post().to(disable_two_factor))
+                        .route("/users", web::get().to(list_users).wrap(from_fn(require_owner)))
+                        .route("/users", web::post().to(invite_user).wrap(from_fn(require_owner)))
+                        .route("/users/{user_id}/disable", web::post().to(disable_user).wrap(from_fn(require_owner)))
+                        .route("/users/{user_id}/enable", web::post().to(enable_user).wrap(from_fn(require_owner)))
+                        .route("/users/{user_id}/delete", web::post().to(delete_user).wrap(from_fn(require_owner)))
                         .route("/logout", web::post().to(logout))
             )
             .route("/health_check", web::get().to(health_check))
             .route("/subscriptions", web::post().to(subscriptions))
             .route("subscriptions/confirm", web::get().to(confirm))
+            .route("/subscriptions/confirm/resend", web::post().to(resend_confirmation))
+            .route("/subscriptions/unsubscribe", web::get().to(unsubscribe_form))
+            .route("/subscriptions/unsubscribe", web::post().to(unsubscribe))
+            .route("/subscriptions/preferences", web::get().to(preferences_form))
+            .route("/subscriptions/preferences", web::post().to(update_preferences))
+            .route("/issues", web::get().to(issues_index))
+            .route("/issues/{issue_id}", web::get().to(issue_page))
+            .route("/feed.xml", web::get().to(atom_feed))
+            .route("/rss.xml", web::get().to(rss_feed))
+            .route("/webhooks/email/{provider}", web::post().to(email_webhook))
+            .route("/t/o/{token}.gif", web::get().to(track_open))
+            .route("/t/c/{token}", web::get().to(track_click))
+            .route("/invitations/accept", web::get().to(accept_invitation_form))
+            .route("/invitations/accept", web::post().to(accept_invitation))
             .route("/login", web::get().to(login_form))
             .route("/login", web::post().to(login))
+            .route("/login/forgot", web::get().to(forgot_password_form))
+            .route("/login/forgot", web::post().to(forgot_password))
+            .route("/login/reset", web::get().to(reset_password_form))
+            .route("/login/reset", web::post().to(reset_password))
+            .route("/login/2fa", web::get().to(two_factor_form))
+            .route("/login/2fa", web::post().to(two_factor))
             .app_data(db_pool.clone())
             .app_data(email_client.clone())
             .app_data(base_url.clone())
+            .app_data(subscription_token_ttl.clone())
+            .app_data(webhook_token.clone())
+            .app_data(hmac_secret.clone())
+            .app_data(login_throttle.clone())
+            .app_data(trusted_proxies.clone())
+            .app_data(MultipartFormConfig::default().memory_limit(16 * 1024 * 1024))
     })
     .listen(listener)?
     .run();
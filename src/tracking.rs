@@ -0,0 +1,244 @@
+//! Open and click tracking for newsletter issues.
+//!
+//! Tracking URLs carry the issue, the subscriber and, for clicks, the target
+//! URL in a token signed with the application's `hmac_secret`, so they cannot
+//! be forged or turned into an open redirect.
+use base64::{engine::general_purpose::URL_SAFE_NO_PAD, Engine};
+use hmac::{Hmac, Mac};
+use sha2::Sha256;
+use uuid::Uuid;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum TrackingEvent {
+    Open {
+        issue_id: Uuid,
+        subscriber_id: Uuid,
+    },
+    Click {
+        issue_id: Uuid,
+        subscriber_id: Uuid,
+        url: String,
+    },
+}
+
+impl TrackingEvent {
+    pub fn issue_id(&self) -> Uuid {
+        match self {
+            Self::Open { issue_id, .. } | Self::Click { issue_id, .. } => *issue_id,
+        }
+    }
+
+    pub fn subscriber_id(&self) -> Uuid {
+        match self {
+            Self::Open { subscriber_id, .. } | Self::Click { subscriber_id, .. } => *subscriber_id,
+        }
+    }
+
+    /// The link target, for clicks.
+    pub fn url(&self) -> Option<&str> {
+        match self {
+            Self::Open { .. } => None,
+            Self::Click { url, .. } => Some(url),
+        }
+    }
+
+    pub fn sign(&self, secret: &str) -> String {
+        let payload = match self {
+            Self::Open { issue_id, subscriber_id } => format!("o\n{}\n{}", issue_id, subscriber_id),
+            Self::Click { issue_id, subscriber_id, url } => {
+                format!("c\n{}\n{}\n{}", issue_id, subscriber_id, url)
+            }
+        };
+        let signature = mac(secret, payload.as_bytes()).finalize().into_bytes();
+        format!(
+            "{}.{}",
+            URL_SAFE_NO_PAD.encode(payload),
+            URL_SAFE_NO_PAD.encode(signature)
+        )
+    }
+
+    /// Returns `None` if the token is malformed or its signature does not match.
+    pub fn verify(token: &str, secret: &str) -> Option<TrackingEvent> {
+        let (payload, signature) = token.split_once('.')?;
+        let payload = URL_SAFE_NO_PAD.decode(payload).ok()?;
+        let signature = URL_SAFE_NO_PAD.decode(signature).ok()?;
+        mac(secret, &payload).verify_slice(&signature).ok()?;
+
+        let payload = String::from_utf8(payload).ok()?;
+        let mut fields = payload.splitn(4, '\n');
+        let kind = fields.next()?;
+        let issue_id = fields.next()?.parse().ok()?;
+        let subscriber_id = fields.next()?.parse().ok()?;
+        match (kind, fields.next()) {
+            ("o", None) => Some(Self::Open { issue_id, subscriber_id }),
+            ("c", Some(url)) => Some(Self::Click {
+                issue_id,
+                subscriber_id,
+                url: url.to_string(),
+            }),
+            _ => None,
+        }
+    }
+}
+
+fn mac(secret: &str, payload: &[u8]) -> Hmac<Sha256> {
+    let mut mac =
+        Hmac::<Sha256>::new_from_slice(secret.as_bytes()).expect("HMAC accepts keys of any size");
+    mac.update(payload);
+    mac
+}
+
+/// Rewrites the `http(s)` links of a rendered HTML body to the click endpoint
+/// and appends the open pixel. Links to the subscription management pages are
+/// left untouched.
+pub fn instrument_html(
+    html: &str,
+    base_url: &str,
+    secret: &str,
+    issue_id: Uuid,
+    subscriber_id: Uuid,
+) -> String {
+    let own_pages = format!("{}/subscriptions/", base_url);
+    let mut html = rewrite_links(html, |url| {
+        let is_web_link = url.starts_with("http://") || url.starts_with("https://");
+        if !is_web_link || url.starts_with(&own_pages) {
+            return None;
+        }
+        let event = TrackingEvent::Click {
+            issue_id,
+            subscriber_id,
+            url: url.to_string(),
+        };
+        Some(format!("{}/t/c/{}", base_url, event.sign(secret)))
+    });
+    let open = TrackingEvent::Open { issue_id, subscriber_id };
+    html.push_str(&format!(
+        r#"<img src="{}/t/o/{}.gif" width="1" height="1" alt="">"#,
+        base_url,
+        open.sign(secret)
+    ));
+    html
+}
+
+/// Replaces the quoted `href` of every `<a>` tag for which `rewrite` returns
+/// a new URL. `rewrite` receives the decoded URL; the replacement is inserted
+/// verbatim, so it must not need escaping.
+fn rewrite_links(html: &str, mut rewrite: impl FnMut(&str) -> Option<String>) -> String {
+    // ASCII lowercasing keeps byte offsets aligned with `html`.
+    let lowercase = html.to_ascii_lowercase();
+    let mut output = String::with_capacity(html.len());
+    let mut position = 0;
+    while let Some(offset) = lowercase[position..].find("<a") {
+        let tag_start = position + offset;
+        let Some(tag_length) = lowercase[tag_start..].find('>') else {
+            break;
+        };
+        let tag_end = tag_start + tag_length;
+        let tag = &lowercase[tag_start..tag_end];
+        // Skip other tags starting with `a`, e.g. `<abbr>`.
+        let is_anchor = tag[2..].starts_with(|c: char| c.is_ascii_whitespace());
+        if let Some((value_start, value_end)) = href_value(tag).filter(|_| is_anchor) {
+            let (value_start, value_end) = (tag_start + value_start, tag_start + value_end);
+            let replacement = htmlescape::decode_html(&html[value_start..value_end])
+                .ok()
+                .and_then(|url| rewrite(&url));
+            if let Some(replacement) = replacement {
+                output.push_str(&html[position..value_start]);
+                output.push_str(&replacement);
+                position = value_end;
+            }
+        }
+        output.push_str(&html[position..tag_end]);
+        position = tag_end;
+    }
+    output.push_str(&html[position..]);
+    output
+}
+
+/// The byte range of the quoted `href` value inside a lowercased tag.
+fn href_value(tag: &str) -> Option<(usize, usize)> {
+    let mut search_from = 0;
+    while let Some(offset) = tag[search_from..].find("href") {
+        let name_start = search_from + offset;
+        search_from = name_start + 4;
+        if !tag[..name_start].ends_with(|c: char| c.is_ascii_whitespace()) {
+            continue;
+        }
+        let Some(value) = tag[search_from..].trim_start().strip_prefix('=') else {
+            continue;
+        };
+        let value = value.trim_start();
+        let Some(quote) = value.chars().next().filter(|c| *c == '"' || *c == '\'') else {
+            continue;
+        };
+        let value_start = tag.len() - value.len() + 1;
+        let value_end = value_start + tag[value_start..].find(quote)?;
+        return Some((value_start, value_end));
+    }
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SECRET: &str = "a-very-secret-key";
+
+    fn click(url: &str) -> TrackingEvent {
+        TrackingEvent::Click {
+            issue_id: Uuid::new_v4(),
+            subscriber_id: Uuid::new_v4(),
+            url: url.into(),
+        }
+    }
+
+    #[test]
+    fn signed_tokens_round_trip() {
+        let event = click("https://example.com/?a=1&b=2");
+        assert_eq!(TrackingEvent::verify(&event.sign(SECRET), SECRET), Some(event));
+    }
+
+    #[test]
+    fn tokens_signed_with_another_secret_are_rejected() {
+        let token = click("https://example.com").sign("another-secret");
+        assert_eq!(TrackingEvent::verify(&token, SECRET), None);
+    }
+
+    #[test]
+    fn tampered_tokens_are_rejected() {
+        let token = click("https://example.com").sign(SECRET);
+        let (_, signature) = token.split_once('.').unwrap();
+        let forged = click("https://evil.example.com").sign(SECRET);
+        let (forged_payload, _) = forged.split_once('.').unwrap();
+        let token = format!("{}.{}", forged_payload, signature);
+        assert_eq!(TrackingEvent::verify(&token, SECRET), None);
+    }
+
+    #[test]
+    fn web_links_are_rewritten_and_others_are_kept() {
+        let html = r#"<p><A class="x" HREF="https://example.com/?a=1&amp;b=2">Read</A>
+<a href='mailto:me@example.com'>Mail</a> <abbr href="https://example.com">x</abbr>
+<a href="https://app.example.com/subscriptions/unsubscribe?token=t">Leave</a></p>"#;
+        let issue_id = Uuid::new_v4();
+        let subscriber_id = Uuid::new_v4();
+
+        let instrumented =
+            instrument_html(html, "https://app.example.com", SECRET, issue_id, subscriber_id);
+
+        let start = instrumented.find("https://app.example.com/t/c/").unwrap();
+        let end = start + instrumented[start..].find('"').unwrap();
+        let token = &instrumented[start + "https://app.example.com/t/c/".len()..end];
+        assert_eq!(
+            TrackingEvent::verify(token, SECRET),
+            Some(TrackingEvent::Click {
+                issue_id,
+                subscriber_id,
+                url: "https://example.com/?a=1&b=2".into(),
+            })
+        );
+        assert!(instrumented.contains("href='mailto:me@example.com'"));
+        assert!(instrumented.contains(r#"<abbr href="https://example.com">"#));
+        assert!(instrumented.contains(r#"href="https://app.example.com/subscriptions/unsubscribe?token=t""#));
+        assert!(instrumented.ends_with(r#".gif" width="1" height="1" alt="">"#));
+    }
+}
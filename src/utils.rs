@@ -1,4 +1,6 @@
-use actix_web::HttpResponse;
+use std::net::IpAddr;
+
+use actix_web::{HttpRequest, HttpResponse};
 
 
 
@@ -9,8 +11,82 @@ where
     actix_web::error::ErrorInternalServerError(e)
 }
 
+pub fn e400<T>(e: T) -> actix_web::Error
+where 
+    T: std::fmt::Debug + std::fmt::Display + 'static,
+{
+    actix_web::error::ErrorBadRequest(e)
+}
+
 pub fn see_other(location: &str) -> HttpResponse {
     HttpResponse::SeeOther()
         .insert_header((actix_web::http::header::LOCATION, location))
         .finish()
-}
\ No newline at end of file
+}
+
+/// The address of the client behind the request. `X-Forwarded-For` is only
+/// believed when the peer is one of `trusted_proxies`, reading it from the
+/// right so that entries a client made up itself are never reached.
+pub fn client_ip(request: &HttpRequest, trusted_proxies: &[IpAddr]) -> String {
+    let Some(peer) = request.peer_addr().map(|addr| addr.ip()) else {
+        return "unknown".to_string();
+    };
+    if !trusted_proxies.contains(&peer) {
+        return peer.to_string();
+    }
+    let forwarded: Vec<IpAddr> = request
+        .headers()
+        .get_all("X-Forwarded-For")
+        .filter_map(|value| value.to_str().ok())
+        .flat_map(|value| value.split(','))
+        .filter_map(|entry| entry.trim().parse().ok())
+        .collect();
+    forwarded
+        .into_iter()
+        .rev()
+        .find(|ip| !trusted_proxies.contains(ip))
+        .unwrap_or(peer)
+        .to_string()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::net::{IpAddr, SocketAddr};
+
+    use actix_web::test::TestRequest;
+
+    use super::client_ip;
+
+    fn request(peer: &str, forwarded_for: Option<&str>) -> actix_web::HttpRequest {
+        let mut request = TestRequest::default().peer_addr(SocketAddr::new(peer.parse().unwrap(), 40000));
+        if let Some(forwarded_for) = forwarded_for {
+            request = request.insert_header(("X-Forwarded-For", forwarded_for));
+        }
+        request.to_http_request()
+    }
+
+    fn proxies() -> Vec<IpAddr> {
+        vec!["10.0.0.1".parse().unwrap(), "10.0.0.2".parse().unwrap()]
+    }
+
+    #[test]
+    fn the_header_is_ignored_unless_the_peer_is_a_trusted_proxy() {
+        let request = request("203.0.113.7", Some("198.51.100.1"));
+
+        assert_eq!(client_ip(&request, &proxies()), "203.0.113.7");
+    }
+
+    #[test]
+    fn the_client_is_the_rightmost_untrusted_forwarded_address() {
+        let request = request("10.0.0.1", Some("192.0.2.99, 198.51.100.1, 10.0.0.2"));
+
+        assert_eq!(client_ip(&request, &proxies()), "198.51.100.1");
+    }
+
+    #[test]
+    fn a_trusted_proxy_without_the_header_is_the_client() {
+        let request = request("10.0.0.1", None);
+
+        assert_eq!(client_ip(&request, &proxies()), "10.0.0.1");
+    }
+}
@@ -0,0 +1,115 @@
+use wiremock::{matchers::{method, path}, Mock, ResponseTemplate};
+
+use crate::helpers::{assert_is_redirect_to, spawn_app, TestApp, TestUser};
+
+async fn stored_email(app: &TestApp) -> (Option<String>, bool) {
+    let user = sqlx::query!(
+        "SELECT email, email_confirmed_at FROM users WHERE user_id = $1",
+        app.test_user.user_id
+    )
+    .fetch_one(&app.db_pool)
+    .await
+    .unwrap();
+    (user.email, user.email_confirmed_at.is_some())
+}
+
+#[tokio::test]
+async fn you_must_be_logged_in_to_change_your_email_address() {
+    let app = spawn_app().await;
+
+    let response = app.post_change_email("new.address@example.com").await;
+
+    assert_is_redirect_to(&response, "/login");
+}
+
+#[tokio::test]
+async fn a_new_address_is_only_used_once_it_is_confirmed() {
+    let app = spawn_app().await;
+    app.login_as(&app.test_user).await;
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+
+    let response = app.post_change_email("new.address@example.com").await;
+    assert_is_redirect_to(&response, "/admin/email");
+    let html_page = app.get_change_email_html().await;
+    assert!(html_page.contains("We have sent a confirmation link to new.address@example.com."));
+    assert_eq!(stored_email(&app).await, (Some(app.test_user.email.clone()), true));
+
+    let request = &app.email_server.received_requests().await.unwrap()[0];
+    let body: serde_json::Value = serde_json::from_slice(&request.body).unwrap();
+    assert_eq!(body["To"], "new.address@example.com");
+    let confirmation_link = app.get_confirmation_links(request).html;
+    let response = app.api_client.get(confirmation_link.clone()).send().await.unwrap();
+    assert_is_redirect_to(&response, "/admin/email");
+    let html_page = app.get_change_email_html().await;
+    assert!(html_page.contains("Your email address has been changed to new.address@example.com."));
+    assert_eq!(stored_email(&app).await, (Some("new.address@example.com".into()), true));
+
+    // The link cannot be used twice.
+    app.api_client.get(confirmation_link).send().await.unwrap();
+    let html_page = app.get_change_email_html().await;
+    assert!(html_page.contains("This confirmation link is not valid."));
+}
+
+#[tokio::test]
+async fn a_confirmation_link_only_works_for_the_user_who_asked_for_it() {
+    let app = spawn_app().await;
+    let other_user = TestUser::with_role("editor");
+    other_user.store(&app.db_pool).await;
+    app.login_as(&app.test_user).await;
+    Mock::given(path("/email"))
+        .respond_with(ResponseTemplate::new(200))
+        .mount(&app.email_server)
+        .await;
+    app.post_change_email("new.address@example.com").await;
+    let request = &app.email_server.received_requests().await.unwrap()[0];
+    let confirmation_link = app.get_confirmation_links(request).html;
+
+    app.post_logout().await;
+    app.login_as(&other_user).await;
+    app.api_client.get(confirmation_link).send().await.unwrap();
+
+    let html_page = app.get_change_email_html().await;
+    assert!(html_page.contains("This confirmation link is not valid."));
+    assert_eq!(stored_email(&app).await, (Some(app.test_user.email.clone()), true));
+}
+
+#[tokio::test]
+async fn an_address_used_by_another_account_is_rejected() {
+    let app = spawn_app().await;
+    let other_user = TestUser::with_role("editor");
+    other_user.store(&app.db_pool).await;
+    app.login_as(&app.test_user).await;
+    Mock::given(path("/email"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(0)
+        .mount(&app.email_server)
+        .await;
+
+    let response = app.post_change_email(&other_user.email.to_uppercase()).await;
+
+    assert_is_redirect_to(&response, "/admin/email");
+    let html_page = app.get_change_email_html().await;
+    assert!(html_page.contains("<p><i>Another account already uses that email address.</i></p>"));
+}
+
+#[tokio::test]
+async fn two_accounts_cannot_store_the_same_address() {
+    let app = spawn_app().await;
+    let other_user = TestUser::with_role("editor");
+    other_user.store(&app.db_pool).await;
+
+    let result = sqlx::query!(
+        "UPDATE users SET email = $1 WHERE user_id = $2",
+        other_user.email,
+        app.test_user.user_id
+    )
+    .execute(&app.db_pool)
+    .await;
+
+    assert!(result.is_err());
+}
@@ -117,3 +117,35 @@ async fn changing_password_works() {
     let response = app.post_login(&login_body).await;
     assert_is_redirect_to(&response, "/admin/dashboard");
 }
+
+#[tokio::test]
+async fn new_password_must_follow_the_password_policy() {
+    let app = spawn_app().await;
+    app.login_as(&app.test_user).await;
+    let test_cases = [
+        ("short".to_string(), "The new password must be at least 12 characters long."),
+        ("a".repeat(129), "The new password must be at most 128 characters long."),
+        (app.test_user.password.clone(), "The new password must be different from the current one."),
+        ("Password1234".to_string(), "The new password is too common. Please choose another one."),
+        (
+            "abcdefghijklmnop".to_string(),
+            "The new password is too predictable. Avoid repeated characters and sequences, or make it longer.",
+        ),
+    ];
+
+    for (new_password, error_message) in test_cases {
+        let response = app.post_change_password(&serde_json::json!({
+            "current_password": &app.test_user.password,
+            "new_password": &new_password,
+            "new_password_check": &new_password,
+        })).await;
+        assert_is_redirect_to(&response, "/admin/password");
+
+        let html_page = app.get_change_password_html().await;
+        assert!(html_page.contains(&format!("<p><i>{}</i></p>", error_message)), "Expected `{}`", error_message);
+    }
+
+    app.post_logout().await;
+    let response = app.login_as(&app.test_user).await;
+    assert_is_redirect_to(&response, "/admin/dashboard");
+}
@@ -0,0 +1,180 @@
+use std::time::{Duration, Instant};
+
+use wiremock::{matchers::{method, path}, Mock, ResponseTemplate};
+use zero2prod::{configuration::get_configuration, email_client::EmailClient, issue_delivery_worker::dispatch_pending_emails};
+
+use crate::helpers::{spawn_app, TestApp};
+
+const N_SUBSCRIBERS: usize = 20;
+const PROVIDER_LATENCY: Duration = Duration::from_millis(100);
+
+async fn insert_confirmed_subscribers(app: &TestApp, n: usize) {
+    for i in 0..n {
+        sqlx::query!(
+            r#"
+            INSERT INTO subscriptions (id, email, name, subscribed_at, status, unsubscribe_token)
+            VALUES ($1, $2, $3, now(), 'confirmed', $4)
+            "#,
+            uuid::Uuid::new_v4(),
+            format!("subscriber-{}@example.com", i),
+            format!("Subscriber {}", i),
+            uuid::Uuid::new_v4().to_string(),
+        )
+        .execute(&app.db_pool)
+        .await
+        .unwrap();
+    }
+}
+
+async fn publish_issue(app: &TestApp) {
+    app.post_login(&serde_json::json!({
+        "username": &app.test_user.username,
+        "password": &app.test_user.password,
+    })).await;
+    app.publish_newsletter(&serde_json::json!({
+        "title": "Newsletter title",
+        "text_content": "Newsletter body as plain text.",
+        "html_content": "<p>Newsletter body as HTML.</p>",
+    }))
+    .await;
+}
+
+/// A client that sends one email per call, so that throughput depends on concurrency alone.
+fn unbatched_email_client(app: &TestApp) -> EmailClient {
+    let mut settings = get_configuration().unwrap().email_client;
+    settings.base_url = app.email_server.uri();
+    settings.max_batch_size = Some(1);
+    settings.client()
+}
+
+async fn time_dispatch(concurrency: usize) -> Duration {
+    let app = spawn_app().await;
+    insert_confirmed_subscribers(&app, N_SUBSCRIBERS).await;
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200).set_delay(PROVIDER_LATENCY))
+        .expect(N_SUBSCRIBERS as u64)
+        .mount(&app.email_server)
+        .await;
+    publish_issue(&app).await;
+
+    let email_client = unbatched_email_client(&app);
+
+    let start = Instant::now();
+    let n_completed = dispatch_pending_emails(
+        &app.db_pool,
+        &email_client,
+        &app.address,
+        &app.hmac_secret,
+        concurrency,
+    )
+    .await
+    .unwrap();
+    let elapsed = start.elapsed();
+
+    assert_eq!(n_completed, N_SUBSCRIBERS);
+    elapsed
+}
+
+#[tokio::test]
+async fn concurrent_dispatch_is_faster_than_sequential_dispatch() {
+    let sequential = time_dispatch(1).await;
+    let concurrent = time_dispatch(5).await;
+
+    assert!(sequential >= PROVIDER_LATENCY * N_SUBSCRIBERS as u32);
+    // Five lanes should need about a fifth of the time; leave room for overhead.
+    assert!(
+        concurrent * 2 < sequential,
+        "concurrent: {:?}, sequential: {:?}",
+        concurrent,
+        sequential
+    );
+}
+
+#[tokio::test]
+async fn concurrent_lanes_never_send_the_same_email_twice() {
+    let app = spawn_app().await;
+    insert_confirmed_subscribers(&app, N_SUBSCRIBERS).await;
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .mount(&app.email_server)
+        .await;
+    publish_issue(&app).await;
+
+    dispatch_pending_emails(&app.db_pool, &unbatched_email_client(&app), &app.address, &app.hmac_secret, 8)
+        .await
+        .unwrap();
+
+    let mut recipients: Vec<String> = app
+        .email_server
+        .received_requests()
+        .await
+        .unwrap()
+        .iter()
+        .map(|request| {
+            let body: serde_json::Value = serde_json::from_slice(&request.body).unwrap();
+            body["To"].as_str().unwrap().to_owned()
+        })
+        .collect();
+    assert_eq!(recipients.len(), N_SUBSCRIBERS);
+    recipients.sort();
+    recipients.dedup();
+    assert_eq!(recipients.len(), N_SUBSCRIBERS);
+    let sent = sqlx::query!(r#"SELECT COUNT(*) AS "count!" FROM issue_deliveries WHERE status = 'sent'"#)
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap();
+    assert_eq!(sent.count, N_SUBSCRIBERS as i64);
+}
+
+/// Recipients are formatted as `Name <address>`.
+fn recipient_address(to: &str) -> &str {
+    to.rsplit('<').next().unwrap().trim_end_matches('>')
+}
+
+#[tokio::test]
+async fn batching_providers_receive_one_call_per_batch() {
+    let app = spawn_app().await;
+    insert_confirmed_subscribers(&app, N_SUBSCRIBERS).await;
+    Mock::given(path("/email/batch"))
+        .and(method("POST"))
+        .respond_with(|request: &wiremock::Request| {
+            let messages: Vec<serde_json::Value> = serde_json::from_slice(&request.body).unwrap();
+            let results: Vec<_> = messages
+                .iter()
+                .map(|message| match recipient_address(message["To"].as_str().unwrap()) {
+                    "subscriber-0@example.com" => serde_json::json!({
+                        "ErrorCode": 406,
+                        "Message": "You tried to send to a recipient that has been marked as inactive.",
+                    }),
+                    to => serde_json::json!({"ErrorCode": 0, "MessageID": format!("id-{}", to)}),
+                })
+                .collect();
+            ResponseTemplate::new(200).set_body_json(results)
+        })
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+    publish_issue(&app).await;
+
+    let n_completed = dispatch_pending_emails(&app.db_pool, &app.email_client, &app.address, &app.hmac_secret, 4)
+        .await
+        .unwrap();
+
+    assert_eq!(n_completed, N_SUBSCRIBERS);
+    let deliveries = sqlx::query!(
+        "SELECT subscriber_email, status, provider_message_id FROM issue_deliveries ORDER BY subscriber_email"
+    )
+    .fetch_all(&app.db_pool)
+    .await
+    .unwrap();
+    for delivery in deliveries {
+        if delivery.subscriber_email == "subscriber-0@example.com" {
+            assert_eq!(delivery.status, "failed_permanent");
+        } else {
+            assert_eq!(delivery.status, "sent");
+            assert_eq!(delivery.provider_message_id, Some(format!("id-{}", delivery.subscriber_email)));
+        }
+    }
+}
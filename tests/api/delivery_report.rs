@@ -0,0 +1,144 @@
+use wiremock::{matchers::{method, path}, Mock, ResponseTemplate};
+
+use crate::helpers::{assert_is_redirect_to, create_confirmed_subscriber, spawn_app, TestApp};
+
+async fn publish_and_deliver(app: &TestApp, email_response: ResponseTemplate) -> uuid::Uuid {
+    app.post_login(&serde_json::json!({
+        "username": &app.test_user.username,
+        "password": &app.test_user.password,
+    })).await;
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(email_response)
+        .mount(&app.email_server)
+        .await;
+
+    app.publish_newsletter(&serde_json::json!({
+        "title": "Newsletter title",
+        "text_content": "Newsletter body as plain text.",
+        "html_content": "<p>Newsletter body as HTML.</p>",
+    }))
+    .await;
+    app.dispatch_all_pending_emails().await;
+
+    sqlx::query!("SELECT newsletter_issue_id FROM newsletter_issues")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap()
+        .newsletter_issue_id
+}
+
+struct Delivery {
+    status: String,
+    provider_message_id: Option<String>,
+    last_error: Option<String>,
+}
+
+async fn delivery(app: &TestApp) -> Delivery {
+    sqlx::query_as!(
+        Delivery,
+        "SELECT status, provider_message_id, last_error FROM issue_deliveries",
+    )
+    .fetch_one(&app.db_pool)
+    .await
+    .unwrap()
+}
+
+#[tokio::test]
+async fn sent_deliveries_record_the_provider_message_id() {
+    let app = spawn_app().await;
+    create_confirmed_subscriber(&app).await;
+
+    let issue_id = publish_and_deliver(
+        &app,
+        ResponseTemplate::new(200).set_body_json(serde_json::json!({
+            "MessageID": "b7bc2f4a-e38e-4336-af7d-e6c392c2f817",
+            "ErrorCode": 0,
+        })),
+    )
+    .await;
+
+    let delivery = delivery(&app).await;
+    assert_eq!(delivery.status, "sent");
+    assert_eq!(
+        delivery.provider_message_id.as_deref(),
+        Some("b7bc2f4a-e38e-4336-af7d-e6c392c2f817")
+    );
+    let html_page = app.get_issue_deliveries_html(issue_id).await;
+    assert!(html_page.contains("<li>Sent: 1</li>"));
+    assert!(html_page.contains("No failed deliveries."));
+}
+
+#[tokio::test]
+async fn rejected_deliveries_fail_permanently_and_can_be_retried() {
+    let app = spawn_app().await;
+    create_confirmed_subscriber(&app).await;
+
+    let issue_id = publish_and_deliver(&app, ResponseTemplate::new(422)).await;
+
+    let failed = delivery(&app).await;
+    assert_eq!(failed.status, "failed_permanent");
+    assert!(failed.last_error.is_some());
+    let html_page = app.get_issue_deliveries_html(issue_id).await;
+    assert!(html_page.contains("<li>Failed permanently: 1</li>"));
+    assert!(html_page.contains("<td>ursula_le_guin@gmail.com</td>"));
+
+    let response = app.post_retry_failed_deliveries(issue_id).await;
+    assert_is_redirect_to(&response, &format!("/admin/issues/{}", issue_id));
+    let html_page = app.get_issue_deliveries_html(issue_id).await;
+    assert!(html_page.contains("1 failed deliveries have been queued again."));
+    assert!(html_page.contains("<li>Queued: 1</li>"));
+    let queued = sqlx::query!("SELECT COUNT(*) AS \"count!\" FROM issue_delivery_queue")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap();
+    assert_eq!(queued.count, 1);
+}
+
+#[tokio::test]
+async fn transient_failures_are_recorded_as_retrying() {
+    let app = spawn_app().await;
+    create_confirmed_subscriber(&app).await;
+
+    let issue_id = publish_and_deliver(&app, ResponseTemplate::new(500)).await;
+
+    assert_eq!(delivery(&app).await.status, "failed_retrying");
+    let html_page = app.get_issue_deliveries_html(issue_id).await;
+    assert!(html_page.contains("<li>Failed, retrying: 1</li>"));
+}
+
+#[tokio::test]
+async fn failures_can_be_exported_as_csv() {
+    let app = spawn_app().await;
+    create_confirmed_subscriber(&app).await;
+    let issue_id = publish_and_deliver(&app, ResponseTemplate::new(422)).await;
+
+    let response = app
+        .api_client
+        .get(format!("{}/admin/issues/{}/failures.csv", app.address, issue_id))
+        .send()
+        .await
+        .unwrap();
+
+    assert_eq!(response.status().as_u16(), 200);
+    assert_eq!(response.headers()["Content-Type"], "text/csv; charset=utf-8");
+    let csv = response.text().await.unwrap();
+    let mut lines = csv.lines();
+    assert_eq!(lines.next(), Some("email,status,attempts,last_error"));
+    assert!(lines.next().unwrap().starts_with("ursula_le_guin@gmail.com,failed_permanent,1,"));
+    assert_eq!(lines.next(), None);
+}
+
+#[tokio::test]
+async fn you_must_be_logged_in_to_see_the_delivery_report() {
+    let app = spawn_app().await;
+
+    let response = app
+        .api_client
+        .get(format!("{}/admin/issues/{}", app.address, uuid::Uuid::new_v4()))
+        .send()
+        .await
+        .unwrap();
+
+    assert_is_redirect_to(&response, "/login");
+}
@@ -0,0 +1,119 @@
+use wiremock::{matchers::any, Mock, ResponseTemplate};
+
+use crate::helpers::{create_confirmed_subscriber, spawn_app, TestApp};
+
+const HARD_BOUNCE: &str = include_str!("fixtures/postmark_hard_bounce.json");
+const SOFT_BOUNCE: &str = include_str!("fixtures/postmark_soft_bounce.json");
+const SPAM_COMPLAINT: &str = include_str!("fixtures/postmark_spam_complaint.json");
+const DELIVERY: &str = include_str!("fixtures/postmark_delivery.json");
+
+async fn subscriber_status(app: &TestApp) -> String {
+    sqlx::query!("SELECT status FROM subscriptions")
+        .fetch_one(&app.db_pool)
+        .await
+        .expect("Failed to fetch saved subscriber.")
+        .status
+}
+
+#[tokio::test]
+async fn hard_bounces_and_spam_complaints_suppress_the_subscriber() {
+    for payload in [HARD_BOUNCE, SPAM_COMPLAINT] {
+        let app = spawn_app().await;
+        create_confirmed_subscriber(&app).await;
+
+        let response = app.post_email_webhook("postmark", &app.webhook_token, payload).await;
+
+        assert_eq!(response.status().as_u16(), 200);
+        assert_eq!(subscriber_status(&app).await, "suppressed");
+    }
+}
+
+#[tokio::test]
+async fn soft_bounces_and_deliveries_are_recorded_without_suppressing() {
+    let app = spawn_app().await;
+    create_confirmed_subscriber(&app).await;
+
+    for payload in [SOFT_BOUNCE, DELIVERY] {
+        let response = app.post_email_webhook("postmark", &app.webhook_token, payload).await;
+        assert_eq!(response.status().as_u16(), 200);
+    }
+
+    assert_eq!(subscriber_status(&app).await, "confirmed");
+    let events = sqlx::query!(
+        "SELECT event_type, email, detail, occurred_at FROM email_events ORDER BY event_type"
+    )
+    .fetch_all(&app.db_pool)
+    .await
+    .unwrap();
+    assert_eq!(events.len(), 2);
+    assert_eq!(events[0].event_type, "bounce");
+    assert_eq!(events[0].detail.as_deref(), Some("SoftBounce"));
+    assert_eq!(events[1].event_type, "delivery");
+    assert!(events.iter().all(|e| e.email == "ursula_le_guin@gmail.com"));
+    assert!(events.iter().all(|e| e.occurred_at.is_some()));
+}
+
+#[tokio::test]
+async fn suppressed_subscribers_are_not_mailed() {
+    let app = spawn_app().await;
+    create_confirmed_subscriber(&app).await;
+    app.post_email_webhook("postmark", &app.webhook_token, HARD_BOUNCE).await;
+    app.post_login(&serde_json::json!({
+        "username": &app.test_user.username,
+        "password": &app.test_user.password,
+    })).await;
+
+    Mock::given(any())
+        .respond_with(ResponseTemplate::new(200))
+        .expect(0)
+        .mount(&app.email_server)
+        .await;
+
+    app.publish_newsletter(&serde_json::json!({
+        "title": "Newsletter title",
+        "text_content": "Newsletter body as plain text.",
+        "html_content": "<p>Newsletter body as HTML.</p>",
+    }))
+    .await;
+    app.dispatch_all_pending_emails().await;
+    let response = app
+        .post_subscriptions("name=le%20guin&email=ursula_le_guin%40gmail.com".into())
+        .await;
+    assert_eq!(response.status().as_u16(), 200);
+    assert_eq!(subscriber_status(&app).await, "suppressed");
+}
+
+#[tokio::test]
+async fn webhooks_without_valid_credentials_are_rejected() {
+    let app = spawn_app().await;
+    create_confirmed_subscriber(&app).await;
+
+    let response = app.post_email_webhook("postmark", "wrong-token", HARD_BOUNCE).await;
+    assert_eq!(response.status().as_u16(), 401);
+    assert_eq!(
+        response.headers()["WWW-Authenticate"],
+        r#"Basic realm="webhooks""#
+    );
+
+    let response = app
+        .api_client
+        .post(format!("{}/webhooks/email/postmark", app.address))
+        .header("Content-Type", "application/json")
+        .body(HARD_BOUNCE)
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status().as_u16(), 401);
+    assert_eq!(subscriber_status(&app).await, "confirmed");
+}
+
+#[tokio::test]
+async fn unknown_providers_and_malformed_payloads_are_rejected() {
+    let app = spawn_app().await;
+
+    let response = app.post_email_webhook("mailgun", &app.webhook_token, HARD_BOUNCE).await;
+    assert_eq!(response.status().as_u16(), 404);
+
+    let response = app.post_email_webhook("postmark", &app.webhook_token, "{\"RecordType\": \"Bounce\"}").await;
+    assert_eq!(response.status().as_u16(), 400);
+}
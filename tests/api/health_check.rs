@@ -1,4 +1,7 @@
-use crate::helpers::spawn_app;
+use wiremock::{matchers::any, Mock, MockServer, ResponseTemplate};
+use zero2prod::configuration::{EmailProviderKind, FallbackProviderSettings};
+
+use crate::helpers::{create_confirmed_subscriber, spawn_app, spawn_app_with};
 
 
 #[tokio::test]
@@ -10,5 +13,81 @@ async fn health_check_works() {
         .expect("Failed to execute request.");
 
     assert!(response.status().is_success());
-    assert_eq!(Some(0), response.content_length());
-}
\ No newline at end of file
+    let body: serde_json::Value = response.json().await.unwrap();
+    assert_eq!(body["status"], "ok");
+    assert_eq!(body["email_providers"][0]["name"], "postmark");
+    assert_eq!(body["email_providers"][0]["circuit"], "closed");
+}
+
+#[tokio::test]
+async fn health_check_reports_an_open_circuit_and_signups_fail_over() {
+    let fallback_server = MockServer::start().await;
+    let fallback_uri = fallback_server.uri();
+    let app = spawn_app_with(|c| {
+        c.email_client.circuit_breaker.failure_threshold = 1;
+        c.email_client.fallbacks = vec![FallbackProviderSettings {
+            provider: EmailProviderKind::Postmark,
+            base_url: Some(fallback_uri),
+            auth_token: Some(uuid::Uuid::new_v4().to_string()),
+            smtp: None,
+            file_sink: None,
+        }];
+    })
+    .await;
+    Mock::given(any())
+        .respond_with(ResponseTemplate::new(500))
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+    Mock::given(any())
+        .respond_with(ResponseTemplate::new(200))
+        .expect(2)
+        .mount(&fallback_server)
+        .await;
+
+    for email in ["ursula_le_guin%40gmail.com", "octavia_butler%40gmail.com"] {
+        let response = app.post_subscriptions(format!("name=le%20guin&email={}", email)).await;
+        assert_eq!(response.status().as_u16(), 200);
+    }
+
+    let response = reqwest::get(&format!("{}/health_check", &app.address))
+        .await
+        .expect("Failed to execute request.");
+    assert!(response.status().is_success());
+    let body: serde_json::Value = response.json().await.unwrap();
+    assert_eq!(body["status"], "degraded");
+    assert_eq!(body["email_providers"][0]["circuit"], "open");
+    assert_eq!(body["email_providers"][0]["consecutive_failures"], 1);
+    assert_eq!(body["email_providers"][1]["circuit"], "closed");
+}
+
+#[tokio::test]
+async fn health_check_reports_a_circuit_opened_by_the_delivery_worker() {
+    let app = spawn_app_with(|c| c.email_client.circuit_breaker.failure_threshold = 1).await;
+    create_confirmed_subscriber(&app).await;
+    app.post_login(&serde_json::json!({
+        "username": &app.test_user.username,
+        "password": &app.test_user.password,
+    })).await;
+    Mock::given(any())
+        .respond_with(ResponseTemplate::new(500))
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+    app.publish_newsletter(&serde_json::json!({
+        "title": "Newsletter title",
+        "text_content": "Newsletter body as plain text.",
+        "html_content": "<p>Newsletter body as HTML.</p>",
+    }))
+    .await;
+
+    app.dispatch_all_pending_emails().await;
+
+    let response = reqwest::get(&format!("{}/health_check", &app.address))
+        .await
+        .expect("Failed to execute request.");
+    let body: serde_json::Value = response.json().await.unwrap();
+    assert_eq!(body["status"], "degraded");
+    assert_eq!(body["email_providers"][0]["circuit"], "open");
+    assert_eq!(body["email_providers"][0]["consecutive_failures"], 1);
+}
@@ -6,7 +6,11 @@ use reqwest::Url;
 use serde_json::Value;
 use sqlx::{Connection, PgConnection, PgPool, Executor};
 use uuid::Uuid;
-use zero2prod::configuration::{self, DatabaseSettings};
+use wiremock::{matchers::{method, path}, Mock, ResponseTemplate};
+use zero2prod::configuration::{self, DatabaseSettings, Settings};
+use zero2prod::email_client::EmailClient;
+use zero2prod::issue_delivery_worker::{try_execute_task, ExecutionOutcome};
+use zero2prod::issue_scheduler::promote_due_issues;
 use zero2prod::startup::{get_connection_pool, Application};
 use zero2prod::telemetry;
 
@@ -14,18 +18,26 @@ pub struct TestUser {
     pub user_id: Uuid,
     pub username: String,
     pub password: String,
+    pub email: String,
+    pub role: &'static str,
 }
 
 impl TestUser {
     pub fn generate() -> Self {
+        Self::with_role("owner")
+    }
+
+    pub fn with_role(role: &'static str) -> Self {
         TestUser {
             user_id: Uuid::new_v4(),
             username: Uuid::new_v4().to_string(),
             password: Uuid::new_v4().to_string(),
+            email: format!("{}@example.com", Uuid::new_v4()),
+            role,
         }
-    } 
+    }
 
-    async fn store(&self, pool: &PgPool) {
+    pub async fn store(&self, pool: &PgPool) {
         let salt = SaltString::generate(&mut OsRng);
         let password_hash = Argon2::default()
             .hash_password(self.password.as_bytes(), &salt)
@@ -34,12 +46,14 @@ impl TestUser {
 
         sqlx::query!(
             r#"
-            INSERT INTO users (user_id, username, password_hash)
-            VALUES ($1, $2, $3)
+            INSERT INTO users (user_id, username, password_hash, email, email_confirmed_at, role)
+            VALUES ($1, $2, $3, $4, now(), $5)
             "#,
             self.user_id,
             self.username,
-            password_hash
+            password_hash,
+            self.email,
+            self.role
         )
         .execute(pool)
         .await
@@ -54,6 +68,9 @@ pub struct TestApp {
     pub email_server: wiremock::MockServer, 
     pub test_user: TestUser,
     pub api_client: reqwest::Client,
+    pub email_client: EmailClient,
+    pub webhook_token: String,
+    pub hmac_secret: String,
 }
 pub struct ConfirmationLinks {
     pub html: reqwest::Url,
@@ -61,9 +78,25 @@ pub struct ConfirmationLinks {
 }
 
 impl TestApp {
+    pub async fn dispatch_all_pending_emails(&self) {
+        loop {
+            if let ExecutionOutcome::EmptyQueue =
+                try_execute_task(&self.db_pool, &self.email_client, &self.address, &self.hmac_secret)
+                    .await
+                    .unwrap()
+            {
+                break;
+            }
+        }
+    }
+
+    pub async fn promote_scheduled_issues(&self) -> usize {
+        promote_due_issues(&self.db_pool).await.unwrap()
+    }
+
     pub async fn post_subscriptions(&self, body: String) -> reqwest::Response {
         self.api_client
-            .post(&format!("{}/subscriptions", &self.address))
+            .post(format!("{}/subscriptions", &self.address))
             .header("Content-Type", "application/x-www-form-urlencoded")
             .body(body)
             .send()
@@ -71,34 +104,165 @@ impl TestApp {
             .expect("Failed to execute request.")
     } 
 
-    pub async fn post_publish_newsletters<Body>(&self, body: &Body) -> reqwest::Response
-    where 
+    /// Publishes an issue the only way the admin area allows: the content
+    /// goes into a new draft, which is then published with the other fields
+    /// and a fresh idempotency key unless the body carries one.
+    pub async fn publish_newsletter(&self, body: &serde_json::Value) -> reqwest::Response {
+        let mut publish_body = body.clone();
+        let fields = publish_body.as_object_mut().unwrap();
+        fields
+            .entry("idempotency_key")
+            .or_insert_with(|| uuid::Uuid::new_v4().to_string().into());
+        let draft_body: serde_json::Map<String, serde_json::Value> = ["title", "text_content", "html_content"]
+            .into_iter()
+            .filter_map(|field| fields.remove_entry(field))
+            .collect();
+        let response = self.post_create_draft(&draft_body).await;
+        assert_eq!(response.status().as_u16(), 303);
+        let draft_path = response.headers().get("Location").unwrap().to_str().unwrap().to_owned();
+        self.post_draft(&draft_path, "/publish", &publish_body).await
+    }
+
+    pub async fn get_scheduled_issues_html(&self) -> String {
+        self.api_client
+            .get(format!("{}/admin/newsletter/scheduled", &self.address))
+            .send()
+            .await
+            .expect("Failed to execute request.")
+            .text()
+            .await
+            .unwrap()
+    }
+
+    pub async fn get_drafts(&self) -> reqwest::Response {
+        self.api_client
+            .get(format!("{}/admin/newsletter/drafts", &self.address))
+            .send()
+            .await
+            .expect("Failed to execute request.")
+    }
+
+    pub async fn get_drafts_html(&self) -> String {
+        self.get_drafts().await.text().await.unwrap()
+    }
+
+    pub async fn post_create_draft<Body>(&self, body: &Body) -> reqwest::Response
+    where
+        Body: serde::Serialize,
+    {
+        self.api_client
+            .post(format!("{}/admin/newsletter/drafts", &self.address))
+            .form(body)
+            .send()
+            .await
+            .expect("Failed to execute request.")
+    }
+
+    pub async fn post_create_draft_multipart(&self, form: reqwest::multipart::Form) -> reqwest::Response {
+        self.api_client
+            .post(format!("{}/admin/newsletter/drafts", &self.address))
+            .multipart(form)
+            .send()
+            .await
+            .expect("Failed to execute request.")
+    }
+
+    /// `draft_path` is the location returned when the draft is created,
+    /// e.g. `/admin/newsletter/drafts/{id}`; `action` is appended to it.
+    pub async fn get_draft_html(&self, draft_path: &str, action: &str) -> String {
+        self.api_client
+            .get(format!("{}{}{}", &self.address, draft_path, action))
+            .send()
+            .await
+            .expect("Failed to execute request.")
+            .text()
+            .await
+            .unwrap()
+    }
+
+    pub async fn post_draft<Body>(&self, draft_path: &str, action: &str, body: &Body) -> reqwest::Response
+    where
+        Body: serde::Serialize,
+    {
+        self.api_client
+            .post(format!("{}{}{}", &self.address, draft_path, action))
+            .form(body)
+            .send()
+            .await
+            .expect("Failed to execute request.")
+    }
+
+    pub async fn post_draft_multipart(&self, draft_path: &str, form: reqwest::multipart::Form) -> reqwest::Response {
+        self.api_client
+            .post(format!("{}{}", &self.address, draft_path))
+            .multipart(form)
+            .send()
+            .await
+            .expect("Failed to execute request.")
+    }
+
+    pub async fn get_tags_html(&self) -> String {
+        self.api_client
+            .get(format!("{}/admin/tags", &self.address))
+            .send()
+            .await
+            .expect("Failed to execute request.")
+            .text()
+            .await
+            .unwrap()
+    }
+
+    pub async fn post_create_tag<Body>(&self, body: &Body) -> reqwest::Response
+    where
+        Body: serde::Serialize,
+    {
+        self.api_client
+            .post(format!("{}/admin/tags", &self.address))
+            .form(body)
+            .send()
+            .await
+            .expect("Failed to execute request.")
+    }
+
+    pub async fn post_tag_subscriber<Body>(&self, body: &Body) -> reqwest::Response
+    where
         Body: serde::Serialize,
     {
         self.api_client
-            .post(&format!("{}/admin/newsletter", &self.address))
+            .post(format!("{}/admin/tags/subscribers", &self.address))
             .form(body)
             .send()
             .await
             .expect("Failed to execute request.")
     }
 
-    pub async fn get_publish_newsletter(&self) -> reqwest::Response {
+    pub async fn post_cancel_scheduled_issue(&self, issue_id: Uuid) -> reqwest::Response {
         self.api_client
-            .get(format!("{}/admin/newsletter", &self.address))
+            .post(format!("{}/admin/newsletter/scheduled/{}/cancel", &self.address, issue_id))
             .send()
             .await
             .expect("Failed to execute request.")
     }
 
-    pub async fn get_publish_newsletter_html(&self) -> String {
-        self.get_publish_newsletter()
+    pub async fn get_issue_deliveries_html(&self, issue_id: Uuid) -> String {
+        self.api_client
+            .get(format!("{}/admin/issues/{}", &self.address, issue_id))
+            .send()
             .await
+            .expect("Failed to execute request.")
             .text()
             .await
             .unwrap()
     }
 
+    pub async fn post_retry_failed_deliveries(&self, issue_id: Uuid) -> reqwest::Response {
+        self.api_client
+            .post(format!("{}/admin/issues/{}/retry", &self.address, issue_id))
+            .send()
+            .await
+            .expect("Failed to execute request.")
+    }
+
     pub fn get_confirmation_links(
         &self,
         email_request: &wiremock::Request,
@@ -117,8 +281,8 @@ impl TestApp {
             confirmation_link.set_port(Some(self.port)).unwrap();
             confirmation_link
         };
-        let html = get_link(&body["HtmlBody"].as_str().unwrap());
-        let plain_text = get_link(&body["TextBody"].as_str().unwrap());
+        let html = get_link(body["HtmlBody"].as_str().unwrap());
+        let plain_text = get_link(body["TextBody"].as_str().unwrap());
 
         ConfirmationLinks { html, plain_text }
     }
@@ -128,16 +292,94 @@ impl TestApp {
         Body: serde::Serialize,
     {
         self.api_client
-            .post(&format!("{}/login", &self.address))
+            .post(format!("{}/login", &self.address))
             .form(body)
             .send()
             .await
             .expect("Failed to execute request.")
     }
 
+    pub async fn login_as(&self, user: &TestUser) -> reqwest::Response {
+        self.post_login(&serde_json::json!({
+            "username": &user.username,
+            "password": &user.password,
+        }))
+        .await
+    }
+
+    pub async fn get_users(&self) -> reqwest::Response {
+        self.api_client
+            .get(format!("{}/admin/users", &self.address))
+            .send()
+            .await
+            .expect("Failed to execute request.")
+    }
+
+    pub async fn get_users_html(&self) -> String {
+        self.get_users().await.text().await.unwrap()
+    }
+
+    pub async fn post_invite_user<Body>(&self, body: &Body) -> reqwest::Response
+    where
+        Body: serde::Serialize,
+    {
+        self.api_client
+            .post(format!("{}/admin/users", &self.address))
+            .form(body)
+            .send()
+            .await
+            .expect("Failed to execute request.")
+    }
+
+    pub async fn post_user_action(&self, user_id: Uuid, action: &str) -> reqwest::Response {
+        self.api_client
+            .post(format!("{}/admin/users/{}/{}", &self.address, user_id, action))
+            .send()
+            .await
+            .expect("Failed to execute request.")
+    }
+
+    pub async fn post_login_code(&self, code: &str) -> reqwest::Response {
+        self.api_client
+            .post(format!("{}/login/2fa", &self.address))
+            .form(&serde_json::json!({ "code": code }))
+            .send()
+            .await
+            .expect("Failed to execute request.")
+    }
+
+    pub async fn get_two_factor_settings_html(&self) -> String {
+        self.api_client
+            .get(format!("{}/admin/2fa", &self.address))
+            .send()
+            .await
+            .expect("Failed to execute request.")
+            .text()
+            .await
+            .unwrap()
+    }
+
+    pub async fn post_enable_two_factor(&self, code: &str) -> reqwest::Response {
+        self.api_client
+            .post(format!("{}/admin/2fa", &self.address))
+            .form(&serde_json::json!({ "code": code }))
+            .send()
+            .await
+            .expect("Failed to execute request.")
+    }
+
+    pub async fn post_disable_two_factor(&self, code: &str) -> reqwest::Response {
+        self.api_client
+            .post(format!("{}/admin/2fa/disable", &self.address))
+            .form(&serde_json::json!({ "code": code }))
+            .send()
+            .await
+            .expect("Failed to execute request.")
+    }
+
     pub async fn get_login_html(&self) -> String {
         self.api_client
-            .get(&format!("{}/login", &self.address))
+            .get(format!("{}/login", &self.address))
             .send()
             .await
             .expect("Failed to execute request.")
@@ -148,7 +390,7 @@ impl TestApp {
 
     pub async fn get_admin_dashboard(&self) -> reqwest::Response {
         self.api_client
-            .get(&format!("{}/admin/dashboard", &self.address))
+            .get(format!("{}/admin/dashboard", &self.address))
             .send()
             .await
             .expect("Failed to execute request.")
@@ -158,6 +400,26 @@ impl TestApp {
         self.get_admin_dashboard().await.text().await.unwrap()
     }
 
+    pub async fn get_change_email_html(&self) -> String {
+        self.api_client
+            .get(format!("{}/admin/email", &self.address))
+            .send()
+            .await
+            .expect("Failed to execute request.")
+            .text()
+            .await
+            .unwrap()
+    }
+
+    pub async fn post_change_email(&self, email: &str) -> reqwest::Response {
+        self.api_client
+            .post(format!("{}/admin/email", &self.address))
+            .form(&serde_json::json!({ "email": email }))
+            .send()
+            .await
+            .expect("Failed to execute request.")
+    }
+
     pub async fn get_change_password_html(&self) -> String {
         self.get_change_password()
             .await
@@ -168,7 +430,7 @@ impl TestApp {
 
     pub async fn get_change_password(&self) -> reqwest::Response {
         self.api_client
-            .get(&format!("{}/admin/password", &self.address))
+            .get(format!("{}/admin/password", &self.address))
             .send()
             .await
             .expect("Failed to execute request.")
@@ -179,13 +441,73 @@ impl TestApp {
         Body: serde::Serialize,
     {
         self.api_client
-            .post(&format!("{}/admin/password", &self.address))
+            .post(format!("{}/admin/password", &self.address))
             .form(body)
             .send()
             .await
             .expect("Failed to execute request.")
     }
 
+    pub async fn post_resend_confirmation(&self, subscription_token: &str) -> reqwest::Response {
+        self.api_client
+            .post(format!("{}/subscriptions/confirm/resend", &self.address))
+            .form(&[("subscription_token", subscription_token)])
+            .send()
+            .await
+            .expect("Failed to execute request.")
+    }
+
+    pub async fn get_unsubscribe(&self, token: &str) -> reqwest::Response {
+        self.api_client
+            .get(format!("{}/subscriptions/unsubscribe", &self.address))
+            .query(&[("token", token)])
+            .send()
+            .await
+            .expect("Failed to execute request.")
+    }
+
+    pub async fn post_unsubscribe(&self, token: &str) -> reqwest::Response {
+        self.api_client
+            .post(format!("{}/subscriptions/unsubscribe", &self.address))
+            .query(&[("token", token)])
+            .form(&[("List-Unsubscribe", "One-Click")])
+            .send()
+            .await
+            .expect("Failed to execute request.")
+    }
+
+    pub async fn get_preferences(&self, token: &str) -> reqwest::Response {
+        self.api_client
+            .get(format!("{}/subscriptions/preferences", &self.address))
+            .query(&[("token", token)])
+            .send()
+            .await
+            .expect("Failed to execute request.")
+    }
+
+    /// `body` is sent as-is, so that repeated `lists` fields can be used.
+    pub async fn post_preferences(&self, token: &str, body: String) -> reqwest::Response {
+        self.api_client
+            .post(format!("{}/subscriptions/preferences", &self.address))
+            .query(&[("token", token)])
+            .header("Content-Type", "application/x-www-form-urlencoded")
+            .body(body)
+            .send()
+            .await
+            .expect("Failed to execute request.")
+    }
+
+    pub async fn post_email_webhook(&self, provider: &str, password: &str, payload: &str) -> reqwest::Response {
+        self.api_client
+            .post(format!("{}/webhooks/email/{}", &self.address, provider))
+            .basic_auth("webhooks", Some(password))
+            .header("Content-Type", "application/json")
+            .body(payload.to_owned())
+            .send()
+            .await
+            .expect("Failed to execute request.")
+    }
+
     pub async fn post_logout(&self) -> reqwest::Response {
         self.api_client
             .post(format!("{}/admin/logout", self.address))
@@ -238,6 +560,11 @@ static TRACING: Lazy<()> = Lazy::new(|| {
 });
 
 pub async fn spawn_app() -> TestApp {
+    spawn_app_with(|_| {}).await
+}
+
+/// Spawns the application with configuration tweaks on top of the test defaults.
+pub async fn spawn_app_with(customise: impl FnOnce(&mut Settings)) -> TestApp {
     Lazy::force(&TRACING);
 
     let email_server = wiremock::MockServer::start().await;
@@ -247,12 +574,15 @@ pub async fn spawn_app() -> TestApp {
         c.database.database_name = uuid::Uuid::new_v4().to_string();
         c.application.port = 0;
         c.email_client.base_url = email_server.uri();
+        c.email_client.webhook_token = uuid::Uuid::new_v4().to_string();
+        customise(&mut c);
         c
     };
 
     configure_database(&configuration.database).await; 
 
-    let application = Application::build(configuration.clone())
+    let email_client = configuration.email_client.clone().client();
+    let application = Application::build(configuration.clone(), email_client.clone())
         .await
         .expect("Failed to build application.");
     let application_port = application.port();
@@ -271,11 +601,45 @@ pub async fn spawn_app() -> TestApp {
         email_server,
         test_user: TestUser::generate(),
         api_client: client,
+        webhook_token: configuration.email_client.webhook_token.clone(),
+        hmac_secret: configuration.application.hmac_secret.clone(),
+        email_client: configuration.email_client.worker_client(&email_client),
     };
     test_app.test_user.store(&test_app.db_pool).await;
     test_app
 }
 
+pub async fn create_unconfirmed_subscriber(app: &TestApp) -> ConfirmationLinks {
+    let body = "name=le%20guin&email=ursula_le_guin%40gmail.com";
+
+    let _mock_guard = Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .named("Create unconfirmed subscriber")
+        .expect(1)
+        .mount_as_scoped(&app.email_server)
+        .await;
+
+    app.post_subscriptions(body.into())
+        .await
+        .error_for_status()
+        .unwrap();
+
+    let email_request = app.email_server.received_requests().await.unwrap().pop().unwrap();
+    app.get_confirmation_links(&email_request)
+}
+
+pub async fn create_confirmed_subscriber(app: &TestApp) {
+    let confirmation_links = create_unconfirmed_subscriber(app).await;
+
+    let confirmation_link = confirmation_links.html;
+    reqwest::get(confirmation_link)
+        .await
+        .unwrap()
+        .error_for_status()
+        .unwrap();
+}
+
 pub fn assert_is_redirect_to(response: &reqwest::Response, location: &str) {
     assert_eq!(response.status().as_u16(), 303);
     assert_eq!(response.headers().get("Location").unwrap(), location);
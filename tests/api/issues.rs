@@ -0,0 +1,184 @@
+use wiremock::{matchers::any, Mock, ResponseTemplate};
+
+use crate::helpers::{spawn_app, TestApp};
+
+async fn publish_issue(app: &TestApp, title: &str, html_content: &str) -> uuid::Uuid {
+    app.post_login(&serde_json::json!({
+        "username": &app.test_user.username,
+        "password": &app.test_user.password,
+    })).await;
+    app.publish_newsletter(&serde_json::json!({
+        "title": title,
+        "text_content": "Newsletter body as plain text.",
+        "html_content": html_content,
+    }))
+    .await;
+    sqlx::query!("SELECT newsletter_issue_id FROM newsletter_issues WHERE title = $1", title)
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap()
+        .newsletter_issue_id
+}
+
+async fn get(app: &TestApp, path: &str) -> reqwest::Response {
+    app.api_client
+        .get(format!("{}{}", app.address, path))
+        .send()
+        .await
+        .expect("Failed to execute request.")
+}
+
+#[tokio::test]
+async fn published_issues_are_stored_with_their_author() {
+    let app = spawn_app().await;
+
+    let issue_id = publish_issue(&app, "Issue #1", "<p>Hello</p>").await;
+
+    let saved = sqlx::query!(
+        "SELECT author_id, published_at FROM newsletter_issues WHERE newsletter_issue_id = $1",
+        issue_id
+    )
+    .fetch_one(&app.db_pool)
+    .await
+    .unwrap();
+    assert_eq!(saved.author_id, Some(app.test_user.user_id));
+    assert!(saved.published_at.is_some());
+}
+
+#[tokio::test]
+async fn the_archive_lists_published_issues_without_logging_in() {
+    let app = spawn_app().await;
+    let issue_id = publish_issue(&app, "Issue #1", "<p>Hello</p>").await;
+    app.post_logout().await;
+
+    let response = get(&app, "/issues").await;
+    assert_eq!(response.status().as_u16(), 200);
+    let html_page = response.text().await.unwrap();
+    assert!(html_page.contains(&format!(r#"<a href="/issues/{}">Issue #1</a>"#, issue_id)));
+
+    let html_page = get(&app, &format!("/issues/{}", issue_id)).await.text().await.unwrap();
+    assert!(html_page.contains("<h1>Issue #1</h1>"));
+    assert!(html_page.contains("<p>Hello</p>"));
+    assert!(html_page.contains("Published on "));
+    assert!(!html_page.contains(&app.test_user.username));
+}
+
+#[tokio::test]
+async fn issue_pages_are_sanitised() {
+    let app = spawn_app().await;
+    let issue_id = publish_issue(
+        &app,
+        "Issue #1",
+        r#"<p onclick="steal()">Hello</p><script>alert('pwned')</script>"#,
+    )
+    .await;
+
+    let html_page = get(&app, &format!("/issues/{}", issue_id)).await.text().await.unwrap();
+
+    assert!(html_page.contains("<p>Hello</p>"));
+    assert!(!html_page.contains("<script>"));
+    assert!(!html_page.contains("onclick"));
+}
+
+#[tokio::test]
+async fn drafts_and_scheduled_issues_are_not_public() {
+    let app = spawn_app().await;
+    let draft_id = publish_issue(&app, "Issue #1", "<p>Hello</p>").await;
+    sqlx::query!("UPDATE newsletter_issues SET status = 'draft'")
+        .execute(&app.db_pool)
+        .await
+        .unwrap();
+
+    let response = get(&app, &format!("/issues/{}", draft_id)).await;
+    assert_eq!(response.status().as_u16(), 404);
+    let html_page = get(&app, "/issues").await.text().await.unwrap();
+    assert!(!html_page.contains("Issue #1"));
+    let feed = get(&app, "/feed.xml").await.text().await.unwrap();
+    assert!(!feed.contains("Issue #1"));
+}
+
+#[tokio::test]
+async fn issues_sent_to_a_segment_are_not_public() {
+    let app = spawn_app().await;
+    publish_issue(&app, "Issue #1", "<p>Hello</p>").await;
+    app.post_create_tag(&serde_json::json!({"name": "beta"})).await;
+    app.publish_newsletter(&serde_json::json!({
+        "title": "Beta testers only",
+        "text_content": "Newsletter body as plain text.",
+        "html_content": "<p>Newsletter body as HTML.</p>",
+        "segment": "tag:beta",
+    }))
+    .await;
+    let issue_id = sqlx::query!(
+        "SELECT newsletter_issue_id FROM newsletter_issues WHERE title = $1",
+        "Beta testers only"
+    )
+    .fetch_one(&app.db_pool)
+    .await
+    .unwrap()
+    .newsletter_issue_id;
+    app.post_logout().await;
+
+    let response = get(&app, &format!("/issues/{}", issue_id)).await;
+    assert_eq!(response.status().as_u16(), 404);
+    for path in ["/issues", "/feed.xml", "/rss.xml"] {
+        let body = get(&app, path).await.text().await.unwrap();
+        assert!(body.contains("Issue #1"), "{} should list the public issue", path);
+        assert!(!body.contains("Beta testers only"), "{} should not list the segment issue", path);
+    }
+}
+
+#[tokio::test]
+async fn the_atom_feed_contains_published_issues() {
+    let app = spawn_app().await;
+    Mock::given(any())
+        .respond_with(ResponseTemplate::new(200))
+        .mount(&app.email_server)
+        .await;
+    let issue_id = publish_issue(&app, "Fish & Chips", "<p>Hello<script>x()</script></p>").await;
+
+    let response = get(&app, "/feed.xml").await;
+
+    assert_eq!(response.status().as_u16(), 200);
+    assert_eq!(response.headers()["Content-Type"], "application/atom+xml; charset=utf-8");
+    let feed = response.text().await.unwrap();
+    assert!(feed.contains(r#"<feed xmlns="http://www.w3.org/2005/Atom">"#));
+    assert!(feed.contains("<title>Fish &amp; Chips</title>"));
+    assert!(feed.contains(&format!("<id>urn:uuid:{}</id>", issue_id)));
+    assert!(feed.contains(&format!("/issues/{}", issue_id)));
+    assert!(feed.contains("&lt;p&gt;Hello&lt;/p&gt;"));
+    assert!(!feed.contains("script"));
+    assert!(feed.contains("<author><name>Newsletter</name></author>"));
+    assert!(!feed.contains(&app.test_user.username));
+}
+
+#[tokio::test]
+async fn the_rss_feed_contains_published_issues() {
+    let app = spawn_app().await;
+    let issue_id = publish_issue(&app, "Issue #1", "<p>Hello</p>").await;
+
+    let response = get(&app, "/rss.xml").await;
+
+    assert_eq!(response.status().as_u16(), 200);
+    assert_eq!(response.headers()["Content-Type"], "application/rss+xml; charset=utf-8");
+    let feed = response.text().await.unwrap();
+    assert!(feed.contains(r#"<rss version="2.0">"#));
+    assert!(feed.contains("<title>Issue #1</title>"));
+    assert!(feed.contains(&format!(r#"<guid isPermaLink="false">{}</guid>"#, issue_id)));
+}
+
+#[tokio::test]
+async fn archived_issues_are_rendered_without_subscriber_details() {
+    let app = spawn_app().await;
+    let issue_id = publish_issue(
+        &app,
+        "Issue #1",
+        r#"<p>Hi {{ name | default("reader") }}!</p>{% if unsubscribe_url %}<p>Private</p>{% endif %}"#,
+    )
+    .await;
+
+    let html_page = get(&app, &format!("/issues/{}", issue_id)).await.text().await.unwrap();
+
+    assert!(html_page.contains("<p>Hi reader!</p>"));
+    assert!(!html_page.contains("Private"));
+}
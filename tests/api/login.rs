@@ -1,4 +1,9 @@
-use crate::helpers::{assert_is_redirect_to, spawn_app};
+use zero2prod::{
+    authentication::{forget_stale_failures, lift_expired_lockouts, LoginThrottlePolicy},
+    configuration::Settings,
+};
+
+use crate::helpers::{assert_is_redirect_to, spawn_app, spawn_app_with, TestApp};
 
 #[tokio::test]
 async fn an_error_flash_message_is_set_on_failure() {
@@ -34,4 +39,184 @@ async fn redirect_to_admin_dashboard_after_login_success() {
 
     let html_page = app.get_admin_dashboard_html().await;
     assert!(html_page.contains(&format!("Welcome {}", &app.test_user.username)));
-}
\ No newline at end of file
+}
+
+/// Lockouts after 3 failures per username or 5 per IP, without delays.
+async fn spawn_throttled_app(customise: impl FnOnce(&mut Settings)) -> TestApp {
+    spawn_app_with(|c| {
+        c.login_throttle.free_attempts = 100;
+        c.login_throttle.username_lockout_threshold = 3;
+        c.login_throttle.ip_lockout_threshold = 5;
+        c.login_throttle.lockout_seconds = 900;
+        customise(c);
+    })
+    .await
+}
+
+async fn login_with(app: &TestApp, username: &str, password: &str) -> reqwest::Response {
+    app.post_login(&serde_json::json!({
+        "username": username,
+        "password": password,
+    }))
+    .await
+}
+
+async fn login_forwarded_for(app: &TestApp, forwarded_for: &str, username: &str, password: &str) -> reqwest::Response {
+    app.api_client
+        .post(format!("{}/login", &app.address))
+        .header("X-Forwarded-For", forwarded_for)
+        .form(&serde_json::json!({
+            "username": username,
+            "password": password,
+        }))
+        .send()
+        .await
+        .expect("Failed to execute request.")
+}
+
+#[tokio::test]
+async fn a_username_is_locked_out_after_too_many_failures() {
+    let app = spawn_throttled_app(|c| c.login_throttle.ip_lockout_threshold = 100).await;
+    let username = &app.test_user.username;
+    for _ in 0..3 {
+        login_with(&app, username, "wrong-password").await;
+    }
+
+    // Even the right password is refused.
+    let response = login_with(&app, username, &app.test_user.password).await;
+
+    assert_is_redirect_to(&response, "/login");
+    let html_page = app.get_login_html().await;
+    assert!(html_page.contains("<p><i>Too many failed login attempts. Please try again later.</i></p>"));
+
+    // Unknown usernames get the same treatment, so the message reveals nothing.
+    for _ in 0..4 {
+        login_with(&app, "nobody", "wrong-password").await;
+    }
+    let html_page = app.get_login_html().await;
+    assert!(html_page.contains("<p><i>Too many failed login attempts. Please try again later.</i></p>"));
+}
+
+#[tokio::test]
+async fn a_client_ip_is_locked_out_after_too_many_failures() {
+    let app = spawn_throttled_app(|_| {}).await;
+    // A forged header does not spread the failures over several addresses.
+    for i in 0..5 {
+        login_forwarded_for(&app, &format!("203.0.113.{i}"), &format!("user-{i}"), "wrong-password").await;
+    }
+
+    let response = login_forwarded_for(&app, "203.0.113.99", &app.test_user.username, &app.test_user.password).await;
+
+    assert_is_redirect_to(&response, "/login");
+    let html_page = app.get_login_html().await;
+    assert!(html_page.contains("<p><i>Too many failed login attempts. Please try again later.</i></p>"));
+}
+
+#[tokio::test]
+async fn the_forwarded_address_is_used_behind_a_trusted_proxy() {
+    let app = spawn_throttled_app(|c| c.application.trusted_proxies = vec!["127.0.0.1".parse().unwrap()]).await;
+    for i in 0..5 {
+        login_forwarded_for(&app, "203.0.113.1", &format!("user-{i}"), "wrong-password").await;
+    }
+
+    let response = login_forwarded_for(&app, "203.0.113.1", &app.test_user.username, &app.test_user.password).await;
+    assert_is_redirect_to(&response, "/login");
+
+    let response = login_forwarded_for(&app, "203.0.113.2", &app.test_user.username, &app.test_user.password).await;
+    assert_is_redirect_to(&response, "/admin/dashboard");
+}
+
+#[tokio::test]
+async fn a_successful_login_resets_the_username_failures() {
+    let app = spawn_throttled_app(|c| c.login_throttle.ip_lockout_threshold = 100).await;
+    let username = &app.test_user.username;
+    for _ in 0..2 {
+        login_with(&app, username, "wrong-password").await;
+    }
+    login_with(&app, username, &app.test_user.password).await;
+    app.post_logout().await;
+
+    for _ in 0..2 {
+        login_with(&app, username, "wrong-password").await;
+    }
+    let response = login_with(&app, username, &app.test_user.password).await;
+
+    assert_is_redirect_to(&response, "/admin/dashboard");
+}
+
+#[tokio::test]
+async fn lockouts_expire() {
+    let app = spawn_throttled_app(|c| c.login_throttle.lockout_seconds = 1).await;
+    let username = &app.test_user.username;
+    for _ in 0..3 {
+        login_with(&app, username, "wrong-password").await;
+    }
+
+    tokio::time::sleep(std::time::Duration::from_secs(2)).await;
+    let response = login_with(&app, username, &app.test_user.password).await;
+
+    assert_is_redirect_to(&response, "/admin/dashboard");
+}
+
+#[tokio::test]
+async fn expired_lockouts_are_lifted_by_the_sweeper() {
+    let app = spawn_throttled_app(|c| c.login_throttle.lockout_seconds = 1).await;
+    for _ in 0..3 {
+        login_with(&app, &app.test_user.username, "wrong-password").await;
+    }
+    assert_eq!(lift_expired_lockouts(&app.db_pool).await.unwrap(), 0);
+
+    tokio::time::sleep(std::time::Duration::from_secs(2)).await;
+
+    assert_eq!(lift_expired_lockouts(&app.db_pool).await.unwrap(), 1);
+    let locked = sqlx::query_scalar!(
+        "SELECT COUNT(*) FROM login_attempts WHERE locked_until IS NOT NULL"
+    )
+    .fetch_one(&app.db_pool)
+    .await
+    .unwrap();
+    assert_eq!(locked, Some(0));
+}
+
+#[tokio::test]
+async fn the_sweeper_forgets_failures_outside_the_window_unless_locked() {
+    let app = spawn_throttled_app(|_| {}).await;
+    login_with(&app, "someone-else", "wrong-password").await;
+    for _ in 0..3 {
+        login_with(&app, &app.test_user.username, "wrong-password").await;
+    }
+    tokio::time::sleep(std::time::Duration::from_secs(2)).await;
+
+    let policy = LoginThrottlePolicy {
+        failure_window: std::time::Duration::from_secs(1),
+        free_attempts: 100,
+        base_delay: std::time::Duration::ZERO,
+        max_delay: std::time::Duration::ZERO,
+        username_lockout_threshold: 3,
+        ip_lockout_threshold: 5,
+        lockout: std::time::Duration::from_secs(900),
+    };
+    assert_eq!(forget_stale_failures(&policy, &app.db_pool).await.unwrap(), 2);
+    let remaining = sqlx::query!("SELECT scope, subject, locked_until FROM login_attempts")
+        .fetch_all(&app.db_pool)
+        .await
+        .unwrap();
+    assert_eq!(remaining.len(), 1);
+    assert_eq!(remaining[0].subject, app.test_user.username);
+    assert!(remaining[0].locked_until.is_some());
+}
+
+#[tokio::test]
+async fn failures_past_the_free_attempts_are_delayed() {
+    let app = spawn_app_with(|c| {
+        c.login_throttle.free_attempts = 1;
+        c.login_throttle.base_delay_milliseconds = 1000;
+    })
+    .await;
+    login_with(&app, "nobody", "wrong-password").await;
+
+    let start = std::time::Instant::now();
+    login_with(&app, "nobody", "wrong-password").await;
+
+    assert!(start.elapsed() >= std::time::Duration::from_secs(1));
+}
@@ -3,6 +3,20 @@ mod subscriptions;
 mod health_check;
 mod subscriptions_confirm;
 mod newsletter;
+mod newsletter_drafts;
 mod login;
 mod admin_dashboard;
-mod change_password;
\ No newline at end of file
+mod change_email;
+mod change_password;
+mod unsubscribe;
+mod preferences;
+mod issues;
+mod tags;
+mod email_webhooks;
+mod tracking;
+mod delivery_report;
+mod delivery_concurrency;
+mod newsletter_attachments;
+mod users;
+mod password_reset;
+mod two_factor;
\ No newline at end of file
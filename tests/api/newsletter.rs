@@ -1,68 +1,348 @@
+use std::time::Duration;
+
 use wiremock::{matchers::{any, method, path}, Mock, ResponseTemplate};
 
-use crate::helpers::{assert_is_redirect_to, spawn_app, ConfirmationLinks, TestApp};
+use crate::helpers::{assert_is_redirect_to, create_confirmed_subscriber, create_unconfirmed_subscriber, spawn_app};
+
+#[tokio::test]
+async fn newsletters_are_not_delivered_to_unconfirmed_subscribers() {
+    let app = spawn_app().await;
+    create_unconfirmed_subscriber(&app).await;
+    app.post_login(&serde_json::json!({
+        "username": &app.test_user.username,
+        "password": &app.test_user.password,
+    })).await;
+
+    Mock::given(any())
+        .respond_with(ResponseTemplate::new(200))
+        .expect(0)
+        .mount(&app.email_server)
+        .await;
+
+    let newsletter_request_body = serde_json::json!({
+        "title": "Newsletter titile",
+        "text_content": "Newsletter body as plain text.",
+        "html_content": "<p>Newsletter body as HTML.</p>",
+    });
+    let response = app.publish_newsletter(&newsletter_request_body).await;
+    assert_is_redirect_to(&response, "/admin/newsletter/drafts");
+
+    let html_page = app.get_drafts_html().await;
+    assert!(html_page.contains("<p><i>The newsletter issue has been published!</i></p>"));
+    app.dispatch_all_pending_emails().await;
+}
 
-async fn create_unconfirmed_subscriber(app: &TestApp) -> ConfirmationLinks {
-    let body = "name=le%20guin&email=ursula_le_guin%40gmail.com";
 
-    let _mock_guard = Mock::given(path("/email"))
+#[tokio::test]
+async fn newsletters_are_delivered_to_confirmed_subscribers() {
+    let app = spawn_app().await;
+    create_confirmed_subscriber(&app).await;
+    app.post_login(&serde_json::json!({
+        "username": &app.test_user.username,
+        "password": &app.test_user.password,
+    })).await;
+
+    Mock::given(path("email"))
         .and(method("POST"))
         .respond_with(ResponseTemplate::new(200))
-        .named("Create unconfirmed subscriber")
         .expect(1)
-        .mount_as_scoped(&app.email_server)
+        .mount(&app.email_server)
+        .await;
+
+    let newsletter_request_body = serde_json::json!({
+        "title": "Newsletter title",
+        "text_content": "Newsletter body as plain text.",
+        "html_content": "<p>Newsletter body as HTML.</p>",
+    });
+    let response = app.publish_newsletter(&newsletter_request_body).await;
+    assert_is_redirect_to(&response, "/admin/newsletter/drafts");
+
+    let html_page = app.get_drafts_html().await;
+    assert!(html_page.contains("<p><i>The newsletter issue has been published!</i></p>"));
+    app.dispatch_all_pending_emails().await;
+
+    let request = app.email_server.received_requests().await.unwrap().pop().unwrap();
+    let body: serde_json::Value = serde_json::from_slice(&request.body).unwrap();
+    assert_eq!(body["MessageStream"], "broadcast");
+}
+
+
+#[tokio::test]
+async fn failed_deliveries_are_kept_in_the_queue_for_a_retry() {
+    let app = spawn_app().await;
+    create_confirmed_subscriber(&app).await;
+    app.post_login(&serde_json::json!({
+        "username": &app.test_user.username,
+        "password": &app.test_user.password,
+    })).await;
+
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(500))
+        .expect(1..)
+        .mount(&app.email_server)
         .await;
 
-    app.post_subscriptions(body.into())
+    let newsletter_request_body = serde_json::json!({
+        "title": "Newsletter title",
+        "text_content": "Newsletter body as plain text.",
+        "html_content": "<p>Newsletter body as HTML.</p>",
+    });
+    let response = app.publish_newsletter(&newsletter_request_body).await;
+    assert_is_redirect_to(&response, "/admin/newsletter/drafts");
+    app.dispatch_all_pending_emails().await;
+
+    let saved = sqlx::query!("SELECT subscriber_email, n_retries FROM issue_delivery_queue")
+        .fetch_one(&app.db_pool)
         .await
-        .error_for_status()
-        .unwrap();
+        .expect("Failed to fetch pending delivery task.");
+    assert_eq!(saved.subscriber_email, "ursula_le_guin@gmail.com");
+    assert_eq!(saved.n_retries, 1);
+}
+
+#[tokio::test]
+async fn the_worker_postpones_transient_failures_instead_of_retrying_in_place() {
+    let app = spawn_app().await;
+    create_confirmed_subscriber(&app).await;
+    app.post_login(&serde_json::json!({
+        "username": &app.test_user.username,
+        "password": &app.test_user.password,
+    })).await;
+
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(429).insert_header("Retry-After", "120"))
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+
+    app.publish_newsletter(&serde_json::json!({
+        "title": "Newsletter title",
+        "text_content": "Newsletter body as plain text.",
+        "html_content": "<p>Newsletter body as HTML.</p>",
+    }))
+    .await;
+    app.dispatch_all_pending_emails().await;
+
+    let saved = sqlx::query!(
+        r#"SELECT n_retries, execute_after > now() + interval '100 seconds' AS "honours_retry_after!" FROM issue_delivery_queue"#
+    )
+    .fetch_one(&app.db_pool)
+    .await
+    .expect("Failed to fetch pending delivery task.");
+    assert_eq!(saved.n_retries, 1);
+    assert!(saved.honours_retry_after);
+}
+
+#[tokio::test]
+async fn concurrent_publish_submissions_send_the_issue_once() {
+    let app = spawn_app().await;
+    create_confirmed_subscriber(&app).await;
+    app.post_login(&serde_json::json!({
+        "username": &app.test_user.username,
+        "password": &app.test_user.password,
+    })).await;
+
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200).set_delay(Duration::from_secs(2)))
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+
+    let response = app.post_create_draft(&serde_json::json!({
+        "title": "Newsletter title",
+        "text_content": "Newsletter body as plain text.",
+        "html_content": "<p>Newsletter body as HTML.</p>",
+    }))
+    .await;
+    let draft_path = response.headers().get("Location").unwrap().to_str().unwrap().to_owned();
+    let publish_body = serde_json::json!({"idempotency_key": uuid::Uuid::new_v4().to_string()});
+    let response1 = app.post_draft(&draft_path, "/publish", &publish_body);
+    let response2 = app.post_draft(&draft_path, "/publish", &publish_body);
+    let (response1, response2) = tokio::join!(response1, response2);
+
+    assert_eq!(response1.status(), response2.status());
+    assert_eq!(response1.headers().get("Location"), response2.headers().get("Location"));
+    assert_eq!(response1.text().await.unwrap(), response2.text().await.unwrap());
+    app.dispatch_all_pending_emails().await;
+}
+
+#[tokio::test]
+async fn concurrent_publish_submissions_with_different_keys_send_the_issue_once() {
+    let app = spawn_app().await;
+    create_confirmed_subscriber(&app).await;
+    app.post_login(&serde_json::json!({
+        "username": &app.test_user.username,
+        "password": &app.test_user.password,
+    })).await;
+
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
 
-    let email_request = &app.email_server.received_requests().await.unwrap()[0];
-    app.get_confirmation_links(email_request)
+    let response = app.post_create_draft(&serde_json::json!({
+        "title": "Newsletter title",
+        "text_content": "Newsletter body as plain text.",
+        "html_content": "<p>Newsletter body as HTML.</p>",
+    }))
+    .await;
+    let draft_path = response.headers().get("Location").unwrap().to_str().unwrap().to_owned();
+    let body1 = serde_json::json!({"idempotency_key": uuid::Uuid::new_v4().to_string()});
+    let body2 = serde_json::json!({"idempotency_key": uuid::Uuid::new_v4().to_string()});
+    let response1 = app.post_draft(&draft_path, "/publish", &body1);
+    let response2 = app.post_draft(&draft_path, "/publish", &body2);
+    let (response1, response2) = tokio::join!(response1, response2);
+
+    assert_is_redirect_to(&response1, "/admin/newsletter/drafts");
+    assert_is_redirect_to(&response2, "/admin/newsletter/drafts");
+    app.dispatch_all_pending_emails().await;
 }
 
-async fn create_confirmed_subscriber(app: &TestApp) {
-    let confirmation_links = create_unconfirmed_subscriber(app).await;
+fn scheduled_newsletter_request_body(send_at: &str, timezone: &str) -> serde_json::Value {
+    serde_json::json!({
+        "title": "Scheduled newsletter title",
+        "text_content": "Newsletter body as plain text.",
+        "html_content": "<p>Newsletter body as HTML.</p>",
+        "send_at": send_at,
+        "timezone": timezone,
+    })
+}
 
-    let confirmation_link = confirmation_links.html;
-    reqwest::get(confirmation_link)
+async fn make_scheduled_issues_due(app: &crate::helpers::TestApp) {
+    sqlx::query!("UPDATE newsletter_issues SET send_at = now() - interval '1 minute'")
+        .execute(&app.db_pool)
         .await
-        .unwrap()
-        .error_for_status()
         .unwrap();
 }
 
 #[tokio::test]
-async fn newsletters_are_not_delivered_to_unconfirmed_subscribers() {
+async fn scheduled_issues_are_delivered_once_they_are_due() {
     let app = spawn_app().await;
-    create_unconfirmed_subscriber(&app).await;
+    create_confirmed_subscriber(&app).await;
+    app.post_login(&serde_json::json!({
+        "username": &app.test_user.username,
+        "password": &app.test_user.password,
+    })).await;
+
+    let send_at = (chrono::Utc::now() + chrono::Duration::days(2)).format("%Y-%m-%dT08:00");
+    let response = app
+        .publish_newsletter(&scheduled_newsletter_request_body(&send_at.to_string(), "Europe/Rome"))
+        .await;
+    assert_is_redirect_to(&response, "/admin/newsletter/drafts");
+    let html_page = app.get_drafts_html().await;
+    assert!(html_page.contains("The newsletter issue has been scheduled for"));
+
+    let saved = sqlx::query!("SELECT status, send_at, published_at FROM newsletter_issues")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap();
+    assert_eq!(saved.status, "scheduled");
+    assert!(saved.published_at.is_none());
+    let rome_time = saved.send_at.unwrap().with_timezone(&chrono_tz::Europe::Rome);
+    assert_eq!(rome_time.format("%H:%M").to_string(), "08:00");
+
+    // Not due yet: nothing gets delivered.
+    assert_eq!(app.promote_scheduled_issues().await, 0);
+    {
+        let _mock_guard = Mock::given(any())
+            .respond_with(ResponseTemplate::new(200))
+            .expect(0)
+            .mount_as_scoped(&app.email_server)
+            .await;
+        app.dispatch_all_pending_emails().await;
+    }
+
+    make_scheduled_issues_due(&app).await;
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+    assert_eq!(app.promote_scheduled_issues().await, 1);
+    app.dispatch_all_pending_emails().await;
+
+    let saved = sqlx::query!("SELECT status, published_at FROM newsletter_issues")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap();
+    assert_eq!(saved.status, "published");
+    assert!(saved.published_at.is_some());
+}
+
+#[tokio::test]
+async fn cancelled_scheduled_issues_are_never_delivered() {
+    let app = spawn_app().await;
+    create_confirmed_subscriber(&app).await;
     app.post_login(&serde_json::json!({
         "username": &app.test_user.username,
         "password": &app.test_user.password,
     })).await;
 
+    let send_at = (chrono::Utc::now() + chrono::Duration::days(1)).format("%Y-%m-%dT%H:%M");
+    app.publish_newsletter(&scheduled_newsletter_request_body(&send_at.to_string(), "UTC"))
+        .await;
+    let html_page = app.get_scheduled_issues_html().await;
+    assert!(html_page.contains("Scheduled newsletter title"));
+
+    let issue_id = sqlx::query!("SELECT newsletter_issue_id FROM newsletter_issues")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap()
+        .newsletter_issue_id;
+    let response = app.post_cancel_scheduled_issue(issue_id).await;
+    assert_is_redirect_to(&response, "/admin/newsletter/scheduled");
+    let html_page = app.get_scheduled_issues_html().await;
+    assert!(html_page.contains("<p><i>The scheduled issue has been cancelled.</i></p>"));
+    assert!(!html_page.contains("Scheduled newsletter title"));
+
     Mock::given(any())
         .respond_with(ResponseTemplate::new(200))
         .expect(0)
         .mount(&app.email_server)
         .await;
+    make_scheduled_issues_due(&app).await;
+    assert_eq!(app.promote_scheduled_issues().await, 0);
+    app.dispatch_all_pending_emails().await;
+}
 
-    let newsletter_request_body = serde_json::json!({
-        "title": "Newsletter titile",
-        "text_content": "Newsletter body as plain text.",
-        "html_content": "<p>Newsletter body as HTML.</p>",
-    });
-    let response = app.post_publish_newsletters(&newsletter_request_body).await;
-    assert_is_redirect_to(&response, "/admin/newsletter");
+#[tokio::test]
+async fn invalid_schedules_are_rejected_with_a_flash_message() {
+    let app = spawn_app().await;
+    app.post_login(&serde_json::json!({
+        "username": &app.test_user.username,
+        "password": &app.test_user.password,
+    })).await;
 
-    let html_page = app.get_publish_newsletter_html().await;
-    assert!(html_page.contains("<p><i>The newsletter issue has been published!</i></p>"));
-}
+    let test_cases = vec![
+        ("not-a-date", "UTC", "The scheduled time is not a valid date and time."),
+        ("2099-01-01T08:00", "Mars/Olympus_Mons", "The timezone is not a valid IANA timezone name."),
+        ("2001-01-01T08:00", "UTC", "The scheduled time must be in the future."),
+    ];
+    for (send_at, timezone, error_message) in test_cases {
+        let response = app
+            .publish_newsletter(&scheduled_newsletter_request_body(send_at, timezone))
+            .await;
+        assert_eq!(response.status().as_u16(), 303);
 
+        let html_page = app.get_drafts_html().await;
+        assert!(html_page.contains(&format!("<p><i>{}</i></p>", error_message)));
+    }
+
+    let n_issues = sqlx::query!("SELECT count(*) AS \"n!\" FROM newsletter_issues WHERE status <> 'draft'")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap()
+        .n;
+    assert_eq!(n_issues, 0);
+}
 
 #[tokio::test]
-async fn newsletters_are_delivered_to_confirmed_subscribers() {
+async fn newsletters_are_personalised_for_each_recipient() {
     let app = spawn_app().await;
     create_confirmed_subscriber(&app).await;
     app.post_login(&serde_json::json!({
@@ -70,7 +350,7 @@ async fn newsletters_are_delivered_to_confirmed_subscribers() {
         "password": &app.test_user.password,
     })).await;
 
-    Mock::given(path("email"))
+    Mock::given(path("/email"))
         .and(method("POST"))
         .respond_with(ResponseTemplate::new(200))
         .expect(1)
@@ -79,12 +359,67 @@ async fn newsletters_are_delivered_to_confirmed_subscribers() {
 
     let newsletter_request_body = serde_json::json!({
         "title": "Newsletter title",
-        "text_content": "Newsletter body as plain text.",
-        "html_content": "<p>Newsletter body as HTML.</p>",
+        "text_content": "Hi {{ name | default(\"reader\") }}! Read online: {{ issue_url }}",
+        "html_content": "<p>Hi {{ name }}!</p>{% if unsubscribe_url %}<p>Subscribed</p>{% endif %}",
     });
-    let response = app.post_publish_newsletters(&newsletter_request_body).await;
-    assert_is_redirect_to(&response, "/admin/newsletter");
+    app.publish_newsletter(&newsletter_request_body).await;
+    app.dispatch_all_pending_emails().await;
 
-    let html_page = app.get_publish_newsletter_html().await;
-    assert!(html_page.contains("<p><i>The newsletter issue has been published!</i></p>"));
+    let issue_id = sqlx::query!("SELECT newsletter_issue_id FROM newsletter_issues")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap()
+        .newsletter_issue_id;
+    let email_request = app.email_server.received_requests().await.unwrap().pop().unwrap();
+    let body: serde_json::Value = serde_json::from_slice(&email_request.body).unwrap();
+    let text_body = body["TextBody"].as_str().unwrap();
+    let html_body = body["HtmlBody"].as_str().unwrap();
+    assert!(text_body.starts_with(&format!(
+        "Hi le guin! Read online: {}/issues/{}",
+        app.address, issue_id
+    )));
+    assert!(html_body.starts_with("<p>Hi le guin!</p><p>Subscribed</p>"));
+}
+
+#[tokio::test]
+async fn invalid_templates_are_rejected_before_anything_is_sent() {
+    let app = spawn_app().await;
+    create_confirmed_subscriber(&app).await;
+    app.post_login(&serde_json::json!({
+        "username": &app.test_user.username,
+        "password": &app.test_user.password,
+    })).await;
+
+    Mock::given(any())
+        .respond_with(ResponseTemplate::new(200))
+        .expect(0)
+        .mount(&app.email_server)
+        .await;
+
+    let test_cases = vec![
+        ("Hi {{ name", "<p>Hello</p>"),
+        ("Hello", "{% if name %}<p>Hello</p>"),
+        ("Hi {{ nickname }}", "<p>Hello</p>"),
+    ];
+    for (text_content, html_content) in test_cases {
+        let response = app
+            .publish_newsletter(&serde_json::json!({
+                "title": "Newsletter title",
+                "text_content": text_content,
+                "html_content": html_content,
+            }))
+            .await;
+        assert_eq!(response.status().as_u16(), 303);
+
+        let html_page = app.get_drafts_html().await;
+        assert!(html_page.contains("<p><i>The issue content is not a valid template: "));
+    }
+
+    app.dispatch_all_pending_emails().await;
+    let n_issues = sqlx::query!("SELECT count(*) AS \"n!\" FROM newsletter_issues WHERE status <> 'draft'")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap()
+        .n;
+    assert_eq!(n_issues, 0);
 }
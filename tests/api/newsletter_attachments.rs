@@ -0,0 +1,199 @@
+use reqwest::multipart::{Form, Part};
+use wiremock::{matchers::{method, path}, Mock, ResponseTemplate};
+
+use crate::helpers::{assert_is_redirect_to, create_confirmed_subscriber, spawn_app, TestApp};
+
+const PDF: &[u8] = b"%PDF-1.4\n%\xe2\xe3\xcf\xd3\n1 0 obj\n<<>>\nendobj\ntrailer\n<<>>\n%%EOF\n";
+
+fn draft_form() -> Form {
+    Form::new()
+        .text("title", "Newsletter title")
+        .text("text_content", "Newsletter body as plain text.")
+        .text("html_content", "<p>Newsletter body as HTML.</p>")
+}
+
+fn pdf(file_name: &str) -> Part {
+    Part::bytes(PDF)
+        .file_name(file_name.to_string())
+        .mime_str("application/pdf")
+        .unwrap()
+}
+
+async fn login(app: &TestApp) {
+    app.post_login(&serde_json::json!({
+        "username": &app.test_user.username,
+        "password": &app.test_user.password,
+    })).await;
+}
+
+/// Creates a draft from `form` and returns its path.
+async fn create_draft(app: &TestApp, form: Form) -> String {
+    let response = app.post_create_draft_multipart(form).await;
+    assert_eq!(response.status().as_u16(), 303);
+    response.headers().get("Location").unwrap().to_str().unwrap().to_owned()
+}
+
+/// Publishes the draft at `draft_path` and returns the attachments of the
+/// email sent to the only subscriber.
+async fn publish_and_get_attachments(app: &TestApp, draft_path: &str) -> Option<serde_json::Value> {
+    let response = app
+        .post_draft(
+            draft_path,
+            "/publish",
+            &serde_json::json!({"idempotency_key": uuid::Uuid::new_v4().to_string()}),
+        )
+        .await;
+    assert_is_redirect_to(&response, "/admin/newsletter/drafts");
+    app.dispatch_all_pending_emails().await;
+
+    let request = app.email_server.received_requests().await.unwrap().pop().unwrap();
+    let body: serde_json::Value = serde_json::from_slice(&request.body).unwrap();
+    body.get("Attachments").cloned()
+}
+
+#[tokio::test]
+async fn a_pdf_uploaded_with_a_draft_is_attached_to_every_email() {
+    let app = spawn_app().await;
+    create_confirmed_subscriber(&app).await;
+    login(&app).await;
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+
+    let draft_path = create_draft(&app, draft_form().part("attachment", pdf("issue-42.pdf"))).await;
+    let html_page = app.get_draft_html(&draft_path, "").await;
+    assert!(html_page.contains("<p>Attachment: issue-42.pdf</p>"));
+
+    let attachments = publish_and_get_attachments(&app, &draft_path).await.unwrap();
+    let attachment = &attachments[0];
+    assert_eq!(attachment["Name"], "issue-42.pdf");
+    assert_eq!(attachment["ContentType"], "application/pdf");
+    let content = base64::Engine::decode(
+        &base64::engine::general_purpose::STANDARD,
+        attachment["Content"].as_str().unwrap(),
+    )
+    .unwrap();
+    assert_eq!(content, PDF);
+}
+
+#[tokio::test]
+async fn saving_a_draft_without_a_file_keeps_its_attachment() {
+    let app = spawn_app().await;
+    create_confirmed_subscriber(&app).await;
+    login(&app).await;
+    Mock::given(path("/email"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+    let draft_path = create_draft(&app, draft_form().part("attachment", pdf("issue-42.pdf"))).await;
+
+    let empty_file = Part::bytes(Vec::new())
+        .file_name("")
+        .mime_str("application/octet-stream")
+        .unwrap();
+    let response = app.post_draft_multipart(&draft_path, draft_form().part("attachment", empty_file)).await;
+    assert_is_redirect_to(&response, &draft_path);
+
+    let attachments = publish_and_get_attachments(&app, &draft_path).await.unwrap();
+    assert_eq!(attachments[0]["Name"], "issue-42.pdf");
+}
+
+#[tokio::test]
+async fn a_draft_attachment_can_be_replaced_or_removed() {
+    let app = spawn_app().await;
+    login(&app).await;
+    let draft_path = create_draft(&app, draft_form().part("attachment", pdf("first.pdf"))).await;
+
+    app.post_draft_multipart(&draft_path, draft_form().part("attachment", pdf("second.pdf"))).await;
+    let html_page = app.get_draft_html(&draft_path, "").await;
+    assert!(html_page.contains("<p>Attachment: second.pdf</p>"));
+    assert!(!html_page.contains("first.pdf"));
+
+    app.post_draft_multipart(&draft_path, draft_form().text("remove_attachment", "on")).await;
+    let html_page = app.get_draft_html(&draft_path, "").await;
+    assert!(!html_page.contains("<p>Attachment:"));
+}
+
+#[tokio::test]
+async fn a_draft_without_an_attachment_is_sent_without_one() {
+    let app = spawn_app().await;
+    create_confirmed_subscriber(&app).await;
+    login(&app).await;
+    Mock::given(path("/email"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+
+    let draft_path = create_draft(&app, draft_form()).await;
+
+    assert!(publish_and_get_attachments(&app, &draft_path).await.is_none());
+}
+
+#[tokio::test]
+async fn attachments_that_are_not_pdfs_are_rejected() {
+    let app = spawn_app().await;
+    login(&app).await;
+
+    let test_cases = vec![
+        (
+            Part::bytes(b"#!/bin/sh\nrm -rf /\n".to_vec()).file_name("issue.pdf").mime_str("application/pdf").unwrap(),
+            "a script pretending to be a PDF",
+        ),
+        (
+            Part::bytes(PDF).file_name("issue.txt").mime_str("text/plain").unwrap(),
+            "a PDF sent as plain text",
+        ),
+    ];
+    for (attachment, description) in test_cases {
+        let response = app.post_create_draft_multipart(draft_form().part("attachment", attachment)).await;
+        assert_is_redirect_to(&response, "/admin/newsletter/drafts");
+        let html_page = app.get_drafts_html().await;
+        assert!(
+            html_page.contains("<p><i>Attachments must be PDF files.</i></p>"),
+            "The upload was not rejected when it was {}.",
+            description
+        );
+    }
+
+    let n_issues = sqlx::query_scalar!("SELECT COUNT(*) FROM newsletter_issues")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap();
+    assert_eq!(n_issues, Some(0));
+}
+
+#[tokio::test]
+async fn attachments_larger_than_5_mb_are_rejected() {
+    let app = spawn_app().await;
+    login(&app).await;
+    let draft_path = create_draft(&app, draft_form()).await;
+
+    let mut content = PDF.to_vec();
+    content.resize(5 * 1024 * 1024 + 1, b' ');
+    let attachment = Part::bytes(content)
+        .file_name("huge.pdf")
+        .mime_str("application/pdf")
+        .unwrap();
+    let response = app.post_draft_multipart(&draft_path, draft_form().part("attachment", attachment)).await;
+
+    assert_is_redirect_to(&response, &draft_path);
+    let html_page = app.get_draft_html(&draft_path, "").await;
+    assert!(html_page.contains("<p><i>Attachments must be smaller than 5 MB.</i></p>"));
+    assert!(!html_page.contains("<p>Attachment:"));
+}
+
+#[tokio::test]
+async fn the_draft_forms_accept_a_pdf_upload() {
+    let app = spawn_app().await;
+    login(&app).await;
+
+    let html_page = app.get_drafts_html().await;
+
+    assert!(html_page.contains(r#"enctype="multipart/form-data""#));
+    assert!(html_page.contains(r#"<input type="file" name="attachment" accept="application/pdf">"#));
+}
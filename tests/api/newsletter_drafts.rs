@@ -0,0 +1,265 @@
+use wiremock::{matchers::{any, method, path}, Mock, ResponseTemplate};
+
+use crate::helpers::{assert_is_redirect_to, create_confirmed_subscriber, spawn_app, TestApp};
+
+fn draft_body() -> serde_json::Value {
+    serde_json::json!({
+        "title": "Draft title",
+        "text_content": "Draft body as plain text.",
+        "html_content": "<p>Draft body as HTML.</p>",
+    })
+}
+
+fn publish_body() -> serde_json::Value {
+    serde_json::json!({"idempotency_key": uuid::Uuid::new_v4().to_string()})
+}
+
+async fn login(app: &TestApp) {
+    app.post_login(&serde_json::json!({
+        "username": &app.test_user.username,
+        "password": &app.test_user.password,
+    })).await;
+}
+
+/// Creates a draft and returns its path, e.g. `/admin/newsletter/drafts/{id}`.
+async fn create_draft(app: &TestApp) -> String {
+    let response = app.post_create_draft(&draft_body()).await;
+    assert_eq!(response.status().as_u16(), 303);
+    response.headers().get("Location").unwrap().to_str().unwrap().to_owned()
+}
+
+#[tokio::test]
+async fn you_must_be_logged_in_to_see_the_drafts() {
+    let app = spawn_app().await;
+
+    let response = app.post_create_draft(&draft_body()).await;
+
+    assert_is_redirect_to(&response, "/login");
+}
+
+#[tokio::test]
+async fn drafts_are_not_delivered_until_they_are_published() {
+    let app = spawn_app().await;
+    create_confirmed_subscriber(&app).await;
+    login(&app).await;
+
+    let draft_path = create_draft(&app).await;
+    let html_page = app.get_draft_html(&draft_path, "").await;
+    assert!(html_page.contains("<p><i>The draft has been saved.</i></p>"));
+    assert!(app.get_drafts_html().await.contains("Draft title"));
+    {
+        let _mock_guard = Mock::given(any())
+            .respond_with(ResponseTemplate::new(200))
+            .expect(0)
+            .mount_as_scoped(&app.email_server)
+            .await;
+        app.dispatch_all_pending_emails().await;
+    }
+
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+    let response = app.post_draft(&draft_path, "/publish", &publish_body()).await;
+    assert_is_redirect_to(&response, "/admin/newsletter/drafts");
+    let html_page = app.get_drafts_html().await;
+    assert!(html_page.contains("<p><i>The newsletter issue has been published!</i></p>"));
+    assert!(!html_page.contains("Draft title"));
+    app.dispatch_all_pending_emails().await;
+}
+
+#[tokio::test]
+async fn a_draft_can_only_be_published_once() {
+    let app = spawn_app().await;
+    create_confirmed_subscriber(&app).await;
+    login(&app).await;
+    let draft_path = create_draft(&app).await;
+
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+    app.post_draft(&draft_path, "/publish", &publish_body()).await;
+    let response = app.post_draft(&draft_path, "/publish", &publish_body()).await;
+    assert_is_redirect_to(&response, "/admin/newsletter/drafts");
+    let html_page = app.get_drafts_html().await;
+    assert!(html_page.contains("<p><i>The draft has already been published.</i></p>"));
+    app.dispatch_all_pending_emails().await;
+}
+
+#[tokio::test]
+async fn resubmitting_a_publish_form_replays_the_first_response() {
+    let app = spawn_app().await;
+    create_confirmed_subscriber(&app).await;
+    login(&app).await;
+    let draft_path = create_draft(&app).await;
+
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+    let body = publish_body();
+    let response = app.post_draft(&draft_path, "/publish", &body).await;
+    assert_is_redirect_to(&response, "/admin/newsletter/drafts");
+    app.get_drafts_html().await;
+
+    let response = app.post_draft(&draft_path, "/publish", &body).await;
+    assert_is_redirect_to(&response, "/admin/newsletter/drafts");
+    let html_page = app.get_drafts_html().await;
+    assert!(html_page.contains("<p><i>The newsletter issue has been published!</i></p>"));
+    app.dispatch_all_pending_emails().await;
+}
+
+#[tokio::test]
+async fn resubmitting_a_scheduling_form_says_the_issue_was_scheduled() {
+    let app = spawn_app().await;
+    login(&app).await;
+    let draft_path = create_draft(&app).await;
+    let send_at = (chrono::Utc::now() + chrono::Duration::days(2)).format("%Y-%m-%dT08:00");
+    let body = serde_json::json!({
+        "idempotency_key": uuid::Uuid::new_v4().to_string(),
+        "send_at": send_at.to_string(),
+        "timezone": "UTC",
+    });
+    app.post_draft(&draft_path, "/publish", &body).await;
+    app.get_drafts_html().await;
+
+    let response = app.post_draft(&draft_path, "/publish", &body).await;
+
+    assert_is_redirect_to(&response, "/admin/newsletter/drafts");
+    let html_page = app.get_drafts_html().await;
+    assert!(html_page.contains(&format!(
+        "<p><i>The newsletter issue has been scheduled for {}.</i></p>",
+        send_at.to_string().replace('T', " ") + " UTC"
+    )));
+}
+
+#[tokio::test]
+async fn the_publish_form_carries_an_idempotency_key() {
+    let app = spawn_app().await;
+    login(&app).await;
+    let draft_path = create_draft(&app).await;
+
+    let html_page = app.get_draft_html(&draft_path, "").await;
+
+    assert!(html_page.contains(r#"<input hidden type="text" name="idempotency_key" value=""#));
+}
+
+#[tokio::test]
+async fn publishing_without_an_idempotency_key_is_rejected() {
+    let app = spawn_app().await;
+    login(&app).await;
+    let draft_path = create_draft(&app).await;
+
+    let test_cases = vec![
+        (serde_json::json!({}), "a missing key"),
+        (serde_json::json!({"idempotency_key": ""}), "an empty key"),
+    ];
+    for (body, description) in test_cases {
+        let response = app.post_draft(&draft_path, "/publish", &body).await;
+        assert_eq!(
+            response.status().as_u16(),
+            400,
+            "The publish request was not rejected with {}.",
+            description
+        );
+    }
+    let draft = sqlx::query!("SELECT status FROM newsletter_issues")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap();
+    assert_eq!(draft.status, "draft");
+}
+
+#[tokio::test]
+async fn edits_are_saved_and_shown_in_the_preview() {
+    let app = spawn_app().await;
+    login(&app).await;
+    let draft_path = create_draft(&app).await;
+
+    let response = app
+        .post_draft(&draft_path, "", &serde_json::json!({
+            "title": "Edited title",
+            "text_content": "Edited plain text.",
+            "html_content": "<p>Edited HTML.</p>",
+        }))
+        .await;
+    assert_is_redirect_to(&response, &draft_path);
+
+    let html_page = app.get_draft_html(&draft_path, "/preview").await;
+    assert!(html_page.contains("<h1>Edited title</h1>"));
+    assert!(html_page.contains(r#"srcdoc="&lt;p&gt;Edited"#));
+    assert!(html_page.contains("Edited plain text."));
+}
+
+#[tokio::test]
+async fn the_preview_uses_a_sample_name() {
+    let app = spawn_app().await;
+    login(&app).await;
+    let response = app.post_create_draft(&serde_json::json!({
+        "title": "Draft title",
+        "text_content": "Hi {{ name }}!",
+        "html_content": "<p>Hi {{ name }}!</p>",
+    }))
+    .await;
+    let draft_path = response.headers().get("Location").unwrap().to_str().unwrap().to_owned();
+
+    let html_page = app.get_draft_html(&draft_path, "/preview").await;
+
+    assert!(html_page.contains("Hi Subscriber!"));
+    assert!(!html_page.contains(&app.test_user.username));
+}
+
+#[tokio::test]
+async fn a_test_email_is_only_sent_to_the_logged_in_admin() {
+    let app = spawn_app().await;
+    create_confirmed_subscriber(&app).await;
+    login(&app).await;
+    let draft_path = create_draft(&app).await;
+
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+    let response = app.post_draft(&draft_path, "/test", &serde_json::json!({})).await;
+    assert_is_redirect_to(&response, &draft_path);
+    let html_page = app.get_draft_html(&draft_path, "").await;
+    assert!(html_page.contains(&format!("A test email has been sent to {}.", app.test_user.email)));
+
+    let email_request = app.email_server.received_requests().await.unwrap().pop().unwrap();
+    let body: serde_json::Value = serde_json::from_slice(&email_request.body).unwrap();
+    assert_eq!(body["To"], app.test_user.email);
+    assert_eq!(body["Subject"], "[TEST] Draft title");
+    // The draft is still a draft: nothing was queued for subscribers.
+    app.dispatch_all_pending_emails().await;
+    assert!(app.get_drafts_html().await.contains("Draft title"));
+}
+
+#[tokio::test]
+async fn a_test_email_requires_an_admin_email_address() {
+    let app = spawn_app().await;
+    sqlx::query!("UPDATE users SET email = NULL")
+        .execute(&app.db_pool)
+        .await
+        .unwrap();
+    login(&app).await;
+    let draft_path = create_draft(&app).await;
+
+    Mock::given(any())
+        .respond_with(ResponseTemplate::new(200))
+        .expect(0)
+        .mount(&app.email_server)
+        .await;
+    let response = app.post_draft(&draft_path, "/test", &serde_json::json!({})).await;
+    assert_is_redirect_to(&response, &draft_path);
+    let html_page = app.get_draft_html(&draft_path, "").await;
+    assert!(html_page.contains("<p><i>Set your email address before sending a test email.</i></p>"));
+}
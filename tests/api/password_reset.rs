@@ -0,0 +1,263 @@
+use std::time::Duration;
+
+use reqwest::Url;
+use wiremock::{matchers::{method, path}, Mock, ResponseTemplate};
+
+use crate::helpers::{assert_is_redirect_to, spawn_app, spawn_app_with, TestApp};
+
+const GENERIC_MESSAGE: &str = "If that account exists and has an email address, \
+    we have sent it a link to reset the password.";
+
+/// Reset links are sent in the background.
+async fn reset_link(app: &TestApp) -> Url {
+    for _ in 0..50 {
+        if let Some(request) = app.email_server.received_requests().await.unwrap().pop() {
+            return app.get_confirmation_links(&request).html;
+        }
+        tokio::time::sleep(Duration::from_millis(100)).await;
+    }
+    panic!("No password reset email was sent.");
+}
+
+async fn request_reset(app: &TestApp, username: &str) -> reqwest::Response {
+    app.api_client
+        .post(format!("{}/login/forgot", &app.address))
+        .form(&serde_json::json!({ "username": username }))
+        .send()
+        .await
+        .unwrap()
+}
+
+async fn reset_password(app: &TestApp, token: &str, password: &str, check: &str) -> reqwest::Response {
+    app.api_client
+        .post(format!("{}/login/reset", &app.address))
+        .form(&serde_json::json!({
+            "token": token,
+            "new_password": password,
+            "new_password_check": check,
+        }))
+        .send()
+        .await
+        .unwrap()
+}
+
+fn token(link: &Url) -> String {
+    link.query_pairs()
+        .find(|(key, _)| key == "token")
+        .unwrap()
+        .1
+        .to_string()
+}
+
+async fn mount_email_mock(app: &TestApp, n_emails: u64) {
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(n_emails)
+        .mount(&app.email_server)
+        .await;
+}
+
+#[tokio::test]
+async fn a_reset_link_lets_the_user_choose_a_new_password() {
+    let app = spawn_app().await;
+    mount_email_mock(&app, 1).await;
+
+    let response = request_reset(&app, &app.test_user.username).await;
+    assert_is_redirect_to(&response, "/login");
+    assert!(app.get_login_html().await.contains(GENERIC_MESSAGE));
+
+    let link = reset_link(&app).await;
+    assert_eq!(link.path(), "/login/reset");
+    let response = app.api_client.get(link.clone()).send().await.unwrap();
+    assert_eq!(response.status().as_u16(), 200);
+    let response = reset_password(&app, &token(&link), "a-new-password", "a-new-password").await;
+    assert_is_redirect_to(&response, "/login");
+    assert!(app.get_login_html().await.contains("Your password has been reset. You can now log in."));
+
+    let response = app.post_login(&serde_json::json!({
+        "username": &app.test_user.username,
+        "password": &app.test_user.password,
+    })).await;
+    assert_is_redirect_to(&response, "/login");
+    let response = app.post_login(&serde_json::json!({
+        "username": &app.test_user.username,
+        "password": "a-new-password",
+    })).await;
+    assert_is_redirect_to(&response, "/admin/dashboard");
+}
+
+#[tokio::test]
+async fn unknown_usernames_get_the_same_response_and_no_email() {
+    let app = spawn_app().await;
+    mount_email_mock(&app, 0).await;
+
+    let response = request_reset(&app, "nobody-by-that-name").await;
+
+    assert_is_redirect_to(&response, "/login");
+    assert!(app.get_login_html().await.contains(GENERIC_MESSAGE));
+    tokio::time::sleep(Duration::from_millis(500)).await;
+}
+
+#[tokio::test]
+async fn no_reset_link_is_sent_to_an_unconfirmed_address() {
+    let app = spawn_app().await;
+    mount_email_mock(&app, 0).await;
+    sqlx::query!("UPDATE users SET email_confirmed_at = NULL")
+        .execute(&app.db_pool)
+        .await
+        .unwrap();
+
+    let response = request_reset(&app, &app.test_user.username).await;
+
+    assert_is_redirect_to(&response, "/login");
+    assert!(app.get_login_html().await.contains(GENERIC_MESSAGE));
+    tokio::time::sleep(Duration::from_millis(500)).await;
+}
+
+#[tokio::test]
+async fn a_second_request_within_the_cooldown_sends_no_email() {
+    let app = spawn_app().await;
+    mount_email_mock(&app, 1).await;
+    request_reset(&app, &app.test_user.username).await;
+    reset_link(&app).await;
+
+    let response = request_reset(&app, &app.test_user.username).await;
+
+    assert_is_redirect_to(&response, "/login");
+    assert!(app.get_login_html().await.contains(GENERIC_MESSAGE));
+    tokio::time::sleep(Duration::from_millis(500)).await;
+}
+
+#[tokio::test]
+async fn a_locked_out_client_ip_gets_the_same_response_and_no_email() {
+    let app = spawn_app_with(|c| c.login_throttle.ip_lockout_threshold = 1).await;
+    mount_email_mock(&app, 0).await;
+    request_reset(&app, "nobody-by-that-name").await;
+
+    let response = request_reset(&app, &app.test_user.username).await;
+
+    assert_is_redirect_to(&response, "/login");
+    assert!(app.get_login_html().await.contains(GENERIC_MESSAGE));
+    tokio::time::sleep(Duration::from_millis(500)).await;
+}
+
+#[tokio::test]
+async fn a_reset_link_can_only_be_used_once() {
+    let app = spawn_app().await;
+    mount_email_mock(&app, 1).await;
+    request_reset(&app, &app.test_user.username).await;
+    let token = token(&reset_link(&app).await);
+    reset_password(&app, &token, "a-new-password", "a-new-password").await;
+
+    let response = reset_password(&app, &token, "another-password", "another-password").await;
+
+    assert_is_redirect_to(&response, "/login/forgot");
+    let html_page = app.api_client
+        .get(format!("{}/login/forgot", &app.address))
+        .send()
+        .await
+        .unwrap()
+        .text()
+        .await
+        .unwrap();
+    assert!(html_page.contains("This reset link is invalid or has expired."));
+}
+
+#[tokio::test]
+async fn an_expired_reset_link_is_rejected() {
+    let app = spawn_app().await;
+    mount_email_mock(&app, 1).await;
+    request_reset(&app, &app.test_user.username).await;
+    let link = reset_link(&app).await;
+    sqlx::query!("UPDATE password_reset_tokens SET expires_at = now() - interval '1 minute'")
+        .execute(&app.db_pool)
+        .await
+        .unwrap();
+
+    let response = app.api_client.get(link.clone()).send().await.unwrap();
+    assert_is_redirect_to(&response, "/login/forgot");
+    let response = reset_password(&app, &token(&link), "a-new-password", "a-new-password").await;
+    assert_is_redirect_to(&response, "/login/forgot");
+}
+
+#[tokio::test]
+async fn mismatched_passwords_keep_the_link_valid() {
+    let app = spawn_app().await;
+    mount_email_mock(&app, 1).await;
+    request_reset(&app, &app.test_user.username).await;
+    let link = reset_link(&app).await;
+
+    let response = reset_password(&app, &token(&link), "a-new-password", "a-typo").await;
+
+    assert_is_redirect_to(&response, &format!("/login/reset?token={}", token(&link)));
+    let html_page = app.api_client.get(link.clone()).send().await.unwrap().text().await.unwrap();
+    assert!(html_page.contains("You entered two different passwords."));
+    let response = reset_password(&app, &token(&link), "a-new-password", "a-new-password").await;
+    assert_is_redirect_to(&response, "/login");
+}
+
+#[tokio::test]
+async fn resetting_a_password_logs_out_every_session() {
+    let app = spawn_app().await;
+    mount_email_mock(&app, 1).await;
+    let response = app.post_login(&serde_json::json!({
+        "username": &app.test_user.username,
+        "password": &app.test_user.password,
+    })).await;
+    assert_is_redirect_to(&response, "/admin/dashboard");
+
+    request_reset(&app, &app.test_user.username).await;
+    let token = token(&reset_link(&app).await);
+    // From another browser, without the session cookie.
+    let response = reqwest::Client::builder()
+        .redirect(reqwest::redirect::Policy::none())
+        .build()
+        .unwrap()
+        .post(format!("{}/login/reset", &app.address))
+        .form(&serde_json::json!({
+            "token": token,
+            "new_password": "a-new-password",
+            "new_password_check": "a-new-password",
+        }))
+        .send()
+        .await
+        .unwrap();
+    assert_is_redirect_to(&response, "/login");
+
+    let response = app.get_admin_dashboard().await;
+    assert_is_redirect_to(&response, "/login");
+}
+
+#[tokio::test]
+async fn a_weak_password_keeps_the_link_valid() {
+    let app = spawn_app().await;
+    mount_email_mock(&app, 1).await;
+    request_reset(&app, &app.test_user.username).await;
+    let link = reset_link(&app).await;
+
+    let response = reset_password(&app, &token(&link), "short", "short").await;
+
+    assert_is_redirect_to(&response, &format!("/login/reset?token={}", token(&link)));
+    let html_page = app.api_client.get(link.clone()).send().await.unwrap().text().await.unwrap();
+    assert!(html_page.contains("The new password must be at least 12 characters long."));
+    let response = reset_password(&app, &token(&link), "a-new-password", "a-new-password").await;
+    assert_is_redirect_to(&response, "/login");
+}
+
+#[tokio::test]
+async fn the_current_password_is_rejected_and_keeps_the_link_valid() {
+    let app = spawn_app().await;
+    mount_email_mock(&app, 1).await;
+    request_reset(&app, &app.test_user.username).await;
+    let link = reset_link(&app).await;
+    let current_password = &app.test_user.password;
+
+    let response = reset_password(&app, &token(&link), current_password, current_password).await;
+
+    assert_is_redirect_to(&response, &format!("/login/reset?token={}", token(&link)));
+    let html_page = app.api_client.get(link.clone()).send().await.unwrap().text().await.unwrap();
+    assert!(html_page.contains("The new password must be different from the current one."));
+    let response = reset_password(&app, &token(&link), "a-new-password", "a-new-password").await;
+    assert_is_redirect_to(&response, "/login");
+}
@@ -0,0 +1,183 @@
+use serde_json::Value;
+use wiremock::{matchers::{any, method, path}, Mock, ResponseTemplate};
+
+use crate::helpers::{assert_is_redirect_to, create_confirmed_subscriber, spawn_app, TestApp};
+
+async fn subscriber_token(app: &TestApp) -> String {
+    sqlx::query!("SELECT unsubscribe_token FROM subscriptions")
+        .fetch_one(&app.db_pool)
+        .await
+        .expect("Failed to fetch saved subscriber.")
+        .unsubscribe_token
+}
+
+async fn publish_newsletter(app: &TestApp) {
+    app.publish_newsletter(&serde_json::json!({
+        "title": "Newsletter title",
+        "text_content": "Newsletter body as plain text.",
+        "html_content": "<p>Newsletter body as HTML.</p>",
+    }))
+    .await;
+    app.dispatch_all_pending_emails().await;
+}
+
+#[tokio::test]
+async fn newsletters_link_to_the_preferences_page() {
+    let app = spawn_app().await;
+    create_confirmed_subscriber(&app).await;
+    let token = subscriber_token(&app).await;
+    app.login_as(&app.test_user).await;
+
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+    publish_newsletter(&app).await;
+
+    let email_request = app.email_server.received_requests().await.unwrap().pop().unwrap();
+    let body: Value = serde_json::from_slice(&email_request.body).unwrap();
+    let preferences_link = format!("{}/subscriptions/preferences?token={}", app.address, token);
+    assert!(body["TextBody"].as_str().unwrap().contains(&preferences_link));
+    assert!(body["HtmlBody"].as_str().unwrap().contains(&preferences_link));
+}
+
+#[tokio::test]
+async fn the_preferences_page_shows_the_current_preferences() {
+    let app = spawn_app().await;
+    app.login_as(&app.test_user).await;
+    app.post_create_tag(&serde_json::json!({"name": "rust", "signup_list": "on"})).await;
+    app.post_create_tag(&serde_json::json!({"name": "internal"})).await;
+    create_confirmed_subscriber(&app).await;
+    let token = subscriber_token(&app).await;
+
+    let response = app.get_preferences(&token).await;
+
+    assert_eq!(response.status().as_u16(), 200);
+    let html_page = response.text().await.unwrap();
+    assert!(html_page.contains(&format!(r#"value="{}""#, htmlescape::encode_attribute("le guin"))));
+    assert!(html_page.contains(r#"value="rust""#));
+    assert!(!html_page.contains("internal"));
+}
+
+#[tokio::test]
+async fn unknown_preferences_tokens_are_rejected_with_a_401() {
+    let app = spawn_app().await;
+
+    let response = app.get_preferences("unknown-token").await;
+    assert_eq!(response.status().as_u16(), 401);
+
+    let response = app.post_preferences("unknown-token", "name=someone".into()).await;
+    assert_eq!(response.status().as_u16(), 401);
+}
+
+#[tokio::test]
+async fn subscribers_can_update_their_preferences() {
+    let app = spawn_app().await;
+    app.login_as(&app.test_user).await;
+    app.post_create_tag(&serde_json::json!({"name": "rust", "signup_list": "on"})).await;
+    app.post_create_tag(&serde_json::json!({"name": "go", "signup_list": "on"})).await;
+    app.post_create_tag(&serde_json::json!({"name": "vip"})).await;
+    create_confirmed_subscriber(&app).await;
+    app.post_tag_subscriber(&serde_json::json!({
+        "email": "ursula_le_guin@gmail.com",
+        "tag": "vip",
+        "action": "add",
+    }))
+    .await;
+    let token = subscriber_token(&app).await;
+
+    app.post_preferences(&token, "name=Ursula&lists=rust&lists=go".into()).await;
+    let response = app
+        .post_preferences(&token, "name=Ursula%20K.&lists=go&plain_text_only=on".into())
+        .await;
+    assert_is_redirect_to(&response, &format!("/subscriptions/preferences?token={}", token));
+
+    let html_page = app.get_preferences(&token).await.text().await.unwrap();
+    assert!(html_page.contains("<p><i>Your preferences have been saved.</i></p>"));
+    let saved = sqlx::query!("SELECT name, plain_text_only FROM subscriptions")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap();
+    assert_eq!(saved.name, "Ursula K.");
+    assert!(saved.plain_text_only);
+    let tags = sqlx::query_scalar!(
+        "SELECT t.name FROM subscription_tags st JOIN tags t USING (tag_id) ORDER BY t.name"
+    )
+    .fetch_all(&app.db_pool)
+    .await
+    .unwrap();
+    assert_eq!(tags, ["go", "vip"]);
+}
+
+#[tokio::test]
+async fn invalid_preferences_are_rejected_with_a_flash_message() {
+    let app = spawn_app().await;
+    create_confirmed_subscriber(&app).await;
+    let token = subscriber_token(&app).await;
+
+    for (body, message) in [
+        ("name=%3Cscript%3E", "&lt;script&gt; is not a valid subscriber name."),
+        ("name=Ursula&pause_weeks=60", "Delivery can be paused for 0 to 52 weeks."),
+        ("name=Ursula&lists=unknown", "Unknown signup list."),
+    ] {
+        let response = app.post_preferences(&token, body.into()).await;
+        assert_is_redirect_to(&response, &format!("/subscriptions/preferences?token={}", token));
+        let html_page = app.get_preferences(&token).await.text().await.unwrap();
+        assert!(html_page.contains(message), "{:?} should be rejected", body);
+    }
+    let saved = sqlx::query!("SELECT name FROM subscriptions")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap();
+    assert_eq!(saved.name, "le guin");
+}
+
+#[tokio::test]
+async fn plain_text_only_subscribers_receive_no_html_body() {
+    let app = spawn_app().await;
+    create_confirmed_subscriber(&app).await;
+    let token = subscriber_token(&app).await;
+    app.post_preferences(&token, "name=le%20guin&plain_text_only=on".into()).await;
+    app.login_as(&app.test_user).await;
+
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+    publish_newsletter(&app).await;
+
+    let email_request = app.email_server.received_requests().await.unwrap().pop().unwrap();
+    let body: Value = serde_json::from_slice(&email_request.body).unwrap();
+    assert!(body.get("HtmlBody").is_none());
+    assert!(body["TextBody"].as_str().unwrap().contains("Newsletter body as plain text."));
+}
+
+#[tokio::test]
+async fn paused_subscribers_receive_nothing_until_they_resume() {
+    let app = spawn_app().await;
+    create_confirmed_subscriber(&app).await;
+    let token = subscriber_token(&app).await;
+    app.post_preferences(&token, "name=le%20guin&pause_weeks=2".into()).await;
+    app.login_as(&app.test_user).await;
+
+    let mock_guard = Mock::given(any())
+        .respond_with(ResponseTemplate::new(200))
+        .expect(0)
+        .mount_as_scoped(&app.email_server)
+        .await;
+    publish_newsletter(&app).await;
+    drop(mock_guard);
+
+    app.post_preferences(&token, "name=le%20guin&pause_weeks=0".into()).await;
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+    publish_newsletter(&app).await;
+}
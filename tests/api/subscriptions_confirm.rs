@@ -1,6 +1,6 @@
 use wiremock::{matchers::{method, path}, Mock, ResponseTemplate};
 
-use crate::helpers::spawn_app;
+use crate::helpers::{create_unconfirmed_subscriber, spawn_app};
 
 #[tokio::test]
 async fn confirmations_without_token_are_rejected_with_a_400() {
@@ -63,4 +63,229 @@ async fn clicking_on_the_confirmation_link_confirms_a_subscriber() {
     assert_eq!(saved.email, "ursula_le_guin@gmail.com");
     assert_eq!(saved.name, "le guin");
     assert_eq!(saved.status, "confirmed");
-}
\ No newline at end of file
+}
+fn subscription_token(confirmation_link: &reqwest::Url) -> String {
+    confirmation_link
+        .query_pairs()
+        .find(|(key, _)| key == "subscription_token")
+        .map(|(_, value)| value.into_owned())
+        .unwrap()
+}
+
+async fn expire_subscription_tokens(app: &crate::helpers::TestApp) {
+    sqlx::query!("UPDATE subscription_tokens SET created_at = now() - interval '30 days'")
+        .execute(&app.db_pool)
+        .await
+        .unwrap();
+}
+
+#[tokio::test]
+async fn unknown_tokens_are_rejected_with_a_friendly_page() {
+    let app = spawn_app().await;
+
+    let response = reqwest::get(&format!(
+        "{}/subscriptions/confirm?subscription_token=unknown",
+        app.address
+    ))
+    .await
+    .unwrap();
+
+    assert_eq!(response.status().as_u16(), 401);
+    assert_eq!(response.headers()["Content-Type"], "text/html; charset=utf-8");
+    assert!(response.text().await.unwrap().contains("This confirmation link is not valid"));
+}
+
+#[tokio::test]
+async fn a_confirmation_link_can_only_be_used_once() {
+    let app = spawn_app().await;
+    let confirmation_links = create_unconfirmed_subscriber(&app).await;
+
+    let response = reqwest::get(confirmation_links.html.clone()).await.unwrap();
+    assert_eq!(response.status().as_u16(), 200);
+
+    let response = reqwest::get(confirmation_links.html).await.unwrap();
+    assert_eq!(response.status().as_u16(), 409);
+    assert!(response.text().await.unwrap().contains("already been used"));
+
+    let saved = sqlx::query!("SELECT consumed_at FROM subscription_tokens")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap();
+    assert!(saved.consumed_at.is_some());
+}
+
+#[tokio::test]
+async fn expired_links_do_not_confirm_the_subscriber() {
+    let app = spawn_app().await;
+    let confirmation_links = create_unconfirmed_subscriber(&app).await;
+    expire_subscription_tokens(&app).await;
+
+    let response = reqwest::get(confirmation_links.html).await.unwrap();
+
+    assert_eq!(response.status().as_u16(), 410);
+    let html_page = response.text().await.unwrap();
+    assert!(html_page.contains("This confirmation link has expired"));
+    assert!(html_page.contains(r#"action="/subscriptions/confirm/resend""#));
+    let saved = sqlx::query!("SELECT status FROM subscriptions")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap();
+    assert_eq!(saved.status, "pending_confirmation");
+}
+
+#[tokio::test]
+async fn an_expired_link_can_be_exchanged_for_a_new_one() {
+    let app = spawn_app().await;
+    let confirmation_links = create_unconfirmed_subscriber(&app).await;
+    expire_subscription_tokens(&app).await;
+
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+    let response = app
+        .post_resend_confirmation(&subscription_token(&confirmation_links.html))
+        .await;
+    assert_eq!(response.status().as_u16(), 200);
+
+    let email_request = app.email_server.received_requests().await.unwrap().pop().unwrap();
+    let new_links = app.get_confirmation_links(&email_request);
+    assert_ne!(new_links.html, confirmation_links.html);
+    reqwest::get(new_links.html)
+        .await
+        .unwrap()
+        .error_for_status()
+        .unwrap();
+
+    let saved = sqlx::query!("SELECT status FROM subscriptions")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap();
+    assert_eq!(saved.status, "confirmed");
+}
+
+#[tokio::test]
+async fn a_failed_resend_still_tells_the_subscriber_to_check_their_inbox() {
+    let app = spawn_app().await;
+    let confirmation_links = create_unconfirmed_subscriber(&app).await;
+    expire_subscription_tokens(&app).await;
+
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(422))
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+    let response = app
+        .post_resend_confirmation(&subscription_token(&confirmation_links.html))
+        .await;
+
+    assert_eq!(response.status().as_u16(), 200);
+    assert!(response.text().await.unwrap().contains("Check your inbox"));
+}
+
+#[tokio::test]
+async fn no_new_link_is_sent_to_a_confirmed_subscriber() {
+    let app = spawn_app().await;
+    let confirmation_links = create_unconfirmed_subscriber(&app).await;
+    reqwest::get(confirmation_links.html.clone())
+        .await
+        .unwrap()
+        .error_for_status()
+        .unwrap();
+
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(0)
+        .mount(&app.email_server)
+        .await;
+    let response = app
+        .post_resend_confirmation(&subscription_token(&confirmation_links.html))
+        .await;
+
+    assert_eq!(response.status().as_u16(), 409);
+}
+
+#[tokio::test]
+async fn a_new_link_is_sent_at_most_once_every_few_minutes() {
+    let app = spawn_app().await;
+    let confirmation_links = create_unconfirmed_subscriber(&app).await;
+    expire_subscription_tokens(&app).await;
+    let token = subscription_token(&confirmation_links.html);
+
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+    for _ in 0..3 {
+        let response = app.post_resend_confirmation(&token).await;
+        assert_eq!(response.status().as_u16(), 200);
+        assert!(response.text().await.unwrap().contains("Please check your inbox."));
+    }
+
+    let n_tokens = sqlx::query_scalar!("SELECT COUNT(*) FROM subscription_tokens")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap();
+    assert_eq!(n_tokens, Some(2));
+}
+
+#[tokio::test]
+async fn a_used_link_does_not_claim_an_unsubscribed_subscription_is_confirmed() {
+    let app = spawn_app().await;
+    let confirmation_links = create_unconfirmed_subscriber(&app).await;
+    reqwest::get(confirmation_links.html.clone())
+        .await
+        .unwrap()
+        .error_for_status()
+        .unwrap();
+    sqlx::query!("UPDATE subscriptions SET status = 'unsubscribed'")
+        .execute(&app.db_pool)
+        .await
+        .unwrap();
+
+    let response = reqwest::get(confirmation_links.html.clone()).await.unwrap();
+    assert_eq!(response.status().as_u16(), 409);
+    let html_page = response.text().await.unwrap();
+    assert!(html_page.contains("This subscription is no longer active"));
+    assert!(!html_page.contains("Your subscription is confirmed"));
+
+    let response = app
+        .post_resend_confirmation(&subscription_token(&confirmation_links.html))
+        .await;
+    assert_eq!(response.status().as_u16(), 409);
+    assert!(response.text().await.unwrap().contains("This subscription is no longer active"));
+}
+
+#[tokio::test]
+async fn a_link_used_before_subscribing_again_points_to_the_latest_one() {
+    let app = spawn_app().await;
+    let confirmation_links = create_unconfirmed_subscriber(&app).await;
+    reqwest::get(confirmation_links.html.clone())
+        .await
+        .unwrap()
+        .error_for_status()
+        .unwrap();
+    sqlx::query!("UPDATE subscriptions SET status = 'unsubscribed'")
+        .execute(&app.db_pool)
+        .await
+        .unwrap();
+    create_unconfirmed_subscriber(&app).await;
+
+    let response = reqwest::get(confirmation_links.html).await.unwrap();
+
+    assert_eq!(response.status().as_u16(), 409);
+    let html_page = response.text().await.unwrap();
+    assert!(html_page.contains("Please check your inbox for the latest link."));
+    assert!(!html_page.contains("Subscribe again"));
+    let saved = sqlx::query!("SELECT status FROM subscriptions")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap();
+    assert_eq!(saved.status, "pending_confirmation");
+}
@@ -0,0 +1,176 @@
+use wiremock::{matchers::{method, path}, Mock, ResponseTemplate};
+
+use crate::helpers::{assert_is_redirect_to, spawn_app, TestApp};
+
+async fn create_confirmed_subscriber_with_email(app: &TestApp, email: &str) {
+    let _mock_guard = Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount_as_scoped(&app.email_server)
+        .await;
+    app.post_subscriptions(format!("name=reader&email={}", urlencoding::encode(email)))
+        .await
+        .error_for_status()
+        .unwrap();
+    let email_request = app.email_server.received_requests().await.unwrap().pop().unwrap();
+    let confirmation_links = app.get_confirmation_links(&email_request);
+    reqwest::get(confirmation_links.html)
+        .await
+        .unwrap()
+        .error_for_status()
+        .unwrap();
+}
+
+async fn tags_of(app: &TestApp, email: &str) -> Vec<String> {
+    sqlx::query_scalar!(
+        r#"
+        SELECT t.name
+        FROM subscription_tags st
+        JOIN tags t ON t.tag_id = st.tag_id
+        JOIN subscriptions s ON s.id = st.subscriber_id
+        WHERE s.email = $1
+        ORDER BY t.name
+        "#,
+        email
+    )
+    .fetch_all(&app.db_pool)
+    .await
+    .unwrap()
+}
+
+#[tokio::test]
+async fn subscribers_can_join_signup_lists() {
+    let app = spawn_app().await;
+    app.login_as(&app.test_user).await;
+    app.post_create_tag(&serde_json::json!({"name": "rust", "signup_list": "on"})).await;
+    app.post_create_tag(&serde_json::json!({"name": "Go", "signup_list": "on"})).await;
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .mount(&app.email_server)
+        .await;
+
+    let response = app
+        .post_subscriptions("name=le%20guin&email=ursula_le_guin%40gmail.com&lists=rust&lists=go".into())
+        .await;
+
+    assert_eq!(response.status().as_u16(), 200);
+    assert_eq!(tags_of(&app, "ursula_le_guin@gmail.com").await, ["go", "rust"]);
+}
+
+#[tokio::test]
+async fn subscribe_rejects_lists_that_are_not_offered_at_signup() {
+    let app = spawn_app().await;
+    app.login_as(&app.test_user).await;
+    app.post_create_tag(&serde_json::json!({"name": "internal"})).await;
+
+    for list in ["internal", "unknown", "not%20a%20tag"] {
+        let response = app
+            .post_subscriptions(format!("name=le%20guin&email=ursula_le_guin%40gmail.com&lists={}", list))
+            .await;
+        assert_eq!(response.status().as_u16(), 400, "list {:?} should be rejected", list);
+    }
+    let saved = sqlx::query!("SELECT id FROM subscriptions")
+        .fetch_optional(&app.db_pool)
+        .await
+        .unwrap();
+    assert!(saved.is_none());
+}
+
+#[tokio::test]
+async fn admins_can_tag_and_untag_subscribers() {
+    let app = spawn_app().await;
+    app.login_as(&app.test_user).await;
+    create_confirmed_subscriber_with_email(&app, "reader@example.com").await;
+    let response = app.post_create_tag(&serde_json::json!({"name": "beta"})).await;
+    assert_is_redirect_to(&response, "/admin/tags");
+
+    let response = app
+        .post_tag_subscriber(&serde_json::json!({
+            "email": "reader@example.com",
+            "tag": "beta",
+            "action": "add",
+        }))
+        .await;
+    assert_is_redirect_to(&response, "/admin/tags");
+    let html_page = app.get_tags_html().await;
+    assert!(html_page.contains("<p><i>reader@example.com is now tagged beta.</i></p>"));
+    assert!(html_page.contains("beta - 1 subscriber(s)"));
+    assert_eq!(tags_of(&app, "reader@example.com").await, ["beta"]);
+
+    app.post_tag_subscriber(&serde_json::json!({
+        "email": "reader@example.com",
+        "tag": "beta",
+        "action": "remove",
+    }))
+    .await;
+    assert!(tags_of(&app, "reader@example.com").await.is_empty());
+}
+
+#[tokio::test]
+async fn newsletters_are_delivered_only_to_the_target_segment() {
+    let app = spawn_app().await;
+    app.login_as(&app.test_user).await;
+    for tag in ["rust", "beta"] {
+        app.post_create_tag(&serde_json::json!({"name": tag})).await;
+    }
+    for (email, tags) in [
+        ("rustacean@example.com", vec!["rust"]),
+        ("tester@example.com", vec!["rust", "beta"]),
+        ("other@example.com", vec![]),
+    ] {
+        create_confirmed_subscriber_with_email(&app, email).await;
+        for tag in tags {
+            app.post_tag_subscriber(&serde_json::json!({"email": email, "tag": tag, "action": "add"}))
+                .await;
+        }
+    }
+
+    let response = app
+        .publish_newsletter(&serde_json::json!({
+            "title": "Newsletter title",
+            "text_content": "Newsletter body as plain text.",
+            "html_content": "<p>Newsletter body as HTML.</p>",
+            "segment": "tag:rust AND NOT tag:beta",
+        }))
+        .await;
+    assert_is_redirect_to(&response, "/admin/newsletter/drafts");
+
+    let recipients = sqlx::query_scalar!("SELECT subscriber_email FROM issue_delivery_queue")
+        .fetch_all(&app.db_pool)
+        .await
+        .unwrap();
+    assert_eq!(recipients, ["rustacean@example.com"]);
+}
+
+#[tokio::test]
+async fn invalid_segments_are_rejected_with_a_flash_message() {
+    let app = spawn_app().await;
+    app.login_as(&app.test_user).await;
+    app.post_create_tag(&serde_json::json!({"name": "rust"})).await;
+
+    for (segment, message) in [
+        ("tag:rust AND", "The segment is not valid: The segment ends unexpectedly."),
+        ("tag:rust OR tag:<b>", "The segment is not valid: "),
+        ("tag:rust OR tag:golang", "The segment refers to an unknown tag: golang"),
+    ] {
+        let response = app
+            .publish_newsletter(&serde_json::json!({
+                "title": "Newsletter title",
+                "text_content": "Newsletter body as plain text.",
+                "html_content": "<p>Newsletter body as HTML.</p>",
+                "segment": segment,
+            }))
+            .await;
+        assert_eq!(response.status().as_u16(), 303);
+        let html_page = app.get_drafts_html().await;
+        assert!(html_page.contains(message), "{:?} should be rejected", segment);
+        assert!(!html_page.contains("<b>"));
+    }
+    let saved = sqlx::query!("SELECT newsletter_issue_id FROM newsletter_issues WHERE status <> 'draft'")
+        .fetch_optional(&app.db_pool)
+        .await
+        .unwrap();
+    assert!(saved.is_none());
+}
@@ -0,0 +1,121 @@
+use serde_json::Value;
+use wiremock::{matchers::{method, path}, Mock, ResponseTemplate};
+
+use crate::helpers::{create_confirmed_subscriber, spawn_app, TestApp};
+
+async fn publish_and_deliver(app: &TestApp, tracking: bool) -> String {
+    app.post_login(&serde_json::json!({
+        "username": &app.test_user.username,
+        "password": &app.test_user.password,
+    })).await;
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+
+    let mut body = serde_json::json!({
+        "title": "Newsletter title",
+        "text_content": "Newsletter body as plain text.",
+        "html_content": r#"<p>Read <a href="https://example.com/post?a=1&amp;b=2">the post</a>.</p>"#,
+    });
+    if tracking {
+        body["tracking"] = "on".into();
+    }
+    app.publish_newsletter(&body).await;
+    app.dispatch_all_pending_emails().await;
+
+    let email_request = app.email_server.received_requests().await.unwrap().pop().unwrap();
+    let body: Value = serde_json::from_slice(&email_request.body).unwrap();
+    body["HtmlBody"].as_str().unwrap().to_owned()
+}
+
+/// The first URL in `html` starting with `prefix`, up to the closing quote.
+fn find_url(html: &str, prefix: &str) -> String {
+    let start = html.find(prefix).expect("No tracking URL found.");
+    let end = start + html[start..].find('"').unwrap();
+    html[start..end].to_owned()
+}
+
+async fn issue_id(app: &TestApp) -> uuid::Uuid {
+    sqlx::query!("SELECT newsletter_issue_id FROM newsletter_issues")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap()
+        .newsletter_issue_id
+}
+
+#[tokio::test]
+async fn tracked_issues_record_opens_and_clicks() {
+    let app = spawn_app().await;
+    create_confirmed_subscriber(&app).await;
+    let html = publish_and_deliver(&app, true).await;
+
+    assert!(!html.contains("https://example.com/post"));
+    let click_url = find_url(&html, &format!("{}/t/c/", app.address));
+    let open_url = find_url(&html, &format!("{}/t/o/", app.address));
+
+    let response = app.api_client.get(&open_url).send().await.unwrap();
+    assert_eq!(response.status().as_u16(), 200);
+    assert_eq!(response.headers()["Content-Type"], "image/gif");
+    app.api_client.get(&open_url).send().await.unwrap();
+    for _ in 0..2 {
+        let response = app.api_client.get(&click_url).send().await.unwrap();
+        assert_eq!(response.status().as_u16(), 302);
+        assert_eq!(response.headers()["Location"], "https://example.com/post?a=1&b=2");
+    }
+
+    let stats_page = app
+        .api_client
+        .get(format!("{}/admin/issues/{}/stats", app.address, issue_id(&app).await))
+        .send()
+        .await
+        .unwrap()
+        .text()
+        .await
+        .unwrap();
+    assert!(stats_page.contains("<li>Recipients: 1</li>"));
+    assert!(stats_page.contains("<li>Unique opens: 1</li>"));
+    assert!(stats_page.contains("<li>Unique clicks: 1</li>"));
+    assert!(stats_page.contains("<li>Click-through rate: 100.0%</li>"));
+    assert!(stats_page.contains("<tr><td>https://example.com/post?a=1&amp;b=2</td><td>2</td><td>1</td></tr>"));
+}
+
+#[tokio::test]
+async fn untracked_issues_keep_their_links() {
+    let app = spawn_app().await;
+    create_confirmed_subscriber(&app).await;
+
+    let html = publish_and_deliver(&app, false).await;
+
+    assert!(html.contains(r#"href="https://example.com/post?a=1&amp;b=2""#));
+    assert!(!html.contains("/t/c/"));
+    assert!(!html.contains("/t/o/"));
+}
+
+#[tokio::test]
+async fn forged_tracking_tokens_are_not_recorded() {
+    let app = spawn_app().await;
+    create_confirmed_subscriber(&app).await;
+    let html = publish_and_deliver(&app, true).await;
+    let click_url = find_url(&html, &format!("{}/t/c/", app.address));
+    let (payload, _) = click_url.rsplit_once('.').unwrap();
+    let forged = format!("{}.AAAA", payload);
+
+    let response = app.api_client.get(&forged).send().await.unwrap();
+    assert_eq!(response.status().as_u16(), 404);
+    let response = app
+        .api_client
+        .get(format!("{}/t/o/not-a-token.gif", app.address))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status().as_u16(), 200);
+
+    let events = sqlx::query!("SELECT event_id FROM tracking_events")
+        .fetch_all(&app.db_pool)
+        .await
+        .unwrap();
+    assert!(events.is_empty());
+}
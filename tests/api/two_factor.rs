@@ -0,0 +1,191 @@
+use data_encoding::BASE32_NOPAD;
+use hmac::{Hmac, Mac};
+use sha1::Sha1;
+
+use crate::helpers::{assert_is_redirect_to, spawn_app, spawn_app_with, TestApp};
+
+/// An independent RFC 6238 implementation, to generate the codes an
+/// authenticator app would show.
+fn totp_code(secret: &str, seconds_from_now: i64) -> String {
+    let key = BASE32_NOPAD.decode(secret.as_bytes()).unwrap();
+    let step = (chrono::Utc::now().timestamp() + seconds_from_now) / 30;
+    let mut mac = Hmac::<Sha1>::new_from_slice(&key).unwrap();
+    mac.update(&(step as u64).to_be_bytes());
+    let digest = mac.finalize().into_bytes();
+    let offset = (digest[19] & 0x0f) as usize;
+    let binary = u32::from_be_bytes(digest[offset..offset + 4].try_into().unwrap()) & 0x7fff_ffff;
+    format!("{:06}", binary % 1_000_000)
+}
+
+/// Enrols the logged-in test user with a code of the previous time step,
+/// leaving the current one free to log in with. Returns the secret and
+/// the recovery codes.
+async fn enrol(app: &TestApp) -> (String, Vec<String>) {
+    let html_page = app.get_two_factor_settings_html().await;
+    assert!(html_page.contains("<svg"));
+    assert!(html_page.contains("otpauth://totp/Newsletter:"));
+    let secret = html_page
+        .split("Key: <code>")
+        .nth(1)
+        .unwrap()
+        .split("</code>")
+        .next()
+        .unwrap()
+        .to_string();
+
+    let response = app.post_enable_two_factor(&totp_code(&secret, -30)).await;
+    assert_eq!(response.status().as_u16(), 200);
+    let html_page = response.text().await.unwrap();
+    let recovery_codes: Vec<String> = html_page
+        .split("<li><code>")
+        .skip(1)
+        .map(|item| item.split("</code>").next().unwrap().to_string())
+        .collect();
+    assert_eq!(recovery_codes.len(), 10);
+    (secret, recovery_codes)
+}
+
+#[tokio::test]
+async fn login_asks_for_a_code_once_two_factor_authentication_is_enabled() {
+    let app = spawn_app().await;
+    app.login_as(&app.test_user).await;
+    let (secret, _) = enrol(&app).await;
+    app.post_logout().await;
+
+    let response = app.login_as(&app.test_user).await;
+    assert_is_redirect_to(&response, "/login/2fa");
+
+    // Half-authenticated sessions are treated as anonymous.
+    let response = app.get_admin_dashboard().await;
+    assert_is_redirect_to(&response, "/login");
+
+    let response = app.post_login_code(&totp_code(&secret, 0)).await;
+    assert_is_redirect_to(&response, "/admin/dashboard");
+    let html_page = app.get_admin_dashboard_html().await;
+    assert!(html_page.contains(&format!("Welcome {}", app.test_user.username)));
+}
+
+#[tokio::test]
+async fn a_code_cannot_be_used_twice() {
+    let app = spawn_app().await;
+    app.login_as(&app.test_user).await;
+    let (secret, _) = enrol(&app).await;
+    app.post_logout().await;
+
+    app.login_as(&app.test_user).await;
+    // The code that confirmed the enrolment.
+    let response = app.post_login_code(&totp_code(&secret, -30)).await;
+    assert_is_redirect_to(&response, "/login/2fa");
+
+    let code = totp_code(&secret, 0);
+    let response = app.post_login_code(&code).await;
+    assert_is_redirect_to(&response, "/admin/dashboard");
+    app.post_logout().await;
+
+    app.login_as(&app.test_user).await;
+    let response = app.post_login_code(&code).await;
+    assert_is_redirect_to(&response, "/login/2fa");
+}
+
+#[tokio::test]
+async fn too_many_invalid_codes_require_logging_in_again() {
+    let app = spawn_app().await;
+    app.login_as(&app.test_user).await;
+    let (secret, _) = enrol(&app).await;
+    app.post_logout().await;
+    app.login_as(&app.test_user).await;
+
+    for _ in 0..4 {
+        let response = app.post_login_code("000000").await;
+        assert_is_redirect_to(&response, "/login/2fa");
+    }
+    let response = app.post_login_code("000000").await;
+    assert_is_redirect_to(&response, "/login");
+    let html_page = app.get_login_html().await;
+    assert!(html_page.contains("<p><i>Too many invalid codes. Please log in again.</i></p>"));
+
+    let response = app.post_login_code(&totp_code(&secret, 0)).await;
+    assert_is_redirect_to(&response, "/login");
+}
+
+#[tokio::test]
+async fn invalid_codes_count_towards_the_login_lockout() {
+    let app = spawn_app_with(|c| {
+        c.login_throttle.free_attempts = 100;
+        c.login_throttle.username_lockout_threshold = 7;
+    })
+    .await;
+    app.login_as(&app.test_user).await;
+    let (secret, _) = enrol(&app).await;
+    app.post_logout().await;
+
+    // Logging in again with the right password must not reset the count.
+    app.login_as(&app.test_user).await;
+    for _ in 0..5 {
+        app.post_login_code("000000").await;
+    }
+    app.login_as(&app.test_user).await;
+    for _ in 0..2 {
+        app.post_login_code("000000").await;
+    }
+
+    let response = app.post_login_code(&totp_code(&secret, 0)).await;
+    assert_is_redirect_to(&response, "/login");
+    let html_page = app.get_login_html().await;
+    assert!(html_page.contains("<p><i>Too many failed login attempts. Please try again later.</i></p>"));
+    let response = app.login_as(&app.test_user).await;
+    assert_is_redirect_to(&response, "/login");
+}
+
+#[tokio::test]
+async fn recovery_codes_can_be_used_once() {
+    let app = spawn_app().await;
+    app.login_as(&app.test_user).await;
+    let (_, recovery_codes) = enrol(&app).await;
+    app.post_logout().await;
+
+    app.login_as(&app.test_user).await;
+    let response = app.post_login_code(&recovery_codes[0].to_uppercase()).await;
+    assert_is_redirect_to(&response, "/admin/dashboard");
+    app.post_logout().await;
+
+    app.login_as(&app.test_user).await;
+    let response = app.post_login_code(&recovery_codes[0]).await;
+    assert_is_redirect_to(&response, "/login/2fa");
+}
+
+#[tokio::test]
+async fn enrolment_requires_a_valid_code() {
+    let app = spawn_app().await;
+    app.login_as(&app.test_user).await;
+    app.get_two_factor_settings_html().await;
+
+    let response = app.post_enable_two_factor("000000").await;
+
+    assert_is_redirect_to(&response, "/admin/2fa");
+    let html_page = app.get_two_factor_settings_html().await;
+    assert!(html_page.contains("<p><i>The code is invalid. Check your device's clock and try again.</i></p>"));
+    app.post_logout().await;
+    let response = app.login_as(&app.test_user).await;
+    assert_is_redirect_to(&response, "/admin/dashboard");
+}
+
+#[tokio::test]
+async fn disabling_two_factor_authentication_requires_a_code() {
+    let app = spawn_app().await;
+    app.login_as(&app.test_user).await;
+    let (secret, _) = enrol(&app).await;
+
+    let response = app.post_disable_two_factor("000000").await;
+    assert_is_redirect_to(&response, "/admin/2fa");
+    let html_page = app.get_two_factor_settings_html().await;
+    assert!(html_page.contains("<p><i>The code is invalid.</i></p>"));
+    assert!(html_page.contains("Two-factor authentication is enabled."));
+
+    app.post_disable_two_factor(&totp_code(&secret, 0)).await;
+    let html_page = app.get_two_factor_settings_html().await;
+    assert!(html_page.contains("<p><i>Two-factor authentication has been disabled.</i></p>"));
+    app.post_logout().await;
+    let response = app.login_as(&app.test_user).await;
+    assert_is_redirect_to(&response, "/admin/dashboard");
+}
@@ -0,0 +1,149 @@
+use serde_json::Value;
+use wiremock::{matchers::{any, method, path}, Mock, ResponseTemplate};
+
+use crate::helpers::{create_confirmed_subscriber, spawn_app, TestApp};
+
+async fn unsubscribe_token(app: &TestApp) -> String {
+    sqlx::query!("SELECT unsubscribe_token FROM subscriptions")
+        .fetch_one(&app.db_pool)
+        .await
+        .expect("Failed to fetch saved subscriber.")
+        .unsubscribe_token
+}
+
+async fn publish_newsletter(app: &TestApp) {
+    app.post_login(&serde_json::json!({
+        "username": &app.test_user.username,
+        "password": &app.test_user.password,
+    })).await;
+    app.publish_newsletter(&serde_json::json!({
+        "title": "Newsletter title",
+        "text_content": "Newsletter body as plain text.",
+        "html_content": "<p>Newsletter body as HTML.</p>",
+    }))
+    .await;
+    app.dispatch_all_pending_emails().await;
+}
+
+#[tokio::test]
+async fn newsletters_carry_an_unsubscribe_link_and_list_unsubscribe_headers() {
+    let app = spawn_app().await;
+    create_confirmed_subscriber(&app).await;
+    let token = unsubscribe_token(&app).await;
+
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+    publish_newsletter(&app).await;
+
+    let email_request = app.email_server.received_requests().await.unwrap().pop().unwrap();
+    let body: Value = serde_json::from_slice(&email_request.body).unwrap();
+    let unsubscribe_link = format!("{}/subscriptions/unsubscribe?token={}", app.address, token);
+
+    assert!(body["TextBody"].as_str().unwrap().contains(&unsubscribe_link));
+    assert!(body["HtmlBody"].as_str().unwrap().contains(&unsubscribe_link));
+    let headers = body["Headers"].as_array().unwrap();
+    assert!(headers.contains(&serde_json::json!({
+        "Name": "List-Unsubscribe",
+        "Value": format!("<{}>", unsubscribe_link),
+    })));
+    assert!(headers.contains(&serde_json::json!({
+        "Name": "List-Unsubscribe-Post",
+        "Value": "List-Unsubscribe=One-Click",
+    })));
+}
+
+#[tokio::test]
+async fn the_unsubscribe_page_asks_for_confirmation_without_unsubscribing() {
+    let app = spawn_app().await;
+    create_confirmed_subscriber(&app).await;
+    let token = unsubscribe_token(&app).await;
+
+    let response = app.get_unsubscribe(&token).await;
+    assert_eq!(response.status().as_u16(), 200);
+    let html_page = response.text().await.unwrap();
+    assert!(html_page.contains(r#"method="post""#));
+
+    let saved = sqlx::query!("SELECT status FROM subscriptions")
+        .fetch_one(&app.db_pool)
+        .await
+        .expect("Failed to fetch saved subscriber.");
+    assert_eq!(saved.status, "confirmed");
+}
+
+#[tokio::test]
+async fn one_click_unsubscribe_marks_the_subscriber_as_unsubscribed() {
+    let app = spawn_app().await;
+    create_confirmed_subscriber(&app).await;
+    let token = unsubscribe_token(&app).await;
+
+    let response = app.post_unsubscribe(&token).await;
+    assert_eq!(response.status().as_u16(), 200);
+
+    let saved = sqlx::query!("SELECT status FROM subscriptions")
+        .fetch_one(&app.db_pool)
+        .await
+        .expect("Failed to fetch saved subscriber.");
+    assert_eq!(saved.status, "unsubscribed");
+}
+
+#[tokio::test]
+async fn newsletters_are_not_delivered_to_unsubscribed_subscribers() {
+    let app = spawn_app().await;
+    create_confirmed_subscriber(&app).await;
+    let token = unsubscribe_token(&app).await;
+    app.post_unsubscribe(&token).await.error_for_status().unwrap();
+
+    Mock::given(any())
+        .respond_with(ResponseTemplate::new(200))
+        .expect(0)
+        .mount(&app.email_server)
+        .await;
+    publish_newsletter(&app).await;
+}
+
+#[tokio::test]
+async fn unknown_unsubscribe_tokens_are_rejected_with_a_401() {
+    let app = spawn_app().await;
+
+    let response = app.get_unsubscribe("unknown-token").await;
+    assert_eq!(response.status().as_u16(), 401);
+
+    let response = app.post_unsubscribe("unknown-token").await;
+    assert_eq!(response.status().as_u16(), 401);
+}
+
+#[tokio::test]
+async fn unsubscribing_a_suppressed_address_does_not_let_it_be_mailed_again() {
+    let app = spawn_app().await;
+    create_confirmed_subscriber(&app).await;
+    let token = unsubscribe_token(&app).await;
+    app.post_email_webhook(
+        "postmark",
+        &app.webhook_token,
+        include_str!("fixtures/postmark_hard_bounce.json"),
+    )
+    .await
+    .error_for_status()
+    .unwrap();
+
+    Mock::given(any())
+        .respond_with(ResponseTemplate::new(200))
+        .expect(0)
+        .mount(&app.email_server)
+        .await;
+    app.post_unsubscribe(&token).await.error_for_status().unwrap();
+    let response = app
+        .post_subscriptions("name=le%20guin&email=ursula_le_guin%40gmail.com".into())
+        .await;
+
+    assert_eq!(response.status().as_u16(), 200);
+    let saved = sqlx::query!("SELECT status FROM subscriptions")
+        .fetch_one(&app.db_pool)
+        .await
+        .expect("Failed to fetch saved subscriber.");
+    assert_eq!(saved.status, "suppressed");
+}
@@ -0,0 +1,403 @@
+use wiremock::{matchers::{method, path}, Mock, ResponseTemplate};
+
+use crate::helpers::{assert_is_redirect_to, spawn_app, TestApp, TestUser};
+
+async fn store_user(app: &TestApp, role: &'static str) -> TestUser {
+    let user = TestUser::with_role(role);
+    user.store(&app.db_pool).await;
+    user
+}
+
+/// A client with its own cookie jar, to act as a second logged-in user.
+async fn logged_in_client(app: &TestApp, user: &TestUser) -> reqwest::Client {
+    let client = reqwest::Client::builder()
+        .redirect(reqwest::redirect::Policy::none())
+        .cookie_store(true)
+        .build()
+        .unwrap();
+    let response = client
+        .post(format!("{}/login", &app.address))
+        .form(&serde_json::json!({
+            "username": &user.username,
+            "password": &user.password,
+        }))
+        .send()
+        .await
+        .unwrap();
+    assert_is_redirect_to(&response, "/admin/dashboard");
+    client
+}
+
+fn invitation_body(username: &str) -> serde_json::Value {
+    serde_json::json!({
+        "username": username,
+        "email": "new.editor@example.com",
+        "role": "editor",
+    })
+}
+
+#[tokio::test]
+async fn an_invited_user_can_set_a_password_and_log_in() {
+    let app = spawn_app().await;
+    app.login_as(&app.test_user).await;
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+
+    let response = app.post_invite_user(&invitation_body("new-editor")).await;
+    assert_is_redirect_to(&response, "/admin/users");
+    let html_page = app.get_users_html().await;
+    assert!(html_page.contains("<p><i>new-editor has been invited with the editor role.</i></p>"));
+    assert!(html_page.contains("<td>new-editor</td>"));
+    assert!(html_page.contains("<td>invited</td>"));
+
+    let email_request = &app.email_server.received_requests().await.unwrap()[0];
+    let invitation_link = app.get_confirmation_links(email_request).html;
+    assert_eq!(invitation_link.path(), "/invitations/accept");
+    let response = reqwest::get(invitation_link.clone()).await.unwrap();
+    assert_eq!(response.status().as_u16(), 200);
+    let invitation_token = invitation_link
+        .query_pairs()
+        .find(|(key, _)| key == "invitation_token")
+        .unwrap()
+        .1
+        .to_string();
+    // The password policy applies to invited users too.
+    let response = app
+        .api_client
+        .post(format!("{}/invitations/accept", &app.address))
+        .form(&serde_json::json!({
+            "invitation_token": &invitation_token,
+            "password": "password1234",
+            "password_check": "password1234",
+        }))
+        .send()
+        .await
+        .unwrap();
+    assert_is_redirect_to(&response, &format!("/invitations/accept?invitation_token={}", invitation_token));
+    let html_page = app.api_client.get(invitation_link.clone()).send().await.unwrap().text().await.unwrap();
+    assert!(html_page.contains("<p><i>The new password is too common. Please choose another one.</i></p>"));
+    let response = app
+        .api_client
+        .post(format!("{}/invitations/accept", &app.address))
+        .form(&serde_json::json!({
+            "invitation_token": &invitation_token,
+            "password": "a-brand-new-password",
+            "password_check": "a-brand-new-password",
+        }))
+        .send()
+        .await
+        .unwrap();
+    assert_is_redirect_to(&response, "/login");
+
+    app.post_logout().await;
+    let response = app
+        .post_login(&serde_json::json!({
+            "username": "new-editor",
+            "password": "a-brand-new-password",
+        }))
+        .await;
+    assert_is_redirect_to(&response, "/admin/dashboard");
+    let html_page = app.get_admin_dashboard_html().await;
+    assert!(html_page.contains("Welcome new-editor (editor)"));
+    assert!(!html_page.contains(r#"<a href="/admin/users">"#));
+
+    // The link cannot be used twice.
+    let response = reqwest::get(invitation_link).await.unwrap();
+    assert_eq!(response.status().as_u16(), 401);
+}
+
+#[tokio::test]
+async fn only_a_hash_of_the_invitation_token_is_stored() {
+    let app = spawn_app().await;
+    app.login_as(&app.test_user).await;
+    Mock::given(path("/email"))
+        .respond_with(ResponseTemplate::new(200))
+        .mount(&app.email_server)
+        .await;
+
+    app.post_invite_user(&invitation_body("new-editor")).await;
+
+    let email_request = &app.email_server.received_requests().await.unwrap()[0];
+    let invitation_link = app.get_confirmation_links(email_request).html;
+    let invitation_token = invitation_link
+        .query_pairs()
+        .find(|(key, _)| key == "invitation_token")
+        .unwrap()
+        .1
+        .to_string();
+    let stored = sqlx::query_scalar!("SELECT token_hash FROM user_invitations")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap();
+    assert_ne!(stored, invitation_token);
+    assert_eq!(stored.len(), 64);
+}
+
+#[tokio::test]
+async fn the_invitation_of_a_disabled_user_cannot_be_accepted() {
+    let app = spawn_app().await;
+    app.login_as(&app.test_user).await;
+    Mock::given(path("/email"))
+        .respond_with(ResponseTemplate::new(200))
+        .mount(&app.email_server)
+        .await;
+    app.post_invite_user(&invitation_body("new-editor")).await;
+    let email_request = &app.email_server.received_requests().await.unwrap()[0];
+    let invitation_link = app.get_confirmation_links(email_request).html;
+    let invitation_token = invitation_link
+        .query_pairs()
+        .find(|(key, _)| key == "invitation_token")
+        .unwrap()
+        .1
+        .to_string();
+    let user_id = sqlx::query_scalar!("SELECT user_id FROM users WHERE username = 'new-editor'")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap();
+
+    app.post_user_action(user_id, "disable").await;
+
+    let response = reqwest::get(invitation_link).await.unwrap();
+    assert_eq!(response.status().as_u16(), 410);
+    let response = app
+        .api_client
+        .post(format!("{}/invitations/accept", &app.address))
+        .form(&serde_json::json!({
+            "invitation_token": &invitation_token,
+            "password": "a-brand-new-password",
+            "password_check": "a-brand-new-password",
+        }))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status().as_u16(), 410);
+}
+
+#[tokio::test]
+async fn nothing_is_stored_if_the_invitation_email_cannot_be_sent() {
+    let app = spawn_app().await;
+    app.login_as(&app.test_user).await;
+    Mock::given(path("/email"))
+        .respond_with(ResponseTemplate::new(422))
+        .mount(&app.email_server)
+        .await;
+
+    app.post_invite_user(&invitation_body("new-editor")).await;
+
+    let html_page = app.get_users_html().await;
+    assert!(html_page.contains("<p><i>The invitation email could not be sent. Please try again.</i></p>"));
+    let n_users = sqlx::query_scalar!("SELECT COUNT(*) FROM users WHERE username = 'new-editor'")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap();
+    assert_eq!(n_users, Some(0));
+    let n_invitations = sqlx::query_scalar!("SELECT COUNT(*) FROM user_invitations")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap();
+    assert_eq!(n_invitations, Some(0));
+}
+
+#[tokio::test]
+async fn invitations_are_validated() {
+    let app = spawn_app().await;
+    app.login_as(&app.test_user).await;
+    let test_cases = vec![
+        (
+            serde_json::json!({"username": "", "email": "a@example.com", "role": "viewer"}),
+            "Usernames are 1 to 50 characters long.",
+        ),
+        (
+            serde_json::json!({"username": "someone", "email": "not-an-email", "role": "viewer"}),
+            "Enter a valid email address.",
+        ),
+        (
+            serde_json::json!({"username": "someone", "email": "a@example.com", "role": "admin"}),
+            "Choose a role for the new user.",
+        ),
+        (
+            serde_json::json!({"username": &app.test_user.username, "email": "a@example.com", "role": "viewer"}),
+            "is already taken.",
+        ),
+        (
+            serde_json::json!({"username": "someone", "email": &app.test_user.email, "role": "viewer"}),
+            "Another account already uses that email address.",
+        ),
+    ];
+
+    for (body, error_message) in test_cases {
+        let response = app.post_invite_user(&body).await;
+        assert_is_redirect_to(&response, "/admin/users");
+        let html_page = app.get_users_html().await;
+        assert!(html_page.contains(error_message), "Expected `{}`", error_message);
+    }
+}
+
+#[tokio::test]
+async fn concurrent_invitations_for_the_same_address_create_a_single_user() {
+    let app = spawn_app().await;
+    app.login_as(&app.test_user).await;
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+
+    let (body1, body2) = (invitation_body("first-editor"), invitation_body("second-editor"));
+    let (response1, response2) = tokio::join!(app.post_invite_user(&body1), app.post_invite_user(&body2));
+
+    assert_is_redirect_to(&response1, "/admin/users");
+    assert_is_redirect_to(&response2, "/admin/users");
+    let n_users = sqlx::query_scalar!(
+        "SELECT COUNT(*) FROM users WHERE email = 'new.editor@example.com'"
+    )
+    .fetch_one(&app.db_pool)
+    .await
+    .unwrap();
+    assert_eq!(n_users, Some(1));
+}
+
+#[tokio::test]
+async fn only_owners_can_manage_users() {
+    let app = spawn_app().await;
+    let editor = store_user(&app, "editor").await;
+    app.login_as(&editor).await;
+
+    let response = app.get_users().await;
+    assert_is_redirect_to(&response, "/admin/dashboard");
+    let html_page = app.get_admin_dashboard_html().await;
+    assert!(html_page.contains("<p><i>You do not have permission to do that.</i></p>"));
+
+    let response = app.post_user_action(app.test_user.user_id, "disable").await;
+    assert_is_redirect_to(&response, "/admin/dashboard");
+    let disabled = sqlx::query_scalar!(
+        "SELECT disabled_at IS NOT NULL AS \"disabled!\" FROM users WHERE user_id = $1",
+        app.test_user.user_id
+    )
+    .fetch_one(&app.db_pool)
+    .await
+    .unwrap();
+    assert!(!disabled);
+}
+
+#[tokio::test]
+async fn viewers_can_read_issues_but_not_publish() {
+    let app = spawn_app().await;
+    let viewer = store_user(&app, "viewer").await;
+    app.login_as(&viewer).await;
+
+    let response = app.api_client
+        .get(format!("{}/admin/issues", &app.address))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status().as_u16(), 200);
+
+    let response = app.get_drafts().await;
+    assert_is_redirect_to(&response, "/admin/dashboard");
+    let response = app.post_create_draft(&serde_json::json!({
+        "title": "Newsletter title",
+        "text_content": "Newsletter body as plain text.",
+        "html_content": "<p>Newsletter body as HTML.</p>",
+    }))
+    .await;
+    assert_is_redirect_to(&response, "/admin/dashboard");
+    let n_issues = sqlx::query_scalar!("SELECT COUNT(*) FROM newsletter_issues")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap();
+    assert_eq!(n_issues, Some(0));
+}
+
+#[tokio::test]
+async fn viewers_cannot_export_failed_deliveries() {
+    let app = spawn_app().await;
+    let viewer = store_user(&app, "viewer").await;
+    app.login_as(&viewer).await;
+
+    let response = app.api_client
+        .get(format!("{}/admin/issues/{}/failures.csv", &app.address, uuid::Uuid::new_v4()))
+        .send()
+        .await
+        .unwrap();
+
+    assert_is_redirect_to(&response, "/admin/dashboard");
+}
+
+#[tokio::test]
+async fn editors_can_publish() {
+    let app = spawn_app().await;
+    let editor = store_user(&app, "editor").await;
+    app.login_as(&editor).await;
+
+    let response = app.get_drafts().await;
+
+    assert_eq!(response.status().as_u16(), 200);
+}
+
+#[tokio::test]
+async fn disabled_users_are_logged_out_and_cannot_log_in_again() {
+    let app = spawn_app().await;
+    let viewer = store_user(&app, "viewer").await;
+    let viewer_client = logged_in_client(&app, &viewer).await;
+    app.login_as(&app.test_user).await;
+
+    let response = app.post_user_action(viewer.user_id, "disable").await;
+    assert_is_redirect_to(&response, "/admin/users");
+    let html_page = app.get_users_html().await;
+    assert!(html_page.contains(&format!("<p><i>{} has been disabled.</i></p>", viewer.username)));
+
+    let response = viewer_client
+        .get(format!("{}/admin/dashboard", &app.address))
+        .send()
+        .await
+        .unwrap();
+    assert_is_redirect_to(&response, "/login");
+    let response = viewer_client
+        .post(format!("{}/login", &app.address))
+        .form(&serde_json::json!({
+            "username": &viewer.username,
+            "password": &viewer.password,
+        }))
+        .send()
+        .await
+        .unwrap();
+    assert_is_redirect_to(&response, "/login");
+
+    app.post_user_action(viewer.user_id, "enable").await;
+    logged_in_client(&app, &viewer).await;
+}
+
+#[tokio::test]
+async fn owners_cannot_disable_or_delete_themselves() {
+    let app = spawn_app().await;
+    app.login_as(&app.test_user).await;
+
+    app.post_user_action(app.test_user.user_id, "disable").await;
+    let html_page = app.get_users_html().await;
+    assert!(html_page.contains("<p><i>You cannot disable your own account.</i></p>"));
+
+    app.post_user_action(app.test_user.user_id, "delete").await;
+    let html_page = app.get_users_html().await;
+    assert!(html_page.contains("<p><i>You cannot delete your own account.</i></p>"));
+    assert!(html_page.contains("<td>active</td>"));
+}
+
+#[tokio::test]
+async fn deleted_users_are_removed() {
+    let app = spawn_app().await;
+    let editor = store_user(&app, "editor").await;
+    app.login_as(&app.test_user).await;
+
+    let response = app.post_user_action(editor.user_id, "delete").await;
+
+    assert_is_redirect_to(&response, "/admin/users");
+    let html_page = app.get_users_html().await;
+    assert!(html_page.contains(&format!("<p><i>{} has been deleted.</i></p>", editor.username)));
+    assert!(!html_page.contains(&format!("<td>{}</td>", editor.username)));
+}